@@ -10,6 +10,8 @@ pub fn main() {
         mean_resolution: 128.0,
         max_resolution: 128.0,
         min_resolution: 8.0,
+        fixed_resolution: None,
+        axis_weights: glam::Vec3::ONE,
     };
     let mesh = opensaft::mesh_from_sdf(&graph, root, mesh_options).unwrap();
     eprintln!(
@@ -25,4 +27,27 @@ pub fn main() {
     tiny_bench::bench_with_configuration_labeled("mech_from_sdf", &bench_cfg, || {
         opensaft::mesh_from_sdf(&graph, root, mesh_options)
     });
+
+    // What `InterpreterPool` saves by reusing a context, compared to making one for each point.
+    let sdf = opensaft::CompiledSdf::new(&graph, root).unwrap();
+    let pool = sdf.pool::<f32>();
+    let positions: Vec<_> = (0..100_000)
+        .map(|i| {
+            let t = i as f32;
+            glam::Vec3::new((t * 0.37).sin(), (t * 0.11).cos(), (t * 0.73).sin()) * 3.0
+        })
+        .collect();
+    tiny_bench::bench_with_configuration_labeled("interpret_new_context", &bench_cfg, || {
+        positions
+            .iter()
+            .map(|pos| pool.interpret(*pos).unwrap())
+            .sum::<f32>()
+    });
+    tiny_bench::bench_with_configuration_labeled("interpret_reused_context", &bench_cfg, || {
+        let mut context = pool.new_context();
+        positions
+            .iter()
+            .map(|pos| opensaft::Interpreter::interpret(&mut context, *pos).unwrap())
+            .sum::<f32>()
+    });
 }
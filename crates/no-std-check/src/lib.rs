@@ -0,0 +1,50 @@
+//! Checks that the grid and the mesher of `opensaft` build with only `alloc`, see its `std` feature.
+//!
+//! This crate has its own panic handler, so it fails to compile if anything pulls in `std`.
+//! Build it with `cargo build`, and run the test with `cargo test`, from this directory.
+
+#![no_std]
+
+extern crate alloc;
+
+use glam::Vec3;
+use opensaft::Grid3;
+use opensaft::TriangleMesh;
+
+/// Meshes a sphere of `radius` grid cells in the middle of a grid of `size` points along each axis.
+pub fn mesh_sphere(size: usize, radius: f32) -> TriangleMesh {
+    let center = Vec3::splat((size - 1) as f32 / 2.0);
+    let mut grid = Grid3::<f32>::new([size; 3]);
+    grid.set_truncated(
+        |[x, y, z]| {
+            let pos = Vec3::new(x as f32, y as f32, z as f32);
+            opensaft::sd_sphere(pos, center, radius)
+        },
+        2.0,
+    );
+    grid.marching_cubes()
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
+    loop {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere() {
+        let mesh = mesh_sphere(16, 5.0);
+        assert!(!mesh.indices.is_empty());
+        assert_eq!(mesh.positions.len(), mesh.normals.len());
+
+        let center = Vec3::splat(7.5);
+        for &pos in &mesh.positions {
+            let distance = Vec3::from(pos).distance(center);
+            assert!((distance - 5.0).abs() < 0.1, "{distance}");
+        }
+    }
+}
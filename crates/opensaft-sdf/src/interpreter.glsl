@@ -4,7 +4,7 @@
 #define Opcode_Sphere         1 // center: vec3, radius: f32
 #define Opcode_Capsule        2 // p0: vec3, p1: vec3, radius: f32
 #define Opcode_TaperedCapsule 3 // p0: vec3, p1: vec3, radius: f32
-#define Opcode_Material       4 // rgb: vec3
+#define Opcode_Material       4 // rgba: vec4, metallic: f32, roughness: f32
 
 // Combinators:
 #define Opcode_Union           5
@@ -29,6 +29,34 @@
 #define Opcode_Torus           20
 #define Opcode_TorusSector     21
 #define Opcode_Cone            22
+#define Opcode_Gyroid          23
+#define Opcode_SchwarzP        24
+#define Opcode_SolidAngle      25
+#define Opcode_Link            26
+#define Opcode_CutSphere       27
+#define Opcode_CutHollowSphere 28
+#define Opcode_Vesica          29
+
+#define Opcode_PushAffine      30
+#define Opcode_PopAffine       31
+#define Opcode_PushElongate    32
+#define Opcode_PopElongate     33
+#define Opcode_UnionStairs     34
+#define Opcode_Pipe            35
+#define Opcode_Groove          36
+#define Opcode_UnionChamfer    37
+#define Opcode_SubtractChamfer 38
+#define Opcode_IntersectChamfer 39
+#define Opcode_ConvexHull      40
+#define Opcode_PushRevolve     41
+#define Opcode_PushWarp        42
+#define Opcode_Tube            43
+#define Opcode_Egg             44
+#define Opcode_Heart           45
+#define Opcode_ScaleDistance   46
+#define Opcode_CappedCone      47
+#define Opcode_PushWarpSample  48
+#define Opcode_PopWarpSample   49
 
 // Using a subset of opcodes here would allow to make a stackless
 // batched interpreter that basically runs the vm at a kernel dispatch level
@@ -60,6 +88,10 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
     uint transform_sp = 0;
     vec3 transform_stack[64];
 
+    // The running `PushWarpSample` loops: where the repeated code starts, and which offset is being sampled.
+    uint warp_sample_sp = 0;
+    uvec3 warp_sample_stack[8];
+
     vec3 current_position = pos;
 
     while (true) {
@@ -73,6 +105,16 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
             }
             break;
 
+            case Opcode_ConvexHull: {
+                uint num_planes = uint(read_float(cp));
+                float distance_ = sd_convex_hull_empty();
+                for (uint i = 0u; i < num_planes; i++) {
+                    distance_ = max(distance_, sd_plane(current_position, read_vec4(cp)));
+                }
+                stack[sp++] = sdrgb_convex_hull(distance_);
+            }
+            break;
+
             case Opcode_Sphere: {
                 vec3 center = read_vec3(cp);
                 float radius = read_float(cp);
@@ -88,6 +130,28 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
             }
             break;
 
+            case Opcode_Tube: {
+                float outer_r = read_float(cp);
+                float inner_r = read_float(cp);
+                float half_height = read_float(cp);
+                stack[sp++] = sdrgb_tube(current_position, outer_r, inner_r, half_height);
+            }
+            break;
+
+            case Opcode_Egg: {
+                float radius = read_float(cp);
+                float tip_radius = read_float(cp);
+                stack[sp++] = sdrgb_egg(current_position, radius, tip_radius);
+            }
+            break;
+
+            case Opcode_Heart: {
+                float size = read_float(cp);
+                float half_thickness = read_float(cp);
+                stack[sp++] = sdrgb_heart(current_position, size, half_thickness);
+            }
+            break;
+
             case Opcode_RoundedCylinder: {
                 float radius = read_float(cp);
                 float height = read_float(cp);
@@ -114,6 +178,14 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
             }
             break;
 
+            case Opcode_CappedCone: {
+                float r1 = read_float(cp);
+                float r2 = read_float(cp);
+                float height = read_float(cp);
+                stack[sp++] = sdrgb_capped_cone(current_position, r1, r2, height);
+            }
+            break;
+
             case Opcode_RoundedBox: {
                 vec3 half_size = read_vec3(cp);
                 float radius = read_float(cp);
@@ -141,9 +213,52 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
             }
             break;
 
+            case Opcode_Vesica: {
+                vec2 q = read_vec2(cp);
+                stack[sp++] = sdrgb_vesica(current_position, q.x, q.y);
+            }
+            break;
+
+            case Opcode_CutSphere: {
+                vec2 q = read_vec2(cp);
+                stack[sp++] = sdrgb_cut_sphere(current_position, q.x, q.y);
+            }
+            break;
+
+            case Opcode_CutHollowSphere: {
+                vec3 params = read_vec3(cp);
+                stack[sp++] = sdrgb_cut_hollow_sphere(current_position, params.x, params.y, params.z);
+            }
+            break;
+
+            case Opcode_Link: {
+                vec3 params = read_vec3(cp);
+                stack[sp++] = sdrgb_link(current_position, params.x, params.y, params.z);
+            }
+            break;
+
+            case Opcode_SolidAngle: {
+                vec3 params = read_vec3(cp);
+                stack[sp++] = sdrgb_solid_angle(current_position, params.xy, params.z);
+            }
+            break;
+
+            case Opcode_Gyroid: {
+                vec2 q = read_vec2(cp);
+                stack[sp++] = sdrgb_gyroid(current_position, q.x, q.y);
+            }
+            break;
+
+            case Opcode_SchwarzP: {
+                vec2 q = read_vec2(cp);
+                stack[sp++] = sdrgb_schwarz_p(current_position, q.x, q.y);
+            }
+            break;
+
             case Opcode_Material: {
-                vec3 rgb = read_vec3(cp);
-                stack[sp - 1].rgb = rgb.rgb;
+                vec4 rgba = read_vec4(cp);
+                cp += 2u; // metallic and roughness
+                stack[sp - 1].rgb = rgba.rgb;
             }
             break;
 
@@ -185,6 +300,50 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
                 sp -= 1;
                 stack[sp - 1] = sdrgb_op_intersect_smooth(stack[sp], stack[sp - 1], smoothness);
             }
+            break;
+
+            case Opcode_UnionChamfer: {
+                float size = read_float(cp);
+                sp -= 1;
+                stack[sp - 1] = sdrgb_op_union_chamfer(stack[sp], stack[sp - 1], size);
+            }
+            break;
+
+            case Opcode_SubtractChamfer: {
+                float size = read_float(cp);
+                sp -= 1;
+                stack[sp - 1] = sdrgb_op_subtract_chamfer(stack[sp], stack[sp - 1], size);
+            }
+            break;
+
+            case Opcode_IntersectChamfer: {
+                float size = read_float(cp);
+                sp -= 1;
+                stack[sp - 1] = sdrgb_op_intersect_chamfer(stack[sp], stack[sp - 1], size);
+            }
+            break;
+
+            case Opcode_UnionStairs: {
+                float size = read_float(cp);
+                float steps = read_float(cp);
+                sp -= 1;
+                stack[sp - 1] = sdrgb_op_union_stairs(stack[sp], stack[sp - 1], size, steps);
+            }
+            break;
+
+            case Opcode_Pipe: {
+                float radius = read_float(cp);
+                sp -= 1;
+                stack[sp - 1] = sdrgb_op_pipe(stack[sp], stack[sp - 1], radius);
+            }
+            break;
+
+            case Opcode_Groove: {
+                float depth = read_float(cp);
+                float half_width = read_float(cp);
+                sp -= 1;
+                stack[sp - 1] = sdrgb_op_groove(stack[sp], stack[sp - 1], depth, half_width);
+            }
             break;
 
                 // Transforms:
@@ -225,6 +384,92 @@ vec4 sdrgb_interpret(vec3 pos, uint opcodes_offset, uint constants_offset) {
             }
             break;
 
+            case Opcode_PushAffine: {
+                transform_stack[transform_sp++] = current_position;
+
+                mat3 m = mat3(read_vec3(cp), read_vec3(cp), read_vec3(cp));
+                vec3 translation = read_vec3(cp);
+                current_position = m * current_position + translation;
+            }
+            break;
+
+            case Opcode_PopAffine: {
+                transform_sp -= 1;
+                current_position = transform_stack[transform_sp];
+
+                float distance_scale = read_float(cp);
+                stack[sp - 1].w *= distance_scale;
+            }
+            break;
+
+            case Opcode_PushRevolve: {
+                transform_stack[transform_sp++] = current_position;
+
+                float offset = read_float(cp);
+                current_position = op_revolve_position(current_position, offset);
+            }
+            break;
+
+            case Opcode_PushWarp: {
+                float strength = read_float(cp);
+                float epsilon = read_float(cp);
+                sp -= 4;
+                vec4 d = vec4(stack[sp].w, stack[sp + 1].w, stack[sp + 2].w, stack[sp + 3].w);
+
+                transform_stack[transform_sp++] = current_position;
+                current_position = op_warp_position(current_position, d, strength, epsilon);
+            }
+            break;
+
+            case Opcode_PushWarpSample: {
+                float epsilon = read_float(cp);
+                warp_sample_stack[warp_sample_sp++] = uvec3(pc, cp, 0u);
+                transform_stack[transform_sp++] = current_position;
+                current_position += epsilon * warp_gradient_offset(0u);
+            }
+            break;
+
+            case Opcode_PopWarpSample: {
+                transform_sp -= 1;
+                current_position = transform_stack[transform_sp];
+                uvec3 sample_ = warp_sample_stack[warp_sample_sp - 1];
+                sample_.z += 1u;
+                if (sample_.z < 4u) {
+                    // Run the code again at the next offset, with the epsilon from right before it.
+                    pc = sample_.x;
+                    cp = sample_.y;
+                    warp_sample_stack[warp_sample_sp - 1] = sample_;
+                    transform_stack[transform_sp++] = current_position;
+                    current_position += SDF_CONSTANTS[cp - 1u] * warp_gradient_offset(sample_.z);
+                } else {
+                    warp_sample_sp -= 1;
+                }
+            }
+            break;
+
+            case Opcode_PushElongate: {
+                transform_stack[transform_sp++] = current_position;
+
+                vec3 amount = read_vec3(cp);
+                current_position = op_elongate_position(current_position, amount);
+            }
+            break;
+
+            case Opcode_PopElongate: {
+                transform_sp -= 1;
+                current_position = transform_stack[transform_sp];
+
+                vec3 amount = read_vec3(cp);
+                stack[sp - 1] = sdrgb_op_elongate_distance(stack[sp - 1], current_position, amount);
+            }
+            break;
+
+            case Opcode_ScaleDistance: {
+                float factor = read_float(cp);
+                stack[sp - 1].w *= factor;
+            }
+            break;
+
             default:
             case Opcode_End: {
                 return stack[sp - 1];
@@ -4,6 +4,7 @@ use super::Opcode;
 use crate::sdf;
 use crate::structs::Material;
 use crate::structs::SignedDistance;
+use core::borrow::BorrowMut;
 use glam::Quat;
 use glam::Vec3;
 use glam::Vec4;
@@ -21,15 +22,82 @@ impl<SD: SignedDistance> Default for Interpreter<SD> {
     }
 }
 
-pub struct InterpreterContext<'a, SD: SignedDistance, const STACK_DEPTH: usize = 64> {
+/// How deeply `PushWarpSample` loops can nest, i.e. warps in the warp field of warps.
+/// Each level evaluates the code inside of it four times, so this also bounds the work for malformed programs.
+const WARP_SAMPLE_DEPTH: usize = 8;
+
+/// A `PushWarpSample` loop that is running.
+#[derive(Copy, Clone)]
+struct WarpSample {
+    /// Where the code that is repeated starts.
+    pc: usize,
+    constant_idx: usize,
+    epsilon: f32,
+    /// Which of the `WARP_GRADIENT_OFFSETS` is being sampled.
+    index: usize,
+}
+
+/// The stacks an [`InterpreterContext`] evaluates a program with.
+///
+/// A context owns its stacks, unless it borrows them with [`Interpreter::new_context_with_stacks`],
+/// which lets them be kept around for evaluating a program that isn't borrowed for as long.
+#[derive(Clone)]
+pub struct InterpreterStacks<SD: SignedDistance, const STACK_DEPTH: usize = 64> {
+    stack: [SD; STACK_DEPTH],
+    position_stack: [Vec3; STACK_DEPTH],
+    warp_sample_stack: [WarpSample; WARP_SAMPLE_DEPTH],
+}
+
+impl<SD: SignedDistance + Copy + Clone, const STACK_DEPTH: usize>
+    InterpreterStacks<SD, STACK_DEPTH>
+{
+    pub fn new() -> Self {
+        Self {
+            stack: uninit([SignedDistance::infinity(); STACK_DEPTH]),
+            position_stack: uninit([Vec3::ZERO; STACK_DEPTH]),
+            warp_sample_stack: uninit(
+                [WarpSample {
+                    pc: 0,
+                    constant_idx: 0,
+                    epsilon: 0.0,
+                    index: 0,
+                }; WARP_SAMPLE_DEPTH],
+            ),
+        }
+    }
+}
+
+impl<SD: SignedDistance + Copy + Clone, const STACK_DEPTH: usize> Default
+    for InterpreterStacks<SD, STACK_DEPTH>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SD: SignedDistance, const STACK_DEPTH: usize> core::fmt::Debug
+    for InterpreterStacks<SD, STACK_DEPTH>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("InterpreterStacks").finish_non_exhaustive()
+    }
+}
+
+pub struct InterpreterContext<
+    'a,
+    SD: SignedDistance,
+    const STACK_DEPTH: usize = 64,
+    S = InterpreterStacks<SD, STACK_DEPTH>,
+> {
     opcodes: &'a [Opcode],
     constants: &'a [f32],
 
-    stack: [SD; STACK_DEPTH],
+    stacks: S,
     stack_ptr: usize,
     constant_idx: usize,
-    position_stack: [Vec3; STACK_DEPTH],
     position_stack_ptr: usize,
+    warp_sample_stack_ptr: usize,
+    carrier: core::marker::PhantomData<SD>,
 }
 
 fn uninit<T>(_t: T) -> T {
@@ -43,91 +111,130 @@ fn uninit<T>(_t: T) -> T {
     ret
 }
 
-impl<'a, SD: SignedDistance + Copy + Clone, const STACK_DEPTH: usize>
-    InterpreterContext<'a, SD, STACK_DEPTH>
+impl<'a, SD, const STACK_DEPTH: usize, S> InterpreterContext<'a, SD, STACK_DEPTH, S>
+where
+    SD: SignedDistance + Copy + Clone,
+    S: BorrowMut<InterpreterStacks<SD, STACK_DEPTH>>,
 {
-    fn new(opcodes: &'a [Opcode], constants: &'a [f32]) -> Self {
+    fn new(opcodes: &'a [Opcode], constants: &'a [f32], stacks: S) -> Self {
         Self {
             opcodes,
             constants,
-            stack: uninit([SignedDistance::infinity(); STACK_DEPTH]),
+            stacks,
             stack_ptr: 0,
             constant_idx: 0,
-            position_stack: uninit([Vec3::ZERO; STACK_DEPTH]),
             position_stack_ptr: 0,
+            warp_sample_stack_ptr: 0,
+            carrier: core::marker::PhantomData,
         }
     }
 
     fn reset(&mut self) {
         self.stack_ptr = 0;
         self.position_stack_ptr = 0;
+        self.warp_sample_stack_ptr = 0;
         self.constant_idx = 0;
     }
 
-    fn float32(&mut self) -> f32 {
-        let ret = self.constants[self.constant_idx];
+    // All reads and stack operations return `None` instead of panicking when the program is malformed,
+    // as programs may come from untrusted data.
+
+    fn float32(&mut self) -> Option<f32> {
+        let ret = *self.constants.get(self.constant_idx)?;
         self.constant_idx += 1;
-        ret
+        Some(ret)
     }
 
-    fn vec3(&mut self) -> Vec3 {
-        Vec3::new(self.float32(), self.float32(), self.float32())
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.float32()?, self.float32()?, self.float32()?))
     }
 
     // TODO (nummelin): How much faster is it to only allow 16-bytes alignment
     // when running through spirv?
-    fn vec4(&mut self) -> Vec4 {
-        Vec4::new(
-            self.float32(),
-            self.float32(),
-            self.float32(),
-            self.float32(),
-        )
+    fn vec4(&mut self) -> Option<Vec4> {
+        Some(Vec4::new(
+            self.float32()?,
+            self.float32()?,
+            self.float32()?,
+            self.float32()?,
+        ))
     }
 
-    fn quat(&mut self) -> Quat {
-        Quat::from_xyzw(
-            self.float32(),
-            self.float32(),
-            self.float32(),
-            self.float32(),
-        )
+    fn quat(&mut self) -> Option<Quat> {
+        Some(Quat::from_xyzw(
+            self.float32()?,
+            self.float32()?,
+            self.float32()?,
+            self.float32()?,
+        ))
     }
 
-    fn material(&mut self) -> Material {
-        self.vec3().into()
+    fn material(&mut self) -> Option<Material> {
+        let rgba = self.vec4()?;
+        let metallic = self.float32()?;
+        let roughness = self.float32()?;
+        Some(Material::new_rgba(rgba).with_metallic_roughness(metallic, roughness))
     }
 
-    fn push_sd(&mut self, v: SD) {
-        self.stack[self.stack_ptr] = v;
+    /// The next `len` constants, for opcodes with a variable number of them.
+    fn constant_slice(&mut self, len: usize) -> Option<&'a [f32]> {
+        let end = self.constant_idx.checked_add(len)?;
+        let ret = self.constants.get(self.constant_idx..end)?;
+        self.constant_idx = end;
+        Some(ret)
+    }
+
+    fn push_sd(&mut self, v: SD) -> Option<()> {
+        *self.stacks.borrow_mut().stack.get_mut(self.stack_ptr)? = v;
         self.stack_ptr += 1;
+        Some(())
     }
 
     fn pop_sd(&mut self) -> Option<SD> {
-        self.stack_ptr -= 1;
-        self.stack.get(self.stack_ptr).copied()
+        self.stack_ptr = self.stack_ptr.checked_sub(1)?;
+        self.stacks.borrow().stack.get(self.stack_ptr).copied()
+    }
+
+    /// Tags the distance on top of the stack as coming from the primitive at opcode `pc`.
+    fn tag_primitive(&mut self, pc: usize) {
+        let top = self
+            .stack_ptr
+            .checked_sub(1)
+            .and_then(|i| self.stacks.borrow_mut().stack.get_mut(i));
+        if let Some(sd) = top {
+            *sd = sd.with_primitive_id(pc as u32);
+        }
     }
 
     fn pop_sd_unchecked(&mut self) -> SD {
         self.stack_ptr -= 1;
-        self.stack[self.stack_ptr]
+        self.stacks.borrow().stack[self.stack_ptr]
     }
 
-    fn push_position(&mut self, pos: Vec3) {
-        self.position_stack[self.position_stack_ptr] = pos;
+    fn push_position(&mut self, pos: Vec3) -> Option<()> {
+        *self
+            .stacks
+            .borrow_mut()
+            .position_stack
+            .get_mut(self.position_stack_ptr)? = pos;
         self.position_stack_ptr += 1;
+        Some(())
     }
 
-    fn pop_position_unchecked(&mut self) -> Vec3 {
-        self.position_stack_ptr -= 1;
-        self.position_stack[self.position_stack_ptr]
+    fn pop_position(&mut self) -> Option<Vec3> {
+        self.position_stack_ptr = self.position_stack_ptr.checked_sub(1)?;
+        self.stacks
+            .borrow()
+            .position_stack
+            .get(self.position_stack_ptr)
+            .copied()
     }
 
     // See comment at the end of the `interpret` function below.
     #[allow(dead_code)]
     fn top_is_finite(&self) -> bool {
         if self.stack_ptr > 0 {
-            if let Some(value) = self.stack.get(self.stack_ptr - 1) {
+            if let Some(value) = self.stacks.borrow().stack.get(self.stack_ptr - 1) {
                 value.is_distance_finite()
             } else {
                 // Wacky
@@ -144,25 +251,44 @@ impl<SD: SignedDistance + Copy + Clone, const STACK_DEPTH: usize> Interpreter<SD
         opcodes: &'a [Opcode],
         constants: &'a [f32],
     ) -> InterpreterContext<'a, SD, STACK_DEPTH> {
-        InterpreterContext::<SD, STACK_DEPTH>::new(opcodes, constants)
+        InterpreterContext::new(opcodes, constants, InterpreterStacks::new())
+    }
+
+    /// Like [`Self::new_context`], but evaluates with `stacks` instead of making new ones,
+    /// so they can be reused between contexts.
+    pub fn new_context_with_stacks<'a>(
+        opcodes: &'a [Opcode],
+        constants: &'a [f32],
+        stacks: &'a mut InterpreterStacks<SD, STACK_DEPTH>,
+    ) -> InterpreterContext<'a, SD, STACK_DEPTH, &'a mut InterpreterStacks<SD, STACK_DEPTH>> {
+        InterpreterContext::new(opcodes, constants, stacks)
     }
-    pub fn interpret(
-        ctx: &mut InterpreterContext<'_, SD, STACK_DEPTH>,
+
+    /// Evaluates the program at `position`.
+    ///
+    /// Returns `None` if the program is malformed, e.g. if it reads past the end of the opcodes
+    /// or constants, or over- or underflows a stack.
+    pub fn interpret<S: BorrowMut<InterpreterStacks<SD, STACK_DEPTH>>>(
+        ctx: &mut InterpreterContext<'_, SD, STACK_DEPTH, S>,
         position: Vec3,
     ) -> Option<SD> {
-        Self::interpret_internal(ctx, position);
+        Self::interpret_internal(ctx, position)?;
         ctx.pop_sd()
     }
 
-    pub fn interpret_unchecked(
-        ctx: &mut InterpreterContext<'_, SD, STACK_DEPTH>,
+    /// Like [`Self::interpret`], but panics if the program is malformed.
+    pub fn interpret_unchecked<S: BorrowMut<InterpreterStacks<SD, STACK_DEPTH>>>(
+        ctx: &mut InterpreterContext<'_, SD, STACK_DEPTH, S>,
         position: Vec3,
     ) -> SD {
-        Self::interpret_internal(ctx, position);
+        Self::interpret_internal(ctx, position).unwrap();
         ctx.pop_sd_unchecked()
     }
 
-    fn interpret_internal(ctx: &mut InterpreterContext<'_, SD, STACK_DEPTH>, position: Vec3) {
+    fn interpret_internal<S: BorrowMut<InterpreterStacks<SD, STACK_DEPTH>>>(
+        ctx: &mut InterpreterContext<'_, SD, STACK_DEPTH, S>,
+        position: Vec3,
+    ) -> Option<()> {
         #[allow(clippy::enum_glob_use)]
         use Opcode::*;
 
@@ -173,147 +299,344 @@ impl<SD: SignedDistance + Copy + Clone, const STACK_DEPTH: usize> Interpreter<SD
         let mut pc = 0;
 
         loop {
-            let opcode = ctx.opcodes[pc];
+            let opcode = *ctx.opcodes.get(pc)?;
             pc += 1;
 
             match opcode {
                 Plane => {
-                    let sd = sdf::sd_plane(current_position, ctx.vec4());
-                    ctx.push_sd(sd);
+                    let sd = sdf::sd_plane(current_position, ctx.vec4()?);
+                    ctx.push_sd(sd)?;
+                }
+                ConvexHull => {
+                    let num_planes = ctx.float32()? as usize;
+                    let planes = ctx.constant_slice(num_planes.checked_mul(4)?)?;
+                    let planes = (0..num_planes).map(|i| Vec4::from_slice(&planes[i * 4..]));
+                    let sd = sdf::sd_convex_hull(current_position, planes);
+                    ctx.push_sd(sd)?;
                 }
                 Sphere => {
-                    let sd = sdf::sd_sphere(current_position, ctx.vec3(), ctx.float32());
-                    ctx.push_sd(sd);
+                    let sd = sdf::sd_sphere(current_position, ctx.vec3()?, ctx.float32()?);
+                    ctx.push_sd(sd)?;
                 }
                 Capsule => {
-                    let sd =
-                        sdf::sd_capsule(current_position, &[ctx.vec3(), ctx.vec3()], ctx.float32());
-                    ctx.push_sd(sd);
+                    let sd = sdf::sd_capsule(
+                        current_position,
+                        &[ctx.vec3()?, ctx.vec3()?],
+                        ctx.float32()?,
+                    );
+                    ctx.push_sd(sd)?;
                 }
                 RoundedCylinder => {
                     let sd = sdf::sd_rounded_cylinder(
                         current_position,
-                        ctx.float32(),
-                        ctx.float32(),
-                        ctx.float32(),
+                        ctx.float32()?,
+                        ctx.float32()?,
+                        ctx.float32()?,
                     );
-                    ctx.push_sd(sd);
+                    ctx.push_sd(sd)?;
+                }
+                Tube => {
+                    let sd = sdf::sd_tube(
+                        current_position,
+                        ctx.float32()?,
+                        ctx.float32()?,
+                        ctx.float32()?,
+                    );
+                    ctx.push_sd(sd)?;
+                }
+                Egg => {
+                    let radius = ctx.float32()?;
+                    let tip_radius = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_egg(current_position, radius, tip_radius))?;
+                }
+                Heart => {
+                    let size = ctx.float32()?;
+                    let half_thickness = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_heart(current_position, size, half_thickness))?;
                 }
                 TaperedCapsule => {
-                    let p0 = ctx.vec3();
-                    let r0 = ctx.float32();
-                    let p1 = ctx.vec3();
-                    let r1 = ctx.float32();
+                    let p0 = ctx.vec3()?;
+                    let r0 = ctx.float32()?;
+                    let p1 = ctx.vec3()?;
+                    let r1 = ctx.float32()?;
                     let sd = sdf::sd_tapered_capsule(current_position, &[p0, p1], [r0, r1]);
-                    ctx.push_sd(sd);
+                    ctx.push_sd(sd)?;
                 }
                 Cone => {
-                    let r = ctx.float32();
-                    let h = ctx.float32();
+                    let r = ctx.float32()?;
+                    let h = ctx.float32()?;
                     let sd = sdf::sd_cone(current_position, r, h);
-                    ctx.push_sd(sd);
+                    ctx.push_sd(sd)?;
+                }
+                CappedCone => {
+                    let r1 = ctx.float32()?;
+                    let r2 = ctx.float32()?;
+                    let height = ctx.float32()?;
+                    let sd = sdf::sd_capped_cone(current_position, r1, r2, height);
+                    ctx.push_sd(sd)?;
                 }
                 RoundedBox => {
-                    let half_size = ctx.vec3();
-                    let radius = ctx.float32();
+                    let half_size = ctx.vec3()?;
+                    let radius = ctx.float32()?;
                     let sd = sdf::sd_rounded_box(current_position, half_size, radius);
-                    ctx.push_sd(sd);
+                    ctx.push_sd(sd)?;
                 }
                 Torus => {
-                    let big_r = ctx.float32();
-                    let small_r = ctx.float32();
-                    ctx.push_sd(sdf::sd_torus(current_position, big_r, small_r));
+                    let big_r = ctx.float32()?;
+                    let small_r = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_torus(current_position, big_r, small_r))?;
                 }
                 TorusSector => {
-                    let big_r = ctx.float32();
-                    let small_r = ctx.float32();
-                    let sin_cos_half_angle = (ctx.float32(), ctx.float32());
+                    let big_r = ctx.float32()?;
+                    let small_r = ctx.float32()?;
+                    let sin_cos_half_angle = (ctx.float32()?, ctx.float32()?);
                     ctx.push_sd(sdf::sd_torus_sector(
                         current_position,
                         big_r,
                         small_r,
                         sin_cos_half_angle,
-                    ));
+                    ))?;
                 }
                 BiconvexLens => {
-                    let lower_sagitta = ctx.float32();
-                    let upper_sagitta = ctx.float32();
-                    let chord = ctx.float32();
+                    let lower_sagitta = ctx.float32()?;
+                    let upper_sagitta = ctx.float32()?;
+                    let chord = ctx.float32()?;
                     let sd = sdf::sd_biconvex_lens(
                         current_position,
                         lower_sagitta,
                         upper_sagitta,
                         chord,
                     );
-                    ctx.push_sd(sd);
+                    ctx.push_sd(sd)?;
+                }
+                Vesica => {
+                    let radius = ctx.float32()?;
+                    let distance = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_vesica(current_position, radius, distance))?;
+                }
+                CutSphere => {
+                    let radius = ctx.float32()?;
+                    let height = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_cut_sphere(current_position, radius, height))?;
+                }
+                CutHollowSphere => {
+                    let radius = ctx.float32()?;
+                    let height = ctx.float32()?;
+                    let thickness = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_cut_hollow_sphere(
+                        current_position,
+                        radius,
+                        height,
+                        thickness,
+                    ))?;
+                }
+                Link => {
+                    let length = ctx.float32()?;
+                    let big_r = ctx.float32()?;
+                    let small_r = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_link(current_position, length, big_r, small_r))?;
+                }
+                SolidAngle => {
+                    let sin_cos_angle = (ctx.float32()?, ctx.float32()?);
+                    let radius = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_solid_angle(current_position, sin_cos_angle, radius))?;
+                }
+                Gyroid => {
+                    let scale = ctx.float32()?;
+                    let thickness = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_gyroid(current_position, scale, thickness))?;
+                }
+                SchwarzP => {
+                    let scale = ctx.float32()?;
+                    let thickness = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_schwarz_p(current_position, scale, thickness))?;
                 }
                 Material => {
-                    let sd = ctx.pop_sd_unchecked();
-                    let material = ctx.material();
-                    ctx.push_sd(sdf::sd_material(sd, material));
+                    let sd = ctx.pop_sd()?;
+                    let material = ctx.material()?;
+                    ctx.push_sd(sdf::sd_material(sd, material))?;
                 }
                 Union => {
-                    let sd1 = ctx.pop_sd_unchecked();
-                    let sd2 = ctx.pop_sd_unchecked();
-                    ctx.push_sd(sdf::sd_op_union(sd1, sd2));
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    ctx.push_sd(sdf::sd_op_union(sd1, sd2))?;
                 }
                 UnionSmooth => {
-                    let sd1 = ctx.pop_sd_unchecked();
-                    let sd2 = ctx.pop_sd_unchecked();
-                    let width = ctx.float32();
-                    ctx.push_sd(sdf::sd_op_union_smooth(sd1, sd2, width));
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let width = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_union_smooth(sd1, sd2, width))?;
                 }
                 Subtract => {
-                    let sd1 = ctx.pop_sd_unchecked();
-                    let sd2 = ctx.pop_sd_unchecked();
-                    ctx.push_sd(sdf::sd_op_subtract(sd1, sd2));
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    ctx.push_sd(sdf::sd_op_subtract(sd1, sd2))?;
                 }
                 SubtractSmooth => {
-                    let sd1 = ctx.pop_sd_unchecked();
-                    let sd2 = ctx.pop_sd_unchecked();
-                    let width = ctx.float32();
-                    ctx.push_sd(sdf::sd_op_subtract_smooth(sd1, sd2, width));
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let width = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_subtract_smooth(sd1, sd2, width))?;
                 }
                 Intersect => {
-                    let sd1 = ctx.pop_sd_unchecked();
-                    let sd2 = ctx.pop_sd_unchecked();
-                    ctx.push_sd(sdf::sd_op_intersect(sd1, sd2));
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    ctx.push_sd(sdf::sd_op_intersect(sd1, sd2))?;
                 }
                 IntersectSmooth => {
-                    let sd1 = ctx.pop_sd_unchecked();
-                    let sd2 = ctx.pop_sd_unchecked();
-                    let width = ctx.float32();
-                    ctx.push_sd(sdf::sd_op_intersect_smooth(sd1, sd2, width));
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let width = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_intersect_smooth(sd1, sd2, width))?;
+                }
+                UnionChamfer => {
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let size = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_union_chamfer(sd1, sd2, size))?;
+                }
+                SubtractChamfer => {
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let size = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_subtract_chamfer(sd1, sd2, size))?;
+                }
+                IntersectChamfer => {
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let size = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_intersect_chamfer(sd1, sd2, size))?;
+                }
+                UnionStairs => {
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let size = ctx.float32()?;
+                    let steps = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_union_stairs(sd1, sd2, size, steps))?;
+                }
+                Pipe => {
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let radius = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_pipe(sd1, sd2, radius))?;
+                }
+                Groove => {
+                    let sd1 = ctx.pop_sd()?;
+                    let sd2 = ctx.pop_sd()?;
+                    let depth = ctx.float32()?;
+                    let half_width = ctx.float32()?;
+                    ctx.push_sd(sdf::sd_op_groove(sd1, sd2, depth, half_width))?;
                 }
                 PushTranslation => {
-                    let translation = ctx.vec3();
-                    ctx.push_position(current_position);
+                    let translation = ctx.vec3()?;
+                    ctx.push_position(current_position)?;
                     current_position += translation;
                 }
                 PopTransform => {
-                    current_position = ctx.pop_position_unchecked();
+                    current_position = ctx.pop_position()?;
                 }
                 PushRotation => {
-                    let rotation = ctx.quat();
-                    ctx.push_position(current_position);
+                    let rotation = ctx.quat()?;
+                    ctx.push_position(current_position)?;
                     current_position = rotation * current_position;
                 }
                 PushScale => {
-                    let inv_scale = ctx.float32();
-                    ctx.push_position(current_position);
+                    let inv_scale = ctx.float32()?;
+                    ctx.push_position(current_position)?;
                     current_position *= inv_scale;
                 }
-                PopScale => {
-                    current_position = ctx.pop_position_unchecked();
-                    let scale = ctx.float32();
-                    let sd = ctx.pop_sd_unchecked();
-                    ctx.push_sd(sd.copy_with_distance(scale * sd.distance()));
+                // Both scale the distance to compensate for the transform.
+                PopScale | PopAffine => {
+                    current_position = ctx.pop_position()?;
+                    let scale = ctx.float32()?;
+                    let sd = ctx.pop_sd()?;
+                    ctx.push_sd(sd.copy_with_distance(scale * sd.distance()))?;
+                }
+                PushAffine => {
+                    let x_axis = ctx.vec3()?;
+                    let y_axis = ctx.vec3()?;
+                    let z_axis = ctx.vec3()?;
+                    let translation = ctx.vec3()?;
+                    ctx.push_position(current_position)?;
+                    current_position = x_axis * current_position.x
+                        + y_axis * current_position.y
+                        + z_axis * current_position.z
+                        + translation;
+                }
+                PushRevolve => {
+                    let offset = ctx.float32()?;
+                    ctx.push_position(current_position)?;
+                    current_position = sdf::op_revolve_position(current_position, offset);
+                }
+                PushWarp => {
+                    let strength = ctx.float32()?;
+                    let epsilon = ctx.float32()?;
+                    let mut distances = [0.0; 4];
+                    for distance in distances.iter_mut().rev() {
+                        *distance = ctx.pop_sd()?.distance();
+                    }
+                    ctx.push_position(current_position)?;
+                    current_position =
+                        sdf::op_warp_position(current_position, distances, strength, epsilon);
+                }
+                PushWarpSample => {
+                    let epsilon = ctx.float32()?;
+                    let sample = WarpSample {
+                        pc,
+                        constant_idx: ctx.constant_idx,
+                        epsilon,
+                        index: 0,
+                    };
+                    *ctx.stacks
+                        .borrow_mut()
+                        .warp_sample_stack
+                        .get_mut(ctx.warp_sample_stack_ptr)? = sample;
+                    ctx.warp_sample_stack_ptr += 1;
+                    ctx.push_position(current_position)?;
+                    current_position += epsilon * sdf::WARP_GRADIENT_OFFSETS[0];
+                }
+                PopWarpSample => {
+                    current_position = ctx.pop_position()?;
+                    let top = ctx.warp_sample_stack_ptr.checked_sub(1)?;
+                    let sample = ctx.stacks.borrow_mut().warp_sample_stack.get_mut(top)?;
+                    sample.index += 1;
+                    if let Some(offset) = sdf::WARP_GRADIENT_OFFSETS.get(sample.index) {
+                        // Run the code again at the next offset.
+                        pc = sample.pc;
+                        ctx.constant_idx = sample.constant_idx;
+                        let sampled_position = current_position + sample.epsilon * *offset;
+                        ctx.push_position(current_position)?;
+                        current_position = sampled_position;
+                    } else {
+                        ctx.warp_sample_stack_ptr = top;
+                    }
+                }
+                PushElongate => {
+                    let amount = ctx.vec3()?;
+                    ctx.push_position(current_position)?;
+                    current_position = sdf::op_elongate_position(current_position, amount);
+                }
+                PopElongate => {
+                    current_position = ctx.pop_position()?;
+                    let amount = ctx.vec3()?;
+                    let sd = ctx.pop_sd()?;
+                    ctx.push_sd(sdf::sd_op_elongate(sd, current_position, amount))?;
+                }
+                ScaleDistance => {
+                    let factor = ctx.float32()?;
+                    let sd = ctx.pop_sd()?;
+                    ctx.push_sd(sd.copy_with_distance(factor * sd.distance()))?;
                 }
                 End => {
-                    break;
+                    return Some(());
                 }
             }
 
+            // Compiled away for carriers that don't keep track of ids.
+            if SD::TRACKS_PRIMITIVES && opcode.stack_effect() == (0, 1) {
+                ctx.tag_primitive(pc - 1);
+            }
+
             // NaN check for debugging! Don't want the overhead by default, so disabled.
             // if !ctx.top_is_finite() {
             //    panic!("Hit infinity at {:?}", opcode);
@@ -1,7 +1,7 @@
 //! Signed distance field function utilities and interpreter
 
 #![cfg_attr(target_arch = "spirv", feature(repr_simd, core_intrinsics))]
-#![cfg_attr(target_arch = "spirv", no_std)]
+#![cfg_attr(any(target_arch = "spirv", not(feature = "std")), no_std)]
 
 mod opcodes;
 pub use opcodes::*;
@@ -13,9 +13,9 @@ pub enum Opcode {
     Plane = 0,          // vec4
     Sphere = 1,         // center: vec3, radius: f32
     Capsule = 2,        // p0: vec3, p1: vec3, radius: f32
-    TaperedCapsule = 3, // p0: vec3, r0: f32, p1: vec3, r0: f32
+    TaperedCapsule = 3, // p0: vec3, r0: f32, p1: vec3, r1: f32
 
-    Material = 4, // rgb: vec3
+    Material = 4, // rgba: vec4, metallic: f32, roughness: f32
 
     // Combinators:
     Union = 5,
@@ -29,8 +29,8 @@ pub enum Opcode {
     PushTranslation = 11,
     PushRotation = 12,
     PopTransform = 13,
-    PushScale = 14,
-    PopScale = 15,
+    PushScale = 14, // 1/scale, negative to mirror
+    PopScale = 15,  // abs(scale)
 
     End = 16,
 
@@ -40,4 +40,169 @@ pub enum Opcode {
     Torus = 20,           // big_r, small_r
     TorusSector = 21,     // big_r, small_r, sin_half_angle, cos_half_angle
     Cone = 22,            // radius, height
+    Gyroid = 23,          // scale, thickness
+    SchwarzP = 24,        // scale, thickness
+    SolidAngle = 25,      // sin_angle, cos_angle, radius
+    Link = 26,            // length, big_r, small_r
+    CutSphere = 27,       // radius, height
+    CutHollowSphere = 28, // radius, height, thickness
+    Vesica = 29,          // radius, distance
+
+    PushAffine = 30, // inverse: x_axis: vec3, y_axis: vec3, z_axis: vec3, translation: vec3
+    PopAffine = 31,  // distance_scale: f32
+
+    PushElongate = 32, // amount: vec3
+    PopElongate = 33,  // amount: vec3
+
+    UnionStairs = 34, // size: f32, steps: f32
+    Pipe = 35,        // radius: f32
+    Groove = 36,      // depth: f32, half_width: f32
+
+    UnionChamfer = 37,     // size: f32
+    SubtractChamfer = 38,  // size: f32
+    IntersectChamfer = 39, // size: f32
+
+    ConvexHull = 40, // num_planes: f32, then each plane: vec4
+
+    PushRevolve = 41, // offset: f32, popped by PopTransform
+    PushWarp = 42,    // strength: f32, epsilon: f32, popped by PopTransform
+
+    Tube = 43, // outer_r, inner_r, half_height
+
+    Egg = 44,   // radius, tip_radius
+    Heart = 45, // size, half_thickness
+
+    ScaleDistance = 46, // factor: f32
+
+    CappedCone = 47, // r1, r2, height
+
+    // Runs the code up to the matching `PopWarpSample` once for each of the `WARP_GRADIENT_OFFSETS`,
+    // at the position moved by epsilon times the offset, leaving the four distances for a `PushWarp`.
+    PushWarpSample = 48, // epsilon: f32
+    PopWarpSample = 49,
+}
+
+impl Opcode {
+    /// How many distances the opcode pops from the stack, and how many it then pushes.
+    ///
+    /// Summed over a well-formed program, the pushes exceed the pops by one: the result.
+    pub const fn stack_effect(self) -> (u8, u8) {
+        match self {
+            Self::Plane
+            | Self::Sphere
+            | Self::Capsule
+            | Self::TaperedCapsule
+            | Self::RoundedBox
+            | Self::BiconvexLens
+            | Self::RoundedCylinder
+            | Self::Torus
+            | Self::TorusSector
+            | Self::Cone
+            | Self::Gyroid
+            | Self::SchwarzP
+            | Self::SolidAngle
+            | Self::Link
+            | Self::CutSphere
+            | Self::CutHollowSphere
+            | Self::Vesica
+            | Self::ConvexHull
+            | Self::Tube
+            | Self::Egg
+            | Self::Heart
+            | Self::CappedCone => (0, 1),
+            Self::Material
+            | Self::PopScale
+            | Self::PopAffine
+            | Self::PopElongate
+            | Self::ScaleDistance => (1, 1),
+            Self::Union
+            | Self::UnionSmooth
+            | Self::Subtract
+            | Self::SubtractSmooth
+            | Self::Intersect
+            | Self::IntersectSmooth
+            | Self::UnionStairs
+            | Self::Pipe
+            | Self::Groove
+            | Self::UnionChamfer
+            | Self::SubtractChamfer
+            | Self::IntersectChamfer => (2, 1),
+            // The distances of the warp field around the position.
+            Self::PushWarp => (4, 0),
+            // The code since `PushWarpSample` runs three more times, each pushing a distance.
+            Self::PopWarpSample => (0, 3),
+            Self::PushTranslation
+            | Self::PushRotation
+            | Self::PopTransform
+            | Self::PushScale
+            | Self::PushAffine
+            | Self::PushElongate
+            | Self::PushRevolve
+            | Self::PushWarpSample
+            | Self::End => (0, 0),
+        }
+    }
+
+    /// Whether the opcode is a primitive shape: it only pushes its distance.
+    pub const fn is_primitive(self) -> bool {
+        matches!(self.stack_effect(), (0, 1))
+    }
+
+    /// How many constants the opcode reads.
+    ///
+    /// [`Self::ConvexHull`] reads four more for each plane, as given by its first constant.
+    pub const fn constant_count(self) -> usize {
+        match self {
+            Self::Union
+            | Self::Subtract
+            | Self::Intersect
+            | Self::PopTransform
+            | Self::PopWarpSample
+            | Self::End => 0,
+            Self::UnionSmooth
+            | Self::SubtractSmooth
+            | Self::IntersectSmooth
+            | Self::PushScale
+            | Self::PopScale
+            | Self::PopAffine
+            | Self::Pipe
+            | Self::UnionChamfer
+            | Self::SubtractChamfer
+            | Self::IntersectChamfer
+            | Self::ConvexHull
+            | Self::PushRevolve
+            | Self::PushWarpSample
+            | Self::ScaleDistance => 1,
+            Self::Torus
+            | Self::Cone
+            | Self::Gyroid
+            | Self::SchwarzP
+            | Self::CutSphere
+            | Self::Vesica
+            | Self::UnionStairs
+            | Self::Groove
+            | Self::PushWarp
+            | Self::Egg
+            | Self::Heart => 2,
+            Self::BiconvexLens
+            | Self::RoundedCylinder
+            | Self::SolidAngle
+            | Self::Link
+            | Self::CutHollowSphere
+            | Self::PushTranslation
+            | Self::PushElongate
+            | Self::PopElongate
+            | Self::Tube
+            | Self::CappedCone => 3,
+            Self::Plane
+            | Self::Sphere
+            | Self::RoundedBox
+            | Self::TorusSector
+            | Self::PushRotation => 4,
+            Self::Material => 6,
+            Self::Capsule => 7,
+            Self::TaperedCapsule => 8,
+            Self::PushAffine => 12,
+        }
+    }
 }
@@ -6,6 +6,13 @@ float sd_plane(vec3 pos, vec4 plane) { return dot(pos, plane.xyz) + plane.w; }
 
 vec4 sdrgb_plane(vec3 pos, vec4 plane) { return vec4(vec3(1.0), sd_plane(pos, plane)); }
 
+// A convex hull is the max of `sd_plane` over its planes, starting from `sd_convex_hull_empty()`.
+float sd_convex_hull_empty() { return uintBitsToFloat(0xff800000u); } // -infinity
+
+float sd_convex_hull(float distance_) { return distance_; }
+
+vec4 sdrgb_convex_hull(float distance_) { return vec4(vec3(1.0), distance_); }
+
 float sd_sphere(vec3 pos, vec3 center, float radius) { return distance(pos, center) - radius; }
 
 vec4 sdrgb_sphere(vec3 pos, vec3 center, float radius) {
@@ -42,10 +49,84 @@ vec4 sdrgb_torus_sector(vec3 pos, float big_r, float small_r, vec2 sin_cos_half_
     return vec4(vec3(1.0), sd_torus_sector(pos, big_r, small_r, sin_cos_half_angle));
 }
 
+float sd_vesica(vec3 pos, float radius, float distance_) {
+    // The 2D vesica from https://iquilezles.org/articles/distfunctions2d/, revolved around Y.
+    vec2 q = vec2(length(pos.xz), abs(pos.y));
+    float b = sqrt(max(radius * radius - distance_ * distance_, 0.0));
+    return ((q.y - b) * distance_ > q.x * b) ? length(q - vec2(0.0, b))
+                                             : length(q - vec2(-distance_, 0.0)) - radius;
+}
+
+vec4 sdrgb_vesica(vec3 pos, float radius, float distance_) {
+    return vec4(vec3(1.0), sd_vesica(pos, radius, distance_));
+}
+
+float sd_cut_sphere(vec3 pos, float radius, float height) {
+    // https://iquilezles.org/articles/distfunctions/
+    float w = sqrt(max(radius * radius - height * height, 0.0));
+    vec2 q = vec2(length(pos.xz), pos.y);
+    float s = max((height - radius) * q.x * q.x + w * w * (height + radius - 2.0 * q.y),
+                  height * q.x - w * q.y);
+    return (s < 0.0) ? length(q) - radius : (q.x < w) ? height - q.y : length(q - vec2(w, height));
+}
+
+vec4 sdrgb_cut_sphere(vec3 pos, float radius, float height) {
+    return vec4(vec3(1.0), sd_cut_sphere(pos, radius, height));
+}
+
+float sd_cut_hollow_sphere(vec3 pos, float radius, float height, float thickness) {
+    // https://iquilezles.org/articles/distfunctions/
+    float w = sqrt(max(radius * radius - height * height, 0.0));
+    vec2 q = vec2(length(pos.xz), pos.y);
+    return ((height * q.x < w * q.y) ? length(q - vec2(w, height)) : abs(length(q) - radius)) -
+           thickness;
+}
+
+vec4 sdrgb_cut_hollow_sphere(vec3 pos, float radius, float height, float thickness) {
+    return vec4(vec3(1.0), sd_cut_hollow_sphere(pos, radius, height, thickness));
+}
+
+float sd_link(vec3 pos, float length_, float big_r, float small_r) {
+    vec2 q = vec2(pos.x, max(abs(pos.y) - length_, 0.0));
+    return length(vec2(length(q) - big_r, pos.z)) - small_r;
+}
+
+vec4 sdrgb_link(vec3 pos, float length_, float big_r, float small_r) {
+    return vec4(vec3(1.0), sd_link(pos, length_, big_r, small_r));
+}
+
+float sd_solid_angle(vec3 pos, vec2 sin_cos_angle, float radius) {
+    vec2 q = vec2(length(pos.xz), pos.y);
+    float l = length(q) - radius;
+    float m = length(q - sin_cos_angle * clamp(dot(q, sin_cos_angle), 0.0, radius));
+    return max(l, m * sign(sin_cos_angle.y * q.x - sin_cos_angle.x * q.y));
+}
+
+vec4 sdrgb_solid_angle(vec3 pos, vec2 sin_cos_angle, float radius) {
+    return vec4(vec3(1.0), sd_solid_angle(pos, sin_cos_angle, radius));
+}
+
+float sd_gyroid(vec3 pos, float scale, float thickness) {
+    return abs(dot(sin(pos), cos(pos.yzx))) * scale - thickness;
+}
+
+vec4 sdrgb_gyroid(vec3 pos, float scale, float thickness) {
+    return vec4(vec3(1.0), sd_gyroid(pos, scale, thickness));
+}
+
+float sd_schwarz_p(vec3 pos, float scale, float thickness) {
+    return abs(dot(cos(pos), vec3(1.0))) * scale - thickness;
+}
+
+vec4 sdrgb_schwarz_p(vec3 pos, float scale, float thickness) {
+    return vec4(vec3(1.0), sd_schwarz_p(pos, scale, thickness));
+}
+
 float sd_capsule(vec3 pos, vec3 p0, vec3 p1, float radius) {
     vec3 pa = pos - p0;
     vec3 ba = p1 - p0;
-    float h = clamp(dot(pa, ba) / dot(ba, ba), 0.0, 1.0);
+    float length_squared = dot(ba, ba);
+    float h = length_squared > 0.0 ? clamp(dot(pa, ba) / length_squared, 0.0, 1.0) : 0.0;
     return distance(pa, ba * h) - radius;
 }
 
@@ -69,6 +150,49 @@ vec4 sdrgb_rounded_cylinder(vec3 pos,
     return vec4(vec3(1.0), sd_rounded_cylinder(pos, cylinder_radius, half_height, rounding_radius));
 }
 
+float sd_tube(vec3 pos, float outer_r, float inner_r, float half_height) {
+    vec2 d = vec2(abs(length(pos.xz) - 0.5 * (outer_r + inner_r)) - 0.5 * (outer_r - inner_r),
+                  abs(pos.y) - half_height);
+    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+vec4 sdrgb_tube(vec3 pos, float outer_r, float inner_r, float half_height) {
+    return vec4(vec3(1.0), sd_tube(pos, outer_r, inner_r, half_height));
+}
+
+float sd_egg(vec3 pos, float radius, float tip_radius) {
+    // https://iquilezles.org/articles/distfunctions2d/, revolved around Y.
+    const float k = sqrt(3.0);
+    vec2 p = vec2(length(pos.xz), pos.y);
+    float r = radius - tip_radius;
+    return ((p.y < 0.0)             ? length(p) - r :
+            (k * (p.x + r) < p.y)   ? length(vec2(p.x, p.y - k * r)) :
+                                      length(vec2(p.x + r, p.y)) - 2.0 * r) - tip_radius;
+}
+
+vec4 sdrgb_egg(vec3 pos, float radius, float tip_radius) {
+    return vec4(vec3(1.0), sd_egg(pos, radius, tip_radius));
+}
+
+float sd_heart(vec3 pos, float size, float half_thickness) {
+    // https://iquilezles.org/articles/distfunctions2d/, extruded along Z.
+    vec2 p = vec2(abs(pos.x), pos.y) / size;
+    float heart;
+    if (p.x + p.y > 1.0) {
+        heart = length(p - vec2(0.25, 0.75)) - sqrt(2.0) / 4.0;
+    } else {
+        vec2 to_top = p - vec2(0.0, 1.0);
+        vec2 to_point = p - 0.5 * max(p.x + p.y, 0.0);
+        heart = sqrt(min(dot(to_top, to_top), dot(to_point, to_point))) * sign(p.x - p.y);
+    }
+    vec2 d = vec2(heart * size, abs(pos.z) - half_thickness);
+    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+vec4 sdrgb_heart(vec3 pos, float size, float half_thickness) {
+    return vec4(vec3(1.0), sd_heart(pos, size, half_thickness));
+}
+
 float sd_tapered_capsule(vec3 pos, vec3 p0, vec3 p1, float r0, float r1) {
     // Straight from https://www.iquilezles.org/www/articles/distfunctions/distfunctions.htm
 
@@ -115,9 +239,35 @@ float sd_cone(vec3 p, float r, float h) {
 
 vec4 sdrgb_cone(vec3 pos, float r, float h) { return vec4(vec3(1.0), sd_cone(pos, r, h)); }
 
-float sd_material(float sd, vec3 rgb) { return sd; }
+/// Base of radius r1 at origin, with a top of radius r2 at height along positive Y.
+float sd_capped_cone(vec3 pos, float r1, float r2, float height) {
+    // https://iquilezles.org/articles/distfunctions/, moved up to stand on the origin.
+    float h = 0.5 * height;
+    vec2 q = vec2(length(pos.xz), pos.y - h);
+    vec2 k1 = vec2(r2, h);
+    vec2 k2 = vec2(r2 - r1, height);
+    vec2 ca = vec2(q.x - min(q.x, (q.y < 0.0) ? r1 : r2), abs(q.y) - h);
+    vec2 cb = q - k1 + k2 * clamp(dot(k1 - q, k2) / dot(k2, k2), 0.0, 1.0);
+    float s = (cb.x < 0.0 && ca.y < 0.0) ? -1.0 : 1.0;
+    return s * sqrt(min(dot(ca, ca), dot(cb, cb)));
+}
+
+vec4 sdrgb_capped_cone(vec3 pos, float r1, float r2, float height) {
+    return vec4(vec3(1.0), sd_capped_cone(pos, r1, r2, height));
+}
+
+struct SdfMaterial {
+    vec4 rgba;
+    float metallic;
+    float roughness;
+};
+
+SdfMaterial sdf_default_material() { return SdfMaterial(vec4(1.0), 0.0, 0.5); }
+
+float sd_material(float sd, SdfMaterial material) { return sd; }
 
-vec4 sdrgb_material(vec4 sd, vec3 rgb) { return vec4(rgb, sd.w); }
+// There is no room for the rest of the material next to rgb and distance, so it is dropped.
+vec4 sdrgb_material(vec4 sd, SdfMaterial material) { return vec4(material.rgba.rgb, sd.w); }
 
 float sd_op_union(float sd1, float sd2) { return min(sd1, sd2); }
 
@@ -167,10 +317,8 @@ float sd_op_subtract_smooth(float d1, float d2, float size) {
 }
 
 vec4 sdrgb_op_subtract_smooth(vec4 d1, vec4 d2, float size) {
-    float h = clamp(0.5 - 0.5 * (d2.w + d1.w) / size, 0.0, 1.0);
-    vec4 mixed = mix(d2, vec4(d1.rgb, -d1.w), h);
-    float dist = mixed.w + size * h * (1.0 - h);
-    return vec4(mixed.rgb, dist);
+    // Keeps the color of d2, so the subtracted shape doesn't tint the carved surface.
+    return vec4(d2.rgb, sd_op_subtract_smooth(d1.w, d2.w, size));
 }
 
 float sd_op_intersect_smooth(float d1, float d2, float size) {
@@ -201,8 +349,178 @@ vec4 sdrgb_biconvex_lens(vec3 pos, float lower_sagitta, float upper_sagitta, flo
     return vec4(vec3(1.0), sd_biconvex_lens(pos, lower_sagitta, upper_sagitta, chord));
 }
 
+float sd_op_union_chamfer(float d1, float d2, float size) {
+    return min(min(d1, d2), (d1 + d2 - size) * sqrt(0.5));
+}
+
+vec4 sdrgb_op_union_chamfer(vec4 d1, vec4 d2, float size) {
+    vec4 sd = sdrgb_op_union(d1, d2);
+    return vec4(sd.rgb, sd_op_union_chamfer(d1.w, d2.w, size));
+}
+
+float sd_op_subtract_chamfer(float d1, float d2, float size) {
+    return max(max(-d1, d2), (d2 - d1 + size) * sqrt(0.5));
+}
+
+vec4 sdrgb_op_subtract_chamfer(vec4 d1, vec4 d2, float size) {
+    vec4 sd = sdrgb_op_subtract(d1, d2);
+    return vec4(sd.rgb, sd_op_subtract_chamfer(d1.w, d2.w, size));
+}
+
+float sd_op_intersect_chamfer(float d1, float d2, float size) {
+    return max(max(d1, d2), (d1 + d2 + size) * sqrt(0.5));
+}
+
+vec4 sdrgb_op_intersect_chamfer(vec4 d1, vec4 d2, float size) {
+    vec4 sd = sdrgb_op_intersect(d1, d2);
+    return vec4(sd.rgb, sd_op_intersect_chamfer(d1.w, d2.w, size));
+}
+
+float sd_op_union_stairs(float d1, float d2, float size, float steps) {
+    float step_size = size / steps;
+    float u = d2 - size;
+    return min(min(d1, d2), 0.5 * (u + d1 + abs(mod(u - d1 + step_size, 2.0 * step_size) - step_size)));
+}
+
+vec4 sdrgb_op_union_stairs(vec4 d1, vec4 d2, float size, float steps) {
+    vec3 rgb = d1.w < d2.w ? d1.rgb : d2.rgb;
+    return vec4(rgb, sd_op_union_stairs(d1.w, d2.w, size, steps));
+}
+
+float sd_op_pipe(float d1, float d2, float radius) { return length(vec2(d1, d2)) - radius; }
+
+vec4 sdrgb_op_pipe(vec4 d1, vec4 d2, float radius) {
+    vec3 rgb = abs(d1.w) < abs(d2.w) ? d1.rgb : d2.rgb;
+    return vec4(rgb, sd_op_pipe(d1.w, d2.w, radius));
+}
+
+float sd_op_groove(float d1, float d2, float depth, float half_width) {
+    return max(d2, min(d2 + depth, half_width - abs(d1)));
+}
+
+vec4 sdrgb_op_groove(vec4 d1, vec4 d2, float depth, float half_width) {
+    float groove = min(d2.w + depth, half_width - abs(d1.w));
+    if (d2.w > groove) {
+        return d2;
+    } else {
+        return vec4(d1.rgb, groove);
+    }
+}
+
 vec3 mul_quat(vec4 q, vec3 v) { return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v); }
 
 float sd_op_scale_distance(float sd, float scale) { return sd * scale; }
 
 vec4 sdrgb_op_scale_distance(vec4 sd, float scale) { return vec4(sd.rgb, sd.w * scale); }
+
+vec3 op_revolve_position(vec3 pos, float offset) { return vec3(length(pos.xz) - offset, pos.y, 0.0); }
+
+// The `WARP_GRADIENT_OFFSETS`, the corners of a tetrahedron.
+vec3 warp_gradient_offset(uint i) {
+    return vec3((i == 0u || i == 3u) ? 1.0 : -1.0, i >= 2u ? 1.0 : -1.0, (i & 1u) == 1u ? 1.0 : -1.0);
+}
+
+// `d` is the warp field sampled at `pos + epsilon * warp_gradient_offset(i)` for each of the four offsets.
+vec3 op_warp_position(vec3 pos, vec4 d, float strength, float epsilon) {
+    vec3 gradient = vec3(1.0, -1.0, -1.0) * d.x + vec3(-1.0, -1.0, 1.0) * d.y + vec3(-1.0, 1.0, -1.0) * d.z + d.w;
+    return pos + strength / (4.0 * epsilon) * gradient;
+}
+
+vec3 sd_op_warp_position(vec3 pos, float d0, float d1, float d2, float d3, float strength, float epsilon) {
+    return op_warp_position(pos, vec4(d0, d1, d2, d3), strength, epsilon);
+}
+
+vec3 sdrgb_op_warp_position(vec3 pos, vec4 d0, vec4 d1, vec4 d2, vec4 d3, float strength, float epsilon) {
+    return op_warp_position(pos, vec4(d0.w, d1.w, d2.w, d3.w), strength, epsilon);
+}
+
+vec3 op_elongate_position(vec3 pos, vec3 amount) { return pos - clamp(pos, -amount, amount); }
+
+float sd_op_elongate_distance(float sd, vec3 pos, vec3 amount) {
+    vec3 q = abs(pos) - amount;
+    return sd + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+vec4 sdrgb_op_elongate_distance(vec4 sd, vec3 pos, vec3 amount) {
+    return vec4(sd.rgb, sd_op_elongate_distance(sd.w, pos, amount));
+}
+
+// Distance together with the id of the winning primitive (`OutputType::DistanceWithId`).
+// The id is stored as a float in `y`, which is exact for ids below 2^24.
+
+vec2 sdid_primitive(float sd, uint id) { return vec2(sd, float(id)); }
+
+vec2 sdid_material(vec2 sd, SdfMaterial material) { return sd; }
+
+vec2 sdid_op_union(vec2 sd1, vec2 sd2) {
+    if (sd1.x < sd2.x) {
+        return sd1;
+    } else {
+        return sd2;
+    }
+}
+
+vec2 sdid_op_subtract(vec2 sd1, vec2 sd2) {
+    if (-sd1.x > sd2.x) {
+        return vec2(-sd1.x, sd1.y);
+    } else {
+        return sd2;
+    }
+}
+
+vec2 sdid_op_intersect(vec2 sd1, vec2 sd2) {
+    if (sd1.x > sd2.x) {
+        return sd1;
+    } else {
+        return sd2;
+    }
+}
+
+vec2 sdid_op_union_smooth(vec2 d1, vec2 d2, float size) {
+    float h = clamp(0.5 + 0.5 * (d2.x - d1.x) / size, 0.0, 1.0);
+    return vec2(sd_op_union_smooth(d1.x, d2.x, size), h > 0.5 ? d1.y : d2.y);
+}
+
+vec2 sdid_op_subtract_smooth(vec2 d1, vec2 d2, float size) {
+    return vec2(sd_op_subtract_smooth(d1.x, d2.x, size), d2.y);
+}
+
+vec2 sdid_op_intersect_smooth(vec2 d1, vec2 d2, float size) {
+    float h = clamp(0.5 - 0.5 * (d2.x - d1.x) / size, 0.0, 1.0);
+    return vec2(sd_op_intersect_smooth(d1.x, d2.x, size), h > 0.5 ? d1.y : d2.y);
+}
+
+vec2 sdid_op_scale_distance(vec2 sd, float scale) { return vec2(sd.x * scale, sd.y); }
+
+vec2 sdid_op_union_chamfer(vec2 d1, vec2 d2, float size) {
+    return vec2(sd_op_union_chamfer(d1.x, d2.x, size), sdid_op_union(d1, d2).y);
+}
+
+vec2 sdid_op_subtract_chamfer(vec2 d1, vec2 d2, float size) {
+    return vec2(sd_op_subtract_chamfer(d1.x, d2.x, size), sdid_op_subtract(d1, d2).y);
+}
+
+vec2 sdid_op_intersect_chamfer(vec2 d1, vec2 d2, float size) {
+    return vec2(sd_op_intersect_chamfer(d1.x, d2.x, size), sdid_op_intersect(d1, d2).y);
+}
+
+vec2 sdid_op_union_stairs(vec2 d1, vec2 d2, float size, float steps) {
+    return vec2(sd_op_union_stairs(d1.x, d2.x, size, steps), d1.x < d2.x ? d1.y : d2.y);
+}
+
+vec2 sdid_op_pipe(vec2 d1, vec2 d2, float radius) {
+    return vec2(sd_op_pipe(d1.x, d2.x, radius), abs(d1.x) < abs(d2.x) ? d1.y : d2.y);
+}
+
+vec2 sdid_op_groove(vec2 d1, vec2 d2, float depth, float half_width) {
+    float groove = min(d2.x + depth, half_width - abs(d1.x));
+    return d2.x > groove ? d2 : vec2(groove, d1.y);
+}
+
+vec2 sdid_op_elongate_distance(vec2 sd, vec3 pos, vec3 amount) {
+    return vec2(sd_op_elongate_distance(sd.x, pos, amount), sd.y);
+}
+
+vec3 sdid_op_warp_position(vec3 pos, vec2 d0, vec2 d1, vec2 d2, vec2 d3, float strength, float epsilon) {
+    return op_warp_position(pos, vec4(d0.x, d1.x, d2.x, d3.x), strength, epsilon);
+}
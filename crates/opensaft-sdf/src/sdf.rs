@@ -3,7 +3,7 @@
 use super::Material;
 use super::SignedDistance;
 use glam::*;
-#[cfg(target_arch = "spirv")]
+#[cfg(any(target_arch = "spirv", not(feature = "std")))]
 use num_traits::Float;
 
 #[inline]
@@ -25,6 +25,15 @@ pub fn sd_plane<T: SignedDistance>(pos: Vec3, plane: Vec4) -> T {
     T::new_with_distance(Material::default(), pos.dot(plane.truncate()) + plane.w)
 }
 
+/// The intersection of the half-spaces behind `planes`, see [`sd_plane`].
+#[inline]
+pub fn sd_convex_hull<T: SignedDistance>(pos: Vec3, planes: impl Iterator<Item = Vec4>) -> T {
+    let distance = planes.fold(f32::NEG_INFINITY, |distance, plane| {
+        distance.max(pos.dot(plane.truncate()) + plane.w)
+    });
+    T::new_with_distance(Material::default(), distance)
+}
+
 #[inline]
 pub fn sd_sphere<T: SignedDistance>(pos: Vec3, center: Vec3, radius: f32) -> T {
     T::new_with_distance(Material::default(), (pos - center).length() - radius)
@@ -82,11 +91,98 @@ pub fn sd_biconvex_lens<T: SignedDistance>(
     )
 }
 
+#[inline]
+pub fn sd_vesica<T: SignedDistance>(pos: Vec3, radius: f32, distance: f32) -> T {
+    // The 2D vesica from https://iquilezles.org/articles/distfunctions2d/, revolved around Y.
+    let q = Vec2::new(hypot(pos.xz()), pos.y.abs());
+    let b = (radius * radius - distance * distance).max(0.0).sqrt();
+    let dist = if (q.y - b) * distance > q.x * b {
+        (q - Vec2::new(0.0, b)).length()
+    } else {
+        (q - Vec2::new(-distance, 0.0)).length() - radius
+    };
+    T::new_with_distance(Material::default(), dist)
+}
+
+#[inline]
+pub fn sd_cut_sphere<T: SignedDistance>(pos: Vec3, radius: f32, height: f32) -> T {
+    // https://iquilezles.org/articles/distfunctions/
+    let w = (radius * radius - height * height).max(0.0).sqrt();
+    let q = Vec2::new(hypot(pos.xz()), pos.y);
+    let s = ((height - radius) * q.x * q.x + w * w * (height + radius - 2.0 * q.y))
+        .max(height * q.x - w * q.y);
+    let dist = if s < 0.0 {
+        q.length() - radius
+    } else if q.x < w {
+        height - q.y
+    } else {
+        (q - Vec2::new(w, height)).length()
+    };
+    T::new_with_distance(Material::default(), dist)
+}
+
+#[inline]
+pub fn sd_cut_hollow_sphere<T: SignedDistance>(
+    pos: Vec3,
+    radius: f32,
+    height: f32,
+    thickness: f32,
+) -> T {
+    // https://iquilezles.org/articles/distfunctions/
+    let w = (radius * radius - height * height).max(0.0).sqrt();
+    let q = Vec2::new(hypot(pos.xz()), pos.y);
+    let dist = if height * q.x < w * q.y {
+        (q - Vec2::new(w, height)).length()
+    } else {
+        (q.length() - radius).abs()
+    } - thickness;
+    T::new_with_distance(Material::default(), dist)
+}
+
+#[inline]
+pub fn sd_link<T: SignedDistance>(pos: Vec3, length: f32, big_r: f32, small_r: f32) -> T {
+    let q = Vec2::new(pos.x, (pos.y.abs() - length).max(0.0));
+    let dist = Vec2::new(q.length() - big_r, pos.z).length() - small_r;
+    T::new_with_distance(Material::default(), dist)
+}
+
+#[inline]
+pub fn sd_solid_angle<T: SignedDistance>(pos: Vec3, sin_cos_angle: (f32, f32), radius: f32) -> T {
+    let c = Vec2::new(sin_cos_angle.0, sin_cos_angle.1);
+    let q = Vec2::new(hypot(pos.xz()), pos.y);
+    let l = q.length() - radius;
+    let m = (q - c * q.dot(c).max(0.0).min(radius)).length();
+    let dist = l.max(m * (c.y * q.x - c.x * q.y).signum());
+    T::new_with_distance(Material::default(), dist)
+}
+
+#[inline]
+pub fn sd_gyroid<T: SignedDistance>(pos: Vec3, scale: f32, thickness: f32) -> T {
+    let (sin, cos) = (
+        Vec3::new(pos.x.sin(), pos.y.sin(), pos.z.sin()),
+        Vec3::new(pos.x.cos(), pos.y.cos(), pos.z.cos()),
+    );
+    let g = sin.dot(cos.yzx());
+    T::new_with_distance(Material::default(), g.abs() * scale - thickness)
+}
+
+#[inline]
+pub fn sd_schwarz_p<T: SignedDistance>(pos: Vec3, scale: f32, thickness: f32) -> T {
+    let p = pos.x.cos() + pos.y.cos() + pos.z.cos();
+    T::new_with_distance(Material::default(), p.abs() * scale - thickness)
+}
+
 #[inline]
 pub fn sd_capsule<T: SignedDistance>(pos: Vec3, points: &[Vec3; 2], radius: f32) -> T {
     let pa = pos - points[0];
     let ba = points[1] - points[0];
-    let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+    // Both points being the same would divide by zero, but then it's a sphere.
+    let length_squared = ba.dot(ba);
+    let h = if length_squared > 0.0 {
+        (pa.dot(ba) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
     let distance = (pa - ba * h).length() - radius;
     T::new_with_distance(Material::default(), distance)
 }
@@ -118,6 +214,56 @@ pub fn sd_rounded_cylinder<T: SignedDistance>(
     )
 }
 
+/// A cylinder with a cylindrical hole along its axis (Y), e.g. a pipe.
+#[inline]
+pub fn sd_tube<T: SignedDistance>(pos: Vec3, outer_r: f32, inner_r: f32, half_height: f32) -> T {
+    // An annulus in the plane of the radius and the height.
+    let mid_r = 0.5 * (outer_r + inner_r);
+    let half_thickness = 0.5 * (outer_r - inner_r);
+    let d = Vec2::new(
+        (hypot(pos.xz()) - mid_r).abs() - half_thickness,
+        pos.y.abs() - half_height,
+    );
+    let distance = d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length();
+    T::new_with_distance(Material::default(), distance)
+}
+
+/// The 2D egg from <https://iquilezles.org/articles/distfunctions2d/>, revolved around Y.
+///
+/// The bottom is a hemisphere of `radius` centered at the origin, narrowing upwards to a tip rounded by `tip_radius`.
+#[inline]
+pub fn sd_egg<T: SignedDistance>(pos: Vec3, radius: f32, tip_radius: f32) -> T {
+    const K: f32 = 1.732_050_8; // sqrt(3)
+    let p = Vec2::new(hypot(pos.xz()), pos.y);
+    let r = radius - tip_radius;
+    let dist = if p.y < 0.0 {
+        p.length() - r
+    } else if K * (p.x + r) < p.y {
+        Vec2::new(p.x, p.y - K * r).length()
+    } else {
+        Vec2::new(p.x + r, p.y).length() - 2.0 * r
+    } - tip_radius;
+    T::new_with_distance(Material::default(), dist)
+}
+
+/// The 2D heart from <https://iquilezles.org/articles/distfunctions2d/> in the XY plane, extruded along Z.
+///
+/// The point is at the origin and the lobes are up along Y, `1.1 * size` high and `1.2 * size` wide.
+#[inline]
+pub fn sd_heart<T: SignedDistance>(pos: Vec3, size: f32, half_thickness: f32) -> T {
+    let p = Vec2::new(pos.x.abs(), pos.y) / size;
+    let heart = if p.x + p.y > 1.0 {
+        (p - Vec2::new(0.25, 0.75)).length() - core::f32::consts::SQRT_2 / 4.0
+    } else {
+        let to_top = (p - Vec2::new(0.0, 1.0)).length_squared();
+        let to_point = (p - 0.5 * (p.x + p.y).max(0.0)).length_squared();
+        to_top.min(to_point).sqrt() * (p.x - p.y).signum()
+    } * size;
+    let d = Vec2::new(heart, pos.z.abs() - half_thickness);
+    let dist = d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length();
+    T::new_with_distance(Material::default(), dist)
+}
+
 #[allow(clippy::many_single_char_names)]
 #[inline]
 pub fn sd_tapered_capsule_f(pos: Vec3, p: &[Vec3; 2], r: [f32; 2]) -> f32 {
@@ -172,9 +318,37 @@ pub fn sd_cone<T: SignedDistance>(pos: Vec3, r: f32, h: f32) -> T {
     T::new_with_distance(Material::default(), sd_cone_f(pos, r, h))
 }
 
+/// The capped cone from <https://iquilezles.org/articles/distfunctions/>, moved up to stand on the origin.
+///
+/// The base has radius `r1` and the top, `height` up the Y axis, has radius `r2`.
+#[allow(clippy::many_single_char_names)]
+#[inline]
+pub fn sd_capped_cone_f(p: Vec3, r1: f32, r2: f32, height: f32) -> f32 {
+    let h = 0.5 * height;
+    let q = vec2(hypot(p.xz()), p.y - h);
+    let k1 = vec2(r2, h);
+    let k2 = vec2(r2 - r1, height);
+    let ca = vec2(
+        q.x - q.x.min(if q.y < 0.0 { r1 } else { r2 }),
+        q.y.abs() - h,
+    );
+    let cb = q - k1 + k2 * ((k1 - q).dot(k2) / k2.dot(k2)).clamp(0.0, 1.0);
+    let s = if cb.x < 0.0 && ca.y < 0.0 { -1.0 } else { 1.0 };
+    s * ca.dot(ca).min(cb.dot(cb)).sqrt()
+}
+
+#[inline]
+pub fn sd_capped_cone<T: SignedDistance>(pos: Vec3, r1: f32, r2: f32, height: f32) -> T {
+    T::new_with_distance(Material::default(), sd_capped_cone_f(pos, r1, r2, height))
+}
+
 #[inline]
 pub fn sd_material<T: SignedDistance>(sd: T, material: Material) -> T {
-    T::new_with_distance(material, sd.distance())
+    let with_material = T::new_with_distance(material, sd.distance());
+    match sd.primitive_id() {
+        Some(id) => with_material.with_primitive_id(id),
+        None => with_material,
+    }
 }
 
 #[inline]
@@ -218,18 +392,20 @@ pub fn sd_op_union_smooth<T: SignedDistance>(d1: T, d2: T, size: f32) -> T {
     new_d.copy_with_distance(distance)
 }
 
+/// `d1` smoothly subtracted from `d2`.
+///
+/// Keeps the material of `d2` everywhere, so the subtracted shape doesn't tint the carved surface,
+/// nor blend into the remaining surface around it.
 #[inline]
 pub fn sd_op_subtract_smooth<T: SignedDistance>(d1: T, d2: T, size: f32) -> T {
     let h = 0.5 - 0.5 * (d2.distance() + d1.distance()) / size;
     let h = h.clamp(0.0, 1.0);
 
-    let d1 = d1.copy_with_distance(-d1.distance());
+    let mixed = d2.distance() + (-d1.distance() - d2.distance()) * h;
 
-    let new_d = d2.lerp(&d1, h);
-
-    let distance = (size * h) * (1.0 - h) + new_d.distance();
+    let distance = (size * h) * (1.0 - h) + mixed;
 
-    new_d.copy_with_distance(distance)
+    d2.copy_with_distance(distance)
 }
 
 #[inline]
@@ -243,3 +419,111 @@ pub fn sd_op_intersect_smooth<T: SignedDistance>(d1: T, d2: T, size: f32) -> T {
 
     new_d.copy_with_distance(distance)
 }
+
+/// The position to sample the child of an elongation at.
+#[inline]
+pub fn op_elongate_position(pos: Vec3, amount: Vec3) -> Vec3 {
+    // Not `clamp`, which panics on a negative amount.
+    pos - pos.max(-amount).min(amount)
+}
+
+/// The position to sample the child of a revolution around the Y axis at.
+///
+/// The child is treated as a 2D profile in the XY plane (at z=0), with x being the distance from
+/// the axis minus `offset`. The distance needs no correction: it's exact if the surface of the child
+/// closest to the position lies in that plane, and a lower bound otherwise.
+#[inline]
+pub fn op_revolve_position(pos: Vec3, offset: f32) -> Vec3 {
+    Vec3::new(hypot(pos.xz()) - offset, pos.y, 0.0)
+}
+
+/// Where the warp field is sampled around a position, relative to the epsilon,
+/// to estimate its gradient for [`op_warp_position`]. The corners of a tetrahedron.
+pub const WARP_GRADIENT_OFFSETS: [Vec3; 4] = [
+    Vec3::new(1.0, -1.0, -1.0),
+    Vec3::new(-1.0, -1.0, 1.0),
+    Vec3::new(-1.0, 1.0, -1.0),
+    Vec3::new(1.0, 1.0, 1.0),
+];
+
+/// The position to sample the child of a warp at: `pos` moved along the gradient of the warp field by `strength`.
+///
+/// `distances` are the warp field sampled at `pos + epsilon * WARP_GRADIENT_OFFSETS[i]`.
+#[inline]
+pub fn op_warp_position(pos: Vec3, distances: [f32; 4], strength: f32, epsilon: f32) -> Vec3 {
+    let mut gradient = Vec3::ZERO;
+    for (offset, distance) in WARP_GRADIENT_OFFSETS.iter().zip(distances) {
+        gradient += *offset * distance;
+    }
+    pos + strength / (4.0 * epsilon) * gradient
+}
+
+/// Corrects the distance of an elongated child sampled at [`op_elongate_position`],
+/// so that it stays exact inside the elongated region.
+#[inline]
+pub fn sd_op_elongate<T: SignedDistance>(sd: T, pos: Vec3, amount: Vec3) -> T {
+    let q = pos.abs() - amount;
+    let distance = sd.distance() + q.max_element().min(0.0);
+    sd.copy_with_distance(distance)
+}
+
+/// A union of `d1` and `d2` with a 45° bevel of `size` where they meet.
+#[inline]
+pub fn sd_op_union_chamfer<T: SignedDistance>(d1: T, d2: T, size: f32) -> T {
+    let chamfer = (d1.distance() + d2.distance() - size) * core::f32::consts::FRAC_1_SQRT_2;
+    let union = sd_op_union(d1, d2);
+    let distance = union.distance().min(chamfer);
+    union.copy_with_distance(distance)
+}
+
+/// `d1` subtracted from `d2` with a 45° bevel of `size` where they meet.
+#[inline]
+pub fn sd_op_subtract_chamfer<T: SignedDistance>(d1: T, d2: T, size: f32) -> T {
+    let chamfer = (d2.distance() - d1.distance() + size) * core::f32::consts::FRAC_1_SQRT_2;
+    let subtract = sd_op_subtract(d1, d2);
+    let distance = subtract.distance().max(chamfer);
+    subtract.copy_with_distance(distance)
+}
+
+/// The intersection of `d1` and `d2` with a 45° bevel of `size` where they meet.
+#[inline]
+pub fn sd_op_intersect_chamfer<T: SignedDistance>(d1: T, d2: T, size: f32) -> T {
+    let chamfer = (d1.distance() + d2.distance() + size) * core::f32::consts::FRAC_1_SQRT_2;
+    let intersect = sd_op_intersect(d1, d2);
+    let distance = intersect.distance().max(chamfer);
+    intersect.copy_with_distance(distance)
+}
+
+/// A union of `d1` and `d2` with a staircase of `steps` steps in the transition.
+#[inline]
+pub fn sd_op_union_stairs<T: SignedDistance>(d1: T, d2: T, size: f32, steps: f32) -> T {
+    let (a, b) = (d1.distance(), d2.distance());
+    let step = size / steps;
+    let u = b - size;
+    // `rem_euclid`, which isn't available without `std`.
+    let period = 2.0 * step;
+    let r = (u - a + step) % period;
+    let r = if r < 0.0 { r + period.abs() } else { r };
+    let stairs = 0.5 * (u + a + (r - step).abs());
+    let closest = if a < b { d1 } else { d2 };
+    closest.copy_with_distance(a.min(b).min(stairs))
+}
+
+/// A pipe along the intersection of the surfaces of `d1` and `d2`.
+#[inline]
+pub fn sd_op_pipe<T: SignedDistance>(d1: T, d2: T, radius: f32) -> T {
+    let (a, b) = (d1.distance(), d2.distance());
+    let closest = if a.abs() < b.abs() { d1 } else { d2 };
+    closest.copy_with_distance(a.hypot(b) - radius)
+}
+
+/// Cuts a groove into `d2` along the surface of `d1`.
+#[inline]
+pub fn sd_op_groove<T: SignedDistance>(d1: T, d2: T, depth: f32, half_width: f32) -> T {
+    let groove = (d2.distance() + depth).min(half_width - d1.distance().abs());
+    if d2.distance() > groove {
+        d2
+    } else {
+        d1.copy_with_distance(groove)
+    }
+}
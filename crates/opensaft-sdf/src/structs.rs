@@ -1,13 +1,54 @@
 use glam::Vec3;
 use glam::Vec4;
+#[cfg(any(target_arch = "spirv", not(feature = "std")))]
+use num_traits::Float;
 
-#[derive(Copy, Clone)]
+/// Converts a [0-1] linear color channel to sRGB, as expected by most image formats and viewers.
+#[inline]
+pub fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts a [0-1] sRGB color channel, e.g. from a color picker, to linear.
+#[inline]
+pub fn srgb_to_linear(srgb: f32) -> f32 {
+    if srgb <= 0.040_45 {
+        srgb / 12.92
+    } else {
+        ((srgb + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "with_speedy", derive(speedy::Writable, speedy::Readable))]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct Material {
     /// [0-1] linear space
     rgb: Vec3,
+    /// [0-1] opacity, where 1 is opaque
+    #[cfg_attr(feature = "with_serde", serde(default = "opaque"))]
+    alpha: f32,
+    /// [0-1] for PBR shading, where 1 is metal
+    #[cfg_attr(feature = "with_serde", serde(default))]
+    metallic: f32,
+    /// [0-1] for PBR shading, where 0 is a mirror
+    #[cfg_attr(feature = "with_serde", serde(default = "default_roughness"))]
+    roughness: f32,
+}
+
+#[cfg(feature = "with_serde")]
+fn opaque() -> f32 {
+    1.0
+}
+
+#[cfg(feature = "with_serde")]
+fn default_roughness() -> f32 {
+    Material::DEFAULT_ROUGHNESS
 }
 
 impl Default for Material {
@@ -18,18 +59,88 @@ impl Default for Material {
 
 impl From<Vec3> for Material {
     fn from(rgb: Vec3) -> Self {
-        Self { rgb }
+        Self::new(rgb)
+    }
+}
+
+impl From<Vec4> for Material {
+    fn from(rgba: Vec4) -> Self {
+        Self::new_rgba(rgba)
     }
 }
 
 impl Material {
+    pub const DEFAULT_ROUGHNESS: f32 = 0.5;
+
+    /// An opaque, non-metallic material.
     pub fn new(rgb: Vec3) -> Self {
-        Self { rgb }
+        Self::new_rgba(rgb.extend(1.0))
+    }
+
+    pub fn new_rgba(rgba: Vec4) -> Self {
+        Self {
+            rgb: rgba.truncate(),
+            alpha: rgba.w,
+            metallic: 0.0,
+            roughness: Self::DEFAULT_ROUGHNESS,
+        }
+    }
+
+    /// An opaque, non-metallic material from an sRGB color, e.g. from a color picker.
+    pub fn from_srgb(srgb: Vec3) -> Self {
+        Self::new(Vec3::new(
+            srgb_to_linear(srgb.x),
+            srgb_to_linear(srgb.y),
+            srgb_to_linear(srgb.z),
+        ))
+    }
+
+    #[must_use]
+    pub fn with_metallic_roughness(mut self, metallic: f32, roughness: f32) -> Self {
+        self.metallic = metallic;
+        self.roughness = roughness;
+        self
     }
 
     pub fn rgb(&self) -> Vec3 {
         self.rgb
     }
+
+    /// The color in sRGB rather than linear space, see [`linear_to_srgb`].
+    pub fn to_srgb(&self) -> Vec3 {
+        Vec3::new(
+            linear_to_srgb(self.rgb.x),
+            linear_to_srgb(self.rgb.y),
+            linear_to_srgb(self.rgb.z),
+        )
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    pub fn rgba(&self) -> Vec4 {
+        self.rgb.extend(self.alpha)
+    }
+
+    pub fn metallic(&self) -> f32 {
+        self.metallic
+    }
+
+    pub fn roughness(&self) -> f32 {
+        self.roughness
+    }
+
+    #[must_use]
+    pub fn lerp(&self, b: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            rgb: self.rgb.lerp(b.rgb, t),
+            alpha: lerp(self.alpha, b.alpha),
+            metallic: lerp(self.metallic, b.metallic),
+            roughness: lerp(self.roughness, b.roughness),
+        }
+    }
 }
 
 pub trait SignedDistance: Copy {
@@ -53,6 +164,24 @@ pub trait SignedDistance: Copy {
     fn new_with_distance(material: Material, distance: f32) -> Self;
 
     fn is_distance_finite(&self) -> bool;
+
+    /// Whether the carrier keeps track of the primitive it comes from, like [`DistanceWithId`],
+    /// so that the interpreter only tags the primitives when it matters.
+    const TRACKS_PRIMITIVES: bool = false;
+
+    /// Tags the distance with the primitive it comes from, if [`Self::TRACKS_PRIMITIVES`].
+    /// The others ignore it.
+    #[must_use]
+    #[inline]
+    fn with_primitive_id(self, _id: u32) -> Self {
+        self
+    }
+
+    /// The id given to [`Self::with_primitive_id`], for carriers that keep track of it.
+    #[inline]
+    fn primitive_id(&self) -> Option<u32> {
+        None
+    }
 }
 
 impl SignedDistance for f32 {
@@ -99,6 +228,8 @@ impl SignedDistance for f32 {
 }
 
 /// r, g, b, distance
+///
+/// Drops the alpha of materials, see [`RgbaWithDistance`].
 #[derive(Default, Copy, Clone, PartialEq)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct RgbWithDistance(pub Vec4);
@@ -146,3 +277,224 @@ impl SignedDistance for RgbWithDistance {
         self.0.is_finite()
     }
 }
+
+/// Like [`RgbWithDistance`], but keeps the alpha of materials too.
+#[derive(Default, Copy, Clone, PartialEq)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+pub struct RgbaWithDistance {
+    pub rgba: Vec4,
+    pub distance: f32,
+}
+
+impl SignedDistance for RgbaWithDistance {
+    #[inline]
+    fn infinity() -> Self {
+        Self {
+            rgba: Vec4::ONE,
+            distance: f32::INFINITY,
+        }
+    }
+
+    #[inline]
+    fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    #[inline]
+    fn material(&self) -> Material {
+        Material::new_rgba(self.rgba)
+    }
+
+    #[inline]
+    fn copy_with_distance(&self, distance: f32) -> Self {
+        Self {
+            rgba: self.rgba,
+            distance,
+        }
+    }
+
+    #[inline]
+    fn multiply_distance_by(&self, factor: f32) -> Self {
+        self.copy_with_distance(self.distance * factor)
+    }
+
+    #[inline]
+    fn new_with_distance(material: Material, distance: f32) -> Self {
+        Self {
+            rgba: material.rgba(),
+            distance,
+        }
+    }
+
+    #[inline]
+    fn lerp(&self, b: &Self, t: f32) -> Self {
+        Self {
+            rgba: self.rgba.lerp(b.rgba, t),
+            distance: self.distance + (b.distance - self.distance) * t,
+        }
+    }
+
+    #[inline]
+    fn is_distance_finite(&self) -> bool {
+        self.distance.is_finite()
+    }
+}
+
+/// Carries the whole [`Material`], e.g. for PBR shading.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+pub struct PbrWithDistance {
+    pub material: Material,
+    pub distance: f32,
+}
+
+impl Default for PbrWithDistance {
+    fn default() -> Self {
+        Self {
+            material: Material::default(),
+            distance: 0.0,
+        }
+    }
+}
+
+impl SignedDistance for PbrWithDistance {
+    #[inline]
+    fn infinity() -> Self {
+        Self {
+            material: Material::default(),
+            distance: f32::INFINITY,
+        }
+    }
+
+    #[inline]
+    fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    #[inline]
+    fn material(&self) -> Material {
+        self.material
+    }
+
+    #[inline]
+    fn copy_with_distance(&self, distance: f32) -> Self {
+        Self {
+            material: self.material,
+            distance,
+        }
+    }
+
+    #[inline]
+    fn multiply_distance_by(&self, factor: f32) -> Self {
+        self.copy_with_distance(self.distance * factor)
+    }
+
+    #[inline]
+    fn new_with_distance(material: Material, distance: f32) -> Self {
+        Self { material, distance }
+    }
+
+    #[inline]
+    fn lerp(&self, b: &Self, t: f32) -> Self {
+        Self {
+            material: self.material.lerp(&b.material, t),
+            distance: self.distance + (b.distance - self.distance) * t,
+        }
+    }
+
+    #[inline]
+    fn is_distance_finite(&self) -> bool {
+        self.distance.is_finite()
+    }
+}
+
+/// A distance along with the primitive it comes from, e.g. for picking.
+///
+/// The id is the index of the primitive's opcode in the program, as tagged by the interpreter.
+/// Blends between primitives take the id of the one with the most weight.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+pub struct DistanceWithId {
+    pub distance: f32,
+    /// [`Self::NO_ID`] if not from a primitive.
+    pub id: u32,
+}
+
+impl DistanceWithId {
+    pub const NO_ID: u32 = u32::MAX;
+}
+
+impl Default for DistanceWithId {
+    fn default() -> Self {
+        Self {
+            distance: 0.0,
+            id: Self::NO_ID,
+        }
+    }
+}
+
+impl SignedDistance for DistanceWithId {
+    #[inline]
+    fn infinity() -> Self {
+        Self {
+            distance: f32::INFINITY,
+            id: Self::NO_ID,
+        }
+    }
+
+    #[inline]
+    fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    #[inline]
+    fn material(&self) -> Material {
+        Material::default()
+    }
+
+    #[inline]
+    fn copy_with_distance(&self, distance: f32) -> Self {
+        Self {
+            distance,
+            id: self.id,
+        }
+    }
+
+    #[inline]
+    fn multiply_distance_by(&self, factor: f32) -> Self {
+        self.copy_with_distance(self.distance * factor)
+    }
+
+    #[inline]
+    fn new_with_distance(_material: Material, distance: f32) -> Self {
+        Self {
+            distance,
+            id: Self::NO_ID,
+        }
+    }
+
+    #[inline]
+    fn lerp(&self, b: &Self, t: f32) -> Self {
+        Self {
+            distance: self.distance + (b.distance - self.distance) * t,
+            id: if t < 0.5 { self.id } else { b.id },
+        }
+    }
+
+    #[inline]
+    fn is_distance_finite(&self) -> bool {
+        self.distance.is_finite()
+    }
+
+    const TRACKS_PRIMITIVES: bool = true;
+
+    #[inline]
+    fn with_primitive_id(self, id: u32) -> Self {
+        Self { id, ..self }
+    }
+
+    #[inline]
+    fn primitive_id(&self) -> Option<u32> {
+        Some(self.id)
+    }
+}
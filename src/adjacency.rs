@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+use ahash::AHashMap as HashMap;
+
+use crate::TriangleMesh;
+
+/// The topology of a [`TriangleMesh`], see [`TriangleMesh::build_adjacency`].
+///
+/// Vertices at the exact same position are welded together, and represented by the first of them.
+/// All vertex indices returned are of such representatives, so they can be used to index the mesh directly.
+#[derive(Clone, Debug, Default)]
+pub struct MeshAdjacency {
+    /// For each vertex, the first vertex at the same position.
+    welded: Vec<u32>,
+    /// For each welded vertex, the sorted welded vertices it shares an edge with. Empty for the others.
+    neighbors: Vec<Vec<u32>>,
+    /// For each edge between welded vertices, with the lowest index first, the triangles using it.
+    edge_triangles: BTreeMap<[u32; 2], Vec<u32>>,
+}
+
+impl MeshAdjacency {
+    /// The vertex that `vertex` was welded into. This is `vertex` itself for the first vertex at each position.
+    pub fn welded_vertex(&self, vertex: u32) -> u32 {
+        self.welded[vertex as usize]
+    }
+
+    /// The number of distinct vertex positions.
+    pub fn num_welded_vertices(&self) -> usize {
+        self.welded
+            .iter()
+            .enumerate()
+            .filter(|(i, w)| *i == **w as usize)
+            .count()
+    }
+
+    /// The welded vertices sharing an edge with `vertex`, in increasing order.
+    pub fn vertex_neighbors(&self, vertex: u32) -> &[u32] {
+        &self.neighbors[self.welded_vertex(vertex) as usize]
+    }
+
+    /// The index (into the triangles, i.e. `indices / 3`) of the triangles with an edge between `a` and `b`.
+    ///
+    /// On a closed manifold mesh every edge has exactly two triangles, and boundary edges have one.
+    pub fn edge_triangles(&self, a: u32, b: u32) -> &[u32] {
+        let (a, b) = (self.welded_vertex(a), self.welded_vertex(b));
+        self.edge_triangles
+            .get(&[a.min(b), a.max(b)])
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Every edge between welded vertices, with the lowest index first, together with the triangles using it.
+    pub fn edges(&self) -> impl Iterator<Item = ([u32; 2], &[u32])> + '_ {
+        self.edge_triangles
+            .iter()
+            .map(|(edge, triangles)| (*edge, triangles.as_slice()))
+    }
+
+    /// True if every edge has exactly two triangles, i.e. the mesh is closed with no holes.
+    pub fn is_closed(&self) -> bool {
+        self.edge_triangles.values().all(|t| t.len() == 2)
+    }
+
+    /// The number of edges with only one triangle, i.e. along the borders of holes.
+    pub fn boundary_edge_count(&self) -> usize {
+        self.edge_triangles
+            .values()
+            .filter(|t| t.len() == 1)
+            .count()
+    }
+}
+
+impl TriangleMesh {
+    /// Which vertices and triangles are next to each other, after welding vertices at the same position.
+    ///
+    /// This is what smoothing, simplification, curvature estimation etc. need.
+    pub fn build_adjacency(&self) -> MeshAdjacency {
+        let mut first_at_position = HashMap::with_capacity(self.positions.len());
+        let welded: Vec<u32> = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                *first_at_position
+                    .entry(p.map(f32::to_bits))
+                    .or_insert(i as u32)
+            })
+            .collect();
+
+        let mut neighbors = vec![vec![]; self.positions.len()];
+        let mut edge_triangles: BTreeMap<[u32; 2], Vec<u32>> = BTreeMap::new();
+        for (triangle, corners) in self.indices.chunks_exact(3).enumerate() {
+            let corners = [0, 1, 2].map(|i| welded[corners[i] as usize]);
+            for i in 0..3 {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                if a == b {
+                    continue; // degenerate
+                }
+                neighbors[a as usize].push(b);
+                neighbors[b as usize].push(a);
+                edge_triangles
+                    .entry([a.min(b), a.max(b)])
+                    .or_default()
+                    .push(triangle as u32);
+            }
+        }
+        for vertex_neighbors in &mut neighbors {
+            vertex_neighbors.sort_unstable();
+            vertex_neighbors.dedup();
+        }
+
+        MeshAdjacency {
+            welded,
+            neighbors,
+            edge_triangles,
+        }
+    }
+
+    /// True if every edge is shared by exactly two triangles after welding, i.e. the mesh is watertight,
+    /// as needed for e.g. 3D printing. See [`MeshAdjacency::is_closed`].
+    ///
+    /// This doesn't check for vertices where several surfaces touch in a single point.
+    pub fn is_manifold(&self) -> bool {
+        self.build_adjacency().is_closed()
+    }
+
+    /// The number of edges with only one triangle after welding, see [`MeshAdjacency::boundary_edge_count`].
+    pub fn boundary_edge_count(&self) -> usize {
+        self.build_adjacency().boundary_edge_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tetrahedron() -> TriangleMesh {
+        TriangleMesh {
+            indices: vec![0, 2, 1, 0, 1, 3, 1, 2, 3, 0, 3, 2],
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tetrahedron_adjacency() {
+        let mesh = tetrahedron();
+        let adjacency = mesh.build_adjacency();
+        assert_eq!(adjacency.num_welded_vertices(), 4);
+        for vertex in 0..4 {
+            assert_eq!(adjacency.vertex_neighbors(vertex).len(), 3);
+            assert!(!adjacency.vertex_neighbors(vertex).contains(&vertex));
+        }
+        assert_eq!(adjacency.edges().count(), 6);
+        assert!(adjacency.is_closed());
+        assert_eq!(adjacency.edge_triangles(1, 0), &[0, 1]);
+
+        // The same tetrahedron with separate vertices for each triangle gets welded.
+        let unwelded = TriangleMesh {
+            indices: (0..12).collect(),
+            positions: mesh
+                .indices
+                .iter()
+                .map(|i| mesh.positions[*i as usize])
+                .collect(),
+            ..Default::default()
+        };
+        let adjacency = unwelded.build_adjacency();
+        assert_eq!(adjacency.num_welded_vertices(), 4);
+        for vertex in 0..12 {
+            assert_eq!(adjacency.vertex_neighbors(vertex).len(), 3);
+        }
+        assert!(adjacency.is_closed());
+    }
+
+    #[test]
+    fn example_is_manifold() {
+        let mut graph = crate::Graph::default();
+        let node = graph.example(&Default::default());
+        let mut mesh = crate::mesh_from_sdf(&graph, node, crate::MeshOptions::low()).unwrap();
+        assert!(mesh.is_manifold());
+        assert_eq!(mesh.boundary_edge_count(), 0);
+
+        // Punching a hole gives a boundary edge for each of its sides.
+        mesh.indices.truncate(mesh.indices.len() - 3);
+        assert!(!mesh.is_manifold());
+        assert_eq!(mesh.boundary_edge_count(), 3);
+    }
+}
@@ -5,12 +5,73 @@ use std::rc::Rc;
 #[allow(clippy::upper_case_acronyms)]
 pub enum Backend {
     GLSL,
+    /// A SPIR-V module with a compute shader entry point, which doesn't need a shader compiler at runtime.
+    ///
+    /// The entry point is called `function_name`, and evaluates the program at `positions[i].xyz`
+    /// into `results[i]`, with [`crate::SPIRV_WORKGROUP_SIZE`] invocations per workgroup.
+    /// The results are of the type of the `_base` function of the GLSL backend for the [`OutputType`].
+    /// The buffers are in descriptor set 0: binding 0 is `vec4 positions[]`, binding 1 is `results[]`,
+    /// and binding 2 is `uint constants[]` with the bits of [`Program::constants`] when using dynamic constants.
+    ///
+    /// Get the module from [`CodeGen::to_binary`], while [`CodeGen::to_code`] disassembles it.
+    /// Malformed programs are reported as errors by `to_binary`, and as a comment by `to_code`.
+    #[cfg(feature = "with_spirv")]
+    SpirV,
 }
 
 #[derive(Copy, Clone)]
 pub enum OutputType {
     DistanceOnly,
     DistanceWithRgb,
+    /// Carries the id of the primitive that is closest, which is the index of its opcode in the program.
+    ///
+    /// Also emits a `{function_name}_id(vec3 pos)` function returning it as a `uint`,
+    /// and a `{function_name}_material(vec3 pos)` function returning the `SdfMaterial` of that primitive.
+    DistanceWithId,
+}
+
+/// A GLSL precision qualifier, see [`GlslOptions::precision`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GlslPrecision {
+    Low,
+    Medium,
+    High,
+}
+
+impl GlslPrecision {
+    pub fn qualifier(self) -> &'static str {
+        match self {
+            Self::Low => "lowp",
+            Self::Medium => "mediump",
+            Self::High => "highp",
+        }
+    }
+}
+
+/// How to emit GLSL, e.g. for GLSL ES instead of desktop GLSL, see [`CodeGen::to_code_with_options`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlslOptions {
+    /// The `#version` of the shader, e.g. `"300 es"` for OpenGL ES 3.0 or `"450"` for desktop, see [`Self::preamble`].
+    pub version: Option<String>,
+
+    /// Qualifies the return types of the generated functions,
+    /// and is the default precision of floats in [`Self::preamble`], which GLSL ES fragment shaders need.
+    pub precision: Option<GlslPrecision>,
+}
+
+impl GlslOptions {
+    /// The `#version` directive and default float precision, which have to come first in the shader,
+    /// before [`CodeGen::get_library_code`] and the generated code.
+    pub fn preamble(&self) -> String {
+        let mut preamble = String::new();
+        if let Some(version) = &self.version {
+            preamble.push_str(&format!("#version {version}\n"));
+        }
+        if let Some(precision) = self.precision {
+            preamble.push_str(&format!("precision {} float;\n", precision.qualifier()));
+        }
+        preamble
+    }
 }
 
 pub struct CodeGenContext<'a> {
@@ -18,10 +79,24 @@ pub struct CodeGenContext<'a> {
     dynamic_constants: bool,
     variable_index: usize,
     constant_index: usize,
+    /// With dynamic constants, a `uint` variable with the index that `constant_index` is relative to,
+    /// once it depends on constants, e.g. after a convex hull with a dynamic number of planes.
+    constant_base: Option<Rc<str>>,
+    /// The declarations of the `constant_base` variables, which go at the start of every function.
+    constant_base_declarations: String,
 
     variable_stack: Vec<Rc<str>>,
     position_variable_stack: Vec<Rc<str>>,
     current_position: Rc<str>,
+
+    /// The primitives that each variable in `variable_stack` was made from.
+    primitive_stack: Vec<Vec<usize>>,
+    /// The primitives of the variables popped since the last push, which the next push is made from.
+    popped_primitives: Vec<usize>,
+    /// The material of each primitive, by its opcode index.
+    primitive_materials: std::collections::BTreeMap<usize, String>,
+    /// The `vec4` variables with the samples of the warp field of each `PushWarpSample` loop.
+    warp_samples_stack: Vec<Rc<str>>,
 }
 
 impl<'a> CodeGenContext<'a> {
@@ -37,9 +112,15 @@ impl<'a> CodeGenContext<'a> {
             dynamic_constants,
             variable_index: 0,
             constant_index: 0,
+            constant_base: None,
+            constant_base_declarations: String::new(),
             variable_stack: Vec::new(),
             position_variable_stack: vec![position.clone()],
             current_position: position,
+            primitive_stack: Vec::new(),
+            popped_primitives: Vec::new(),
+            primitive_materials: Default::default(),
+            warp_samples_stack: Vec::new(),
         }
     }
 
@@ -49,10 +130,51 @@ impl<'a> CodeGenContext<'a> {
 
         let name: Rc<str> = Rc::from(name.as_str());
         self.variable_stack.push(name.clone());
+        self.primitive_stack
+            .push(std::mem::take(&mut self.popped_primitives));
         name
     }
 
+    /// Marks the latest variable as the primitive with opcode index `id`.
+    fn tag_primitive(&mut self, id: usize) {
+        if let Some(primitives) = self.primitive_stack.last_mut() {
+            primitives.push(id);
+        }
+    }
+
+    /// Sets the material of all primitives the latest variable was made from.
+    /// Outer materials are applied later, so they override inner ones, like in the interpreter.
+    fn set_primitive_materials(&mut self, material: &str) {
+        if let Some(primitives) = self.primitive_stack.last() {
+            for &id in primitives {
+                self.primitive_materials.insert(id, material.to_owned());
+            }
+        }
+    }
+
+    /// The index of the next constant as a GLSL `uint` expression, for dynamic constants.
+    fn constant_index_expression(&self) -> String {
+        match &self.constant_base {
+            Some(base) => format!("{} + {}u", base, self.constant_index),
+            None => format!("{}u", self.constant_index),
+        }
+    }
+
+    /// Reads the dynamic constant at the GLSL `uint` expression `index`.
+    fn dynamic_uint32(&self, index: &str) -> String {
+        format!(
+            "{}_constants[{}_constants_offset + (({}) >> 2u)][({}) & 3u]",
+            self.function_name, self.function_name, index, index
+        )
+    }
+
     pub fn uint32(&mut self) -> String {
+        if self.dynamic_constants && self.constant_base.is_some() {
+            let s = self.dynamic_uint32(&self.constant_index_expression());
+            self.constant_index += 1;
+            return s;
+        }
+
         let components = &[".x", ".y", ".z", ".w"];
 
         let s = format!(
@@ -74,6 +196,12 @@ impl<'a> CodeGenContext<'a> {
         s
     }
 
+    /// Skips a constant that is used at code generation time, returning its index.
+    fn skip_constant(&mut self) -> usize {
+        self.constant_index += 1;
+        self.constant_index - 1
+    }
+
     pub fn float32(&mut self) -> String {
         format!("uintBitsToFloat({})", self.uint32())
     }
@@ -106,10 +234,18 @@ impl<'a> CodeGenContext<'a> {
     }
 
     pub fn material(&mut self) -> String {
-        self.vec3()
+        format!(
+            "SdfMaterial({}, {}, {})",
+            self.vec4(),
+            self.float32(),
+            self.float32()
+        )
     }
 
     pub fn pop_variable(&mut self) -> Option<Rc<str>> {
+        if let Some(primitives) = self.primitive_stack.pop() {
+            self.popped_primitives.extend(primitives);
+        }
         self.variable_stack.pop()
     }
 
@@ -146,10 +282,20 @@ impl CodeGen {
         }
     }
 
+    #[cfg(feature = "with_spirv")]
+    pub fn spirv() -> Self {
+        Self {
+            backend: Backend::SpirV,
+        }
+    }
+
     // Generates code that is used by the translated program
     pub fn get_library_code(&self) -> &'static str {
         match self.backend {
             Backend::GLSL => opensaft_sdf::get_glsl_sdf_library_code(),
+            // The module contains everything it needs.
+            #[cfg(feature = "with_spirv")]
+            Backend::SpirV => "",
         }
     }
 
@@ -158,16 +304,22 @@ impl CodeGen {
         function_name: &str,
         output_type: OutputType,
         dynamic_constants: bool,
+        options: &GlslOptions,
     ) -> String {
         use super::Opcode::*;
         use std::fmt::Write;
 
         let mut code = String::new();
+        let precision = options.precision.map_or(String::new(), |precision| {
+            format!("{} ", precision.qualifier())
+        });
+
         code.push_str("// !!! START OF GENERATED CODE !!!\n");
 
         let output_glsl_type = match output_type {
             OutputType::DistanceOnly => "float",
             OutputType::DistanceWithRgb => "vec4",
+            OutputType::DistanceWithId => "vec2",
         };
 
         if !dynamic_constants {
@@ -193,16 +345,24 @@ impl CodeGen {
 
         let _ = writeln!(
             &mut code,
-            "{} {}_base(vec3 pos) {{",
-            output_glsl_type, function_name
+            "{}{} {}_base(vec3 pos) {{",
+            precision, output_glsl_type, function_name
         );
+        let body_start = code.len();
 
         let prefix = match output_type {
             OutputType::DistanceOnly => "sd",
             OutputType::DistanceWithRgb => "sdrgb",
+            OutputType::DistanceWithId => "sdid",
         };
 
-        for opcode in &program.opcodes {
+        // Primitives don't know their id, so they're evaluated as plain distances and tagged afterwards.
+        let (primitive_glsl_type, primitive_prefix) = match output_type {
+            OutputType::DistanceOnly | OutputType::DistanceWithId => ("float", "sd"),
+            OutputType::DistanceWithRgb => ("vec4", "sdrgb"),
+        };
+
+        for (opcode_index, opcode) in program.opcodes.iter().enumerate() {
             match opcode {
                 Plane => {
                     let variable_name = ctx.push_variable();
@@ -210,13 +370,78 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_plane({}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         plane,
                     );
                 }
+                ConvexHull => {
+                    let variable_name = ctx.push_variable();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tfloat {}_distance = sd_convex_hull_empty();",
+                        variable_name
+                    );
+                    if dynamic_constants {
+                        // The number of planes is read at runtime, and so are the indices of the constants after them,
+                        // so that the code only depends on the opcodes.
+                        let num_planes = format!("uint({})", ctx.float32());
+                        let first_plane = ctx.constant_index_expression();
+                        let _ = writeln!(
+                            &mut code,
+                            "\tfor (uint {v}_i = 0u; {v}_i < {}; {v}_i++) {{",
+                            num_planes,
+                            v = variable_name,
+                        );
+                        let component = |c: usize| {
+                            let index =
+                                format!("{} + 4u * {}_i + {}u", first_plane, variable_name, c);
+                            format!("uintBitsToFloat({})", ctx.dynamic_uint32(&index))
+                        };
+                        let plane = format!(
+                            "vec4({}, {}, {}, {})",
+                            component(0),
+                            component(1),
+                            component(2),
+                            component(3)
+                        );
+                        let _ = writeln!(
+                            &mut code,
+                            "\t\t{v}_distance = max({v}_distance, sd_plane({}, {}));\n\t}}",
+                            ctx.current_position(),
+                            plane,
+                            v = variable_name,
+                        );
+                        let base: Rc<str> = Rc::from(format!("{}_constants_end", variable_name));
+                        let _ = writeln!(
+                            &mut ctx.constant_base_declarations,
+                            "\tuint {} = {} + 4u * {};",
+                            base, first_plane, num_planes
+                        );
+                        ctx.constant_base = Some(base);
+                        ctx.constant_index = 0;
+                    } else {
+                        let num_planes = program.constants[ctx.skip_constant()] as usize;
+                        for _ in 0..num_planes {
+                            let plane = ctx.vec4();
+                            let _ = writeln!(
+                                &mut code,
+                                "\t{}_distance = max({}_distance, sd_plane({}, {}));",
+                                variable_name,
+                                variable_name,
+                                ctx.current_position(),
+                                plane,
+                            );
+                        }
+                    }
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_convex_hull({}_distance);",
+                        primitive_glsl_type, variable_name, primitive_prefix, variable_name,
+                    );
+                }
                 Sphere => {
                     let variable_name = ctx.push_variable();
                     let center = ctx.vec3();
@@ -224,9 +449,9 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_sphere({}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         center,
                         radius,
@@ -239,9 +464,9 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_capsule({}, {}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         points[0],
                         points[1],
@@ -256,15 +481,62 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_rounded_cylinder({}, {}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         cylinder_radius,
                         half_height,
                         rounding_radius,
                     );
                 }
+                Tube => {
+                    let variable_name = ctx.push_variable();
+                    let outer_r = ctx.float32();
+                    let inner_r = ctx.float32();
+                    let half_height = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_tube({}, {}, {}, {});",
+                        primitive_glsl_type,
+                        variable_name,
+                        primitive_prefix,
+                        ctx.current_position(),
+                        outer_r,
+                        inner_r,
+                        half_height,
+                    );
+                }
+                Egg => {
+                    let variable_name = ctx.push_variable();
+                    let radius = ctx.float32();
+                    let tip_radius = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_egg({}, {}, {});",
+                        primitive_glsl_type,
+                        variable_name,
+                        primitive_prefix,
+                        ctx.current_position(),
+                        radius,
+                        tip_radius,
+                    );
+                }
+                Heart => {
+                    let variable_name = ctx.push_variable();
+                    let size = ctx.float32();
+                    let half_thickness = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_heart({}, {}, {});",
+                        primitive_glsl_type,
+                        variable_name,
+                        primitive_prefix,
+                        ctx.current_position(),
+                        size,
+                        half_thickness,
+                    );
+                }
                 TaperedCapsule => {
                     let variable_name = ctx.push_variable();
                     let p0 = ctx.vec3();
@@ -274,9 +546,9 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_tapered_capsule({}, {}, {}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         p0,
                         p1,
@@ -291,14 +563,31 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_cone({}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         r,
                         h,
                     );
                 }
+                CappedCone => {
+                    let variable_name = ctx.push_variable();
+                    let r1 = ctx.float32();
+                    let r2 = ctx.float32();
+                    let height = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_capped_cone({}, {}, {}, {});",
+                        primitive_glsl_type,
+                        variable_name,
+                        primitive_prefix,
+                        ctx.current_position(),
+                        r1,
+                        r2,
+                        height,
+                    );
+                }
                 RoundedBox => {
                     let variable_name = ctx.push_variable();
                     let half_size = ctx.vec3();
@@ -306,9 +595,9 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_rounded_box({}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         half_size,
                         radius,
@@ -319,9 +608,9 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_torus({}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         ctx.push_variable(),
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         ctx.float32(),
                         ctx.float32(),
@@ -332,9 +621,9 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_torus_sector({}, {}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         ctx.push_variable(),
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         ctx.float32(),
                         ctx.float32(),
@@ -349,15 +638,108 @@ impl CodeGen {
                     let _ = writeln!(
                         &mut code,
                         "\t{} {} = {}_biconvex_lens({}, {}, {}, {});",
-                        output_glsl_type,
+                        primitive_glsl_type,
                         variable_name,
-                        prefix,
+                        primitive_prefix,
                         ctx.current_position(),
                         lower_sagitta,
                         upper_sagitta,
                         chord,
                     );
                 }
+                Vesica => {
+                    // radius, distance
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_vesica({}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.float32(),
+                        ctx.float32(),
+                    );
+                }
+                CutSphere => {
+                    // radius, height
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_cut_sphere({}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.float32(),
+                        ctx.float32(),
+                    );
+                }
+                CutHollowSphere => {
+                    // radius, height, thickness
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_cut_hollow_sphere({}, {}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.float32(),
+                        ctx.float32(),
+                        ctx.float32(),
+                    );
+                }
+                Link => {
+                    // length, big_r, small_r
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_link({}, {}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.float32(),
+                        ctx.float32(),
+                        ctx.float32(),
+                    );
+                }
+                SolidAngle => {
+                    // sin_cos_angle, radius
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_solid_angle({}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.vec2(),
+                        ctx.float32(),
+                    );
+                }
+                Gyroid => {
+                    // scale, thickness
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_gyroid({}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.float32(),
+                        ctx.float32(),
+                    );
+                }
+                SchwarzP => {
+                    // scale, thickness
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_schwarz_p({}, {}, {});",
+                        primitive_glsl_type,
+                        ctx.push_variable(),
+                        primitive_prefix,
+                        ctx.current_position(),
+                        ctx.float32(),
+                        ctx.float32(),
+                    );
+                }
                 Material => {
                     let sd = ctx.pop_variable().unwrap();
                     let material = ctx.material();
@@ -367,6 +749,7 @@ impl CodeGen {
                         "\t{} {} = {}_material({}, {});",
                         output_glsl_type, variable_name, prefix, sd, material
                     );
+                    ctx.set_primitive_materials(&material);
                 }
                 Union => {
                     let sd1 = ctx.pop_variable().unwrap();
@@ -431,6 +814,74 @@ impl CodeGen {
                         output_glsl_type, variable_name, prefix, sd1, sd2, size
                     );
                 }
+                UnionChamfer => {
+                    let sd1 = ctx.pop_variable().unwrap();
+                    let sd2 = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let size = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_union_chamfer({}, {}, {});",
+                        output_glsl_type, variable_name, prefix, sd1, sd2, size
+                    );
+                }
+                SubtractChamfer => {
+                    let sd1 = ctx.pop_variable().unwrap();
+                    let sd2 = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let size = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_subtract_chamfer({}, {}, {});",
+                        output_glsl_type, variable_name, prefix, sd1, sd2, size
+                    );
+                }
+                IntersectChamfer => {
+                    let sd1 = ctx.pop_variable().unwrap();
+                    let sd2 = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let size = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_intersect_chamfer({}, {}, {});",
+                        output_glsl_type, variable_name, prefix, sd1, sd2, size
+                    );
+                }
+                UnionStairs => {
+                    let sd1 = ctx.pop_variable().unwrap();
+                    let sd2 = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let size = ctx.float32();
+                    let steps = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_union_stairs({}, {}, {}, {});",
+                        output_glsl_type, variable_name, prefix, sd1, sd2, size, steps
+                    );
+                }
+                Pipe => {
+                    let sd1 = ctx.pop_variable().unwrap();
+                    let sd2 = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let radius = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_pipe({}, {}, {});",
+                        output_glsl_type, variable_name, prefix, sd1, sd2, radius
+                    );
+                }
+                Groove => {
+                    let sd1 = ctx.pop_variable().unwrap();
+                    let sd2 = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let depth = ctx.float32();
+                    let half_width = ctx.float32();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_groove({}, {}, {}, {});",
+                        output_glsl_type, variable_name, prefix, sd1, sd2, depth, half_width
+                    );
+                }
                 PushTranslation => {
                     let translation = ctx.vec3();
                     let (new_position, old_position) = ctx.push_transform();
@@ -461,7 +912,8 @@ impl CodeGen {
                         new_position, old_position, scale
                     );
                 }
-                PopScale => {
+                // Both scale the distance to compensate for the transform.
+                PopScale | PopAffine => {
                     ctx.pop_transform();
                     let inv_scale = ctx.float32();
                     let sd = ctx.pop_variable().unwrap();
@@ -472,23 +924,164 @@ impl CodeGen {
                         output_glsl_type, variable_name, prefix, sd, inv_scale
                     );
                 }
+                PushAffine => {
+                    let x_axis = ctx.vec3();
+                    let y_axis = ctx.vec3();
+                    let z_axis = ctx.vec3();
+                    let translation = ctx.vec3();
+                    let (new_position, old_position) = ctx.push_transform();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec3 {} = mat3({}, {}, {}) * {} + {};",
+                        new_position, x_axis, y_axis, z_axis, old_position, translation
+                    );
+                }
+                PushRevolve => {
+                    let offset = ctx.float32();
+                    let (new_position, old_position) = ctx.push_transform();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec3 {} = op_revolve_position({}, {});",
+                        new_position, old_position, offset
+                    );
+                }
+                PushWarpSample => {
+                    let epsilon = ctx.float32();
+                    let samples: Rc<str> = Rc::from(format!("warp_samples{}", ctx.variable_index));
+                    ctx.variable_index += 1;
+                    let (new_position, old_position) = ctx.push_transform();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec4 {s} = vec4(0.0);\n\tfor (uint {s}_i = 0u; {s}_i < 4u; {s}_i++) {{",
+                        s = samples
+                    );
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec3 {} = {} + {} * warp_gradient_offset({}_i);",
+                        new_position, old_position, epsilon, samples
+                    );
+                    ctx.warp_samples_stack.push(samples);
+                }
+                PopWarpSample => {
+                    ctx.pop_transform();
+                    let sd = ctx.pop_variable().unwrap();
+                    let samples = ctx.warp_samples_stack.last().unwrap();
+                    let distance = match output_type {
+                        OutputType::DistanceOnly => "",
+                        OutputType::DistanceWithRgb => ".w",
+                        OutputType::DistanceWithId => ".x",
+                    };
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{s}[{s}_i] = {}{};\n\t}}",
+                        sd,
+                        distance,
+                        s = samples
+                    );
+                }
+                PushWarp
+                    if opcode_index > 0 && program.opcodes[opcode_index - 1] == PopWarpSample =>
+                {
+                    let strength = ctx.float32();
+                    let epsilon = ctx.float32();
+                    let samples = ctx.warp_samples_stack.pop().unwrap();
+                    // The warp field doesn't end up in the output, so neither do its primitives.
+                    ctx.popped_primitives.clear();
+                    let (new_position, old_position) = ctx.push_transform();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec3 {} = op_warp_position({}, {}, {}, {});",
+                        new_position, old_position, samples, strength, epsilon
+                    );
+                }
+                PushWarp => {
+                    let strength = ctx.float32();
+                    let epsilon = ctx.float32();
+                    let mut distances = [(); 4].map(|_| ctx.pop_variable().unwrap());
+                    distances.reverse();
+                    // The warp field doesn't end up in the output, so neither do its primitives.
+                    ctx.popped_primitives.clear();
+                    let (new_position, old_position) = ctx.push_transform();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec3 {} = {}_op_warp_position({}, {}, {}, {});",
+                        new_position,
+                        prefix,
+                        old_position,
+                        distances.join(", "),
+                        strength,
+                        epsilon
+                    );
+                }
+                PushElongate => {
+                    let amount = ctx.vec3();
+                    let (new_position, old_position) = ctx.push_transform();
+                    let _ = writeln!(
+                        &mut code,
+                        "\tvec3 {} = op_elongate_position({}, {});",
+                        new_position, old_position, amount
+                    );
+                }
+                PopElongate => {
+                    ctx.pop_transform();
+                    let amount = ctx.vec3();
+                    let sd = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_elongate_distance({}, {}, {});",
+                        output_glsl_type,
+                        variable_name,
+                        prefix,
+                        sd,
+                        ctx.current_position(),
+                        amount
+                    );
+                }
+                ScaleDistance => {
+                    let factor = ctx.float32();
+                    let sd = ctx.pop_variable().unwrap();
+                    let variable_name = ctx.push_variable();
+                    let _ = writeln!(
+                        &mut code,
+                        "\t{} {} = {}_op_scale_distance({}, {});",
+                        output_glsl_type, variable_name, prefix, sd, factor
+                    );
+                }
                 End => {
                     break;
                 }
             }
+
+            if opcode.is_primitive() {
+                ctx.tag_primitive(opcode_index);
+            }
+
+            if matches!(output_type, OutputType::DistanceWithId) && opcode.is_primitive() {
+                let sd = ctx.pop_variable().unwrap();
+                let variable_name = ctx.push_variable();
+                let _ = writeln!(
+                    &mut code,
+                    "\t{} {} = sdid_primitive({}, {}u);",
+                    output_glsl_type, variable_name, sd, opcode_index
+                );
+            }
         }
 
         let ret = ctx.pop_variable().unwrap();
         let _ = writeln!(&mut code, "\treturn {};\n}}", ret);
+        code.insert_str(body_start, &ctx.constant_base_declarations);
 
         let _ = writeln!(
             &mut code,
-            "float {}(vec3 pos) {{ return {}_base(pos){}; }}",
+            "{}float {}(vec3 pos) {{ return {}_base(pos){}; }}",
+            precision,
             function_name,
             function_name,
             match output_type {
                 OutputType::DistanceOnly => "",
                 OutputType::DistanceWithRgb => ".w",
+                OutputType::DistanceWithId => ".x",
             }
         );
 
@@ -496,16 +1089,37 @@ impl CodeGen {
             OutputType::DistanceWithRgb => {
                 let _ = writeln!(
                     &mut code,
-                    "vec3 {}_color(vec3 pos) {{ return {}_base(pos).rgb; }}",
-                    function_name, function_name,
+                    "{}vec3 {}_color(vec3 pos) {{ return {}_base(pos).rgb; }}",
+                    precision, function_name, function_name,
                 );
             }
             OutputType::DistanceOnly => {
                 let _ = writeln!(
                     &mut code,
-                    "vec3 {}_color(vec3 /*pos*/) {{ return vec3(1.0, 1.0, 1.0); }}",
-                    function_name,
+                    "{}vec3 {}_color(vec3 /*pos*/) {{ return vec3(1.0, 1.0, 1.0); }}",
+                    precision, function_name,
+                );
+            }
+            OutputType::DistanceWithId => {
+                let _ = writeln!(
+                    &mut code,
+                    "{}vec3 {}_color(vec3 /*pos*/) {{ return vec3(1.0, 1.0, 1.0); }}",
+                    precision, function_name,
+                );
+                let _ = writeln!(
+                    &mut code,
+                    "{}uint {}_id(vec3 pos) {{ return uint({}_base(pos).y); }}",
+                    precision, function_name, function_name,
                 );
+                let _ = writeln!(
+                    &mut code,
+                    "SdfMaterial {}_material(vec3 pos) {{\n{}\tswitch ({}_id(pos)) {{",
+                    function_name, ctx.constant_base_declarations, function_name,
+                );
+                for (id, material) in &ctx.primitive_materials {
+                    let _ = writeln!(&mut code, "\tcase {}u: return {};", id, material);
+                }
+                code.push_str("\tdefault: return sdf_default_material();\n\t}\n}\n");
             }
         }
 
@@ -521,10 +1135,276 @@ impl CodeGen {
         output_type: OutputType,
         dynamic_constants: bool,
     ) -> String {
+        self.to_code_with_options(
+            program,
+            function_name,
+            output_type,
+            dynamic_constants,
+            &GlslOptions::default(),
+        )
+    }
+
+    /// Like [`Self::to_code`], with options for the GLSL backend, e.g. to target GLSL ES.
+    /// Put [`GlslOptions::preamble`] first in the shader.
+    ///
+    /// Other backends ignore `glsl_options`.
+    pub fn to_code_with_options(
+        &self,
+        program: &Program,
+        function_name: &str,
+        output_type: OutputType,
+        dynamic_constants: bool,
+        glsl_options: &GlslOptions,
+    ) -> String {
+        match self.backend {
+            Backend::GLSL => Self::build_glsl_code(
+                program,
+                function_name,
+                output_type,
+                dynamic_constants,
+                glsl_options,
+            ),
+            #[cfg(feature = "with_spirv")]
+            Backend::SpirV => {
+                use rspirv::binary::Disassemble;
+                crate::spirv_codegen::build_spirv_module(
+                    program,
+                    function_name,
+                    output_type,
+                    dynamic_constants,
+                )
+                .map_or_else(|err| format!("; {err}\n"), |module| module.disassemble())
+            }
+        }
+    }
+
+    /// Like [`Self::to_code`], but the binary for backends that have one, i.e. the words of a SPIR-V module.
+    ///
+    /// `None` for GLSL, which is only available as source code.
+    /// Fails for malformed programs, e.g. with [`crate::Error::BadStack`] when the stack runs out of distances.
+    #[cfg_attr(not(feature = "with_spirv"), allow(unused_variables))]
+    pub fn to_binary(
+        &self,
+        program: &Program,
+        function_name: &str,
+        output_type: OutputType,
+        dynamic_constants: bool,
+    ) -> Result<Option<Vec<u32>>, crate::Error> {
         match self.backend {
-            Backend::GLSL => {
-                Self::build_glsl_code(program, function_name, output_type, dynamic_constants)
+            Backend::GLSL => Ok(None),
+            #[cfg(feature = "with_spirv")]
+            Backend::SpirV => crate::spirv_codegen::build_spirv(
+                program,
+                function_name,
+                output_type,
+                dynamic_constants,
+            )
+            .map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Graph;
+    use glam::Vec3;
+
+    #[test]
+    fn glsl_with_id() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let capsule = graph.capsule_y(2.0, 0.5);
+        let root = graph.op_union_smooth(sphere, capsule, 0.2);
+        let program = crate::compile(&graph, root);
+
+        let code = CodeGen::glsl().to_code(&program, "scene", OutputType::DistanceWithId, false);
+
+        assert!(code.contains("uint scene_id(vec3 pos)"));
+        assert!(code.contains("sdid_primitive("));
+        assert!(code.contains("sdid_op_union_smooth("));
+    }
+
+    #[test]
+    fn glsl_material_by_id() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let capsule = graph.capsule_y(2.0, 0.5);
+        let capsule = graph.op_rgb(capsule, Vec3::X);
+        let union = graph.op_union(sphere, capsule);
+        let root = graph.op_rgb(union, Vec3::Y);
+        let program = crate::compile(&graph, root);
+
+        let code = CodeGen::glsl().to_code(&program, "scene", OutputType::DistanceWithId, false);
+
+        // Both primitives get the outer material, which overrides the inner one.
+        assert!(code.contains("SdfMaterial scene_material(vec3 pos)"));
+        let cases: Vec<&str> = code
+            .lines()
+            .filter_map(|line| line.strip_prefix("\tcase "))
+            .collect();
+        assert_eq!(cases.len(), 2);
+        let material = |case: &str| case.split_once(": return ").unwrap().1.to_owned();
+        assert_eq!(material(cases[0]), material(cases[1]));
+    }
+
+    #[test]
+    fn glsl_precision() {
+        let mut graph = Graph::default();
+        let root = graph.sphere(Vec3::ZERO, 1.0);
+        let program = crate::compile(&graph, root);
+        let options = GlslOptions {
+            version: Some("300 es".to_owned()),
+            precision: Some(GlslPrecision::High),
+        };
+
+        let code = CodeGen::glsl().to_code_with_options(
+            &program,
+            "scene",
+            OutputType::DistanceWithRgb,
+            false,
+            &options,
+        );
+
+        assert_eq!(
+            options.preamble(),
+            "#version 300 es\nprecision highp float;\n"
+        );
+        assert!(code.contains("highp vec4 scene_base(vec3 pos)"));
+        assert!(code.contains("highp float scene(vec3 pos)"));
+        assert!(code.contains("highp vec3 scene_color(vec3 pos)"));
+
+        let plain = CodeGen::glsl().to_code(&program, "scene", OutputType::DistanceWithRgb, false);
+        assert!(!plain.contains("highp"));
+        assert_eq!(GlslOptions::default().preamble(), "");
+    }
+
+    #[test]
+    fn glsl_pipe_and_groove() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let other_sphere = graph.sphere(Vec3::X, 1.0);
+        let pipe = graph.op_pipe(sphere, other_sphere, 0.1);
+        let groove = graph.op_groove(pipe, other_sphere, 0.2, 0.05);
+        let program = crate::compile(&graph, groove);
+
+        // The operands and constants are passed in the order the interpreter reads them,
+        // i.e. the right hand side first.
+        let constant = |index: usize, value: f32| {
+            assert_eq!(program.constants[index], value);
+            format!("uintBitsToFloat(scene_constants[scene_constants_offset + {index}])")
+        };
+        let code = CodeGen::glsl().to_code(&program, "scene", OutputType::DistanceOnly, false);
+        assert!(code.contains(&format!(
+            "float sdf2 = sd_op_pipe(sdf1, sdf0, {});",
+            constant(8, 0.1)
+        )));
+        assert!(code.contains(&format!(
+            "float sdf4 = sd_op_groove(sdf3, sdf2, {}, {});",
+            constant(13, 0.2),
+            constant(14, 0.05)
+        )));
+
+        // naga checks that the library has the functions for every output type, with the right types.
+        for output_type in [
+            OutputType::DistanceOnly,
+            OutputType::DistanceWithRgb,
+            OutputType::DistanceWithId,
+        ] {
+            let shader = format!(
+                "#version 450\nconst uint scene_constants_offset = 0u;\n{}\n{}\nvoid main() {{}}\n",
+                opensaft_sdf::get_glsl_sdf_library_code(),
+                CodeGen::glsl().to_code(&program, "scene", output_type, false),
+            );
+            let module = naga::front::glsl::Frontend::default()
+                .parse(
+                    &naga::front::glsl::Options::from(naga::ShaderStage::Fragment),
+                    &shader,
+                )
+                .unwrap();
+            naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::empty(),
+            )
+            .validate(&module)
+            .unwrap();
+        }
+    }
+
+    #[cfg(feature = "with_spirv")]
+    #[test]
+    fn spirv_module_is_valid() {
+        use rspirv::binary::Assemble;
+
+        let mut graph = Graph::default();
+        let example = graph.example(&Default::default());
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let gyroid = graph.gyroid(1.0, 0.1);
+        let warped = graph.op_warp(sphere, gyroid, 0.2);
+        let revolved = graph.op_revolve(sphere, 1.0);
+        let elongated = graph.op_elongate(warped, Vec3::new(1.0, 0.0, 0.5));
+        let heart = graph.heart(1.0, 0.25);
+        let egg = graph.egg(1.0, 0.25);
+        let stairs = graph.op_union_stairs(heart, egg, 0.5, 3);
+        let groove = graph.op_groove(revolved, stairs, 0.1, 0.05);
+        let chamfer = graph.op_subtract_chamfer(elongated, groove, 0.2);
+        let frustum = graph.capped_cone(1.0, 0.5, 2.0);
+        let wedge = graph.convex_hull(vec![
+            glam::Vec4::new(1.0, 0.0, 0.0, -1.0),
+            glam::Vec4::NEG_Y,
+        ]);
+        let root = graph.op_union_multi(vec![wedge, example, chamfer, frustum]);
+        let program = crate::compile(&graph, root);
+
+        let codegen = CodeGen::spirv();
+        for output_type in [
+            OutputType::DistanceOnly,
+            OutputType::DistanceWithRgb,
+            OutputType::DistanceWithId,
+        ] {
+            for dynamic_constants in [false, true] {
+                let words = codegen
+                    .to_binary(&program, "scene", output_type, dynamic_constants)
+                    .unwrap()
+                    .unwrap();
+                let module = rspirv::dr::load_words(&words).unwrap();
+                assert_eq!(module.entry_points.len(), 1);
+                assert_eq!(module.assemble(), words);
+
+                // naga checks the types, the control flow and the uses of the ids.
+                let module = naga::front::spv::parse_u8_slice(
+                    &words
+                        .iter()
+                        .flat_map(|word| word.to_le_bytes())
+                        .collect::<Vec<_>>(),
+                    &Default::default(),
+                )
+                .unwrap();
+                naga::valid::Validator::new(
+                    naga::valid::ValidationFlags::all(),
+                    naga::valid::Capabilities::empty(),
+                )
+                .validate(&module)
+                .unwrap();
             }
         }
+        assert!(codegen
+            .to_code(&program, "scene", OutputType::DistanceOnly, false)
+            .contains("OpEntryPoint GLCompute"));
+
+        // With dynamic constants, the module only depends on the opcodes, even the number of planes of a convex hull.
+        let mut other_program = program.clone();
+        for constant in &mut other_program.constants {
+            *constant *= 2.0;
+        }
+        let module = |program| {
+            codegen
+                .to_binary(program, "scene", OutputType::DistanceOnly, true)
+                .unwrap()
+        };
+        assert_eq!(module(&program), module(&other_program));
+        let glsl =
+            |program| CodeGen::glsl().to_code(program, "scene", OutputType::DistanceOnly, true);
+        assert_eq!(glsl(&program), glsl(&other_program));
     }
 }
@@ -0,0 +1,201 @@
+use crate::math::Ray3;
+use crate::sphere_tracing::ClosestHit;
+use crate::sphere_tracing::Options;
+use crate::Error;
+use crate::Graph;
+use crate::Interpreter;
+use crate::InterpreterContext;
+use crate::InterpreterPool;
+use crate::InterpreterStacks;
+use crate::Material;
+use crate::NodeId;
+use crate::PbrWithDistance;
+use crate::Program;
+use crate::RgbaWithDistance;
+use crate::SignedDistance;
+use glam::Vec3;
+use glam::Vec4;
+
+/// A node compiled once, for evaluating it at many points.
+///
+/// [`crate::surface_distance_to`] and [`crate::march`] compile the graph on every call,
+/// which dominates when querying many points.
+///
+/// The stacks of the interpreter are kept between queries, which is why they take `&mut self`.
+/// Use [`Self::pool`] to evaluate the program from many threads, with a context for each.
+///
+/// Programs too deep for the interpreter's stack are rejected up front with [`Error::StackOverflow`],
+/// so the queries can't fail.
+#[derive(Clone, Debug)]
+pub struct CompiledSdf {
+    program: Program,
+    distance_stacks: InterpreterStacks<f32>,
+    color_stacks: InterpreterStacks<RgbaWithDistance>,
+    sample_stacks: InterpreterStacks<PbrWithDistance>,
+}
+
+impl CompiledSdf {
+    pub fn new(graph: &Graph, node: NodeId) -> Result<Self, Error> {
+        Self::from_program(crate::compile(graph, node))
+    }
+
+    pub fn from_program(program: Program) -> Result<Self, Error> {
+        crate::check_interpretable::<f32>(&program)?;
+        Ok(Self {
+            program,
+            distance_stacks: InterpreterStacks::new(),
+            color_stacks: InterpreterStacks::new(),
+            sample_stacks: InterpreterStacks::new(),
+        })
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Contexts for evaluating the program from many threads, see [`InterpreterPool`].
+    pub fn pool<SD: SignedDistance + Copy + Clone>(&self) -> InterpreterPool<'_, SD> {
+        InterpreterPool::new(&self.program)
+    }
+
+    /// The signed distance to the surface at `pos`.
+    pub fn distance(&mut self, pos: Vec3) -> f32 {
+        self.distance_func()(pos)
+    }
+
+    /// The linear RGBA color of the surface nearest to `pos`.
+    pub fn color(&mut self, pos: Vec3) -> Vec4 {
+        let mut context = Interpreter::new_context_with_stacks(
+            &self.program.opcodes,
+            &self.program.constants,
+            &mut self.color_stacks,
+        );
+        interpret(&mut context, pos).material().rgba()
+    }
+
+    /// The signed distance to the surface at `pos`, and the material of the surface nearest to `pos`.
+    pub fn sample(&mut self, pos: Vec3) -> (f32, Material) {
+        let mut context = Interpreter::new_context_with_stacks(
+            &self.program.opcodes,
+            &self.program.constants,
+            &mut self.sample_stacks,
+        );
+        let sd = interpret(&mut context, pos);
+        (sd.distance, sd.material)
+    }
+
+    /// Evaluates the distance at many points, e.g. for a loop, with a single context.
+    fn distance_func(&mut self) -> impl FnMut(Vec3) -> f32 + '_ {
+        let mut context = Interpreter::new_context_with_stacks(
+            &self.program.opcodes,
+            &self.program.constants,
+            &mut self.distance_stacks,
+        );
+        move |pos| interpret(&mut context, pos)
+    }
+
+    /// The point on the surface closest to `pos`, found by stepping from `pos` along the gradient
+    /// by the distance, up to `iterations` times, see [`crate::closest_surface_point`].
+    pub fn closest_surface_point(&mut self, pos: Vec3, iterations: usize) -> Vec3 {
+        // Small enough to resolve sharp features near the surface, large enough for f32 positions away from the origin.
+        const MIN_GRADIENT_EPSILON: f32 = 1e-4;
+
+        let mut sd = self.distance_func();
+        let mut pos = pos;
+        for _ in 0..iterations {
+            let distance = sd(pos);
+            if distance == 0.0 {
+                break;
+            }
+            // Further away, the differences need to be larger than the rounding of the distances,
+            // and the field is smoother anyway.
+            let epsilon = (distance.abs() * 1e-2).max(MIN_GRADIENT_EPSILON);
+            let gradient = Vec3::new(
+                sd(pos + Vec3::X * epsilon) - sd(pos - Vec3::X * epsilon),
+                sd(pos + Vec3::Y * epsilon) - sd(pos - Vec3::Y * epsilon),
+                sd(pos + Vec3::Z * epsilon) - sd(pos - Vec3::Z * epsilon),
+            );
+            // E.g. the center of a sphere, where every direction is as close.
+            let Some(direction) = gradient.try_normalize() else {
+                break;
+            };
+            pos -= direction * distance;
+        }
+        pos
+    }
+
+    /// Like [`crate::march`], but without compiling the graph again.
+    pub fn march(
+        &mut self,
+        ray: Ray3,
+        t_range: std::ops::RangeInclusive<f32>,
+        opt: &Options,
+    ) -> ClosestHit {
+        let mut sd = self.distance_func();
+        crate::sphere_tracing::trace(&mut sd, ray, t_range, opt)
+    }
+}
+
+fn interpret<SD: SignedDistance + Default, const STACK_DEPTH: usize>(
+    context: &mut InterpreterContext<'_, SD, STACK_DEPTH, &mut InterpreterStacks<SD, STACK_DEPTH>>,
+    pos: Vec3,
+) -> SD {
+    // Can't fail, as `CompiledSdf::from_program` checked that the interpreter can run the program.
+    Interpreter::<SD, STACK_DEPTH>::interpret(context, pos).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_surface_distance_to() {
+        let mut graph = Graph::default();
+        let node = graph.example(&Default::default());
+        let mut sdf = CompiledSdf::new(&graph, node).unwrap();
+
+        for i in 0..1000 {
+            let t = i as f32;
+            let pos = Vec3::new((t * 0.37).sin(), (t * 0.11).cos(), (t * 0.73).sin()) * 2.0;
+            assert_eq!(
+                sdf.distance(pos),
+                crate::surface_distance_to(&graph, node, pos)
+            );
+        }
+
+        let ray = Ray3 {
+            origin: Vec3::new(0.0, 0.0, -10.0),
+            dir: Vec3::Z,
+        };
+        let opt = Options::default();
+        assert_eq!(
+            sdf.march(ray, 0.0..=100.0, &opt),
+            crate::march(&graph, node, ray, 0.0..=100.0, &opt)
+        );
+    }
+
+    #[test]
+    fn closest_surface_point() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::new(1.0, 2.0, 3.0), 1.0);
+        let mut sdf = CompiledSdf::new(&graph, sphere).unwrap();
+
+        for pos in [Vec3::new(4.0, 5.0, 6.0), Vec3::new(1.2, 2.1, 3.3)] {
+            let closest = sdf.closest_surface_point(pos, 10);
+            let expected = Vec3::new(1.0, 2.0, 3.0) + (pos - Vec3::new(1.0, 2.0, 3.0)).normalize();
+            assert!(closest.distance(expected) < 1e-4, "{pos}: {closest}");
+            assert!(sdf.distance(closest).abs() < 1e-5);
+        }
+
+        // Away from the corners, points snap straight onto the faces of a box.
+        let cube = graph.rounded_box(Vec3::ONE, 0.0);
+        let closest = crate::closest_surface_point(&graph, cube, Vec3::new(0.5, 3.0, -0.25), 10);
+        assert!(
+            closest.distance(Vec3::new(0.5, 1.0, -0.25)) < 1e-4,
+            "{closest}"
+        );
+
+        // Without any iterations, the point stays put.
+        assert_eq!(sdf.closest_surface_point(Vec3::ZERO, 0), Vec3::ZERO);
+    }
+}
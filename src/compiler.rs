@@ -3,13 +3,19 @@ use super::graph::Node;
 use super::graph::NodeId;
 use super::program::Program;
 use super::Material;
+use glam::Affine3A;
+use glam::Mat3;
 use glam::Quat;
 use glam::Vec2;
 use glam::Vec3;
 use glam::Vec4;
 use opensaft_sdf::Opcode;
+use std::collections::HashMap;
+use std::ops::Range;
 
-#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+/// New variants may be added without a major version bump, so matches need a wildcard arm.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Invalid program: {0}")]
     BadProgram(&'static str),
@@ -22,6 +28,57 @@ pub enum Error {
 
     #[error("NaN encountered in distance field")]
     EvaluatedToNaN,
+
+    #[error("Unsupported program format version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    /// The program needs a deeper stack than the interpreter has, see [`crate::Graph::op_union_balanced`].
+    #[error("Interpreter stack overflow")]
+    StackOverflow,
+
+    /// Infinite shapes like planes can't be meshed as they are, see [`crate::mesh_from_sdf_in_box`].
+    #[error("Infinite bounding box")]
+    InfiniteBoundingBox,
+
+    /// There is nothing to mesh, e.g. for an intersection of shapes that don't overlap.
+    #[error("Empty bounding box")]
+    EmptyBoundingBox,
+
+    /// A fixed resolution needs at least four grid points along each axis, see [`crate::MeshOptions::fixed`].
+    #[error("Too low resolution {0:?}")]
+    BadResolution([usize; 3]),
+
+    /// The axis weights must be positive and finite, see [`crate::MeshOptions::axis_weights`].
+    #[error("Bad axis weights")]
+    BadAxisWeights,
+
+    /// Reading or writing a program or graph failed, with the message of the underlying error.
+    #[error("Serialization failed: {0}")]
+    Serialization(String),
+}
+
+#[cfg(feature = "with_bincode")]
+impl From<Box<bincode::ErrorKind>> for Error {
+    fn from(err: Box<bincode::ErrorKind>) -> Self {
+        Self::Serialization(err.to_string())
+    }
+}
+
+#[cfg(feature = "with_json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serialization(err.to_string())
+    }
+}
+
+#[cfg(feature = "with_ron")]
+impl From<ron::error::SpannedError> for Error {
+    fn from(err: ron::error::SpannedError) -> Self {
+        Self::Serialization(err.to_string())
+    }
 }
 
 pub struct ConstantReader<'a> {
@@ -143,12 +200,63 @@ impl<'a> ConstantEditor<'a> {
     }
 }
 
-fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<NodeId>) {
-    assert!(!path.contains(&root), "Graph cannot contain cycles!");
+/// How far apart the samples of the warp field of a [`Node::Warp`] are, to estimate its gradient,
+/// relative to the size of the warped shape.
+const WARP_GRADIENT_EPSILON: f32 = 1e-3;
+
+/// [`WARP_GRADIENT_EPSILON`] scaled by the size of `child`, or of `warp` if the child is unbounded,
+/// so that the gradient is as accurate for tiny shapes as for huge ones.
+fn warp_gradient_epsilon(graph: &Graph, child: NodeId, warp: NodeId) -> f32 {
+    let size = [child, warp]
+        .into_iter()
+        .map(|node| graph.bounding_box(node))
+        .filter(|bb| bb.is_something() && bb.is_finite())
+        .map(|bb| bb.size().max_element())
+        .find(|size| *size > 0.0);
+    WARP_GRADIENT_EPSILON * size.unwrap_or(1.0)
+}
+
+/// Where the constants of each primitive node ended up in a [`Program`], see [`compile_with_constant_map`].
+#[derive(Clone, Debug, Default)]
+pub struct ConstantMap {
+    ranges: HashMap<NodeId, Vec<Range<usize>>>,
+}
+
+impl ConstantMap {
+    /// The ranges of [`Program::constants`] holding the constants of the primitive `node`,
+    /// one for each time it was compiled. Empty for other nodes.
+    ///
+    /// The constants are laid out as the interpreter reads them, see [`disassemble`].
+    pub fn ranges(&self, node: NodeId) -> &[Range<usize>] {
+        self.ranges.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    #[inline(never)]
+    fn record(&mut self, id: NodeId, node: &Node, constants: Range<usize>) {
+        if node.children().is_empty() && !matches!(node, Node::Graph { .. }) {
+            self.ranges.entry(id).or_default().push(constants);
+        }
+    }
+}
+
+/// State threaded through [`compile_node`].
+#[derive(Default)]
+struct CompileState {
+    /// The nodes being compiled, from the root down, to detect cycles.
+    path: Vec<NodeId>,
+    /// Only recorded when requested, and not for nested graphs, whose node ids are their own.
+    constant_map: Option<ConstantMap>,
+}
+
+/// Helpers like [`push_affine`] and [`ConstantMap::record`] are `#[inline(never)]` to keep the
+/// stack frame of this recursion small for deep graphs.
+fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, state: &mut CompileState) {
+    assert!(!state.path.contains(&root), "Graph cannot contain cycles!");
 
-    path.push(root);
+    state.path.push(root);
 
     let node = graph.get(root).unwrap();
+    let constants_start = ctx.constants.len();
 
     // Interpreter functions divides by the smoothing constant.
     // To prevent NaNs an Infs from ending up in the sdf we clamp
@@ -160,6 +268,13 @@ fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<N
             ctx.opcodes.push(Opcode::Plane);
             ctx.constant_push_vec4(*plane);
         }
+        Node::ConvexHull { planes } => {
+            ctx.opcodes.push(Opcode::ConvexHull);
+            ctx.constants.push(planes.len() as f32);
+            for plane in planes {
+                ctx.constant_push_vec4(*plane);
+            }
+        }
         Node::Sphere { center, radius } => {
             ctx.opcodes.push(Opcode::Sphere);
             ctx.constant_push_vec3(*center);
@@ -186,6 +301,29 @@ fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<N
             ctx.constants.push(*half_height);
             ctx.constants.push(*rounding_radius);
         }
+        Node::Tube {
+            outer_r,
+            inner_r,
+            half_height,
+        } => {
+            ctx.opcodes.push(Opcode::Tube);
+            ctx.constants.push(*outer_r);
+            ctx.constants.push(*inner_r);
+            ctx.constants.push(*half_height);
+        }
+        Node::Egg { radius, tip_radius } => {
+            ctx.opcodes.push(Opcode::Egg);
+            ctx.constants.push(*radius);
+            ctx.constants.push(*tip_radius);
+        }
+        Node::Heart {
+            size,
+            half_thickness,
+        } => {
+            ctx.opcodes.push(Opcode::Heart);
+            ctx.constants.push(*size);
+            ctx.constants.push(*half_thickness);
+        }
         Node::TaperedCapsule { points, radii } => {
             ctx.opcodes.push(Opcode::TaperedCapsule);
             ctx.constant_push_vec3(points[0]);
@@ -198,6 +336,12 @@ fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<N
             ctx.constants.push(*radius);
             ctx.constants.push(*height);
         }
+        Node::CappedCone { r1, r2, height } => {
+            ctx.opcodes.push(Opcode::CappedCone);
+            ctx.constants.push(*r1);
+            ctx.constants.push(*r2);
+            ctx.constants.push(*height);
+        }
         Node::RoundedBox {
             half_size,
             rounding_radius,
@@ -231,26 +375,82 @@ fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<N
             ctx.constants.push(*upper_sagitta);
             ctx.constants.push(*chord);
         }
+        Node::Vesica { radius, distance } => {
+            ctx.opcodes.push(Opcode::Vesica);
+            ctx.constants.push(*radius);
+            ctx.constants.push(*distance);
+        }
+        Node::CutSphere { radius, height } => {
+            ctx.opcodes.push(Opcode::CutSphere);
+            ctx.constants.push(*radius);
+            ctx.constants.push(*height);
+        }
+        Node::CutHollowSphere {
+            radius,
+            height,
+            thickness,
+        } => {
+            ctx.opcodes.push(Opcode::CutHollowSphere);
+            ctx.constants.push(*radius);
+            ctx.constants.push(*height);
+            ctx.constants.push(*thickness);
+        }
+        Node::Link {
+            length,
+            big_r,
+            small_r,
+        } => {
+            ctx.opcodes.push(Opcode::Link);
+            ctx.constants.push(*length);
+            ctx.constants.push(*big_r);
+            ctx.constants.push(*small_r);
+        }
+        Node::SolidAngle {
+            sin_cos_angle,
+            radius,
+        } => {
+            ctx.opcodes.push(Opcode::SolidAngle);
+            ctx.constant_push_vec2(<[f32; 2]>::from(*sin_cos_angle));
+            ctx.constants.push(*radius);
+        }
+        Node::Gyroid { scale, thickness } => {
+            ctx.opcodes.push(Opcode::Gyroid);
+            ctx.constants.push(*scale);
+            ctx.constants.push(*thickness);
+        }
+        Node::SchwarzP { scale, thickness } => {
+            ctx.opcodes.push(Opcode::SchwarzP);
+            ctx.constants.push(*scale);
+            ctx.constants.push(*thickness);
+        }
         Node::Material { child, material } => {
-            compile_node(graph, *child, ctx, path);
+            compile_node(graph, *child, ctx, state);
             ctx.opcodes.push(Opcode::Material);
-            ctx.constant_push_vec3(material.rgb());
+            ctx.constant_push_vec4(material.rgba());
+            ctx.constants.push(material.metallic());
+            ctx.constants.push(material.roughness());
         }
 
         Node::Union { lhs, rhs } => {
-            compile_node(graph, *lhs, ctx, path);
-            compile_node(graph, *rhs, ctx, path);
-            ctx.opcodes.push(Opcode::Union);
+            compile_node(graph, *lhs, ctx, state);
+            // Union is idempotent, so a shared subtree only needs to be evaluated once.
+            if lhs != rhs {
+                compile_node(graph, *rhs, ctx, state);
+                ctx.opcodes.push(Opcode::Union);
+            }
         }
         Node::UnionSmooth { lhs, rhs, size } => {
-            compile_node(graph, *lhs, ctx, path);
-            compile_node(graph, *rhs, ctx, path);
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
             ctx.opcodes.push(Opcode::UnionSmooth);
             ctx.constants.push(size.max(MIN_SMOOTHING));
         }
         Node::UnionMulti { children } => {
             for (idx, child) in children.iter().enumerate() {
-                compile_node(graph, *child, ctx, path);
+                if children[..idx].contains(child) {
+                    continue; // idempotent, see `Node::Union`
+                }
+                compile_node(graph, *child, ctx, state);
                 if idx > 0 {
                     ctx.opcodes.push(Opcode::Union);
                 }
@@ -258,7 +458,7 @@ fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<N
         }
         Node::UnionMultiSmooth { children, size } => {
             for (idx, child) in children.iter().enumerate() {
-                compile_node(graph, *child, ctx, path);
+                compile_node(graph, *child, ctx, state);
                 if idx > 0 {
                     ctx.opcodes.push(Opcode::UnionSmooth);
                     ctx.constants.push(size.max(MIN_SMOOTHING));
@@ -266,66 +466,235 @@ fn compile_node(graph: &Graph, root: NodeId, ctx: &mut Program, path: &mut Vec<N
             }
         }
         Node::Subtract { lhs, rhs } => {
-            compile_node(graph, *lhs, ctx, path);
-            compile_node(graph, *rhs, ctx, path);
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
             ctx.opcodes.push(Opcode::Subtract);
         }
         Node::SubtractSmooth { lhs, rhs, size } => {
-            compile_node(graph, *lhs, ctx, path);
-            compile_node(graph, *rhs, ctx, path);
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
             ctx.opcodes.push(Opcode::SubtractSmooth);
             ctx.constants.push(size.max(MIN_SMOOTHING));
         }
         Node::Intersect { lhs, rhs } => {
-            compile_node(graph, *lhs, ctx, path);
-            compile_node(graph, *rhs, ctx, path);
-            ctx.opcodes.push(Opcode::Intersect);
+            compile_node(graph, *lhs, ctx, state);
+            // Intersection is idempotent too.
+            if lhs != rhs {
+                compile_node(graph, *rhs, ctx, state);
+                ctx.opcodes.push(Opcode::Intersect);
+            }
         }
         Node::IntersectSmooth { lhs, rhs, size } => {
-            compile_node(graph, *lhs, ctx, path);
-            compile_node(graph, *rhs, ctx, path);
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
             ctx.opcodes.push(Opcode::IntersectSmooth);
             ctx.constants.push(size.max(MIN_SMOOTHING));
         }
 
+        Node::UnionChamfer { lhs, rhs, size } => {
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
+            ctx.opcodes.push(Opcode::UnionChamfer);
+            ctx.constants.push(*size);
+        }
+        Node::SubtractChamfer { lhs, rhs, size } => {
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
+            ctx.opcodes.push(Opcode::SubtractChamfer);
+            ctx.constants.push(*size);
+        }
+        Node::IntersectChamfer { lhs, rhs, size } => {
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
+            ctx.opcodes.push(Opcode::IntersectChamfer);
+            ctx.constants.push(*size);
+        }
+        Node::UnionStairs {
+            lhs,
+            rhs,
+            size,
+            steps,
+        } => {
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
+            ctx.opcodes.push(Opcode::UnionStairs);
+            ctx.constants.push(size.max(MIN_SMOOTHING));
+            ctx.constants.push((*steps).max(1) as f32);
+        }
+        Node::Pipe { lhs, rhs, radius } => {
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
+            ctx.opcodes.push(Opcode::Pipe);
+            ctx.constants.push(*radius);
+        }
+        Node::Groove {
+            lhs,
+            rhs,
+            depth,
+            half_width,
+        } => {
+            compile_node(graph, *lhs, ctx, state);
+            compile_node(graph, *rhs, ctx, state);
+            ctx.opcodes.push(Opcode::Groove);
+            ctx.constants.push(*depth);
+            ctx.constants.push(*half_width);
+        }
         Node::Translate { translation, child } => {
             ctx.opcodes.push(Opcode::PushTranslation);
             ctx.constant_push_vec3(-*translation);
-            compile_node(graph, *child, ctx, path);
+            compile_node(graph, *child, ctx, state);
             ctx.opcodes.push(Opcode::PopTransform);
         }
         Node::Rotate { rotation, child } => {
             ctx.opcodes.push(Opcode::PushRotation);
             ctx.constant_push_vec4(rotation.conjugate());
-            compile_node(graph, *child, ctx, path);
+            compile_node(graph, *child, ctx, state);
             ctx.opcodes.push(Opcode::PopTransform);
         }
         Node::Scale { scale, child } => {
+            // A negative scale mirrors the position, but distances are always positive.
             ctx.opcodes.push(Opcode::PushScale);
             ctx.constants.push(1.0 / *scale);
 
-            compile_node(graph, *child, ctx, path);
+            compile_node(graph, *child, ctx, state);
 
             ctx.opcodes.push(Opcode::PopScale);
-            ctx.constants.push(*scale);
+            ctx.constants.push(scale.abs());
+        }
+        Node::Transform { matrix, child } => {
+            let distance_scale = push_affine(ctx, matrix);
+            compile_node(graph, *child, ctx, state);
+            ctx.opcodes.push(Opcode::PopAffine);
+            ctx.constants.push(distance_scale);
+        }
+        Node::Warp {
+            child,
+            warp,
+            strength,
+        } => {
+            // The gradient of the warp field is estimated from samples around the position,
+            // running its code once per sample so nested warps don't grow the program exponentially.
+            let epsilon = warp_gradient_epsilon(graph, *child, *warp);
+            ctx.opcodes.push(Opcode::PushWarpSample);
+            ctx.constants.push(epsilon);
+            compile_node(graph, *warp, ctx, state);
+            ctx.opcodes.push(Opcode::PopWarpSample);
+
+            ctx.opcodes.push(Opcode::PushWarp);
+            ctx.constants.push(*strength);
+            ctx.constants.push(epsilon);
+            compile_node(graph, *child, ctx, state);
+            ctx.opcodes.push(Opcode::PopTransform);
+        }
+        Node::Revolve { offset, child } => {
+            ctx.opcodes.push(Opcode::PushRevolve);
+            ctx.constants.push(*offset);
+            compile_node(graph, *child, ctx, state);
+            ctx.opcodes.push(Opcode::PopTransform);
+        }
+        Node::Elongate { amount, child } => {
+            ctx.opcodes.push(Opcode::PushElongate);
+            ctx.constant_push_vec3(*amount);
+
+            compile_node(graph, *child, ctx, state);
+
+            // The distance correction needs the amount as well.
+            ctx.opcodes.push(Opcode::PopElongate);
+            ctx.constant_push_vec3(*amount);
+        }
+        Node::ClampDistance { child, factor } => {
+            compile_node(graph, *child, ctx, state);
+            ctx.opcodes.push(Opcode::ScaleDistance);
+            ctx.constants.push(*factor);
         }
         Node::Graph { root, graph } => {
-            compile_node(graph, *root, ctx, &mut Vec::new());
+            compile_node(graph, *root, ctx, &mut CompileState::default());
         }
     }
 
-    path.pop();
+    if let Some(constant_map) = &mut state.constant_map {
+        constant_map.record(root, node, constants_start..ctx.constants.len());
+    }
+
+    state.path.pop();
 }
 
+/// Compiles the graph reachable from `root` into a [`Program`].
+///
+/// # Panics
+///
+/// If the graph contains cycles or references missing nodes, see [`Graph::validate`].
 #[must_use]
 pub fn compile(graph: &Graph, root: NodeId) -> Program {
     let mut program = Program::default();
-    compile_node(graph, root, &mut program, &mut Vec::new());
+    compile_node(graph, root, &mut program, &mut CompileState::default());
     program.opcodes.push(Opcode::End);
 
     program
 }
 
+/// Like [`compile`], but also returns where the constants of each primitive ended up,
+/// so they can be changed without compiling again, e.g. for animation.
+/// See [`Program::set_node_constants`].
+#[must_use]
+pub fn compile_with_constant_map(graph: &Graph, root: NodeId) -> (Program, ConstantMap) {
+    let mut program = Program::default();
+    let mut state = CompileState {
+        constant_map: Some(ConstantMap::default()),
+        ..Default::default()
+    };
+    compile_node(graph, root, &mut program, &mut state);
+    program.opcodes.push(Opcode::End);
+
+    (program, state.constant_map.unwrap_or_default())
+}
+
+/// Pushes [`Opcode::PushAffine`] for `matrix`, and returns the constant of the matching [`Opcode::PopAffine`].
+#[inline(never)]
+fn push_affine(ctx: &mut Program, matrix: &Affine3A) -> f32 {
+    let inverse = matrix.inverse();
+    ctx.opcodes.push(Opcode::PushAffine);
+    ctx.constant_push_vec3(inverse.matrix3.x_axis.to_array());
+    ctx.constant_push_vec3(inverse.matrix3.y_axis.to_array());
+    ctx.constant_push_vec3(inverse.matrix3.z_axis.to_array());
+    ctx.constant_push_vec3(inverse.translation.to_array());
+
+    // The inverse stretches distances by at most its largest singular value,
+    // so dividing by that keeps the distance conservative.
+    1.0 / max_singular_value(inverse.matrix3.into())
+}
+
+/// The largest singular value of `m`, i.e. how much it can stretch a vector.
+fn max_singular_value(m: Mat3) -> f32 {
+    // The square root of the largest eigenvalue of the symmetric `mᵀm`,
+    // see https://en.wikipedia.org/wiki/Eigenvalue_algorithm#3%C3%973_matrices
+    let a = m.transpose() * m;
+    let p1 = a.x_axis.y.powi(2) + a.x_axis.z.powi(2) + a.y_axis.z.powi(2);
+    let q = (a.x_axis.x + a.y_axis.y + a.z_axis.z) / 3.0;
+    let p2 =
+        (a.x_axis.x - q).powi(2) + (a.y_axis.y - q).powi(2) + (a.z_axis.z - q).powi(2) + 2.0 * p1;
+    let p = (p2 / 6.0).sqrt();
+    let largest_eigenvalue = if p == 0.0 {
+        q
+    } else {
+        let b = (a - Mat3::from_diagonal(Vec3::splat(q))) * (1.0 / p);
+        let r = (b.determinant() / 2.0).clamp(-1.0, 1.0);
+        q + 2.0 * p * (r.acos() / 3.0).cos()
+    };
+    largest_eigenvalue.max(0.0).sqrt()
+}
+
+/// Reads the plane count and planes of [`Opcode::ConvexHull`].
+fn read_convex_hull_planes(constants: &mut ConstantReader<'_>) -> Result<Vec<Vec4>, Error> {
+    let num_planes = constants.read_f32()?;
+    if num_planes < 0.0 || num_planes.fract() != 0.0 {
+        return Err(Error::BadProgram("bad convex hull plane count"));
+    }
+    (0..num_planes as usize)
+        .map(|_| constants.read_vec4())
+        .collect()
+}
+
 pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId), Error> {
     let mut graph = Graph::default();
     let mut stack = vec![];
@@ -335,12 +704,29 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
     enum Transform {
         Translation(Vec3),
         Rotation(Quat),
+        Revolve(f32),
+        Warp {
+            warp: NodeId,
+            strength: f32,
+        },
+        /// Popped by `PopWarpSample` instead.
+        WarpSample,
     }
 
     let mut transform_stack: Vec<Transform> = vec![];
+    // The sign of the scale is only in the `PushScale` constant.
+    let mut inv_scale_stack: Vec<f32> = vec![];
+    let mut affine_stack: Vec<Affine3A> = vec![];
+    let mut elongate_stack: Vec<Vec3> = vec![];
+    // The warp fields of `PushWarpSample` loops, for the `PushWarp` right after them.
+    let mut warp_field_stack: Vec<NodeId> = vec![];
 
-    for opcode in &program.opcodes {
+    for (opcode_index, opcode) in program.opcodes.iter().enumerate() {
         match opcode {
+            Opcode::ConvexHull => {
+                let planes = read_convex_hull_planes(&mut constants)?;
+                stack.push(graph.convex_hull(planes));
+            }
             Opcode::Sphere => {
                 let center = constants.read_vec3()?;
                 let radius = constants.read_f32()?;
@@ -373,6 +759,44 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
                 let lhs = stack.pop().ok_or(Error::BadStack)?;
                 stack.push(graph.op_subtract(lhs, rhs));
             }
+            Opcode::UnionChamfer => {
+                let rhs = stack.pop().ok_or(Error::BadStack)?;
+                let lhs = stack.pop().ok_or(Error::BadStack)?;
+                let size = constants.read_f32()?;
+                stack.push(graph.op_union_chamfer(lhs, rhs, size));
+            }
+            Opcode::SubtractChamfer => {
+                let rhs = stack.pop().ok_or(Error::BadStack)?;
+                let lhs = stack.pop().ok_or(Error::BadStack)?;
+                let size = constants.read_f32()?;
+                stack.push(graph.op_subtract_chamfer(lhs, rhs, size));
+            }
+            Opcode::IntersectChamfer => {
+                let rhs = stack.pop().ok_or(Error::BadStack)?;
+                let lhs = stack.pop().ok_or(Error::BadStack)?;
+                let size = constants.read_f32()?;
+                stack.push(graph.op_intersect_chamfer(lhs, rhs, size));
+            }
+            Opcode::UnionStairs => {
+                let rhs = stack.pop().ok_or(Error::BadStack)?;
+                let lhs = stack.pop().ok_or(Error::BadStack)?;
+                let size = constants.read_f32()?;
+                let steps = constants.read_f32()? as u32;
+                stack.push(graph.op_union_stairs(lhs, rhs, size, steps));
+            }
+            Opcode::Pipe => {
+                let rhs = stack.pop().ok_or(Error::BadStack)?;
+                let lhs = stack.pop().ok_or(Error::BadStack)?;
+                let radius = constants.read_f32()?;
+                stack.push(graph.op_pipe(lhs, rhs, radius));
+            }
+            Opcode::Groove => {
+                let rhs = stack.pop().ok_or(Error::BadStack)?;
+                let lhs = stack.pop().ok_or(Error::BadStack)?;
+                let depth = constants.read_f32()?;
+                let half_width = constants.read_f32()?;
+                stack.push(graph.op_groove(lhs, rhs, depth, half_width));
+            }
             Opcode::SubtractSmooth => {
                 let rhs = stack.pop().ok_or(Error::BadStack)?;
                 let lhs = stack.pop().ok_or(Error::BadStack)?;
@@ -390,23 +814,54 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
                 let rounding_radius = constants.read_f32()?;
                 stack.push(graph.rounded_cylinder(cylinder_radius, half_height, rounding_radius));
             }
+            Opcode::Tube => {
+                let outer_r = constants.read_f32()?;
+                let inner_r = constants.read_f32()?;
+                let half_height = constants.read_f32()?;
+                stack.push(graph.tube(outer_r, inner_r, half_height));
+            }
+            Opcode::Egg => {
+                let radius = constants.read_f32()?;
+                let tip_radius = constants.read_f32()?;
+                stack.push(graph.egg(radius, tip_radius));
+            }
+            Opcode::Heart => {
+                let size = constants.read_f32()?;
+                let half_thickness = constants.read_f32()?;
+                stack.push(graph.heart(size, half_thickness));
+            }
             Opcode::Cone => {
                 let radius = constants.read_f32()?;
                 let height = constants.read_f32()?;
                 stack.push(graph.cone(radius, height));
             }
+            Opcode::CappedCone => {
+                let r1 = constants.read_f32()?;
+                let r2 = constants.read_f32()?;
+                let height = constants.read_f32()?;
+                stack.push(graph.capped_cone(r1, r2, height));
+            }
+            // The constructors of some nodes adjust their inputs, so we create those nodes
+            // directly to make sure we get back exactly what was compiled.
             Opcode::TaperedCapsule => {
                 let point0 = constants.read_vec3()?;
                 let radius0 = constants.read_f32()?;
                 let point1 = constants.read_vec3()?;
                 let radius1 = constants.read_f32()?;
-                stack.push(graph.tapered_capsule([point0, point1], [radius0, radius1]));
+                stack.push(graph.create_node(Node::TaperedCapsule {
+                    points: [point0, point1],
+                    radii: [radius0, radius1],
+                }));
             }
             Opcode::BiconvexLens => {
                 let lower_sagitta = constants.read_f32()?;
                 let upper_sagitta = constants.read_f32()?;
                 let chord = constants.read_f32()?;
-                stack.push(graph.biconvex_lens(lower_sagitta, upper_sagitta, chord));
+                stack.push(graph.create_node(Node::BiconvexLens {
+                    lower_sagitta,
+                    upper_sagitta,
+                    chord,
+                }));
             }
             Opcode::Capsule => {
                 let point0 = constants.read_vec3()?;
@@ -422,8 +877,56 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
             Opcode::TorusSector => {
                 let big_r = constants.read_f32()?;
                 let small_r = constants.read_f32()?;
-                let half_angle = constants.read_f32()?;
-                stack.push(graph.torus_sector(big_r, small_r, half_angle));
+                let sin_cos_half_angle = constants.read_vec2()?;
+                stack.push(graph.create_node(Node::TorusSector {
+                    big_r,
+                    small_r,
+                    sin_cos_half_angle: sin_cos_half_angle.into(),
+                }));
+            }
+            Opcode::Vesica => {
+                let radius = constants.read_f32()?;
+                let distance = constants.read_f32()?;
+                stack.push(graph.vesica(radius, distance));
+            }
+            Opcode::CutSphere => {
+                let radius = constants.read_f32()?;
+                let height = constants.read_f32()?;
+                stack.push(graph.create_node(Node::CutSphere { radius, height }));
+            }
+            Opcode::CutHollowSphere => {
+                let radius = constants.read_f32()?;
+                let height = constants.read_f32()?;
+                let thickness = constants.read_f32()?;
+                stack.push(graph.create_node(Node::CutHollowSphere {
+                    radius,
+                    height,
+                    thickness,
+                }));
+            }
+            Opcode::Link => {
+                let length = constants.read_f32()?;
+                let big_r = constants.read_f32()?;
+                let small_r = constants.read_f32()?;
+                stack.push(graph.link(length, big_r, small_r));
+            }
+            Opcode::SolidAngle => {
+                let sin_cos_angle = constants.read_vec2()?;
+                let radius = constants.read_f32()?;
+                stack.push(graph.create_node(Node::SolidAngle {
+                    sin_cos_angle: sin_cos_angle.into(),
+                    radius,
+                }));
+            }
+            Opcode::Gyroid => {
+                let scale = constants.read_f32()?;
+                let thickness = constants.read_f32()?;
+                stack.push(graph.gyroid(scale, thickness));
+            }
+            Opcode::SchwarzP => {
+                let scale = constants.read_f32()?;
+                let thickness = constants.read_f32()?;
+                stack.push(graph.schwarz_p(scale, thickness));
             }
             Opcode::Plane => {
                 let plane = constants.read_vec4()?;
@@ -431,7 +934,11 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
             }
             Opcode::Material => {
                 let child = stack.pop().ok_or(Error::BadStack)?;
-                let material = Material::from(constants.read_vec3()?);
+                let rgba = constants.read_vec4()?;
+                let metallic = constants.read_f32()?;
+                let roughness = constants.read_f32()?;
+                let material =
+                    Material::new_rgba(rgba).with_metallic_roughness(metallic, roughness);
                 stack.push(graph.op_material(child, material));
             }
             Opcode::End => {
@@ -439,13 +946,46 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
                 break;
             }
             Opcode::PushScale => {
-                constants.skip(1);
+                inv_scale_stack.push(constants.read_f32()?);
             }
             Opcode::PopScale => {
                 let child = stack.pop().ok_or(Error::BadStack)?;
-                let scale = constants.read_f32()?;
+                let inv_scale = inv_scale_stack.pop().ok_or(Error::BadStack)?;
+                let scale = constants.read_f32()?.copysign(inv_scale);
                 stack.push(graph.op_scale(child, scale));
             }
+            Opcode::PushAffine => {
+                let x_axis = constants.read_vec3()?;
+                let y_axis = constants.read_vec3()?;
+                let z_axis = constants.read_vec3()?;
+                let translation = constants.read_vec3()?;
+                let inverse = Affine3A::from_cols(
+                    x_axis.into(),
+                    y_axis.into(),
+                    z_axis.into(),
+                    translation.into(),
+                );
+                affine_stack.push(inverse.inverse());
+            }
+            Opcode::PopAffine => {
+                let child = stack.pop().ok_or(Error::BadStack)?;
+                let matrix = affine_stack.pop().ok_or(Error::BadStack)?;
+                constants.skip(1);
+                stack.push(graph.op_affine(child, matrix));
+            }
+            Opcode::PushElongate => {
+                elongate_stack.push(constants.read_vec3()?);
+            }
+            Opcode::PopElongate => {
+                let child = stack.pop().ok_or(Error::BadStack)?;
+                let amount = elongate_stack.pop().ok_or(Error::BadStack)?;
+                constants.skip(3);
+                stack.push(graph.op_elongate(child, amount));
+            }
+            Opcode::ScaleDistance => {
+                let child = stack.pop().ok_or(Error::BadStack)?;
+                stack.push(graph.op_clamp_distance(child, constants.read_f32()?));
+            }
             Opcode::PushTranslation => {
                 let translation = constants.read_vec3()?;
                 transform_stack.push(Transform::Translation(-translation));
@@ -454,6 +994,29 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
                 let rotation = constants.read_quat()?;
                 transform_stack.push(Transform::Rotation(rotation.conjugate()));
             }
+            Opcode::PushRevolve => {
+                transform_stack.push(Transform::Revolve(constants.read_f32()?));
+            }
+            Opcode::PushWarp => {
+                let strength = constants.read_f32()?;
+                constants.skip(1);
+                if opcode_index == 0 || program.opcodes[opcode_index - 1] != Opcode::PopWarpSample {
+                    return Err(Error::BadProgram("PushWarp must follow its warp field"));
+                }
+                let warp = warp_field_stack.pop().ok_or(Error::BadStack)?;
+                transform_stack.push(Transform::Warp { warp, strength });
+            }
+            Opcode::PushWarpSample => {
+                constants.skip(1);
+                transform_stack.push(Transform::WarpSample);
+            }
+            Opcode::PopWarpSample => {
+                let warp = stack.pop().ok_or(Error::BadStack)?;
+                match transform_stack.pop() {
+                    Some(Transform::WarpSample) => warp_field_stack.push(warp),
+                    _ => return Err(Error::BadStack),
+                }
+            }
             Opcode::PopTransform => {
                 let child = stack.pop().ok_or(Error::BadStack)?;
                 match transform_stack.pop().ok_or(Error::BadStack)? {
@@ -463,6 +1026,13 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
                     Transform::Rotation(rotation) => {
                         stack.push(graph.op_rotate(child, rotation));
                     }
+                    Transform::Revolve(offset) => {
+                        stack.push(graph.op_revolve(child, offset));
+                    }
+                    Transform::Warp { warp, strength } => {
+                        stack.push(graph.op_warp(child, warp, strength));
+                    }
+                    Transform::WarpSample => return Err(Error::BadStack),
                 }
             }
         }
@@ -480,7 +1050,11 @@ pub fn decompile(program: &Program, constants: &[f32]) -> Result<(Graph, NodeId)
     if !constants.at_end() {
         return Err(Error::BadProgram("Unused constants"));
     }
-    Ok((graph, stack.pop().unwrap()))
+    if !warp_field_stack.is_empty() {
+        return Err(Error::BadStack);
+    }
+    let root = stack.pop().unwrap();
+    Ok((graph, root))
 }
 
 pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Error> {
@@ -492,6 +1066,10 @@ pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Erro
     // TODO: Show the constants, too.
     for opcode in opcodes {
         match opcode {
+            Opcode::ConvexHull => {
+                let planes = read_convex_hull_planes(&mut constants)?;
+                let _ = writeln!(&mut s, "ConvexHull {:?}", planes);
+            }
             Opcode::Sphere => {
                 let center = constants.read_vec3()?;
                 let radius = constants.read_f32()?;
@@ -514,6 +1092,32 @@ pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Erro
             Opcode::Subtract => {
                 s.push_str("Subtract\n");
             }
+            Opcode::UnionChamfer => {
+                let size = constants.read_f32()?;
+                let _ = writeln!(&mut s, "UnionChamfer {}", size);
+            }
+            Opcode::SubtractChamfer => {
+                let size = constants.read_f32()?;
+                let _ = writeln!(&mut s, "SubtractChamfer {}", size);
+            }
+            Opcode::IntersectChamfer => {
+                let size = constants.read_f32()?;
+                let _ = writeln!(&mut s, "IntersectChamfer {}", size);
+            }
+            Opcode::UnionStairs => {
+                let size = constants.read_f32()?;
+                let steps = constants.read_f32()?;
+                let _ = writeln!(&mut s, "UnionStairs {} {}", size, steps);
+            }
+            Opcode::Pipe => {
+                let radius = constants.read_f32()?;
+                let _ = writeln!(&mut s, "Pipe {}", radius);
+            }
+            Opcode::Groove => {
+                let depth = constants.read_f32()?;
+                let half_width = constants.read_f32()?;
+                let _ = writeln!(&mut s, "Groove {} {}", depth, half_width);
+            }
             Opcode::SubtractSmooth => {
                 let smooth_size = constants.read_f32()?;
                 let _ = writeln!(&mut s, "SubtractSmooth {}", smooth_size);
@@ -533,11 +1137,41 @@ pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Erro
                     cylinder_radius, half_height, rounding_radius
                 );
             }
+            Opcode::Tube => {
+                let outer_r = constants.read_f32()?;
+                let inner_r = constants.read_f32()?;
+                let half_height = constants.read_f32()?;
+                let _ = writeln!(
+                    &mut s,
+                    "Tube outer_r={} inner_r={} h={}",
+                    outer_r, inner_r, half_height
+                );
+            }
+            Opcode::Egg => {
+                let radius = constants.read_f32()?;
+                let tip_radius = constants.read_f32()?;
+                let _ = writeln!(&mut s, "Egg r={} tip_r={}", radius, tip_radius);
+            }
+            Opcode::Heart => {
+                let size = constants.read_f32()?;
+                let half_thickness = constants.read_f32()?;
+                let _ = writeln!(
+                    &mut s,
+                    "Heart size={} half_thickness={}",
+                    size, half_thickness
+                );
+            }
             Opcode::Cone => {
                 let radius = constants.read_f32()?;
                 let height = constants.read_f32()?;
                 let _ = writeln!(&mut s, "Cone r={} h={}", radius, height);
             }
+            Opcode::CappedCone => {
+                let r1 = constants.read_f32()?;
+                let r2 = constants.read_f32()?;
+                let height = constants.read_f32()?;
+                let _ = writeln!(&mut s, "CappedCone r1={} r2={} h={}", r1, r2, height);
+            }
             Opcode::TaperedCapsule => {
                 let point0 = constants.read_vec3()?;
                 let radius0 = constants.read_f32()?;
@@ -573,20 +1207,75 @@ pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Erro
             Opcode::TorusSector => {
                 let big_r = constants.read_f32()?;
                 let small_r = constants.read_f32()?;
-                let half_angle = constants.read_f32()?;
+                let sin_cos_half_angle = constants.read_vec2()?;
+                let _ = writeln!(
+                    &mut s,
+                    "TorusSector big_r={} small_R={} sin_cos_half_angle={}",
+                    big_r, small_r, sin_cos_half_angle
+                );
+            }
+            Opcode::Vesica => {
+                let radius = constants.read_f32()?;
+                let distance = constants.read_f32()?;
+                let _ = writeln!(&mut s, "Vesica r={} d={}", radius, distance);
+            }
+            Opcode::CutSphere => {
+                let radius = constants.read_f32()?;
+                let height = constants.read_f32()?;
+                let _ = writeln!(&mut s, "CutSphere r={} h={}", radius, height);
+            }
+            Opcode::CutHollowSphere => {
+                let radius = constants.read_f32()?;
+                let height = constants.read_f32()?;
+                let thickness = constants.read_f32()?;
+                let _ = writeln!(
+                    &mut s,
+                    "CutHollowSphere r={} h={} thickness={}",
+                    radius, height, thickness
+                );
+            }
+            Opcode::Link => {
+                let length = constants.read_f32()?;
+                let big_r = constants.read_f32()?;
+                let small_r = constants.read_f32()?;
+                let _ = writeln!(
+                    &mut s,
+                    "Link length={} big_r={} small_R={}",
+                    length, big_r, small_r
+                );
+            }
+            Opcode::SolidAngle => {
+                let sin_cos_angle = constants.read_vec2()?;
+                let radius = constants.read_f32()?;
                 let _ = writeln!(
                     &mut s,
-                    "TorusSector big_r={} small_R={} half_angle={}",
-                    big_r, small_r, half_angle
+                    "SolidAngle sin_cos_angle={} r={}",
+                    sin_cos_angle, radius
                 );
             }
+            Opcode::Gyroid => {
+                let scale = constants.read_f32()?;
+                let thickness = constants.read_f32()?;
+                let _ = writeln!(&mut s, "Gyroid scale={} thickness={}", scale, thickness);
+            }
+            Opcode::SchwarzP => {
+                let scale = constants.read_f32()?;
+                let thickness = constants.read_f32()?;
+                let _ = writeln!(&mut s, "SchwarzP scale={} thickness={}", scale, thickness);
+            }
             Opcode::Plane => {
                 let plane = constants.read_vec4()?;
                 let _ = writeln!(&mut s, "Plane: {}", plane);
             }
             Opcode::Material => {
-                let material = constants.read_vec3()?;
-                let _ = writeln!(&mut s, "Material: {}", material);
+                let rgba = constants.read_vec4()?;
+                let metallic = constants.read_f32()?;
+                let roughness = constants.read_f32()?;
+                let _ = writeln!(
+                    &mut s,
+                    "Material: {} metallic: {} roughness: {}",
+                    rgba, metallic, roughness
+                );
             }
             Opcode::End => {
                 s.push_str("End\n");
@@ -607,9 +1296,52 @@ pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Erro
                 let rotation = constants.read_quat()?;
                 let _ = writeln!(&mut s, "PushRotation: {}", rotation);
             }
+            Opcode::PushRevolve => {
+                let offset = constants.read_f32()?;
+                let _ = writeln!(&mut s, "PushRevolve: {}", offset);
+            }
+            Opcode::PushWarp => {
+                let strength = constants.read_f32()?;
+                let epsilon = constants.read_f32()?;
+                let _ = writeln!(&mut s, "PushWarp: {} (epsilon {})", strength, epsilon);
+            }
+            Opcode::PushWarpSample => {
+                let epsilon = constants.read_f32()?;
+                let _ = writeln!(&mut s, "PushWarpSample: {}", epsilon);
+            }
+            Opcode::PopWarpSample => {
+                s.push_str("PopWarpSample\n");
+            }
             Opcode::PopTransform => {
                 s.push_str("PopTransform\n");
             }
+            Opcode::PushAffine => {
+                let x_axis = constants.read_vec3()?;
+                let y_axis = constants.read_vec3()?;
+                let z_axis = constants.read_vec3()?;
+                let translation = constants.read_vec3()?;
+                let _ = writeln!(
+                    &mut s,
+                    "PushAffine: x_axis={} y_axis={} z_axis={} translation={}",
+                    x_axis, y_axis, z_axis, translation
+                );
+            }
+            Opcode::PopAffine => {
+                let distance_scale = constants.read_f32()?;
+                let _ = writeln!(&mut s, "PopAffine: {}", distance_scale);
+            }
+            Opcode::PushElongate => {
+                let amount = constants.read_vec3()?;
+                let _ = writeln!(&mut s, "PushElongate: {}", amount);
+            }
+            Opcode::PopElongate => {
+                let amount = constants.read_vec3()?;
+                let _ = writeln!(&mut s, "PopElongate: {}", amount);
+            }
+            Opcode::ScaleDistance => {
+                let factor = constants.read_f32()?;
+                let _ = writeln!(&mut s, "ScaleDistance: {}", factor);
+            }
         }
     }
 
@@ -620,6 +1352,7 @@ pub fn disassemble(opcodes: &[Opcode], constants: &[f32]) -> Result<String, Erro
 #[cfg(test)]
 mod tests {
     use super::*;
+    use opensaft_sdf::Interpreter;
 
     #[test]
     fn roundtrip() {
@@ -651,4 +1384,221 @@ mod tests {
         assert!(program.opcodes == recomp_program.opcodes);
         assert!(program.constants == recomp_program.constants);
     }
+
+    /// Keep the values exactly representable-ish and free of NaNs, so that constants compare equal.
+    #[cfg(feature = "with_arbitrary")]
+    fn arbitrary_float(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<f32> {
+        Ok(u.int_in_range(1..=1000)? as f32 / 100.0)
+    }
+
+    #[cfg(feature = "with_arbitrary")]
+    fn arbitrary_vec3(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<Vec3> {
+        let float = arbitrary_float;
+        Ok(Vec3::new(float(u)?, -float(u)?, float(u)?))
+    }
+
+    #[cfg(feature = "with_arbitrary")]
+    const NUM_ARBITRARY_PRIMITIVES: u32 = 22;
+
+    /// Builds one of the [`NUM_ARBITRARY_PRIMITIVES`] primitive nodes.
+    #[cfg(feature = "with_arbitrary")]
+    fn arbitrary_primitive(
+        u: &mut arbitrary::Unstructured<'_>,
+        graph: &mut Graph,
+        choice: u32,
+    ) -> arbitrary::Result<NodeId> {
+        let float = arbitrary_float;
+        let vec3 = arbitrary_vec3;
+
+        let node = match choice {
+            0 => graph.plane(Vec4::new(0.0, 1.0, 0.0, float(u)?)),
+            1 => graph.sphere(vec3(u)?, float(u)?),
+            2 => graph.capsule([vec3(u)?, vec3(u)?], float(u)?),
+            3 => graph.rounded_cylinder(float(u)?, float(u)?, float(u)?),
+            4 => graph.create_node(Node::TaperedCapsule {
+                points: [vec3(u)?, vec3(u)?],
+                radii: [float(u)?, float(u)?],
+            }),
+            5 => graph.cone(float(u)?, float(u)?),
+            6 => graph.rounded_box(vec3(u)?, float(u)?),
+            7 => graph.torus(float(u)?, float(u)?),
+            8 => graph.torus_sector(float(u)?, float(u)?, float(u)?),
+            9 => graph.biconvex_lens(float(u)?, float(u)?, float(u)?),
+            10 => graph.gyroid(float(u)?, float(u)?),
+            11 => graph.schwarz_p(float(u)?, float(u)?),
+            12 => graph.solid_angle(float(u)?, float(u)?),
+            13 => graph.link(float(u)?, float(u)?, float(u)?),
+            14 => graph.cut_sphere(float(u)?, float(u)?),
+            15 => graph.cut_hollow_sphere(float(u)?, float(u)?, float(u)?),
+            16 => {
+                let num_planes = u.int_in_range(1..=6)?;
+                let planes = (0..num_planes)
+                    .map(|_| Ok(vec3(u)?.normalize_or(Vec3::Y).extend(float(u)?)))
+                    .collect::<arbitrary::Result<_>>()?;
+                graph.convex_hull(planes)
+            }
+            17 => graph.tube(float(u)?, float(u)?, float(u)?),
+            18 => graph.egg(float(u)?, float(u)?),
+            19 => graph.heart(float(u)?, float(u)?),
+            20 => graph.capped_cone(float(u)?, float(u)?, float(u)?),
+            _ => graph.vesica(float(u)?, float(u)?),
+        };
+        Ok(node)
+    }
+
+    /// Builds a random, but valid, graph using every kind of node the compiler can emit opcodes for.
+    #[cfg(feature = "with_arbitrary")]
+    fn arbitrary_node(
+        u: &mut arbitrary::Unstructured<'_>,
+        graph: &mut Graph,
+        depth: u32,
+    ) -> arbitrary::Result<NodeId> {
+        let float = arbitrary_float;
+        let vec3 = arbitrary_vec3;
+
+        let choice = if depth == 0 {
+            u.int_in_range(0..=NUM_ARBITRARY_PRIMITIVES - 1)?
+        } else {
+            u.int_in_range(0..=NUM_ARBITRARY_PRIMITIVES + 16)?
+        };
+        if choice < NUM_ARBITRARY_PRIMITIVES {
+            return arbitrary_primitive(u, graph, choice);
+        }
+
+        let choice = choice - NUM_ARBITRARY_PRIMITIVES;
+        let node = match choice {
+            0 => {
+                let child = arbitrary_node(u, graph, depth - 1)?;
+                graph.op_rgb(child, vec3(u)?)
+            }
+            1..=6 => {
+                let lhs = arbitrary_node(u, graph, depth - 1)?;
+                let rhs = arbitrary_node(u, graph, depth - 1)?;
+                let size = float(u)?;
+                match choice {
+                    1 => graph.op_union(lhs, rhs),
+                    2 => graph.op_union_smooth(lhs, rhs, size),
+                    3 => graph.op_subtract(lhs, rhs),
+                    4 => graph.op_subtract_smooth(lhs, rhs, size),
+                    5 => graph.op_intersect(lhs, rhs),
+                    _ => graph.op_intersect_smooth(lhs, rhs, size),
+                }
+            }
+            7 | 8 => {
+                let children = (0..u.int_in_range(1..=4)?)
+                    .map(|_| arbitrary_node(u, graph, depth - 1))
+                    .collect::<arbitrary::Result<Vec<_>>>()?;
+                if choice == 7 {
+                    graph.op_union_multi(children)
+                } else {
+                    graph.op_union_multi_smooth(children, float(u)?)
+                }
+            }
+            9 => {
+                let child = arbitrary_node(u, graph, depth - 1)?;
+                graph.op_translate(child, vec3(u)?)
+            }
+            10 => {
+                let child = arbitrary_node(u, graph, depth - 1)?;
+                graph.op_rotate(child, Quat::from_axis_angle(Vec3::Y, float(u)?))
+            }
+            11 => {
+                let child = arbitrary_node(u, graph, depth - 1)?;
+                match u.int_in_range(0..=3)? {
+                    0 => graph.op_revolve(child, float(u)?),
+                    1 => {
+                        let warp = arbitrary_node(u, graph, depth - 1)?;
+                        graph.op_warp(child, warp, float(u)?)
+                    }
+                    2 => graph.op_elongate(child, vec3(u)?.abs()),
+                    _ => graph.op_clamp_distance(child, float(u)? / 10.0),
+                }
+            }
+            12..=15 => {
+                let lhs = arbitrary_node(u, graph, depth - 1)?;
+                let rhs = arbitrary_node(u, graph, depth - 1)?;
+                let size = float(u)?;
+                match choice {
+                    12 => graph.op_union_stairs(lhs, rhs, size, u.int_in_range(1..=8)?),
+                    13 => graph.op_pipe(lhs, rhs, size),
+                    14 => graph.op_groove(lhs, rhs, size, float(u)?),
+                    _ => {
+                        use crate::CsgOp;
+                        let op = *u.choose(&[CsgOp::Union, CsgOp::Subtract, CsgOp::Intersect])?;
+                        graph.op_csg_chamfer(lhs, op, rhs, size)
+                    }
+                }
+            }
+            _ => {
+                let child = arbitrary_node(u, graph, depth - 1)?;
+                let scale = float(u)?;
+                let scale = if u.arbitrary()? { -scale } else { scale };
+                graph.op_scale(child, scale)
+            }
+        };
+        Ok(node)
+    }
+
+    #[cfg(feature = "with_arbitrary")]
+    #[test]
+    fn roundtrip_arbitrary() {
+        // A small xorshift to feed `arbitrary`, so the test is deterministic.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let bytes: Vec<u8> = (0..1 << 16)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut u = arbitrary::Unstructured::new(&bytes);
+
+        for _ in 0..200 {
+            let mut graph = Graph::default();
+            let Ok(root) = arbitrary_node(&mut u, &mut graph, 4) else {
+                break; // out of entropy
+            };
+            let program = compile(&graph, root);
+
+            let (decomp_graph, decomp_root) = decompile(&program, &program.constants).unwrap();
+            let recomp_program = compile(&decomp_graph, decomp_root);
+
+            assert_eq!(program.opcodes, recomp_program.opcodes);
+            assert_eq!(program.constants, recomp_program.constants);
+        }
+    }
+
+    #[test]
+    fn deduplicate_shared_subtrees() {
+        let mut graph = Graph::default();
+        let mut children = vec![];
+        for _ in 0..4 {
+            let sphere = graph.sphere(Vec3::ZERO, 1.0);
+            let sphere = graph.op_translate(sphere, Vec3::X);
+            children.push(sphere);
+        }
+        let capsule = graph.capsule_y(1.0, 0.5);
+        children.push(capsule);
+        let root = graph.op_union_multi(children);
+
+        let program = compile(&graph, root);
+
+        let remap = graph.deduplicate();
+        let root = remap.get(&root).copied().unwrap_or(root);
+        assert_eq!(graph.nodes().count(), 4);
+
+        let dedup_program = compile(&graph, root);
+        assert!(dedup_program.opcodes.len() < program.opcodes.len());
+
+        let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+        let mut dedup_context =
+            Interpreter::new_context(&dedup_program.opcodes, &dedup_program.constants);
+        for pos in [Vec3::ZERO, Vec3::X, Vec3::new(2.0, 1.0, -1.0)] {
+            assert_eq!(
+                Interpreter::<f32>::interpret(&mut context, pos),
+                Interpreter::<f32>::interpret(&mut dedup_context, pos)
+            );
+        }
+    }
 }
@@ -1,5 +1,6 @@
 use super::Material;
 use crate::math::BoundingBox;
+use glam::Affine3A;
 use glam::Quat;
 use glam::Vec3;
 use glam::Vec4;
@@ -9,10 +10,81 @@ use std::hash::Hash;
 /// A high-level definition of a signed distance field function
 #[derive(Clone, Debug, Default)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "with_serde", serde(from = "sorted_nodes::SerializedGraph"))]
 #[cfg_attr(feature = "with_speedy", derive(speedy::Writable, speedy::Readable))]
 pub struct Graph {
     id_allocator: u32,
-    nodes: HashMap<NodeId, Node>, // TODO (nummelin): This should really be a Vec?
+    #[cfg_attr(feature = "with_serde", serde(with = "sorted_nodes"))]
+    /// Indexed by [`NodeId`], with `None` for removed nodes.
+    nodes: Vec<Option<Node>>,
+}
+
+/// Serializes the nodes as a list of `(id, node)` sorted by [`NodeId`], skipping removed nodes.
+#[cfg(feature = "with_serde")]
+mod sorted_nodes {
+    use super::Graph;
+    use super::Node;
+    use super::NodeId;
+    use serde::de::Error as _;
+    use serde::Deserialize;
+    use serde::Serialize;
+
+    /// The ids of removed nodes leave gaps, but at most this many slots per node, or this many in total,
+    /// so a file with huge ids can't make us allocate all of memory.
+    const MAX_SLOTS_PER_NODE: usize = 16;
+    const MIN_MAX_SLOTS: usize = 1 << 16;
+
+    /// What a [`Graph`] deserializes from, before making sure new ids don't overwrite the loaded nodes.
+    #[derive(Deserialize)]
+    pub struct SerializedGraph {
+        id_allocator: u32,
+        #[serde(with = "self")]
+        nodes: Vec<Option<Node>>,
+    }
+
+    impl From<SerializedGraph> for Graph {
+        fn from(graph: SerializedGraph) -> Self {
+            Self {
+                id_allocator: graph.id_allocator.max(graph.nodes.len() as u32),
+                nodes: graph.nodes,
+            }
+        }
+    }
+
+    pub fn serialize<S: serde::Serializer>(
+        nodes: &[Option<Node>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let sorted: Vec<(NodeId, &Node)> = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| Some((NodeId(index as u32), node.as_ref()?)))
+            .collect();
+        sorted.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Option<Node>>, D::Error> {
+        let sorted = Vec::<(NodeId, Node)>::deserialize(deserializer)?;
+        let max_slots = (sorted.len() * MAX_SLOTS_PER_NODE).max(MIN_MAX_SLOTS);
+        let num_slots = sorted.iter().map(|(id, _)| id.0 as usize + 1).max();
+        let num_slots = num_slots.unwrap_or(0);
+        if num_slots > max_slots {
+            return Err(D::Error::custom(format!(
+                "node id {} is too large for a graph of {} nodes",
+                num_slots - 1,
+                sorted.len()
+            )));
+        }
+
+        let mut nodes = Vec::new();
+        nodes.resize_with(num_slots, || None);
+        for (id, node) in sorted {
+            nodes[id.0 as usize] = Some(node);
+        }
+        Ok(nodes)
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -29,6 +101,15 @@ pub enum Node {
     /// The plane normal (plane.xyz) should be unit length (normalized).
     Plane(Vec4),
 
+    /// A convex polytope, the intersection of the half-spaces behind each of the `planes`.
+    ///
+    /// distance = max over planes of plane.xyz.dot(pos) + plane.w,
+    /// which is exact inside, and a lower bound outside.
+    /// The plane normals (plane.xyz) should be unit length (normalized), and point outwards.
+    ConvexHull {
+        planes: Vec<Vec4>,
+    },
+
     Sphere {
         center: Vec3,
         radius: f32,
@@ -56,6 +137,33 @@ pub enum Node {
         rounding_radius: f32,
     },
 
+    /// A cylinder with a cylindrical hole along its axis, e.g. a pipe or a ring.
+    ///
+    /// Centered at origin, with the Y axis through the hole. The ends are flat rings,
+    /// so the surface is closed, with a single hole through it (genus 1).
+    Tube {
+        outer_r: f32,
+        inner_r: f32,
+        half_height: f32,
+    },
+
+    /// An egg standing on the Y axis: the bottom half is a sphere of `radius` centered at origin,
+    /// narrowing upwards to a tip rounded by `tip_radius`, which is at most `radius`.
+    ///
+    /// The top of the tip is at `sqrt(3) * (radius - tip_radius) + tip_radius`.
+    Egg {
+        radius: f32,
+        tip_radius: f32,
+    },
+
+    /// A heart in the XY plane, extruded by `half_thickness` in each direction along Z.
+    ///
+    /// The point is at origin and the lobes are up along Y, about `1.1 * size` high and `1.2 * size` wide.
+    Heart {
+        size: f32,
+        half_thickness: f32,
+    },
+
     /// The convex hull of two spheres.
     ///
     /// The full length of the round cone is `(points[0] - points[1]).length() + radii[0] + radii[1]`
@@ -72,6 +180,18 @@ pub enum Node {
         height: f32,
     },
 
+    /// A cone with its tip cut off (a frustum), with the base center at origin
+    /// and the top center `height` up the Y axis.
+    ///
+    /// With `r1 == r2` it is a cylinder, and with `r2 == 0` a [`Self::Cone`].
+    CappedCone {
+        /// Radius of the base
+        r1: f32,
+        /// Radius of the top
+        r2: f32,
+        height: f32,
+    },
+
     /// A box with rounded edges / corners.
     ///
     /// The rounding is subtracted from the edges and corners (sandpapered down).
@@ -123,6 +243,81 @@ pub enum Node {
         chord: f32,
     },
 
+    /// A vesica revolved around the Y axis, e.g. a spindle or an elongated eye.
+    ///
+    /// The 2D vesica is the intersection of two circles of `radius`,
+    /// with their centers `distance` away from the Y axis on either side.
+    /// Centered at origin, with the pointy tips along the Y axis.
+    Vesica {
+        radius: f32,
+        /// Should be less than `radius`.
+        distance: f32,
+    },
+
+    /// A sphere centered at origin, with everything below `height` cut away, e.g. a dome.
+    ///
+    /// `height` is in `[-radius, radius]`, with `height=0` being a half sphere.
+    CutSphere {
+        radius: f32,
+        height: f32,
+    },
+
+    /// A spherical shell centered at origin, with everything above `height` cut away, e.g. a bowl.
+    ///
+    /// `height` is in `[-radius, radius]`, with `height=0` being a half sphere.
+    CutHollowSphere {
+        radius: f32,
+        height: f32,
+        /// Half the thickness of the shell.
+        thickness: f32,
+    },
+
+    /// A chain link, e.g. a torus stretched along the Y axis.
+    ///
+    /// Centered at origin, lying in the XY plane.
+    Link {
+        /// Half the length of the straight parts of the link.
+        length: f32,
+        big_r: f32,
+        small_r: f32,
+    },
+
+    /// A spherical sector, e.g. a cone with a spherical cap.
+    ///
+    /// The tip is at origin, and it opens up along the positive Y axis.
+    SolidAngle {
+        /// The sin/cos of the angle between the Y axis and the side of the cone,
+        /// so that `angle=PI/2` means a half sphere, and `angle=PI` means a full sphere.
+        sin_cos_angle: (f32, f32),
+        radius: f32,
+    },
+
+    /// A gyroid, a triply periodic minimal surface thickened into a shell.
+    ///
+    /// `distance = abs(sin(x)cos(y) + sin(y)cos(z) + sin(z)cos(x)) * scale - thickness`.
+    ///
+    /// The surface repeats every `2π` along each axis and is infinite,
+    /// so you probably want to intersect it with something.
+    Gyroid {
+        /// Multiplies the implicit function. It is not an exact distance, so
+        /// values below one makes sphere tracing more conservative.
+        scale: f32,
+        thickness: f32,
+    },
+
+    /// A Schwarz P surface, a triply periodic minimal surface thickened into a shell.
+    ///
+    /// `distance = abs(cos(x) + cos(y) + cos(z)) * scale - thickness`.
+    ///
+    /// The surface repeats every `2π` along each axis and is infinite,
+    /// so you probably want to intersect it with something.
+    SchwarzP {
+        /// Multiplies the implicit function. It is not an exact distance, so
+        /// values below one makes sphere tracing more conservative.
+        scale: f32,
+        thickness: f32,
+    },
+
     /// Set material of all child nodes:
     Material {
         child: NodeId,
@@ -164,6 +359,51 @@ pub enum Node {
         rhs: NodeId,
         size: f32,
     },
+    /// Like [`Node::Union`], but with a 45° bevel of `size` where the children meet.
+    UnionChamfer {
+        lhs: NodeId,
+        rhs: NodeId,
+        size: f32,
+    },
+    /// Like [`Node::Subtract`], but with a 45° bevel of `size` where the children meet.
+    SubtractChamfer {
+        lhs: NodeId,
+        rhs: NodeId,
+        size: f32,
+    },
+    /// Like [`Node::Intersect`], but with a 45° bevel of `size` where the children meet.
+    IntersectChamfer {
+        lhs: NodeId,
+        rhs: NodeId,
+        size: f32,
+    },
+    /// A union where the transition is a staircase `size` wide with `steps` steps.
+    ///
+    /// From Mercury's [hg_sdf](https://mercury.sexy/hg_sdf/) (`fOpUnionStairs`).
+    UnionStairs {
+        lhs: NodeId,
+        rhs: NodeId,
+        size: f32,
+        steps: u32,
+    },
+    /// A pipe of `radius` running along where the surfaces of `lhs` and `rhs` meet.
+    ///
+    /// From Mercury's [hg_sdf](https://mercury.sexy/hg_sdf/) (`fOpPipe`).
+    Pipe {
+        lhs: NodeId,
+        rhs: NodeId,
+        radius: f32,
+    },
+    /// A groove cut `depth` deep into `lhs`, following the surface of `rhs`
+    /// and `half_width` wide on either side of it.
+    ///
+    /// From Mercury's [hg_sdf](https://mercury.sexy/hg_sdf/) (`fOpGroove`).
+    Groove {
+        lhs: NodeId,
+        rhs: NodeId,
+        depth: f32,
+        half_width: f32,
+    },
 
     // Transforms:
     Translate {
@@ -174,10 +414,60 @@ pub enum Node {
         rotation: Quat,
         child: NodeId,
     },
+    /// Uniform scale. A negative scale mirrors the child through the origin.
     Scale {
         scale: f32,
         child: NodeId,
     },
+    /// Any affine transform, e.g. translation, rotation and (non-uniform) scale in one node.
+    ///
+    /// Non-uniform scale and shearing make the distances conservative (too small) rather than exact.
+    Transform {
+        matrix: Affine3A,
+        child: NodeId,
+    },
+    /// Stretches the child by `amount` in each direction along the axes,
+    /// e.g. turning a sphere into a capsule, or a box into a longer box.
+    ///
+    /// The child is cut at its origin and the halves are moved apart, so the child
+    /// should be centered at origin. Distances stay exact.
+    Elongate {
+        /// Half the added length along each axis.
+        amount: Vec3,
+        child: NodeId,
+    },
+    /// Spins the child around the Y axis, like a lathe, e.g. turning a circle into a torus.
+    ///
+    /// The child is a 2D profile, evaluated in the XY plane (at z=0), where x is the distance
+    /// from the axis minus `offset`. Distances are exact if the surface of the child closest to points in that plane
+    /// lies in it too, e.g. for a sphere or box centered on it, and otherwise only a lower bound.
+    Revolve {
+        /// How far from the axis the origin of the profile ends up.
+        offset: f32,
+        child: NodeId,
+    },
+    /// Moves the position the child is sampled at along the gradient of another field, `warp`,
+    /// e.g. for organic detail.
+    ///
+    /// The gradient is estimated from four extra evaluations of `warp` at every sample,
+    /// so this is at least four times as expensive as `warp` itself.
+    /// The distance is no longer exact, so meshing or tracing may need a finer resolution or smaller steps.
+    Warp {
+        child: NodeId,
+        warp: NodeId,
+        /// How far to move along the gradient of `warp`, which is at most one unit long.
+        strength: f32,
+    },
+    /// Multiplies the distance of the child by `factor`, leaving the surface where it is.
+    ///
+    /// Fields that overestimate distances, e.g. after smooth operations or [`Node::Warp`], can make
+    /// sphere tracing step through the surface. A `factor` below one makes them safe to trace,
+    /// at the cost of more steps, but only where needed, unlike a lower step constant for the whole scene.
+    ClampDistance {
+        child: NodeId,
+        /// In `(0, 1]`.
+        factor: f32,
+    },
     // Yo dawg, I heard you like graphs:
     Graph {
         root: NodeId,
@@ -185,6 +475,842 @@ pub enum Node {
     },
 }
 
+/// The parameters of a node, borrowed as `$slot`s, see [`Node::parameter_slots`] and [`Node::parameter_refs`],
+/// which only differ in whether they borrow the node mutably.
+macro_rules! parameter_slots {
+    ($node:expr, $slot:ident $(, $mut:tt)?) => {{
+        use $slot::Angle;
+        use $slot::Scalar;
+        use $slot::Vector;
+
+        match $node {
+            Node::Plane(plane) => {
+                let plane = &$($mut)? **plane;
+                let normal = [&$($mut)? plane.x, &$($mut)? plane.y, &$($mut)? plane.z];
+                vec![
+                    ("normal", $slot::Components(normal)),
+                    ("offset", Scalar(&$($mut)? plane.w)),
+                ]
+            }
+            Node::Sphere { center, radius } => {
+                vec![("center", Vector(center)), ("radius", Scalar(radius))]
+            }
+            Node::Capsule {
+                points: [start, end],
+                radius,
+            } => vec![
+                ("points[0]", Vector(start)),
+                ("points[1]", Vector(end)),
+                ("radius", Scalar(radius)),
+            ],
+            Node::RoundedCylinder {
+                cylinder_radius,
+                half_height,
+                rounding_radius,
+            } => vec![
+                ("cylinder_radius", Scalar(cylinder_radius)),
+                ("half_height", Scalar(half_height)),
+                ("rounding_radius", Scalar(rounding_radius)),
+            ],
+            Node::Tube {
+                outer_r,
+                inner_r,
+                half_height,
+            } => vec![
+                ("outer_r", Scalar(outer_r)),
+                ("inner_r", Scalar(inner_r)),
+                ("half_height", Scalar(half_height)),
+            ],
+            Node::Egg { radius, tip_radius } => {
+                vec![
+                    ("radius", Scalar(radius)),
+                    ("tip_radius", Scalar(tip_radius)),
+                ]
+            }
+            Node::Heart {
+                size,
+                half_thickness,
+            } => vec![
+                ("size", Scalar(size)),
+                ("half_thickness", Scalar(half_thickness)),
+            ],
+            Node::TaperedCapsule {
+                points: [start, end],
+                radii: [start_radius, end_radius],
+            } => vec![
+                ("points[0]", Vector(start)),
+                ("points[1]", Vector(end)),
+                ("radii[0]", Scalar(start_radius)),
+                ("radii[1]", Scalar(end_radius)),
+            ],
+            Node::RoundedBox {
+                half_size,
+                rounding_radius,
+            } => vec![
+                ("half_size", Vector(half_size)),
+                ("rounding_radius", Scalar(rounding_radius)),
+            ],
+            Node::Torus { big_r, small_r } => {
+                vec![("big_r", Scalar(big_r)), ("small_r", Scalar(small_r))]
+            }
+            Node::TorusSector {
+                big_r,
+                small_r,
+                sin_cos_half_angle,
+            } => vec![
+                ("big_r", Scalar(big_r)),
+                ("small_r", Scalar(small_r)),
+                ("half_angle", Angle(sin_cos_half_angle)),
+            ],
+            Node::BiconvexLens {
+                lower_sagitta,
+                upper_sagitta,
+                chord,
+            } => vec![
+                ("lower_sagitta", Scalar(lower_sagitta)),
+                ("upper_sagitta", Scalar(upper_sagitta)),
+                ("chord", Scalar(chord)),
+            ],
+            Node::Vesica { radius, distance } => {
+                vec![("radius", Scalar(radius)), ("distance", Scalar(distance))]
+            }
+            Node::Cone { radius, height } | Node::CutSphere { radius, height } => {
+                vec![("radius", Scalar(radius)), ("height", Scalar(height))]
+            }
+            Node::CappedCone { r1, r2, height } => vec![
+                ("r1", Scalar(r1)),
+                ("r2", Scalar(r2)),
+                ("height", Scalar(height)),
+            ],
+            Node::CutHollowSphere {
+                radius,
+                height,
+                thickness,
+            } => vec![
+                ("radius", Scalar(radius)),
+                ("height", Scalar(height)),
+                ("thickness", Scalar(thickness)),
+            ],
+            Node::Link {
+                length,
+                big_r,
+                small_r,
+            } => vec![
+                ("length", Scalar(length)),
+                ("big_r", Scalar(big_r)),
+                ("small_r", Scalar(small_r)),
+            ],
+            Node::SolidAngle {
+                sin_cos_angle,
+                radius,
+            } => vec![("angle", Angle(sin_cos_angle)), ("radius", Scalar(radius))],
+            Node::Gyroid { scale, thickness } | Node::SchwarzP { scale, thickness } => {
+                vec![("scale", Scalar(scale)), ("thickness", Scalar(thickness))]
+            }
+            Node::UnionSmooth { size, .. }
+            | Node::UnionMultiSmooth { size, .. }
+            | Node::SubtractSmooth { size, .. }
+            | Node::IntersectSmooth { size, .. }
+            | Node::UnionChamfer { size, .. }
+            | Node::SubtractChamfer { size, .. }
+            | Node::IntersectChamfer { size, .. }
+            | Node::UnionStairs { size, .. } => vec![("size", Scalar(size))],
+            Node::Pipe { radius, .. } => vec![("radius", Scalar(radius))],
+            Node::Groove {
+                depth, half_width, ..
+            } => vec![("depth", Scalar(depth)), ("half_width", Scalar(half_width))],
+            Node::Translate { translation, .. } => vec![("translation", Vector(translation))],
+            Node::Rotate { rotation, .. } => vec![("rotation", $slot::Rotation(rotation))],
+            Node::Scale { scale, .. } => vec![("scale", Scalar(scale))],
+            Node::Elongate { amount, .. } => vec![("amount", Vector(amount))],
+            Node::Revolve { offset, .. } => vec![("offset", Scalar(offset))],
+            Node::Warp { strength, .. } => vec![("strength", Scalar(strength))],
+            Node::ClampDistance { factor, .. } => vec![("factor", Scalar(factor))],
+            Node::ConvexHull { .. }
+            | Node::Material { .. }
+            | Node::Union { .. }
+            | Node::UnionMulti { .. }
+            | Node::Subtract { .. }
+            | Node::Intersect { .. }
+            | Node::Transform { .. }
+            | Node::Graph { .. } => vec![],
+        }
+    }};
+}
+
+impl Node {
+    /// The name of the first parameter that is NaN, infinite or otherwise out of range
+    /// (e.g. a negative radius), if any.
+    ///
+    /// Does not look at the children, see [`Graph::validate`] for that.
+    pub fn invalid_parameter(&self) -> Option<&'static str> {
+        fn non_negative(x: f32) -> bool {
+            x.is_finite() && x >= 0.0
+        }
+
+        fn first_invalid(parameters: &[(&'static str, bool)]) -> Option<&'static str> {
+            parameters
+                .iter()
+                .find(|(_, valid)| !valid)
+                .map(|(name, _)| *name)
+        }
+
+        match self {
+            Self::Plane(plane) => {
+                first_invalid(&[("plane", plane.is_finite() && plane.truncate() != Vec3::ZERO)])
+            }
+            Self::ConvexHull { planes } => first_invalid(&[(
+                "planes",
+                !planes.is_empty()
+                    && planes
+                        .iter()
+                        .all(|plane| plane.is_finite() && plane.truncate() != Vec3::ZERO),
+            )]),
+            Self::Sphere { center, radius } => first_invalid(&[
+                ("center", center.is_finite()),
+                ("radius", non_negative(*radius)),
+            ]),
+            Self::Capsule { points, radius } => first_invalid(&[
+                ("points", points.iter().all(|p| p.is_finite())),
+                ("radius", non_negative(*radius)),
+            ]),
+            Self::Tube {
+                outer_r,
+                inner_r,
+                half_height,
+            } => first_invalid(&[
+                ("outer_r", non_negative(*outer_r)),
+                ("inner_r", non_negative(*inner_r) && inner_r <= outer_r),
+                ("half_height", non_negative(*half_height)),
+            ]),
+            Self::Egg { radius, tip_radius } => first_invalid(&[
+                ("radius", non_negative(*radius)),
+                (
+                    "tip_radius",
+                    non_negative(*tip_radius) && tip_radius <= radius,
+                ),
+            ]),
+            Self::Heart {
+                size,
+                half_thickness,
+            } => first_invalid(&[
+                ("size", size.is_finite() && *size > 0.0),
+                ("half_thickness", non_negative(*half_thickness)),
+            ]),
+            Self::RoundedCylinder {
+                cylinder_radius,
+                half_height,
+                rounding_radius,
+            } => first_invalid(&[
+                ("cylinder_radius", non_negative(*cylinder_radius)),
+                ("half_height", non_negative(*half_height)),
+                ("rounding_radius", non_negative(*rounding_radius)),
+            ]),
+            Self::TaperedCapsule { points, radii } => first_invalid(&[
+                ("points", points.iter().all(|p| p.is_finite())),
+                ("radii", radii.iter().all(|r| non_negative(*r))),
+            ]),
+            Self::Cone { radius, height } => first_invalid(&[
+                ("radius", non_negative(*radius)),
+                ("height", non_negative(*height)),
+            ]),
+            Self::CappedCone { r1, r2, height } => first_invalid(&[
+                ("r1", non_negative(*r1)),
+                ("r2", non_negative(*r2)),
+                ("height", height.is_finite() && *height > 0.0),
+            ]),
+            Self::RoundedBox {
+                half_size,
+                rounding_radius,
+            } => first_invalid(&[
+                (
+                    "half_size",
+                    half_size.to_array().iter().all(|x| non_negative(*x)),
+                ),
+                ("rounding_radius", non_negative(*rounding_radius)),
+            ]),
+            Self::Torus { big_r, small_r } => first_invalid(&[
+                ("big_r", non_negative(*big_r)),
+                ("small_r", non_negative(*small_r)),
+            ]),
+            Self::TorusSector {
+                big_r,
+                small_r,
+                sin_cos_half_angle,
+            } => first_invalid(&[
+                ("big_r", non_negative(*big_r)),
+                ("small_r", non_negative(*small_r)),
+                (
+                    "sin_cos_half_angle",
+                    sin_cos_half_angle.0.is_finite() && sin_cos_half_angle.1.is_finite(),
+                ),
+            ]),
+            Self::BiconvexLens {
+                lower_sagitta,
+                upper_sagitta,
+                chord,
+            } => first_invalid(&[
+                ("lower_sagitta", non_negative(*lower_sagitta)),
+                ("upper_sagitta", non_negative(*upper_sagitta)),
+                ("chord", non_negative(*chord)),
+            ]),
+            Self::Vesica { radius, distance } => first_invalid(&[
+                ("radius", non_negative(*radius)),
+                ("distance", non_negative(*distance)),
+            ]),
+            Self::CutSphere { radius, height } => first_invalid(&[
+                ("radius", non_negative(*radius)),
+                ("height", height.is_finite()),
+            ]),
+            Self::CutHollowSphere {
+                radius,
+                height,
+                thickness,
+            } => first_invalid(&[
+                ("radius", non_negative(*radius)),
+                ("height", height.is_finite()),
+                ("thickness", non_negative(*thickness)),
+            ]),
+            Self::Link {
+                length,
+                big_r,
+                small_r,
+            } => first_invalid(&[
+                ("length", non_negative(*length)),
+                ("big_r", non_negative(*big_r)),
+                ("small_r", non_negative(*small_r)),
+            ]),
+            Self::SolidAngle {
+                sin_cos_angle,
+                radius,
+            } => first_invalid(&[
+                (
+                    "sin_cos_angle",
+                    sin_cos_angle.0.is_finite() && sin_cos_angle.1.is_finite(),
+                ),
+                ("radius", non_negative(*radius)),
+            ]),
+            Self::Gyroid { scale, thickness } | Self::SchwarzP { scale, thickness } => {
+                first_invalid(&[
+                    ("scale", scale.is_finite()),
+                    ("thickness", thickness.is_finite()),
+                ])
+            }
+            Self::Material { material, .. } => first_invalid(&[(
+                "material",
+                material.rgba().is_finite()
+                    && material.metallic().is_finite()
+                    && material.roughness().is_finite(),
+            )]),
+            Self::UnionMulti { children } => first_invalid(&[("children", !children.is_empty())]),
+            Self::UnionMultiSmooth { children, size } => first_invalid(&[
+                ("children", !children.is_empty()),
+                ("size", non_negative(*size)),
+            ]),
+            Self::UnionSmooth { size, .. }
+            | Self::SubtractSmooth { size, .. }
+            | Self::IntersectSmooth { size, .. }
+            | Self::UnionChamfer { size, .. }
+            | Self::SubtractChamfer { size, .. }
+            | Self::IntersectChamfer { size, .. } => {
+                first_invalid(&[("size", non_negative(*size))])
+            }
+            Self::UnionStairs { size, steps, .. } => {
+                first_invalid(&[("size", non_negative(*size)), ("steps", *steps > 0)])
+            }
+            Self::Pipe { radius, .. } => first_invalid(&[("radius", non_negative(*radius))]),
+            Self::Groove {
+                depth, half_width, ..
+            } => first_invalid(&[
+                ("depth", non_negative(*depth)),
+                ("half_width", non_negative(*half_width)),
+            ]),
+            Self::Translate { translation, .. } => {
+                first_invalid(&[("translation", translation.is_finite())])
+            }
+            Self::Rotate { rotation, .. } => first_invalid(&[("rotation", rotation.is_finite())]),
+            Self::Scale { scale, .. } => {
+                first_invalid(&[("scale", scale.is_finite() && *scale != 0.0)])
+            }
+            Self::Transform { matrix, .. } => first_invalid(&[(
+                "matrix",
+                matrix.is_finite() && matrix.matrix3.determinant() != 0.0,
+            )]),
+            Self::Elongate { amount, .. } => {
+                first_invalid(&[("amount", amount.to_array().iter().all(|x| non_negative(*x)))])
+            }
+            Self::Revolve { offset, .. } => first_invalid(&[("offset", offset.is_finite())]),
+            Self::Warp { strength, .. } => first_invalid(&[("strength", strength.is_finite())]),
+            Self::ClampDistance { factor, .. } => {
+                first_invalid(&[("factor", *factor > 0.0 && *factor <= 1.0)])
+            }
+            Self::Union { .. }
+            | Self::Subtract { .. }
+            | Self::Intersect { .. }
+            | Self::Graph { .. } => None,
+        }
+    }
+
+    /// The nodes directly referenced by this node, in the order they are compiled.
+    ///
+    /// Does not descend into nested [`Node::Graph`]s, since those have their own id space.
+    pub fn children(&self) -> Vec<NodeId> {
+        match self {
+            Self::Plane(_)
+            | Self::ConvexHull { .. }
+            | Self::Sphere { .. }
+            | Self::Capsule { .. }
+            | Self::RoundedCylinder { .. }
+            | Self::Tube { .. }
+            | Self::Egg { .. }
+            | Self::Heart { .. }
+            | Self::TaperedCapsule { .. }
+            | Self::Cone { .. }
+            | Self::CappedCone { .. }
+            | Self::RoundedBox { .. }
+            | Self::Torus { .. }
+            | Self::TorusSector { .. }
+            | Self::BiconvexLens { .. }
+            | Self::Vesica { .. }
+            | Self::CutSphere { .. }
+            | Self::CutHollowSphere { .. }
+            | Self::Link { .. }
+            | Self::SolidAngle { .. }
+            | Self::Gyroid { .. }
+            | Self::SchwarzP { .. }
+            | Self::Graph { .. } => vec![],
+            Self::Material { child, .. }
+            | Self::Translate { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Scale { child, .. }
+            | Self::Transform { child, .. }
+            | Self::Elongate { child, .. }
+            | Self::Revolve { child, .. }
+            | Self::ClampDistance { child, .. } => vec![*child],
+            Self::Warp { child, warp, .. } => vec![*warp, *child],
+            Self::Union { lhs, rhs }
+            | Self::UnionSmooth { lhs, rhs, .. }
+            | Self::Subtract { lhs, rhs }
+            | Self::SubtractSmooth { lhs, rhs, .. }
+            | Self::Intersect { lhs, rhs }
+            | Self::IntersectSmooth { lhs, rhs, .. }
+            | Self::UnionChamfer { lhs, rhs, .. }
+            | Self::SubtractChamfer { lhs, rhs, .. }
+            | Self::IntersectChamfer { lhs, rhs, .. }
+            | Self::UnionStairs { lhs, rhs, .. }
+            | Self::Pipe { lhs, rhs, .. }
+            | Self::Groove { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Self::UnionMulti { children } | Self::UnionMultiSmooth { children, .. } => {
+                children.clone()
+            }
+        }
+    }
+
+    /// Mutable references to the nodes directly referenced by this node.
+    ///
+    /// See [`Self::children`].
+    pub fn children_mut(&mut self) -> Vec<&mut NodeId> {
+        match self {
+            Self::Plane(_)
+            | Self::ConvexHull { .. }
+            | Self::Sphere { .. }
+            | Self::Capsule { .. }
+            | Self::RoundedCylinder { .. }
+            | Self::Tube { .. }
+            | Self::Egg { .. }
+            | Self::Heart { .. }
+            | Self::TaperedCapsule { .. }
+            | Self::Cone { .. }
+            | Self::CappedCone { .. }
+            | Self::RoundedBox { .. }
+            | Self::Torus { .. }
+            | Self::TorusSector { .. }
+            | Self::BiconvexLens { .. }
+            | Self::Vesica { .. }
+            | Self::CutSphere { .. }
+            | Self::CutHollowSphere { .. }
+            | Self::Link { .. }
+            | Self::SolidAngle { .. }
+            | Self::Gyroid { .. }
+            | Self::SchwarzP { .. }
+            | Self::Graph { .. } => vec![],
+            Self::Material { child, .. }
+            | Self::Translate { child, .. }
+            | Self::Rotate { child, .. }
+            | Self::Scale { child, .. }
+            | Self::Transform { child, .. }
+            | Self::Elongate { child, .. }
+            | Self::Revolve { child, .. }
+            | Self::ClampDistance { child, .. } => vec![child],
+            Self::Warp { child, warp, .. } => vec![warp, child],
+            Self::Union { lhs, rhs }
+            | Self::UnionSmooth { lhs, rhs, .. }
+            | Self::Subtract { lhs, rhs }
+            | Self::SubtractSmooth { lhs, rhs, .. }
+            | Self::Intersect { lhs, rhs }
+            | Self::IntersectSmooth { lhs, rhs, .. }
+            | Self::UnionChamfer { lhs, rhs, .. }
+            | Self::SubtractChamfer { lhs, rhs, .. }
+            | Self::IntersectChamfer { lhs, rhs, .. }
+            | Self::UnionStairs { lhs, rhs, .. }
+            | Self::Pipe { lhs, rhs, .. }
+            | Self::Groove { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::UnionMulti { children } | Self::UnionMultiSmooth { children, .. } => {
+                children.iter_mut().collect()
+            }
+        }
+    }
+
+    /// The kind of node and its parameters, but not its children, e.g. `Sphere center=[0, 0, 0] radius=1`.
+    ///
+    /// See [`Graph::to_tree_string`].
+    pub fn describe(&self) -> String {
+        let angle = |(sin, cos): (f32, f32)| sin.atan2(cos);
+        match self {
+            Self::Plane(plane) => format!("Plane {plane}"),
+            Self::ConvexHull { planes } => {
+                let planes: Vec<String> = planes.iter().map(|p| p.to_string()).collect();
+                format!("ConvexHull planes=[{}]", planes.join(", "))
+            }
+            Self::Sphere { center, radius } => format!("Sphere center={center} radius={radius}"),
+            Self::Capsule { points, radius } => {
+                format!("Capsule points={} {} radius={radius}", points[0], points[1])
+            }
+            Self::RoundedCylinder {
+                cylinder_radius,
+                half_height,
+                rounding_radius,
+            } => format!(
+                "RoundedCylinder cylinder_radius={cylinder_radius} half_height={half_height} rounding_radius={rounding_radius}"
+            ),
+            Self::Tube {
+                outer_r,
+                inner_r,
+                half_height,
+            } => format!("Tube outer_r={outer_r} inner_r={inner_r} half_height={half_height}"),
+            Self::Egg { radius, tip_radius } => {
+                format!("Egg radius={radius} tip_radius={tip_radius}")
+            }
+            Self::Heart {
+                size,
+                half_thickness,
+            } => format!("Heart size={size} half_thickness={half_thickness}"),
+            Self::TaperedCapsule { points, radii } => format!(
+                "TaperedCapsule points={} {} radii={} {}",
+                points[0], points[1], radii[0], radii[1]
+            ),
+            Self::Cone { radius, height } => format!("Cone radius={radius} height={height}"),
+            Self::CappedCone { r1, r2, height } => {
+                format!("CappedCone r1={r1} r2={r2} height={height}")
+            }
+            Self::RoundedBox {
+                half_size,
+                rounding_radius,
+            } => format!("RoundedBox half_size={half_size} rounding_radius={rounding_radius}"),
+            Self::Torus { big_r, small_r } => format!("Torus big_r={big_r} small_r={small_r}"),
+            Self::TorusSector {
+                big_r,
+                small_r,
+                sin_cos_half_angle,
+            } => format!(
+                "TorusSector big_r={big_r} small_r={small_r} half_angle={}",
+                angle(*sin_cos_half_angle)
+            ),
+            Self::BiconvexLens {
+                lower_sagitta,
+                upper_sagitta,
+                chord,
+            } => format!(
+                "BiconvexLens lower_sagitta={lower_sagitta} upper_sagitta={upper_sagitta} chord={chord}"
+            ),
+            Self::Vesica { radius, distance } => {
+                format!("Vesica radius={radius} distance={distance}")
+            }
+            Self::CutSphere { radius, height } => format!("CutSphere radius={radius} height={height}"),
+            Self::CutHollowSphere {
+                radius,
+                height,
+                thickness,
+            } => format!("CutHollowSphere radius={radius} height={height} thickness={thickness}"),
+            Self::Link {
+                length,
+                big_r,
+                small_r,
+            } => format!("Link length={length} big_r={big_r} small_r={small_r}"),
+            Self::SolidAngle {
+                sin_cos_angle,
+                radius,
+            } => format!(
+                "SolidAngle angle={} radius={radius}",
+                angle(*sin_cos_angle)
+            ),
+            Self::Gyroid { scale, thickness } => {
+                format!("Gyroid scale={scale} thickness={thickness}")
+            }
+            Self::SchwarzP { scale, thickness } => {
+                format!("SchwarzP scale={scale} thickness={thickness}")
+            }
+            Self::Material { material, .. } => format!(
+                "Material rgba={} metallic={} roughness={}",
+                material.rgba(),
+                material.metallic(),
+                material.roughness()
+            ),
+            Self::Union { .. } => "Union".to_owned(),
+            Self::UnionMulti { .. } => "UnionMulti".to_owned(),
+            Self::UnionSmooth { size, .. } => format!("UnionSmooth size={size}"),
+            Self::UnionMultiSmooth { size, .. } => format!("UnionMultiSmooth size={size}"),
+            Self::Subtract { .. } => "Subtract".to_owned(),
+            Self::SubtractSmooth { size, .. } => format!("SubtractSmooth size={size}"),
+            Self::Intersect { .. } => "Intersect".to_owned(),
+            Self::IntersectSmooth { size, .. } => format!("IntersectSmooth size={size}"),
+            Self::UnionChamfer { size, .. } => format!("UnionChamfer size={size}"),
+            Self::SubtractChamfer { size, .. } => format!("SubtractChamfer size={size}"),
+            Self::IntersectChamfer { size, .. } => format!("IntersectChamfer size={size}"),
+            Self::UnionStairs { size, steps, .. } => {
+                format!("UnionStairs size={size} steps={steps}")
+            }
+            Self::Pipe { radius, .. } => format!("Pipe radius={radius}"),
+            Self::Groove {
+                depth, half_width, ..
+            } => format!("Groove depth={depth} half_width={half_width}"),
+            Self::Translate { translation, .. } => format!("Translate {translation}"),
+            Self::Rotate { rotation, .. } => format!("Rotate {rotation}"),
+            Self::Scale { scale, .. } => format!("Scale {scale}"),
+            Self::Transform { matrix, .. } => format!("Transform {matrix}"),
+            Self::Elongate { amount, .. } => format!("Elongate {amount}"),
+            Self::Revolve { offset, .. } => format!("Revolve offset={offset}"),
+            Self::Warp { strength, .. } => format!("Warp strength={strength}"),
+            Self::ClampDistance { factor, .. } => format!("ClampDistance factor={factor}"),
+            Self::Graph { .. } => "Graph".to_owned(),
+        }
+    }
+
+    /// The parameters of this node with their current values, e.g. to generate a property panel for any node.
+    ///
+    /// Children are not parameters, and neither are the planes of a [`Node::ConvexHull`], materials,
+    /// the steps of [`Node::UnionStairs`] or the matrix of a [`Node::Transform`], which don't fit a [`ParamKind`].
+    pub fn parameters(&self) -> Vec<Parameter> {
+        self.parameter_refs()
+            .into_iter()
+            .map(|(name, slot)| Parameter {
+                name,
+                value: slot.get(),
+            })
+            .collect()
+    }
+
+    /// Sets one of the [`Self::parameters`].
+    ///
+    /// Returns `false`, leaving the node unchanged, if it has no parameter called `name` of the same kind as `value`.
+    pub fn set_parameter(&mut self, name: &str, value: ParamValue) -> bool {
+        self.parameter_slots()
+            .into_iter()
+            .find(|(slot_name, _)| *slot_name == name)
+            .is_some_and(|(_, slot)| slot.set(value))
+    }
+
+    /// Identifies the node by its variant, children and the bits of everything else, see [`Graph::deduplicate`].
+    ///
+    /// Nested graphs are too expensive to compare, so they are identified by their `id` instead.
+    fn structure_key(&self, id: NodeId) -> StructureKey {
+        let variant = std::mem::discriminant(self);
+        let children = self.children();
+        let mut bits = vec![];
+        match self {
+            Self::ConvexHull { planes } => {
+                bits.extend(
+                    planes
+                        .iter()
+                        .flat_map(|plane| plane.to_array().map(f32::to_bits)),
+                );
+            }
+            Self::Material { material, .. } => {
+                bits.extend(material.rgba().to_array().map(f32::to_bits));
+                bits.extend([material.metallic(), material.roughness()].map(f32::to_bits));
+            }
+            Self::UnionStairs { steps, .. } => bits.push(*steps),
+            Self::Transform { matrix, .. } => {
+                bits.extend(matrix.to_cols_array().map(f32::to_bits));
+            }
+            Self::Graph { .. } => bits.push(id.0),
+            // Everything else is in the parameters.
+            Self::Plane(_)
+            | Self::Sphere { .. }
+            | Self::Capsule { .. }
+            | Self::RoundedCylinder { .. }
+            | Self::Tube { .. }
+            | Self::Egg { .. }
+            | Self::Heart { .. }
+            | Self::TaperedCapsule { .. }
+            | Self::Cone { .. }
+            | Self::CappedCone { .. }
+            | Self::RoundedBox { .. }
+            | Self::Torus { .. }
+            | Self::TorusSector { .. }
+            | Self::BiconvexLens { .. }
+            | Self::Vesica { .. }
+            | Self::CutSphere { .. }
+            | Self::CutHollowSphere { .. }
+            | Self::Link { .. }
+            | Self::SolidAngle { .. }
+            | Self::Gyroid { .. }
+            | Self::SchwarzP { .. }
+            | Self::Union { .. }
+            | Self::UnionMulti { .. }
+            | Self::UnionSmooth { .. }
+            | Self::UnionMultiSmooth { .. }
+            | Self::Subtract { .. }
+            | Self::SubtractSmooth { .. }
+            | Self::Intersect { .. }
+            | Self::IntersectSmooth { .. }
+            | Self::UnionChamfer { .. }
+            | Self::SubtractChamfer { .. }
+            | Self::IntersectChamfer { .. }
+            | Self::Pipe { .. }
+            | Self::Groove { .. }
+            | Self::Translate { .. }
+            | Self::Rotate { .. }
+            | Self::Scale { .. }
+            | Self::Elongate { .. }
+            | Self::Revolve { .. }
+            | Self::Warp { .. }
+            | Self::ClampDistance { .. } => {}
+        }
+        for (_, slot) in self.parameter_refs() {
+            slot.push_bits(&mut bits);
+        }
+        StructureKey {
+            variant,
+            bits,
+            children,
+        }
+    }
+
+    fn parameter_slots(&mut self) -> Vec<(&'static str, ParameterSlot<'_>)> {
+        parameter_slots!(self, ParameterSlot, mut)
+    }
+
+    fn parameter_refs(&self) -> Vec<(&'static str, ParameterRef<'_>)> {
+        parameter_slots!(self, ParameterRef)
+    }
+}
+
+/// What kind of value a [`Parameter`] holds, e.g. to pick a widget for it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParamKind {
+    Scalar,
+    Vec3,
+    Quat,
+    /// An angle in radians.
+    Angle,
+}
+
+/// The value of a [`Parameter`], see [`Node::set_parameter`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    Scalar(f32),
+    Vec3(Vec3),
+    Quat(Quat),
+    /// An angle in radians.
+    Angle(f32),
+}
+
+impl ParamValue {
+    pub fn kind(&self) -> ParamKind {
+        match self {
+            Self::Scalar(_) => ParamKind::Scalar,
+            Self::Vec3(_) => ParamKind::Vec3,
+            Self::Quat(_) => ParamKind::Quat,
+            Self::Angle(_) => ParamKind::Angle,
+        }
+    }
+}
+
+/// A named parameter of a [`Node`], see [`Node::parameters`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Parameter {
+    /// The name of the field in the [`Node`], e.g. `radius`, or `points[0]` for the elements of arrays.
+    pub name: &'static str,
+    pub value: ParamValue,
+}
+
+impl Parameter {
+    pub fn kind(&self) -> ParamKind {
+        self.value.kind()
+    }
+}
+
+/// Identifies a [`Node`] by its variant, the bits of its floats and its children, see [`Node::structure_key`].
+#[derive(PartialEq, Eq, Hash)]
+struct StructureKey {
+    variant: std::mem::Discriminant<Node>,
+    bits: Vec<u32>,
+    children: Vec<NodeId>,
+}
+
+/// Where a [`Parameter`] is stored in a [`Node`].
+enum ParameterSlot<'a> {
+    Scalar(&'a mut f32),
+    Vector(&'a mut Vec3),
+    Rotation(&'a mut Quat),
+    /// Stored as its sine and cosine.
+    Angle(&'a mut (f32, f32)),
+    /// A vector stored in separate components, e.g. the normal of a [`Node::Plane`].
+    Components([&'a mut f32; 3]),
+}
+
+/// Like [`ParameterSlot`], but only for reading.
+enum ParameterRef<'a> {
+    Scalar(&'a f32),
+    Vector(&'a Vec3),
+    Rotation(&'a Quat),
+    Angle(&'a (f32, f32)),
+    Components([&'a f32; 3]),
+}
+
+impl ParameterRef<'_> {
+    fn get(&self) -> ParamValue {
+        match self {
+            Self::Scalar(x) => ParamValue::Scalar(**x),
+            Self::Vector(v) => ParamValue::Vec3(**v),
+            Self::Rotation(q) => ParamValue::Quat(**q),
+            Self::Angle((sin, cos)) => ParamValue::Angle(sin.atan2(*cos)),
+            Self::Components([x, y, z]) => ParamValue::Vec3(Vec3::new(**x, **y, **z)),
+        }
+    }
+
+    /// Appends the bits of the stored floats, e.g. to compare parameters exactly.
+    fn push_bits(&self, bits: &mut Vec<u32>) {
+        match self {
+            Self::Scalar(x) => bits.push(x.to_bits()),
+            Self::Vector(v) => bits.extend(v.to_array().map(f32::to_bits)),
+            Self::Rotation(q) => bits.extend(q.to_array().map(f32::to_bits)),
+            Self::Angle((sin, cos)) => bits.extend([sin.to_bits(), cos.to_bits()]),
+            Self::Components(components) => bits.extend(components.iter().map(|x| x.to_bits())),
+        }
+    }
+}
+
+impl ParameterSlot<'_> {
+    /// Returns `false` if `value` is of the wrong kind.
+    fn set(self, value: ParamValue) -> bool {
+        match (self, value) {
+            (Self::Scalar(x), ParamValue::Scalar(value)) => *x = value,
+            (Self::Vector(v), ParamValue::Vec3(value)) => *v = value,
+            (Self::Rotation(q), ParamValue::Quat(value)) => *q = value,
+            (Self::Angle(sin_cos), ParamValue::Angle(value)) => *sin_cos = value.sin_cos(),
+            (Self::Components(components), ParamValue::Vec3(value)) => {
+                for (component, value) in components.into_iter().zip(value.to_array()) {
+                    *component = value;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CsgOp {
     Union,
@@ -192,26 +1318,91 @@ pub enum CsgOp {
     Intersect,
 }
 
+/// Why a [`Graph`] can't be compiled, see [`Graph::validate`].
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    #[error("Node {0:?} is part of a cycle")]
+    Cycle(NodeId),
+
+    #[error("Node {0:?} does not exist")]
+    DanglingNode(NodeId),
+
+    #[error("Node {node:?} has an invalid `{parameter}`")]
+    InvalidParameter {
+        node: NodeId,
+        parameter: &'static str,
+    },
+}
+
 // Constructors
 impl Graph {
     pub fn create_node(&mut self, node: Node) -> NodeId {
         let id = NodeId(self.id_allocator);
         self.id_allocator += 1;
-        self.nodes.insert(id, node);
+        self.insert(id, node);
         id
     }
 
     pub fn graph(&mut self, graph: Self, root: NodeId) -> NodeId {
         let id = NodeId(self.id_allocator);
         self.id_allocator += 1;
-        self.nodes.insert(id, Node::Graph { root, graph });
+        self.insert(id, Node::Graph { root, graph });
+        id
+    }
+
+    /// Copies the nodes of `other` reachable from `other_root` into this graph, with fresh [`NodeId`]s,
+    /// and returns the id of the copied root.
+    ///
+    /// Unlike [`Self::graph`], the result doesn't contain any [`Node::Graph`], also not the ones nested in `other`,
+    /// so tools that walk the graph don't need to recurse into nested graphs.
+    /// Missing nodes in `other` are missing in the copy too, see [`Self::validate`].
+    pub fn import(&mut self, other: &Self, other_root: NodeId) -> NodeId {
+        self.import_node(other, other_root, &mut HashMap::new())
+    }
+
+    fn import_node(
+        &mut self,
+        other: &Self,
+        other_id: NodeId,
+        imported: &mut HashMap<NodeId, NodeId>,
+    ) -> NodeId {
+        if let Some(id) = imported.get(&other_id) {
+            return *id;
+        }
+        if let Some(Node::Graph { root, graph }) = other.get(other_id) {
+            let id = self.import(graph, *root);
+            imported.insert(other_id, id);
+            return id;
+        }
+
+        // Allocated before the children, so that cycles stay cycles instead of recursing forever.
+        let id = NodeId(self.id_allocator);
+        self.id_allocator += 1;
+        imported.insert(other_id, id);
+        if let Some(mut node) = other.get(other_id).cloned() {
+            for child in node.children_mut() {
+                *child = self.import_node(other, *child, imported);
+            }
+            self.insert(id, node);
+        }
         id
     }
 
     /// distance = plane.xyz.dot(pos) + plane.w
-    /// The plane normal (plane.xyz) should be unit length (normalized).
+    ///
+    /// The plane is normalized, so that e.g. `(0, 2, 0, -2)` is the same plane as `(0, 1, 0, -1)`.
+    /// A zero normal is kept as is, and reported by [`Self::validate`].
     pub fn plane(&mut self, plane: Vec4) -> NodeId {
-        self.create_node(Node::Plane(plane))
+        self.create_node(Node::Plane(normalize_plane(plane)))
+    }
+
+    /// A convex polytope, the intersection of the half-spaces behind each of the `planes`.
+    ///
+    /// Like for [`Self::plane`], the planes are normalized.
+    /// The normals point out of the polytope.
+    pub fn convex_hull(&mut self, planes: Vec<Vec4>) -> NodeId {
+        let planes = planes.into_iter().map(normalize_plane).collect();
+        self.create_node(Node::ConvexHull { planes })
     }
 
     pub fn sphere(&mut self, center: Vec3, radius: f32) -> NodeId {
@@ -265,10 +1456,87 @@ impl Graph {
         })
     }
 
+    /// A vesica revolved around the Y axis, e.g. a spindle or an elongated eye.
+    ///
+    /// The 2D vesica is the intersection of two circles of `radius`,
+    /// with their centers `distance` away from the Y axis on either side.
+    /// So the vesica is `2 * (radius - distance)` wide, and has its tips
+    /// at `y = ±sqrt(radius² - distance²)`.
+    pub fn vesica(&mut self, radius: f32, distance: f32) -> NodeId {
+        self.create_node(Node::Vesica { radius, distance })
+    }
+
+    /// A sphere centered at origin, with everything below `height` cut away, e.g. a dome.
+    ///
+    /// With `height=0` you get a half sphere.
+    pub fn cut_sphere(&mut self, radius: f32, height: f32) -> NodeId {
+        self.create_node(Node::CutSphere {
+            radius,
+            height: height.clamp(-radius, radius),
+        })
+    }
+
+    /// A spherical shell centered at origin, with everything above `height` cut away, e.g. a bowl.
+    ///
+    /// With `height=0` you get a half sphere. The shell is `2 * thickness` thick.
+    pub fn cut_hollow_sphere(&mut self, radius: f32, height: f32, thickness: f32) -> NodeId {
+        self.create_node(Node::CutHollowSphere {
+            radius,
+            height: height.clamp(-radius, radius),
+            thickness,
+        })
+    }
+
+    /// A chain link, e.g. a torus stretched along the Y axis.
+    ///
+    /// Centered at origin, lying in the XY plane.
+    /// The straight parts of the link are `2 * length` long.
+    pub fn link(&mut self, length: f32, big_r: f32, small_r: f32) -> NodeId {
+        self.create_node(Node::Link {
+            length,
+            big_r,
+            small_r,
+        })
+    }
+
+    /// A spherical sector, e.g. a cone with a spherical cap.
+    ///
+    /// The tip is at origin, and it opens up along the positive Y axis.
+    ///
+    /// `angle` is between the Y axis and the side of the cone,
+    /// so with `angle=PI/4` you get a 90° wide cone, and with `angle=PI/2` you get a half sphere.
+    pub fn solid_angle(&mut self, angle: f32, radius: f32) -> NodeId {
+        self.create_node(Node::SolidAngle {
+            sin_cos_angle: angle.sin_cos(),
+            radius,
+        })
+    }
+
+    /// A gyroid lattice of the given `thickness`, repeating every `2π`. See [`Node::Gyroid`].
+    ///
+    /// Intersect it with something to get a finite shape.
+    pub fn gyroid(&mut self, scale: f32, thickness: f32) -> NodeId {
+        self.create_node(Node::Gyroid { scale, thickness })
+    }
+
+    /// A Schwarz P lattice of the given `thickness`, repeating every `2π`. See [`Node::SchwarzP`].
+    ///
+    /// Intersect it with something to get a finite shape.
+    pub fn schwarz_p(&mut self, scale: f32, thickness: f32) -> NodeId {
+        self.create_node(Node::SchwarzP { scale, thickness })
+    }
+
     pub fn capsule(&mut self, points: [Vec3; 2], radius: f32) -> NodeId {
         self.create_node(Node::Capsule { points, radius })
     }
 
+    /// A line segment, i.e. a capsule with zero radius, e.g. for construction or debugging.
+    ///
+    /// It has no inside, so it won't show up when meshed on its own.
+    pub fn segment(&mut self, points: [Vec3; 2]) -> NodeId {
+        self.capsule(points, 0.0)
+    }
+
     /// A simple capsule from the origin along the Y axis.
     pub fn capsule_y(&mut self, length: f32, radius: f32) -> NodeId {
         self.create_node(Node::Capsule {
@@ -298,6 +1566,32 @@ impl Graph {
         })
     }
 
+    /// A cylinder with a cylindrical hole along the Y axis, see [`Node::Tube`].
+    ///
+    /// Centered at origin. `inner_r` must be at most `outer_r`.
+    pub fn tube(&mut self, outer_r: f32, inner_r: f32, half_height: f32) -> NodeId {
+        self.create_node(Node::Tube {
+            outer_r,
+            inner_r,
+            half_height,
+        })
+    }
+
+    /// An egg standing on the Y axis, see [`Node::Egg`].
+    ///
+    /// `tip_radius` must be at most `radius`.
+    pub fn egg(&mut self, radius: f32, tip_radius: f32) -> NodeId {
+        self.create_node(Node::Egg { radius, tip_radius })
+    }
+
+    /// A heart in the XY plane with its point at origin, see [`Node::Heart`].
+    pub fn heart(&mut self, size: f32, half_thickness: f32) -> NodeId {
+        self.create_node(Node::Heart {
+            size,
+            half_thickness,
+        })
+    }
+
     /// The convex hull of two spheres.
     pub fn tapered_capsule(&mut self, points: [Vec3; 2], radii: [f32; 2]) -> NodeId {
         // The current SDF renderer freaks out in the degenerate case where one sphere fully contains the other, so
@@ -314,11 +1608,25 @@ impl Graph {
         }
     }
 
+    /// A cone along the Y axis with rounded ends: a sphere of radius `r1` at origin,
+    /// and one of radius `r2` at `height` up the Y axis, with the convex hull between them.
+    ///
+    /// Like [`Self::tapered_capsule`], this is a sphere if one end fully contains the other.
+    pub fn rounded_cone(&mut self, r1: f32, r2: f32, height: f32) -> NodeId {
+        self.tapered_capsule([Vec3::ZERO, Vec3::new(0.0, height, 0.0)], [r1, r2])
+    }
+
     /// Base center at origin, extending `height` along positive Y axis.
     pub fn cone(&mut self, radius: f32, height: f32) -> NodeId {
         self.create_node(Node::Cone { radius, height })
     }
 
+    /// A cone with its tip cut off, see [`Node::CappedCone`]: the base of radius `r1` is centered at origin,
+    /// and the top of radius `r2` is `height` up the Y axis.
+    pub fn capped_cone(&mut self, r1: f32, r2: f32, height: f32) -> NodeId {
+        self.create_node(Node::CappedCone { r1, r2, height })
+    }
+
     pub fn op_material(&mut self, child: NodeId, material: Material) -> NodeId {
         self.create_node(Node::Material { child, material })
     }
@@ -327,6 +1635,11 @@ impl Graph {
         self.op_material(child, Material::new(rgb.into()))
     }
 
+    /// Like [`Self::op_rgb`], but with an alpha in `rgba.w`, where 1 is opaque.
+    pub fn op_rgba(&mut self, child: NodeId, rgba: impl Into<Vec4>) -> NodeId {
+        self.op_material(child, Material::new_rgba(rgba.into()))
+    }
+
     pub fn op_union(&mut self, lhs: NodeId, rhs: NodeId) -> NodeId {
         self.create_node(Node::Union { lhs, rhs })
     }
@@ -339,6 +1652,30 @@ impl Graph {
         self.create_node(Node::UnionMulti { children })
     }
 
+    /// Union of all `children` as a balanced tree of [`Node::Union`].
+    ///
+    /// A deeply nested chain of unions needs one interpreter stack slot per level,
+    /// while the balanced tree only needs `O(log n)`, so prefer this for large sets
+    /// of children (e.g. point clouds).
+    pub fn op_union_balanced(&mut self, children: Vec<NodeId>) -> NodeId {
+        if children.is_empty() {
+            return self.op_union_multi(children);
+        }
+
+        let mut level = children;
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match *pair {
+                    [lhs, rhs] => self.op_union(lhs, rhs),
+                    [single] => single,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level[0]
+    }
+
     pub fn op_union_multi_smooth(&mut self, children: Vec<NodeId>, size: f32) -> NodeId {
         self.create_node(Node::UnionMultiSmooth { children, size })
     }
@@ -359,6 +1696,46 @@ impl Graph {
         self.create_node(Node::IntersectSmooth { lhs, rhs, size })
     }
 
+    /// See [`Node::UnionChamfer`].
+    pub fn op_union_chamfer(&mut self, lhs: NodeId, rhs: NodeId, size: f32) -> NodeId {
+        self.create_node(Node::UnionChamfer { lhs, rhs, size })
+    }
+
+    /// See [`Node::SubtractChamfer`].
+    pub fn op_subtract_chamfer(&mut self, lhs: NodeId, rhs: NodeId, size: f32) -> NodeId {
+        self.create_node(Node::SubtractChamfer { lhs, rhs, size })
+    }
+
+    /// See [`Node::IntersectChamfer`].
+    pub fn op_intersect_chamfer(&mut self, lhs: NodeId, rhs: NodeId, size: f32) -> NodeId {
+        self.create_node(Node::IntersectChamfer { lhs, rhs, size })
+    }
+
+    /// See [`Node::UnionStairs`].
+    pub fn op_union_stairs(&mut self, lhs: NodeId, rhs: NodeId, size: f32, steps: u32) -> NodeId {
+        self.create_node(Node::UnionStairs {
+            lhs,
+            rhs,
+            size,
+            steps,
+        })
+    }
+
+    /// See [`Node::Pipe`].
+    pub fn op_pipe(&mut self, lhs: NodeId, rhs: NodeId, radius: f32) -> NodeId {
+        self.create_node(Node::Pipe { lhs, rhs, radius })
+    }
+
+    /// See [`Node::Groove`].
+    pub fn op_groove(&mut self, lhs: NodeId, rhs: NodeId, depth: f32, half_width: f32) -> NodeId {
+        self.create_node(Node::Groove {
+            lhs,
+            rhs,
+            depth,
+            half_width,
+        })
+    }
+
     pub fn op_csg(&mut self, lhs: NodeId, op: CsgOp, rhs: NodeId) -> NodeId {
         match op {
             CsgOp::Union => self.create_node(Node::Union { lhs, rhs }),
@@ -375,6 +1752,14 @@ impl Graph {
         }
     }
 
+    pub fn op_csg_chamfer(&mut self, lhs: NodeId, op: CsgOp, rhs: NodeId, size: f32) -> NodeId {
+        match op {
+            CsgOp::Union => self.create_node(Node::UnionChamfer { lhs, rhs, size }),
+            CsgOp::Subtract => self.create_node(Node::SubtractChamfer { lhs, rhs, size }),
+            CsgOp::Intersect => self.create_node(Node::IntersectChamfer { lhs, rhs, size }),
+        }
+    }
+
     pub fn op_rotate(&mut self, child: NodeId, rotation: impl Into<Quat>) -> NodeId {
         self.create_node(Node::Rotate {
             rotation: rotation.into(),
@@ -389,6 +1774,45 @@ impl Graph {
         })
     }
 
+    /// Transform the child by any affine transform, see [`Node::Transform`].
+    ///
+    /// For just translation, rotation and uniform scale, this is equivalent to (but cheaper than)
+    /// [`Self::op_scale`], then [`Self::op_rotate`], then [`Self::op_translate`].
+    pub fn op_affine(&mut self, child: NodeId, matrix: Affine3A) -> NodeId {
+        self.create_node(Node::Transform { matrix, child })
+    }
+
+    /// Stretch the child by `amount` in each direction, see [`Node::Elongate`].
+    pub fn op_elongate(&mut self, child: NodeId, amount: impl Into<Vec3>) -> NodeId {
+        self.create_node(Node::Elongate {
+            amount: amount.into(),
+            child,
+        })
+    }
+
+    /// Spin the child, a 2D profile in the XY plane, around the Y axis, see [`Node::Revolve`].
+    pub fn op_revolve(&mut self, child: NodeId, offset: f32) -> NodeId {
+        self.create_node(Node::Revolve { offset, child })
+    }
+
+    /// Sample the child at positions moved along the gradient of `warp` by `strength`, see [`Node::Warp`].
+    pub fn op_warp(&mut self, child: NodeId, warp: NodeId, strength: f32) -> NodeId {
+        self.create_node(Node::Warp {
+            child,
+            warp,
+            strength,
+        })
+    }
+
+    /// Scale the distance of the child by `factor`, in `(0, 1]`, to make it safe to sphere trace,
+    /// see [`Node::ClampDistance`].
+    pub fn op_clamp_distance(&mut self, child: NodeId, factor: f32) -> NodeId {
+        self.create_node(Node::ClampDistance { child, factor })
+    }
+
+    /// Uniformly scale the child.
+    ///
+    /// A negative scale mirrors the child through the origin, i.e. `-1.0` maps `pos` to `-pos`.
     pub fn op_scale(&mut self, child: NodeId, scale: impl Into<f32>) -> NodeId {
         self.create_node(Node::Scale {
             scale: scale.into(),
@@ -396,6 +1820,32 @@ impl Graph {
         })
     }
 
+    /// Like [`Self::op_scale`], but about `pivot` instead of the origin, so `pivot` stays in place.
+    pub fn op_scale_about(
+        &mut self,
+        child: NodeId,
+        pivot: impl Into<Vec3>,
+        scale: impl Into<f32>,
+    ) -> NodeId {
+        let pivot = pivot.into();
+        let mut node = self.op_translate(child, -pivot);
+        node = self.op_scale(node, scale);
+        self.op_translate(node, pivot)
+    }
+
+    /// Like [`Self::op_rotate`], but about `pivot` instead of the origin, so `pivot` stays in place.
+    pub fn op_rotate_about(
+        &mut self,
+        child: NodeId,
+        pivot: impl Into<Vec3>,
+        rotation: impl Into<Quat>,
+    ) -> NodeId {
+        let pivot = pivot.into();
+        let mut node = self.op_translate(child, -pivot);
+        node = self.op_rotate(node, rotation);
+        self.op_translate(node, pivot)
+    }
+
     pub fn op_iso_transform(
         &mut self,
         mut node: NodeId,
@@ -424,23 +1874,374 @@ impl Graph {
 // misc
 impl Graph {
     pub fn get(&self, node_id: NodeId) -> Option<&Node> {
-        self.nodes.get(&node_id)
+        self.nodes.get(node_id.0 as usize)?.as_ref()
     }
 
     pub fn get_mut(&mut self, node_id: NodeId) -> Option<&mut Node> {
-        self.nodes.get_mut(&node_id)
+        self.nodes.get_mut(node_id.0 as usize)?.as_mut()
+    }
+
+    /// The transform from the child's space to the parent's space of a transform node,
+    /// i.e. [`Node::Translate`], [`Node::Rotate`], [`Node::Scale`] or [`Node::Transform`].
+    ///
+    /// `None` for all other nodes.
+    pub fn local_transform(&self, node_id: NodeId) -> Option<Affine3A> {
+        match self.get(node_id)? {
+            Node::Translate { translation, .. } => Some(Affine3A::from_translation(*translation)),
+            Node::Rotate { rotation, .. } => Some(Affine3A::from_quat(*rotation)),
+            Node::Scale { scale, .. } => Some(Affine3A::from_scale(Vec3::splat(*scale))),
+            Node::Transform { matrix, .. } => Some(*matrix),
+            Node::Plane(_)
+            | Node::ConvexHull { .. }
+            | Node::Sphere { .. }
+            | Node::Capsule { .. }
+            | Node::RoundedCylinder { .. }
+            | Node::Tube { .. }
+            | Node::Egg { .. }
+            | Node::Heart { .. }
+            | Node::TaperedCapsule { .. }
+            | Node::Cone { .. }
+            | Node::CappedCone { .. }
+            | Node::RoundedBox { .. }
+            | Node::Torus { .. }
+            | Node::TorusSector { .. }
+            | Node::BiconvexLens { .. }
+            | Node::Vesica { .. }
+            | Node::CutSphere { .. }
+            | Node::CutHollowSphere { .. }
+            | Node::Link { .. }
+            | Node::SolidAngle { .. }
+            | Node::Gyroid { .. }
+            | Node::SchwarzP { .. }
+            | Node::Material { .. }
+            | Node::Union { .. }
+            | Node::UnionMulti { .. }
+            | Node::UnionSmooth { .. }
+            | Node::UnionMultiSmooth { .. }
+            | Node::Subtract { .. }
+            | Node::SubtractSmooth { .. }
+            | Node::Intersect { .. }
+            | Node::IntersectSmooth { .. }
+            | Node::UnionChamfer { .. }
+            | Node::SubtractChamfer { .. }
+            | Node::IntersectChamfer { .. }
+            | Node::UnionStairs { .. }
+            | Node::Pipe { .. }
+            | Node::Groove { .. }
+            | Node::Elongate { .. }
+            | Node::Revolve { .. }
+            | Node::Warp { .. }
+            | Node::ClampDistance { .. }
+            | Node::Graph { .. } => None,
+        }
+    }
+
+    /// Sets the transform of a transform node, see [`Self::local_transform`].
+    ///
+    /// The node keeps its kind if it can represent `transform`, and otherwise becomes a [`Node::Transform`],
+    /// e.g. when rotating a [`Node::Translate`] with a gizmo.
+    ///
+    /// Returns `false`, leaving the graph unchanged, if the node is not a transform node.
+    pub fn set_local_transform(&mut self, node_id: NodeId, transform: Affine3A) -> bool {
+        if self.local_transform(node_id).is_none() {
+            return false;
+        }
+        let node = self.get_mut(node_id).unwrap();
+        let child = node.children()[0];
+
+        let matrix3 = transform.matrix3;
+        let no_translation = transform.translation == glam::Vec3A::ZERO;
+        let (scale, rotation, _) = transform.to_scale_rotation_translation();
+        let uniform_scale = matrix3.x_axis.x;
+
+        *node = if matches!(node, Node::Translate { .. }) && matrix3 == glam::Mat3A::IDENTITY {
+            Node::Translate {
+                translation: transform.translation.into(),
+                child,
+            }
+        } else if matches!(node, Node::Rotate { .. })
+            && no_translation
+            && scale.abs_diff_eq(Vec3::ONE, 1e-5)
+        {
+            Node::Rotate { rotation, child }
+        } else if matches!(node, Node::Scale { .. })
+            && no_translation
+            && matrix3 == glam::Mat3A::from_diagonal(Vec3::splat(uniform_scale))
+        {
+            Node::Scale {
+                scale: uniform_scale,
+                child,
+            }
+        } else {
+            Node::Transform {
+                matrix: transform,
+                child,
+            }
+        };
+        true
+    }
+
+    /// All nodes, in increasing [`NodeId`] order.
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| Some((NodeId(index as u32), node.as_ref()?)))
+    }
+
+    fn insert(&mut self, id: NodeId, node: Node) {
+        let index = id.0 as usize;
+        if self.nodes.len() <= index {
+            self.nodes.resize_with(index + 1, || None);
+        }
+        self.nodes[index] = Some(node);
+    }
+
+    fn remove(&mut self, id: NodeId) {
+        if let Some(node) = self.nodes.get_mut(id.0 as usize) {
+            *node = None;
+        }
+    }
+
+    /// Human-readable JSON, with the nodes sorted by id for stable diffs.
+    #[cfg(feature = "with_json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a graph is always representable as JSON")
+    }
+
+    #[cfg(feature = "with_json")]
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// A human-readable tree of `root` and its descendants, one node per line, indented by depth.
+    ///
+    /// Each line is [`Node::describe`], e.g.
+    /// ```text
+    /// Union
+    ///   Sphere center=[0, 0, 0] radius=1
+    ///   Translate [1, 0, 0]
+    ///     RoundedBox half_size=[1, 1, 1] rounding_radius=0.1
+    /// ```
+    /// Nodes used more than once are printed each time, and nested [`Node::Graph`]s are expanded.
+    pub fn to_tree_string(&self, root: NodeId) -> String {
+        let mut s = String::new();
+        self.write_tree(&mut s, root, 0);
+        s
+    }
+
+    fn write_tree(&self, s: &mut String, id: NodeId, depth: usize) {
+        use std::fmt::Write as _;
+
+        let indent = "  ".repeat(depth);
+        match self.get(id) {
+            None => {
+                let _ = writeln!(s, "{indent}<missing {id:?}>");
+            }
+            Some(node) => {
+                let _ = writeln!(s, "{indent}{}", node.describe());
+                if let Node::Graph { root, graph } = node {
+                    graph.write_tree(s, *root, depth + 1);
+                }
+                for child in node.children() {
+                    self.write_tree(s, child, depth + 1);
+                }
+            }
+        }
+    }
+
+    /// Removes all nodes that can't be reached from `root`.
+    ///
+    /// Useful when the graph is being edited interactively, since nodes are never removed otherwise.
+    pub fn retain_reachable(&mut self, root: NodeId) {
+        let mut reachable = std::collections::HashSet::with_capacity(self.nodes.len());
+        let mut to_visit = vec![root];
+        while let Some(id) = to_visit.pop() {
+            if reachable.insert(id) {
+                if let Some(node) = self.get(id) {
+                    to_visit.extend(node.children());
+                }
+            }
+        }
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if !reachable.contains(&NodeId(index as u32)) {
+                *node = None;
+            }
+        }
+    }
+
+    /// Checks that the graph reachable from `root` can be compiled, i.e. that it has
+    /// no cycles, no references to missing nodes, and no NaN or out of range parameters.
+    ///
+    /// [`crate::compile`] panics on cycles and dangling references, so graphs from
+    /// untrusted sources (e.g. an editor) should be validated first.
+    ///
+    /// Errors in a nested [`Node::Graph`] refer to node ids in that graph.
+    pub fn validate(&self, root: NodeId) -> Result<(), GraphError> {
+        let mut path = Vec::new();
+        let mut validated = std::collections::HashSet::new();
+        self.validate_node(root, &mut path, &mut validated)
+    }
+
+    fn validate_node(
+        &self,
+        id: NodeId,
+        path: &mut Vec<NodeId>,
+        validated: &mut std::collections::HashSet<NodeId>,
+    ) -> Result<(), GraphError> {
+        if path.contains(&id) {
+            return Err(GraphError::Cycle(id));
+        }
+        if validated.contains(&id) {
+            return Ok(());
+        }
+
+        let node = self.get(id).ok_or(GraphError::DanglingNode(id))?;
+        if let Some(parameter) = node.invalid_parameter() {
+            return Err(GraphError::InvalidParameter {
+                node: id,
+                parameter,
+            });
+        }
+        if let Node::Graph { root, graph } = node {
+            graph.validate(*root)?;
+        }
+
+        path.push(id);
+        for child in node.children() {
+            self.validate_node(child, path, validated)?;
+        }
+        path.pop();
+
+        validated.insert(id);
+        Ok(())
+    }
+
+    /// Renumbers the nodes so that the ids are contiguous, starting from zero.
+    ///
+    /// Returns a map from old to new [`NodeId`]s. Best used after [`Self::retain_reachable`].
+    pub fn compact_ids(&mut self) -> HashMap<NodeId, NodeId> {
+        let remap: HashMap<NodeId, NodeId> = self
+            .nodes()
+            .enumerate()
+            .map(|(index, (id, _))| (id, NodeId(index as u32)))
+            .collect();
+
+        self.nodes = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .flatten()
+            .map(|mut node| {
+                for child in node.children_mut() {
+                    // Dangling references must stay dangling after renumbering.
+                    *child = remap.get(child).copied().unwrap_or(NodeId(u32::MAX));
+                }
+                Some(node)
+            })
+            .collect();
+        self.id_allocator = self.nodes.len() as u32;
+        remap
+    }
+
+    /// Merges structurally identical nodes, so that each unique subtree is only stored once,
+    /// and rewrites all references to point at the remaining copy.
+    ///
+    /// Returns a map from every removed [`NodeId`] to the node that replaced it,
+    /// so callers can update any ids (e.g. roots) they are holding on to.
+    ///
+    /// Parameters are compared by their bits, and nested graphs ([`Node::Graph`]) are only merged
+    /// with themselves. Nodes that are part of a cycle are left as they are.
+    pub fn deduplicate(&mut self) -> HashMap<NodeId, NodeId> {
+        let ids: Vec<NodeId> = self.nodes().map(|(id, _)| id).collect();
+
+        let mut canonical = HashMap::with_capacity(ids.len());
+        let mut by_structure = HashMap::with_capacity(ids.len());
+        let mut visiting = std::collections::HashSet::new();
+        for id in ids {
+            self.canonicalize(id, &mut canonical, &mut by_structure, &mut visiting);
+        }
+
+        let remap: HashMap<NodeId, NodeId> = canonical
+            .into_iter()
+            .filter(|(id, canonical_id)| id != canonical_id)
+            .collect();
+        for id in remap.keys() {
+            self.remove(*id);
+        }
+        remap
     }
 
-    pub fn nodes(&self) -> impl Iterator<Item = (&NodeId, &Node)> {
-        self.nodes.iter()
+    /// `visiting` holds the nodes above `id`, to stop at cycles.
+    fn canonicalize(
+        &mut self,
+        id: NodeId,
+        canonical: &mut HashMap<NodeId, NodeId>,
+        by_structure: &mut HashMap<StructureKey, NodeId>,
+        visiting: &mut std::collections::HashSet<NodeId>,
+    ) -> NodeId {
+        if let Some(&canonical_id) = canonical.get(&id) {
+            return canonical_id;
+        }
+        if !visiting.insert(id) {
+            return id; // part of a cycle, leave it be
+        }
+        let Some(mut node) = self.get(id).cloned() else {
+            return id; // dangling reference, leave it be
+        };
+
+        for child in node.children_mut() {
+            *child = self.canonicalize(*child, canonical, by_structure, visiting);
+        }
+        visiting.remove(&id);
+
+        // With the children canonicalized, two nodes are identical iff their keys are.
+        let canonical_id = *by_structure.entry(node.structure_key(id)).or_insert(id);
+        self.insert(id, node);
+        canonical.insert(id, canonical_id);
+        canonical_id
     }
 
+    /// The bounding box of `node`, visiting subtrees shared by several nodes only once.
     pub fn bounding_box(&self, node: NodeId) -> BoundingBox {
-        let node = self.nodes.get(&node).unwrap();
+        self.memoized_bounding_box(node, &mut HashMap::new())
+    }
+
+    /// The bounding boxes of `root` and every node below it.
+    ///
+    /// Unlike calling [`Self::bounding_box`] for each node, every bounding box is only computed once,
+    /// even for subtrees shared by several nodes.
+    pub fn compute_all_bounding_boxes(&self, root: NodeId) -> HashMap<NodeId, BoundingBox> {
+        let mut boxes = HashMap::new();
+        self.memoized_bounding_box(root, &mut boxes);
+        boxes
+    }
+
+    /// Each bounding box is computed once, and then looked up in `boxes`.
+    fn memoized_bounding_box(
+        &self,
+        node: NodeId,
+        boxes: &mut HashMap<NodeId, BoundingBox>,
+    ) -> BoundingBox {
+        if let Some(bbox) = boxes.get(&node) {
+            return *bbox;
+        }
+        let bbox =
+            self.node_bounding_box(node, &mut |child| self.memoized_bounding_box(child, boxes));
+        boxes.insert(node, bbox);
+        bbox
+    }
+
+    /// The bounding box of `node`, given the bounding boxes of its children.
+    fn node_bounding_box(
+        &self,
+        node: NodeId,
+        child_box: &mut dyn FnMut(NodeId) -> BoundingBox,
+    ) -> BoundingBox {
+        let node = self.get(node).unwrap();
 
         #[allow(clippy::match_same_arms)] // nicer to have the cases separately here, for now?
         match node {
             Node::Plane { .. } => BoundingBox::everything(),
+            Node::ConvexHull { planes } => convex_hull_bounding_box(planes),
             Node::Sphere { center, radius } => {
                 BoundingBox::from_center_size(*center, Vec3::splat(2.0 * radius))
             }
@@ -465,6 +2266,34 @@ impl Graph {
                 Vec3::new(-*cylinder_radius, -*half_height, -*cylinder_radius),
                 Vec3::new(*cylinder_radius, *half_height, *cylinder_radius),
             ),
+            Node::Tube {
+                outer_r,
+                half_height,
+                ..
+            } => BoundingBox::from_min_max(
+                Vec3::new(-*outer_r, -*half_height, -*outer_r),
+                Vec3::new(*outer_r, *half_height, *outer_r),
+            ),
+            Node::Egg { radius, tip_radius } => BoundingBox::from_min_max(
+                Vec3::splat(-*radius),
+                Vec3::new(
+                    *radius,
+                    3f32.sqrt() * (radius - tip_radius) + tip_radius,
+                    *radius,
+                ),
+            ),
+            Node::Heart {
+                size,
+                half_thickness,
+            } => {
+                // The lobes are circles of radius sqrt(2) / 4 around [±0.25, 0.75].
+                let lobe_radius = std::f32::consts::SQRT_2 / 4.0;
+                let half_width = (0.25 + lobe_radius) * size;
+                BoundingBox::from_min_max(
+                    Vec3::new(-half_width, 0.0, -half_thickness),
+                    Vec3::new(half_width, (0.75 + lobe_radius) * size, *half_thickness),
+                )
+            }
             Node::TaperedCapsule { points, radii } => {
                 let min = Vec3::new(
                     (points[0].x - radii[0]).min(points[1].x - radii[1]),
@@ -482,6 +2311,13 @@ impl Graph {
                 Vec3::new(-radius, 0.0, -radius),
                 Vec3::new(*radius, *height, *radius),
             ),
+            Node::CappedCone { r1, r2, height } => {
+                let radius = r1.max(*r2);
+                BoundingBox::from_min_max(
+                    Vec3::new(-radius, 0.0, -radius),
+                    Vec3::new(radius, *height, radius),
+                )
+            }
             Node::RoundedBox { half_size, .. } => {
                 BoundingBox::from_center_size(Vec3::ZERO, *half_size * 2.0)
             }
@@ -523,59 +2359,145 @@ impl Graph {
                     Vec3::new(chord_radius, *upper_sagitta, chord_radius),
                 )
             }
-
-            Node::Material { child, .. } => self.bounding_box(*child),
-            Node::Union { lhs, rhs } => self.bounding_box(*lhs).union(self.bounding_box(*rhs)),
-            Node::UnionSmooth { lhs, rhs, size: _ } => {
-                // The smooth union operator sometimes makes the surface
-                // grow outside the bounding boxes of the parts, which is unfortunate.
-                // So what we should do here is probably expand by `size`, but in practice
-                // this is seldome needed and makes the bounding boxes a lot larger
-                // TODO: Find a smooth union operator
-                // that never grows outside the original bounding boxes?
-                self.bounding_box(*lhs).union(self.bounding_box(*rhs))
-                // .expanded(Vec3::splat(*size))
+            Node::Vesica { radius, distance } => {
+                let half_width = radius - distance;
+                let half_height = (radius * radius - distance * distance).max(0.0).sqrt();
+                BoundingBox::from_center_size(
+                    Vec3::ZERO,
+                    2.0 * Vec3::new(half_width, half_height, half_width),
+                )
+            }
+            Node::CutSphere { radius, height } => {
+                // Cut above the equator the widest part is the cut itself.
+                let rim_radius = (radius * radius - height * height).max(0.0).sqrt();
+                let half_width = if *height > 0.0 { rim_radius } else { *radius };
+                BoundingBox::from_min_max(
+                    Vec3::new(-half_width, *height, -half_width),
+                    Vec3::new(half_width, *radius, half_width),
+                )
+            }
+            Node::CutHollowSphere {
+                radius,
+                height,
+                thickness,
+            } => {
+                // Cut below the equator the widest part is the rim.
+                let rim_radius = (radius * radius - height * height).max(0.0).sqrt();
+                let half_width = if *height < 0.0 { rim_radius } else { *radius };
+                BoundingBox::from_min_max(
+                    Vec3::new(-half_width, -radius, -half_width),
+                    Vec3::new(half_width, *height, half_width),
+                )
+                .expanded(Vec3::splat(*thickness))
+            }
+            Node::Link {
+                length,
+                big_r,
+                small_r,
+            } => BoundingBox::from_center_size(
+                Vec3::ZERO,
+                2.0 * Vec3::new(big_r + small_r, length + big_r + small_r, *small_r),
+            ),
+            Node::SolidAngle {
+                sin_cos_angle,
+                radius,
+            } => {
+                let (sin, cos) = *sin_cos_angle;
+                // Wider than a half sphere reaches the full radius sideways, and below the tip.
+                let half_width = if cos > 0.0 { radius * sin } else { *radius };
+                BoundingBox::from_min_max(
+                    Vec3::new(-half_width, (radius * cos).min(0.0), -half_width),
+                    Vec3::new(half_width, *radius, half_width),
+                )
+            }
+            Node::Gyroid { .. } | Node::SchwarzP { .. } => BoundingBox::everything(),
+
+            Node::Material { child, .. } => child_box(*child),
+            Node::Union { lhs, rhs } => child_box(*lhs).union(child_box(*rhs)),
+            Node::UnionSmooth { lhs, rhs, size } => {
+                // The smooth union operator can make the surface grow outside
+                // the bounding boxes of the parts. The polynomial smooth min subtracts
+                // at most `size / 4` from the distance, so that's as far as it can bulge.
+                child_box(*lhs)
+                    .union(child_box(*rhs))
+                    .expanded(Vec3::splat(smooth_union_padding(*size)))
             }
             Node::UnionMulti { children } => {
                 let mut bbox = BoundingBox::nothing();
                 for child in children.iter() {
-                    bbox = bbox.union(self.bounding_box(*child));
+                    bbox = bbox.union(child_box(*child));
                 }
                 bbox
             }
-            Node::UnionMultiSmooth { children, size: _ } => {
+            Node::UnionMultiSmooth { children, size } => {
                 let mut bbox = BoundingBox::nothing();
                 for child in children.iter() {
-                    bbox = bbox.union(self.bounding_box(*child));
+                    bbox = bbox.union(child_box(*child));
                 }
-                bbox
+                // Compiled as a chain of smooth unions, each of which can bulge.
+                let num_unions = children.len().saturating_sub(1) as f32;
+                bbox.expanded(Vec3::splat(num_unions * smooth_union_padding(*size)))
+            }
+            Node::Subtract { lhs, .. } => child_box(*lhs),
+            Node::SubtractSmooth { lhs, .. } => child_box(*lhs),
+            Node::Intersect { lhs, rhs } => child_box(*lhs).intersection(child_box(*rhs)),
+            Node::IntersectSmooth { lhs, rhs, .. } => child_box(*lhs).intersection(child_box(*rhs)),
+            // The bevel only adds to where both children are within `size`.
+            Node::UnionChamfer { lhs, rhs, size } => {
+                let (lhs, rhs) = (child_box(*lhs), child_box(*rhs));
+                let padding = Vec3::splat(size.max(0.0));
+                lhs.union(rhs)
+                    .union(lhs.expanded(padding).intersection(rhs.expanded(padding)))
             }
-            Node::Subtract { lhs, .. } => self.bounding_box(*lhs),
-            Node::SubtractSmooth { lhs, .. } => self.bounding_box(*lhs),
-            Node::Intersect { lhs, rhs } => self
-                .bounding_box(*lhs)
-                .intersection(self.bounding_box(*rhs)),
-            Node::IntersectSmooth { lhs, rhs, .. } => self
-                .bounding_box(*lhs)
-                .intersection(self.bounding_box(*rhs)),
-            Node::Translate { translation, child } => {
-                self.bounding_box(*child).translated(*translation)
+            Node::SubtractChamfer { lhs, .. } => child_box(*lhs),
+            Node::IntersectChamfer { lhs, rhs, .. } => {
+                child_box(*lhs).intersection(child_box(*rhs))
             }
-            Node::Rotate { rotation, child } => {
-                self.bounding_box(*child).rotated_around_origin(rotation)
+            // The stairs stay within `size` of both children.
+            Node::UnionStairs { lhs, rhs, size, .. } => child_box(*lhs)
+                .union(child_box(*rhs))
+                .expanded(Vec3::splat(size.max(0.0))),
+            Node::Pipe { lhs, rhs, radius } => {
+                let padding = Vec3::splat(radius.max(0.0));
+                child_box(*lhs)
+                    .expanded(padding)
+                    .intersection(child_box(*rhs).expanded(padding))
             }
+            Node::Groove { lhs, .. } => child_box(*lhs),
+            Node::Translate { translation, child } => child_box(*child).translated(*translation),
+            Node::Rotate { rotation, child } => child_box(*child).rotated_around_origin(rotation),
             Node::Scale { scale, child } => {
-                assert!(*scale >= 0.0, "TODO: prevent and/or support negative scale");
-                let mut bbox = self.bounding_box(*child);
-                bbox.min *= *scale;
-                bbox.max *= *scale;
-                bbox
+                let bbox = child_box(*child);
+                // A negative scale swaps min and max.
+                let (a, b) = (bbox.min * *scale, bbox.max * *scale);
+                BoundingBox::from_min_max(a.min(b), a.max(b))
+            }
+            Node::Transform { matrix, child } => child_box(*child).transformed(matrix),
+            Node::Elongate { amount, child } => child_box(*child).expanded(*amount),
+            Node::ClampDistance { child, .. } => child_box(*child),
+            // The gradient of a distance field is at most one unit long.
+            Node::Warp {
+                child, strength, ..
+            } => child_box(*child).expanded(Vec3::splat(strength.abs())),
+            Node::Revolve { offset, child } => {
+                // The profile is only evaluated at distances from the axis of `x + offset >= 0`.
+                let profile = child_box(*child);
+                let radius = (profile.max.x + offset).max(0.0);
+                BoundingBox::from_min_max(
+                    Vec3::new(-radius, profile.min.y, -radius),
+                    Vec3::new(radius, profile.max.y, radius),
+                )
             }
             Node::Graph { graph, root } => graph.bounding_box(*root),
         }
     }
 }
 
+/// How far a smooth union can grow outside the bounding boxes of its parts.
+fn smooth_union_padding(size: f32) -> f32 {
+    0.25 * size.max(0.0)
+}
+
 /// Allows you to animate and play with the example scene.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
@@ -698,3 +2620,658 @@ impl Graph {
         self.op_union_multi(nodes)
     }
 }
+
+/// Scales `plane` so that its normal (xyz) is unit length, which keeps the distance the same
+/// along the normal. Zero or non-finite normals are returned unchanged, and so are normals
+/// that are already unit length up to rounding, so that normalizing is idempotent.
+fn normalize_plane(plane: Vec4) -> Vec4 {
+    let length = plane.truncate().length();
+    if length > 0.0 && length.is_finite() && (length - 1.0).abs() > 1e-6 {
+        plane / length
+    } else {
+        plane
+    }
+}
+
+/// The bounding box of the edges of the polytope behind `planes`, or everything if it is unbounded.
+///
+/// This is `O(n³)` in the number of planes: every pair of planes meets in a line,
+/// which is clipped against all other planes to get an edge.
+fn convex_hull_bounding_box(planes: &[Vec4]) -> BoundingBox {
+    // Within a big box, every corner is at the end of an edge.
+    // Corners on the big box mean the polytope is unbounded.
+    const LIMIT: f32 = 1e6;
+    let mut all_planes = planes.to_vec();
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+        all_planes.push(axis.extend(-LIMIT));
+        all_planes.push((-axis).extend(-LIMIT));
+    }
+
+    let mut bb = BoundingBox::nothing();
+    for (i, a) in all_planes.iter().enumerate() {
+        for (j, b) in all_planes.iter().enumerate().skip(i + 1) {
+            let (normal_a, normal_b) = (a.truncate(), b.truncate());
+            let cross = normal_a.cross(normal_b);
+            let cross_length_squared = cross.length_squared();
+            if cross_length_squared < 1e-12 {
+                continue;
+            }
+            // The point on the line that is closest to the origin, and the direction of the line.
+            let origin =
+                (-a.w * normal_b.cross(cross) - b.w * cross.cross(normal_a)) / cross_length_squared;
+            let direction = cross / cross_length_squared.sqrt();
+            let tolerance = 1e-4 * (1.0 + origin.abs().max_element());
+
+            let (mut t_min, mut t_max) = (f32::NEG_INFINITY, f32::INFINITY);
+            for (k, plane) in all_planes.iter().enumerate() {
+                if k == i || k == j {
+                    continue;
+                }
+                let along = plane.truncate().dot(direction);
+                let at_origin = plane.truncate().dot(origin) + plane.w;
+                if along.abs() < 1e-9 {
+                    if at_origin > tolerance {
+                        t_max = f32::NEG_INFINITY;
+                    }
+                } else if along > 0.0 {
+                    t_max = t_max.min(-at_origin / along);
+                } else {
+                    t_min = t_min.max(-at_origin / along);
+                }
+                if t_min > t_max + tolerance {
+                    break;
+                }
+            }
+            // Edges that are a single corner can end up slightly reversed.
+            if t_min <= t_max + tolerance && t_min.is_finite() && t_max.is_finite() {
+                bb.extend(origin + t_min * direction);
+                bb.extend(origin + t_max * direction);
+            }
+        }
+    }
+
+    if bb.is_something()
+        && (bb.min.min_element() <= -0.5 * LIMIT || bb.max.max_element() >= 0.5 * LIMIT)
+    {
+        BoundingBox::everything()
+    } else {
+        bb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn retain_reachable() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let _dangling_box = graph.rounded_box(Vec3::ONE, 0.1);
+        let capsule = graph.capsule_y(2.0, 0.5);
+        let _dangling_union = graph.op_union(sphere, capsule);
+        let root = graph.op_subtract(sphere, capsule);
+        let _dangling_torus = graph.torus(1.0, 0.2);
+
+        let program = compile(&graph, root);
+
+        graph.retain_reachable(root);
+        assert_eq!(graph.nodes().count(), 3);
+        assert_eq!(compile(&graph, root), program);
+
+        let remap = graph.compact_ids();
+        let root = remap[&root];
+        assert!(graph.nodes().all(|(id, _)| id.0 < 3));
+        assert_eq!(compile(&graph, root), program);
+    }
+
+    #[test]
+    fn solid_angle() {
+        // 90° wide
+        let mut graph = Graph::default();
+        let node = graph.solid_angle(std::f32::consts::FRAC_PI_4, 1.0);
+        let distance = |pos| crate::surface_distance_to(&graph, node, pos);
+
+        assert!(distance(Vec3::new(0.0, 0.5, 0.0)) < 0.0);
+        assert!(distance(Vec3::new(0.4, 0.5, 0.0)) < 0.0);
+        assert!(distance(Vec3::new(0.0, 0.5, -0.4)) < 0.0);
+        assert!(distance(Vec3::new(0.5, 0.4, 0.0)) > 0.0);
+        assert!(distance(Vec3::new(0.0, -0.5, 0.0)) > 0.0);
+        assert!(distance(Vec3::new(0.0, 1.1, 0.0)) > 0.0);
+
+        let bb = graph.bounding_box(node);
+        assert!(bb.contains(Vec3::new(0.0, 1.0, 0.0)));
+        assert!(bb.contains(Vec3::new(0.7, 0.7, 0.0)));
+        assert!(!bb.contains(Vec3::new(0.0, -0.1, 0.0)));
+    }
+
+    #[test]
+    fn compute_all_bounding_boxes() {
+        let mut graph = Graph::default();
+        let root = graph.example(&ExampleParams::default());
+        let boxes = graph.compute_all_bounding_boxes(root);
+        assert!(boxes.contains_key(&root));
+        for (node, bbox) in &boxes {
+            assert_eq!(*bbox, graph.bounding_box(*node));
+        }
+
+        // Every level uses the one below twice, so computing the boxes without caching takes 2^40 steps.
+        let mut node = graph.sphere(Vec3::ZERO, 1.0);
+        for _ in 0..40 {
+            let translated = graph.op_translate(node, Vec3::X);
+            node = graph.op_union(translated, node);
+        }
+        assert_eq!(graph.compute_all_bounding_boxes(node).len(), 1 + 40 * 2);
+        assert_eq!(graph.bounding_box(node).max.x, 41.0);
+    }
+
+    #[test]
+    fn parameters() {
+        let mut node = Node::RoundedBox {
+            half_size: Vec3::ONE,
+            rounding_radius: 0.1,
+        };
+        let kinds: Vec<_> = node
+            .parameters()
+            .iter()
+            .map(|p| (p.name, p.kind()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("half_size", ParamKind::Vec3),
+                ("rounding_radius", ParamKind::Scalar)
+            ]
+        );
+
+        assert!(node.set_parameter("rounding_radius", ParamValue::Scalar(0.2)));
+        assert!(!node.set_parameter("rounding_radius", ParamValue::Vec3(Vec3::ONE)));
+        assert!(!node.set_parameter("radius", ParamValue::Scalar(0.3)));
+        assert!(matches!(
+            node,
+            Node::RoundedBox { rounding_radius, .. } if rounding_radius == 0.2
+        ));
+
+        let mut plane = Node::Plane(Vec4::new(0.0, 1.0, 0.0, 2.0));
+        assert!(plane.set_parameter("normal", ParamValue::Vec3(Vec3::X)));
+        assert!(matches!(plane, Node::Plane(p) if p == Vec4::new(1.0, 0.0, 0.0, 2.0)));
+
+        let mut sector = Node::TorusSector {
+            big_r: 1.0,
+            small_r: 0.1,
+            sin_cos_half_angle: (0.0, 1.0),
+        };
+        assert!(sector.set_parameter("half_angle", ParamValue::Angle(2.0)));
+        let Some(ParamValue::Angle(half_angle)) = sector.parameters().last().map(|p| p.value)
+        else {
+            panic!("Expected an angle");
+        };
+        assert!((half_angle - 2.0).abs() < 1e-6);
+
+        // Every parameter can be set to its own value, but not to a value of another kind.
+        let mut graph = Graph::default();
+        graph.example(&Default::default());
+        graph.example_operations(&Default::default());
+        for (_, node) in graph.nodes() {
+            let mut node = node.clone();
+            for parameter in node.parameters() {
+                assert!(node.set_parameter(parameter.name, parameter.value));
+                let wrong_kind = if parameter.kind() == ParamKind::Scalar {
+                    ParamValue::Quat(Quat::IDENTITY)
+                } else {
+                    ParamValue::Scalar(1.0)
+                };
+                assert!(!node.set_parameter(parameter.name, wrong_kind));
+            }
+        }
+    }
+
+    #[test]
+    fn local_transform() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let translated = graph.op_translate(sphere, Vec3::X);
+        let rotated = graph.op_rotate(sphere, Quat::from_rotation_y(1.0));
+        let scaled = graph.op_scale(sphere, 2.0);
+
+        assert_eq!(graph.local_transform(sphere), None);
+        assert!(!graph.set_local_transform(sphere, Affine3A::IDENTITY));
+        assert_eq!(
+            graph.local_transform(translated),
+            Some(Affine3A::from_translation(Vec3::X))
+        );
+
+        // Transforms the node can represent keep its kind.
+        let translation = Affine3A::from_translation(Vec3::new(1.0, 2.0, 3.0));
+        assert!(graph.set_local_transform(translated, translation));
+        assert_eq!(graph.local_transform(translated), Some(translation));
+        assert!(matches!(
+            graph.get(translated),
+            Some(Node::Translate { .. })
+        ));
+
+        let rotation = Affine3A::from_quat(Quat::from_rotation_x(0.5));
+        assert!(graph.set_local_transform(rotated, rotation));
+        assert!(graph
+            .local_transform(rotated)
+            .unwrap()
+            .abs_diff_eq(rotation, 1e-6));
+        assert!(matches!(graph.get(rotated), Some(Node::Rotate { .. })));
+
+        let scale = Affine3A::from_scale(Vec3::splat(-0.5));
+        assert!(graph.set_local_transform(scaled, scale));
+        assert_eq!(graph.local_transform(scaled), Some(scale));
+        assert!(matches!(graph.get(scaled), Some(Node::Scale { .. })));
+
+        // Others turn it into a general transform, keeping the child.
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::new(1.0, 2.0, 3.0),
+            Quat::from_rotation_z(0.3),
+            Vec3::Y,
+        );
+        for node in [translated, rotated, scaled] {
+            assert!(graph.set_local_transform(node, transform));
+            assert_eq!(graph.local_transform(node), Some(transform));
+            assert_eq!(graph.get(node).unwrap().children(), vec![sphere]);
+        }
+    }
+
+    #[test]
+    fn tree_string() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let rounded_box = graph.rounded_box(Vec3::ONE, 0.1);
+        let moved_box = graph.op_translate(rounded_box, Vec3::X);
+        let union = graph.op_union(sphere, moved_box);
+        let node = graph.op_rgb(union, Vec3::new(1.0, 0.5, 0.0));
+
+        assert_eq!(
+            graph.to_tree_string(node),
+            "\
+Material rgba=[1, 0.5, 0, 1] metallic=0 roughness=0.5
+  Union
+    Sphere center=[0, 0, 0] radius=1
+    Translate [1, 0, 0]
+      RoundedBox half_size=[1, 1, 1] rounding_radius=0.1
+"
+        );
+    }
+
+    #[test]
+    fn plane_is_normalized() {
+        let mut graph = Graph::default();
+        let scaled = graph.plane(Vec4::new(0.0, 2.0, 0.0, -2.0));
+        let unit = graph.plane(Vec4::new(0.0, 1.0, 0.0, -1.0));
+        assert!(
+            matches!(graph.get(scaled), Some(Node::Plane(p)) if *p == Vec4::new(0.0, 1.0, 0.0, -1.0))
+        );
+
+        for y in [-3.0, 0.0, 1.0, 2.5] {
+            let pos = Vec3::new(0.3, y, -0.7);
+            let distance = crate::surface_distance_to(&graph, scaled, pos);
+            assert_eq!(distance, crate::surface_distance_to(&graph, unit, pos));
+            assert_eq!(distance, y - 1.0);
+        }
+
+        let zero = graph.plane(Vec4::new(0.0, 0.0, 0.0, 1.0));
+        assert!(graph.validate(zero).is_err());
+    }
+
+    #[test]
+    fn convex_hull_bounding_box() {
+        let mut graph = Graph::default();
+        let unit_cube = graph.convex_hull(
+            [Vec3::X, Vec3::Y, Vec3::Z, -Vec3::X, -Vec3::Y, -Vec3::Z]
+                .map(|normal| normal.extend(-1.0))
+                .to_vec(),
+        );
+        let bb = graph.bounding_box(unit_cube);
+        assert!((bb.min + Vec3::ONE).abs().max_element() < 1e-4);
+        assert!((bb.max - Vec3::ONE).abs().max_element() < 1e-4);
+
+        // The corners of an octahedron are not where its planes meet the axes.
+        let mut octahedron_planes = vec![];
+        for x in [-1.0, 1.0] {
+            for y in [-1.0, 1.0] {
+                for z in [-1.0, 1.0] {
+                    octahedron_planes.push(Vec4::new(x, y, z, -2.0));
+                }
+            }
+        }
+        let octahedron = graph.convex_hull(octahedron_planes);
+        let bb = graph.bounding_box(octahedron);
+        assert!((bb.min + Vec3::splat(2.0)).abs().max_element() < 1e-4);
+        assert!((bb.max - Vec3::splat(2.0)).abs().max_element() < 1e-4);
+
+        let half_space = graph.convex_hull(vec![Vec4::new(0.0, 1.0, 0.0, 0.0)]);
+        assert!(!graph.bounding_box(half_space).is_finite());
+    }
+
+    #[test]
+    fn vesica() {
+        let mut graph = Graph::default();
+        let node = graph.vesica(1.0, 0.5);
+        let distance = |pos| crate::surface_distance_to(&graph, node, pos);
+
+        // At the center we're `radius - distance` from the sides.
+        assert!((distance(Vec3::ZERO) + 0.5).abs() < 1e-6);
+        // The tips are at `sqrt(1 - 0.5²)`, and we're closest to them along the axis.
+        let tip = 0.75_f32.sqrt();
+        assert!((distance(Vec3::new(0.0, 2.0, 0.0)) - (2.0 - tip)).abs() < 1e-6);
+        assert!(distance(Vec3::new(0.0, 0.0, 0.6)) > 0.0);
+
+        let bb = graph.bounding_box(node);
+        assert_eq!(bb.max, Vec3::new(0.5, tip, 0.5));
+    }
+
+    #[test]
+    fn rounded_cone() {
+        let mut graph = Graph::default();
+        let cone = graph.rounded_cone(0.5, 0.2, 2.0);
+        let capsule = graph.tapered_capsule([Vec3::ZERO, Vec3::new(0.0, 2.0, 0.0)], [0.5, 0.2]);
+        assert!(matches!(
+            graph.get(cone),
+            Some(Node::TaperedCapsule { points, radii })
+                if *points == [Vec3::ZERO, Vec3::new(0.0, 2.0, 0.0)] && *radii == [0.5, 0.2]
+        ));
+        assert_eq!(
+            graph.bounding_box(cone),
+            BoundingBox::from_min_max(Vec3::new(-0.5, -0.5, -0.5), Vec3::new(0.5, 2.2, 0.5))
+        );
+        for i in 0..100 {
+            let t = i as f32;
+            let pos = Vec3::new((t * 0.37).sin(), (t * 0.11).cos() * 3.0, (t * 0.73).sin());
+            assert_eq!(
+                crate::surface_distance_to(&graph, cone, pos),
+                crate::surface_distance_to(&graph, capsule, pos)
+            );
+        }
+
+        // Either end swallowing the other leaves just the bigger sphere.
+        let base = graph.rounded_cone(1.0, 0.2, 0.5);
+        assert!(matches!(
+            graph.get(base),
+            Some(Node::Sphere { center, radius }) if *center == Vec3::ZERO && *radius == 1.0
+        ));
+        let top = graph.rounded_cone(0.2, 1.0, 0.5);
+        assert!(matches!(
+            graph.get(top),
+            Some(Node::Sphere { center, radius }) if *center == Vec3::new(0.0, 0.5, 0.0) && *radius == 1.0
+        ));
+    }
+
+    #[test]
+    fn transform_about_pivot() {
+        let mut graph = Graph::default();
+        let center = Vec3::new(5.0, 0.0, 0.0);
+        let sphere = graph.sphere(center, 1.0);
+        let scaled = graph.op_scale_about(sphere, center, 2.0);
+
+        // Rotating a box about its end swings the rest of it around that end.
+        let cuboid = graph.rounded_box(Vec3::new(1.0, 0.5, 0.5), 0.0);
+        let cuboid = graph.op_translate(cuboid, center + Vec3::new(1.0, 0.0, 0.0));
+        let rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let rotated = graph.op_rotate_about(cuboid, center, rotation);
+
+        let distance = |node, pos| crate::surface_distance_to(&graph, node, pos);
+        assert!((distance(scaled, center) + 2.0).abs() < 1e-5);
+        assert!(distance(scaled, Vec3::new(7.0, 0.0, 0.0)).abs() < 1e-5);
+        assert!(distance(scaled, Vec3::new(5.0, -2.0, 0.0)).abs() < 1e-5);
+        let bb = graph.bounding_box(scaled);
+        assert!(bb.center().distance(center) < 1e-5);
+        assert!(bb.size().distance(Vec3::splat(4.0)) < 1e-5);
+
+        assert!((distance(rotated, center + Vec3::new(0.0, 1.0, 0.0)) + 0.5).abs() < 1e-5);
+        assert!(distance(rotated, center + Vec3::new(1.0, 0.0, 0.0)) > 0.4);
+    }
+
+    #[test]
+    fn import() {
+        let mut inner = Graph::default();
+        let sphere = inner.sphere(Vec3::ZERO, 1.0);
+        let torus = inner.torus(1.0, 0.2);
+        let inner_root = inner.op_union(sphere, torus);
+        let shared = inner.op_translate(inner_root, Vec3::X);
+        let inner_root = inner.op_union(shared, shared);
+
+        let mut innermost = Graph::default();
+        let capsule = innermost.capsule_y(2.0, 0.5);
+        let nested = inner.graph(innermost, capsule);
+        let inner_root = inner.op_subtract(inner_root, nested);
+
+        let mut graph = Graph::default();
+        let cuboid = graph.rounded_box(Vec3::splat(0.5), 0.1);
+        let cuboid = graph.op_translate(cuboid, Vec3::new(0.0, 3.0, 0.0));
+        let nested = graph.graph(inner.clone(), inner_root);
+        let nested = graph.op_union(cuboid, nested);
+        let imported = graph.import(&inner, inner_root);
+        let imported = graph.op_union(cuboid, imported);
+
+        assert_eq!(compile(&graph, imported), compile(&graph, nested));
+        assert_eq!(graph.validate(imported), Ok(()));
+
+        // Shared nodes are imported once, and the nested graph is replaced by its capsule.
+        let mut flattened = Graph::default();
+        let root = flattened.import(&inner, inner_root);
+        assert_eq!(flattened.nodes().count(), inner.nodes().count());
+        assert!(flattened
+            .nodes()
+            .all(|(_, node)| !matches!(node, Node::Graph { .. })));
+        assert_eq!(compile(&flattened, root), compile(&inner, inner_root));
+    }
+
+    #[test]
+    fn smooth_union_bounding_box_contains_blend() {
+        let mut graph = Graph::default();
+        let a = graph.sphere(Vec3::new(-1.05, 0.0, 0.0), 1.0);
+        let b = graph.sphere(Vec3::new(1.05, 0.0, 0.0), 1.0);
+        let plain_union = graph.op_union(a, b);
+        let plain_bb = graph.bounding_box(plain_union);
+
+        for root in [
+            graph.op_union_smooth(a, b, 2.0),
+            graph.op_union_multi_smooth(vec![a, b], 2.0),
+        ] {
+            let bb = graph.bounding_box(root);
+            let mesh = crate::mesh_from_sdf(&graph, root, crate::MeshOptions::low()).unwrap();
+
+            let tolerance = Vec3::splat(1e-3);
+            let max = mesh
+                .positions
+                .iter()
+                .fold(Vec3::splat(f32::MIN), |max, p| max.max(Vec3::from(*p)));
+            let min = mesh
+                .positions
+                .iter()
+                .fold(Vec3::splat(f32::MAX), |min, p| min.min(Vec3::from(*p)));
+
+            // The blend bulges outside the plain union ...
+            assert!(max.y > plain_bb.max.y);
+            // ... but not outside the smooth union box.
+            assert!(bb.expanded(tolerance).contains(min));
+            assert!(bb.expanded(tolerance).contains(max));
+        }
+    }
+
+    #[test]
+    fn union_balanced_stack_depth() {
+        use opensaft_sdf::Interpreter;
+
+        let mut graph = Graph::default();
+        let spheres: Vec<NodeId> = (0..1000)
+            .map(|i| graph.sphere(Vec3::new(i as f32 * 3.0, 0.0, 0.0), 1.0))
+            .collect();
+
+        // A nested chain keeps every left-hand side on the stack.
+        let chain = spheres
+            .iter()
+            .rev()
+            .copied()
+            .reduce(|rhs, lhs| graph.op_union(lhs, rhs))
+            .unwrap();
+        let balanced = graph.op_union_balanced(spheres);
+
+        let eval = |program: &crate::Program, pos: Vec3| {
+            let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+            Interpreter::<f32>::interpret(&mut context, pos)
+        };
+
+        let chain_program = compile(&graph, chain);
+        assert_eq!(eval(&chain_program, Vec3::ZERO), None);
+
+        let balanced_program = compile(&graph, balanced);
+        for i in [0, 1, 499, 999] {
+            let pos = Vec3::new(i as f32 * 3.0, 2.0, 0.0);
+            let d = eval(&balanced_program, pos).unwrap();
+            assert!((d - 1.0).abs() < 1e-4, "{d}");
+        }
+    }
+
+    #[test]
+    fn deterministic_node_order() {
+        let build = || {
+            let mut graph = Graph::default();
+            let root = graph.example(&ExampleParams::default());
+            let _dangling = graph.torus(1.0, 0.2);
+            graph.retain_reachable(root);
+            graph.deduplicate();
+            graph
+        };
+        let (a, b) = (build(), build());
+
+        let ids: Vec<NodeId> = a.nodes().map(|(id, _)| id).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let debug = |graph: &Graph| format!("{:?}", graph.nodes().collect::<Vec<_>>());
+        assert_eq!(debug(&a), debug(&b));
+    }
+
+    #[test]
+    fn validate() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let capsule = graph.capsule_y(2.0, 0.5);
+        let root = graph.op_union(sphere, capsule);
+        assert_eq!(graph.validate(root), Ok(()));
+
+        let example = graph.example(&ExampleParams::default());
+        assert_eq!(graph.validate(example), Ok(()));
+    }
+
+    #[test]
+    fn validate_cycle() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let translated = graph.op_translate(sphere, Vec3::X);
+        let root = graph.op_union(sphere, translated);
+        *graph.get_mut(translated).unwrap() = Node::Translate {
+            translation: Vec3::X,
+            child: root,
+        };
+
+        assert_eq!(graph.validate(root), Err(GraphError::Cycle(root)));
+
+        // Deduplicating stops at the cycle instead of recursing forever.
+        let other_sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let remap = graph.deduplicate();
+        assert_eq!(remap, HashMap::from([(other_sphere, sphere)]));
+    }
+
+    #[test]
+    fn deduplicate_by_bits() {
+        let mut graph = Graph::default();
+        let a = graph.sphere(Vec3::ZERO, 1.0);
+        let b = graph.sphere(Vec3::ZERO, 1.0);
+        let negative_zero = graph.sphere(Vec3::new(-0.0, 0.0, 0.0), 1.0);
+        let nan = graph.sphere(Vec3::ZERO, f32::NAN);
+        let other_nan = graph.sphere(Vec3::ZERO, f32::NAN);
+        let hull = graph.convex_hull(vec![Vec4::X - Vec4::W]);
+        let other_hull = graph.convex_hull(vec![Vec4::Y - Vec4::W]);
+        let stairs = graph.op_union_stairs(a, hull, 0.5, 3);
+        let other_stairs = graph.op_union_stairs(b, hull, 0.5, 4);
+
+        let remap = graph.deduplicate();
+        assert_eq!(remap, HashMap::from([(b, a), (other_nan, nan)]));
+        for id in [negative_zero, other_hull, stairs, other_stairs] {
+            assert!(graph.get(id).is_some());
+        }
+    }
+
+    #[test]
+    fn validate_dangling() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let missing = NodeId(1234);
+        let root = graph.op_union(sphere, missing);
+
+        assert_eq!(graph.validate(root), Err(GraphError::DanglingNode(missing)));
+        assert_eq!(
+            graph.validate(missing),
+            Err(GraphError::DanglingNode(missing))
+        );
+    }
+
+    #[test]
+    fn validate_parameters() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, -1.0);
+        let root = graph.op_translate(sphere, Vec3::X);
+        assert_eq!(
+            graph.validate(root),
+            Err(GraphError::InvalidParameter {
+                node: sphere,
+                parameter: "radius"
+            })
+        );
+
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let root = graph.op_translate(sphere, Vec3::new(f32::NAN, 0.0, 0.0));
+        assert_eq!(
+            graph.validate(root),
+            Err(GraphError::InvalidParameter {
+                node: root,
+                parameter: "translation"
+            })
+        );
+
+        let root = graph.op_union_multi(vec![]);
+        assert_eq!(
+            graph.validate(root),
+            Err(GraphError::InvalidParameter {
+                node: root,
+                parameter: "children"
+            })
+        );
+    }
+
+    #[cfg(feature = "with_json")]
+    #[test]
+    fn json_roundtrip() {
+        let mut graph = Graph::default();
+        let root = graph.example(&ExampleParams::default());
+
+        let json = graph.to_json();
+        let roundtripped = Graph::from_json(&json).unwrap();
+
+        assert_eq!(compile(&roundtripped, root), compile(&graph, root));
+        assert_eq!(roundtripped.to_json(), json);
+    }
+
+    #[cfg(feature = "with_json")]
+    #[test]
+    fn json_with_bad_ids() {
+        let sphere = r#"{"Sphere": {"center": [0.0, 0.0, 0.0], "radius": 1.0}}"#;
+
+        // New nodes get fresh ids, even if the stored allocator is behind the nodes.
+        let json = format!(r#"{{"id_allocator": 0, "nodes": [[3, {sphere}]]}}"#);
+        let mut graph = Graph::from_json(&json).unwrap();
+        let box_ = graph.rounded_box(Vec3::ONE, 0.0);
+        assert_ne!(box_, NodeId(3));
+        assert!(matches!(graph.get(NodeId(3)), Some(Node::Sphere { .. })));
+
+        // Huge ids are rejected instead of allocating room for every id before them.
+        let json = format!(r#"{{"id_allocator": 0, "nodes": [[4000000000, {sphere}]]}}"#);
+        assert!(Graph::from_json(&json).is_err());
+    }
+}
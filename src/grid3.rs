@@ -1,5 +1,13 @@
+use alloc::vec;
+use alloc::vec::Vec;
 use glam::Vec3;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
 
+use crate::progress::Progress;
+use crate::BoundingBox;
+use crate::GridTransform;
+use crate::Material;
 use crate::SignedDistance;
 
 // TODO: use u32 as index? Should be large enough.
@@ -9,10 +17,15 @@ pub type Index3 = [usize; 3];
 /// Stores values on a 3D cube lattice on the coordinates \[0,0,0\] - \[w-1, h-1, d-1\].
 /// A 3D tensor, basically.
 ///
-/// TODO (nummelin): Should we separate storage so we can store just a bitset of inside/outside (which is enough for most uses).
+/// See [`crate::OccupancyGrid`] for storing just a bitset of inside/outside (which is enough for most uses).
 pub struct Grid3<T = f32> {
     size: Index3,
     data: Vec<T>,
+    /// Values with a smaller absolute distance than this are exact, see [`Self::is_truncated_cell`].
+    exact_within: f32,
+    /// The distance between neighboring grid points, in the units of the values:
+    /// one for grid units, until [`Grid3::scale_distances`] changes the units.
+    cell_size: f32,
 }
 
 impl<T> Grid3<T> {
@@ -21,12 +34,33 @@ impl<T> Grid3<T> {
         &self.data
     }
 
+    /// flat data
+    pub fn data_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
     pub fn size(&self) -> Index3 {
         self.size
     }
+
+    /// Iterates over all grid points with their world position,
+    /// given the bounding box the grid was sampled in (as done by [`crate::sample_grid`]).
+    ///
+    /// The positions are those of [`GridTransform::world_from_cell`].
+    pub fn iter_world<'a>(&'a self, bb: &BoundingBox) -> impl Iterator<Item = (Vec3, T)> + 'a
+    where
+        T: Copy,
+    {
+        let [w, h, _] = self.size;
+        let transform = GridTransform::new(*bb, self.size);
+        self.data.iter().enumerate().map(move |(index, value)| {
+            let cell = [index % w, (index / w) % h, index / (w * h)];
+            (transform.world_from_cell(cell), *value)
+        })
+    }
 }
 
-impl<T> std::ops::Index<Index3> for Grid3<T> {
+impl<T> core::ops::Index<Index3> for Grid3<T> {
     type Output = T;
 
     #[inline]
@@ -38,7 +72,7 @@ impl<T> std::ops::Index<Index3> for Grid3<T> {
     }
 }
 
-impl<T> std::ops::IndexMut<Index3> for Grid3<T> {
+impl<T> core::ops::IndexMut<Index3> for Grid3<T> {
     #[inline]
     fn index_mut(&mut self, p: Index3) -> &mut Self::Output {
         debug_assert!(p[0] < self.size[0]);
@@ -48,22 +82,46 @@ impl<T> std::ops::IndexMut<Index3> for Grid3<T> {
     }
 }
 
-impl<T: std::cmp::PartialEq> std::cmp::PartialEq for Grid3<T> {
+impl<T: core::cmp::PartialEq> core::cmp::PartialEq for Grid3<T> {
     fn eq(&self, other: &Self) -> bool {
         self.size == other.size && self.data == other.data
     }
 }
 
-impl<T: SignedDistance + Copy + Clone + Default> Grid3<T> {
+impl<T: Default + Clone> Grid3<T> {
+    /// A grid of default values, e.g. zero distances, or for auxiliary data like masks and labels.
     pub fn new(size: Index3) -> Self {
         Self {
             size,
             data: vec![T::default(); size[0] * size[1] * size[2]],
+            exact_within: f32::INFINITY,
+            cell_size: 1.0,
+        }
+    }
+}
+
+impl<T> Grid3<T> {
+    /// A grid with the value of `f` at every point, so unlike [`Self::set_truncated`], every value is exact.
+    pub fn from_fn_exact(size: Index3, mut f: impl FnMut(Index3) -> T) -> Self {
+        let mut data = Vec::with_capacity(size[0] * size[1] * size[2]);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    data.push(f([x, y, z]));
+                }
+            }
+        }
+        Self {
+            size,
+            data,
+            exact_within: f32::INFINITY,
+            cell_size: 1.0,
         }
     }
 
     /// Set the grid values using the given function.
     pub fn set(&mut self, mut f: impl FnMut(Index3) -> T) {
+        self.exact_within = f32::INFINITY;
         let mut index = 0;
         for z in 0..self.size[2] {
             for y in 0..self.size[1] {
@@ -74,6 +132,40 @@ impl<T: SignedDistance + Copy + Clone + Default> Grid3<T> {
             }
         }
     }
+
+    /// Like [`Self::set`], but runs in parallel when the `with_rayon` feature is enabled.
+    ///
+    /// Unlike [`Self::set_truncated`], every value is exact.
+    #[cfg(feature = "with_rayon")]
+    pub fn set_parallel(&mut self, f: impl Fn(Index3) -> T + Send + Sync)
+    where
+        T: Send,
+    {
+        use rayon::prelude::*;
+
+        self.exact_within = f32::INFINITY;
+        let [w, h, _] = self.size;
+        if w == 0 {
+            return;
+        }
+        self.data
+            .par_chunks_mut(w)
+            .enumerate()
+            .for_each(|(row, x_slice)| {
+                let (y, z) = (row % h, row / h);
+                for (x, value) in x_slice.iter_mut().enumerate() {
+                    *value = f([x, y, z]);
+                }
+            });
+    }
+
+    /// Like [`Self::set`], but runs in parallel when the `with_rayon` feature is enabled.
+    ///
+    /// Unlike [`Self::set_truncated`], every value is exact.
+    #[cfg(not(feature = "with_rayon"))]
+    pub fn set_parallel(&mut self, f: impl Fn(Index3) -> T + Send + Sync) {
+        self.set(f);
+    }
 }
 
 impl<T> Grid3<T>
@@ -126,11 +218,25 @@ where
         Vec3::new(dx, dy, dz) // (should divide by 2 here, but it doesn't matter as we normalize later)
     }
 
+    /// True if the value at `p` may only be an approximate distance, as stored by
+    /// [`Self::set_truncated`] far from the surface.
+    ///
+    /// Every cell within the truncate distance of the surface is exact. Some exact cells
+    /// further away may also count as truncated, but never the other way around.
+    /// Gradients (e.g. [`Self::gradient_clamped`]) next to truncated cells are not reliable.
+    ///
+    /// Always false after [`Self::set`] or [`Self::set_parallel`].
+    /// Values written with [`Self::data_mut`] or by indexing are not tracked,
+    /// so use [`Grid3::scale_distances`] to change their units.
+    pub fn is_truncated_cell(&self, p: Index3) -> bool {
+        self[p].distance().abs() > self.exact_within
+    }
+
     fn set_truncated_span(
         x_slice: &mut [T],
         y: usize,
         z: usize,
-        sdf: impl Fn(Index3) -> T + Send + Sync,
+        mut sdf: impl FnMut(Index3) -> T,
         truncate_dist: f32,
     ) {
         let w = x_slice.len();
@@ -153,9 +259,16 @@ where
         }
     }
 
+    /// Values skipped in [`Self::set_truncated_span`] are copies of a sample more than
+    /// `truncate_dist + 1` away from the surface, so anything closer is exact.
+    fn exact_within_truncated(truncate_dist: f32) -> f32 {
+        truncate_dist + 1.0
+    }
+
     /// Will set all values closer than the given truncate distance
     ///
-    /// Cells outside the given truncate distance will have approximated distances.
+    /// Cells outside the given truncate distance will have approximated distances,
+    /// see [`Self::is_truncated_cell`].
     ///
     /// Will run synchronously regardless of `with_rayon` feature availability.
     pub fn set_truncated_sync(
@@ -163,6 +276,7 @@ where
         sdf: impl Fn(Index3) -> T + Send + Sync,
         truncate_dist: f32,
     ) {
+        self.exact_within = Self::exact_within_truncated(truncate_dist);
         let _d = self.size[2];
         let h = self.size[1];
         let w = self.size[0];
@@ -179,7 +293,8 @@ where
 
     /// Will set all values closer than the given truncate distance
     ///
-    /// Cells outside the given truncate distance will have approximated distances.
+    /// Cells outside the given truncate distance will have approximated distances,
+    /// see [`Self::is_truncated_cell`].
     #[cfg(not(feature = "with_rayon"))]
     pub fn set_truncated(&mut self, sdf: impl Fn(Index3) -> T + Send + Sync, truncate_dist: f32) {
         self.set_truncated_sync(sdf, truncate_dist);
@@ -187,32 +302,259 @@ where
 
     /// Will set all values closer than the given truncate distance
     ///
-    /// Cells outside the given truncate distance will have approximated distances.
+    /// Cells outside the given truncate distance will have approximated distances,
+    /// see [`Self::is_truncated_cell`].
     #[cfg(feature = "with_rayon")]
     pub fn set_truncated(&mut self, sdf: impl Fn(Index3) -> T + Send + Sync, truncate_dist: f32)
     where
         T: Send,
     {
-        let _d = self.size[2];
+        self.set_truncated_with_init(|| (), |(), p| sdf(p), truncate_dist);
+    }
+
+    /// Like [`Self::set_truncated`], but each worker gets its own state from `init`,
+    /// which is passed to `sdf` for every sample, e.g. to reuse an interpreter context.
+    ///
+    /// `init` is called at most once per row along the x axis.
+    #[cfg(not(feature = "with_rayon"))]
+    pub fn set_truncated_with_init<S>(
+        &mut self,
+        init: impl Fn() -> S + Send + Sync,
+        sdf: impl Fn(&mut S, Index3) -> T + Send + Sync,
+        truncate_dist: f32,
+    ) {
+        self.set_truncated_with_progress(init, sdf, truncate_dist, &Progress::none());
+    }
+
+    /// Like [`Self::set_truncated_with_init`], but reports each row along the x axis to `progress`,
+    /// and skips the remaining rows once it's cancelled.
+    #[cfg(not(feature = "with_rayon"))]
+    pub(crate) fn set_truncated_with_progress<S>(
+        &mut self,
+        init: impl Fn() -> S + Send + Sync,
+        sdf: impl Fn(&mut S, Index3) -> T + Send + Sync,
+        truncate_dist: f32,
+        progress: &Progress<'_>,
+    ) {
+        self.exact_within = Self::exact_within_truncated(truncate_dist);
+        let h = self.size[1];
+        let w = self.size[0];
+        let mut state = init();
+
+        self.data
+            .chunks_mut(w)
+            .enumerate()
+            .for_each(|(row, x_slice)| {
+                if progress.is_cancelled() {
+                    return;
+                }
+                Self::set_truncated_span(
+                    x_slice,
+                    row % h,
+                    row / h,
+                    |p| sdf(&mut state, p),
+                    truncate_dist,
+                );
+                progress.row_sampled();
+            });
+    }
+
+    /// Like [`Self::set_truncated`], but each worker gets its own state from `init`,
+    /// which is passed to `sdf` for every sample, e.g. to reuse an interpreter context.
+    ///
+    /// `init` is called at most once per row along the x axis.
+    #[cfg(feature = "with_rayon")]
+    pub fn set_truncated_with_init<S>(
+        &mut self,
+        init: impl Fn() -> S + Send + Sync,
+        sdf: impl Fn(&mut S, Index3) -> T + Send + Sync,
+        truncate_dist: f32,
+    ) where
+        T: Send,
+    {
+        self.set_truncated_with_progress(init, sdf, truncate_dist, &Progress::none());
+    }
+
+    /// Like [`Self::set_truncated_with_init`], but reports each row along the x axis to `progress`,
+    /// and skips the remaining rows once it's cancelled.
+    ///
+    /// The rows are sampled in parallel, so they can finish in any order.
+    #[cfg(feature = "with_rayon")]
+    pub(crate) fn set_truncated_with_progress<S>(
+        &mut self,
+        init: impl Fn() -> S + Send + Sync,
+        sdf: impl Fn(&mut S, Index3) -> T + Send + Sync,
+        truncate_dist: f32,
+        progress: &Progress<'_>,
+    ) where
+        T: Send,
+    {
+        self.exact_within = Self::exact_within_truncated(truncate_dist);
         let h = self.size[1];
         let w = self.size[0];
 
         use rayon::prelude::*;
 
         self.data
-            .par_chunks_mut(w * h)
+            .par_chunks_mut(w)
             .enumerate()
-            .for_each(|(z, xy_plane)| {
-                xy_plane
-                    .par_chunks_mut(w)
-                    .enumerate()
-                    .for_each(|(y, x_slice)| {
-                        Self::set_truncated_span(x_slice, y, z, &sdf, truncate_dist);
-                    });
+            .for_each_init(init, |state, (row, x_slice)| {
+                if progress.is_cancelled() {
+                    return;
+                }
+                Self::set_truncated_span(
+                    x_slice,
+                    row % h,
+                    row / h,
+                    |p| sdf(state, p),
+                    truncate_dist,
+                );
+                progress.row_sampled();
             });
     }
 }
 
+impl Grid3<f32> {
+    /// Like `==`, but allows the values to differ by `epsilon`, relative to their size if that's above one,
+    /// e.g. to compare grids sampled in different ways, which may round differently.
+    pub fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.size == other.size
+            && self
+                .data
+                .iter()
+                .zip(&other.data)
+                .all(|(&a, &b)| crate::math::approx_eq(a, b, epsilon))
+    }
+
+    /// Multiplies every distance by `factor`, e.g. to go from grid units to world units,
+    /// keeping track of which cells are exact (see [`Self::is_truncated_cell`]).
+    pub fn scale_distances(&mut self, factor: f32) {
+        for distance in &mut self.data {
+            *distance *= factor;
+        }
+        self.exact_within *= factor.abs();
+        self.cell_size *= factor.abs();
+    }
+
+    /// A grid with half the resolution, averaging each 2×2×2 block of samples, e.g. for LOD pyramids.
+    ///
+    /// Sample `i` of the result lies at `2 * i + 0.5` in this grid, i.e. in the middle of its block.
+    /// The distances are halved, so distances in grid units, as when meshing, stay in grid units.
+    ///
+    /// Odd sizes are rounded up, with the last block repeating the last samples.
+    pub fn downsample_2x(&self) -> Self {
+        let size = self.size.map(|n| n.div_ceil(2));
+        let mut data = Vec::with_capacity(size[0] * size[1] * size[2]);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let mut sum = 0.0;
+                    for corner in 0..8 {
+                        let p = [
+                            (2 * x + (corner & 1)).min(self.size[0] - 1),
+                            (2 * y + ((corner >> 1) & 1)).min(self.size[1] - 1),
+                            (2 * z + (corner >> 2)).min(self.size[2] - 1),
+                        ];
+                        sum += self[p];
+                    }
+                    data.push(sum / 16.0);
+                }
+            }
+        }
+        // A block with a truncated cell is more than two cells closer to the surface than `exact_within`,
+        // and none of its values are closer than that.
+        let exact_within = ((self.exact_within - 2.0 * self.cell_size) / 2.0).max(0.0);
+        Self {
+            size,
+            data,
+            exact_within,
+            cell_size: self.cell_size,
+        }
+    }
+
+    /// Quantizes the distances to a byte each in grid units, see [`QuantizedDistance`].
+    ///
+    /// Distances in other units, e.g. from [`Self::scale_distances`], are converted to grid units first.
+    pub fn quantized(&self) -> Grid3<QuantizedDistance> {
+        Grid3 {
+            size: self.size,
+            data: self
+                .data
+                .iter()
+                .map(|distance| QuantizedDistance::new(*distance / self.cell_size))
+                .collect(),
+            // Clamped values are not exact either.
+            exact_within: (self.exact_within / self.cell_size)
+                .min(QuantizedDistance::RANGE - QuantizedDistance::STEP),
+            cell_size: 1.0,
+        }
+    }
+}
+
+/// A distance in grid units stored in a single byte, using a quarter of the memory of an `f32`.
+///
+/// Distances are clamped to `±`[`Self::RANGE`] and rounded to steps of [`Self::STEP`].
+/// Only distances near the surface matter for meshing, so this is enough for
+/// [`Grid3::marching_cubes`], see [`Grid3::quantized`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuantizedDistance(i8);
+
+impl QuantizedDistance {
+    /// Distances are clamped to this many grid units.
+    pub const RANGE: f32 = 4.0;
+
+    /// The quantization step, i.e. twice the largest rounding error.
+    pub const STEP: f32 = Self::RANGE / i8::MAX as f32;
+
+    pub fn new(distance: f32) -> Self {
+        let steps = (distance / Self::STEP).round();
+        Self(steps.clamp(-f32::from(i8::MAX), f32::from(i8::MAX)) as i8)
+    }
+}
+
+impl SignedDistance for QuantizedDistance {
+    #[inline]
+    fn infinity() -> Self {
+        Self(i8::MAX)
+    }
+
+    #[inline]
+    fn distance(&self) -> f32 {
+        f32::from(self.0) * Self::STEP
+    }
+
+    #[inline]
+    fn copy_with_distance(&self, distance: f32) -> Self {
+        Self::new(distance)
+    }
+
+    #[inline]
+    fn multiply_distance_by(&self, factor: f32) -> Self {
+        Self::new(self.distance() * factor)
+    }
+
+    #[inline]
+    fn material(&self) -> Material {
+        Material::default()
+    }
+
+    #[inline]
+    fn lerp(&self, b: &Self, t: f32) -> Self {
+        let distance = self.distance();
+        Self::new(distance + (b.distance() - distance) * t)
+    }
+
+    #[inline]
+    fn new_with_distance(_material: Material, distance: f32) -> Self {
+        Self::new(distance)
+    }
+
+    #[inline]
+    fn is_distance_finite(&self) -> bool {
+        true
+    }
+}
+
 // TODO: Optimize updating and meshing by
 // evaluating the sdf recursively / divide and conquer
 // Start with big blocks, test sdf. If distance is bigger than block
@@ -0,0 +1,69 @@
+use crate::BoundingBox;
+use crate::Index3;
+use glam::Vec3;
+
+/// Maps between world positions and positions in a grid sampled over `bb`, e.g. by [`crate::sample_grid`].
+///
+/// Grid point `[0, 0, 0]` is at `bb.min`, and grid point `resolution - 1` is at `bb.max`, with the points
+/// evenly spaced along each axis. The cells are cubes for a bounding box picked by [`crate::sdf_bb_and_resolution`],
+/// unless [`crate::MeshOptions::axis_weights`] are used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridTransform {
+    pub bb: BoundingBox,
+    /// The size of the grid.
+    pub resolution: Index3,
+}
+
+impl GridTransform {
+    pub fn new(bb: BoundingBox, resolution: Index3) -> Self {
+        Self { bb, resolution }
+    }
+
+    /// The size of a grid cell along each axis, in world units.
+    pub fn cell_size(&self) -> Vec3 {
+        let [w, h, d] = self.resolution.map(|r| r as f32 - 1.0);
+        self.bb.size() / Vec3::new(w, h, d)
+    }
+
+    /// The size of the largest side of a grid cell in world units.
+    ///
+    /// This is the unit of the distances in a grid sampled for meshing, so that a distance of one
+    /// is at least one cell along every axis.
+    pub fn world_from_grid_scale(&self) -> f32 {
+        self.cell_size().max_element()
+    }
+
+    /// The inverse of [`Self::world_from_grid_scale`].
+    pub fn grid_from_world_scale(&self) -> f32 {
+        1.0 / self.world_from_grid_scale()
+    }
+
+    pub fn world_from_grid(&self, pos_in_grid: Vec3) -> Vec3 {
+        self.bb.min + self.cell_size() * pos_in_grid
+    }
+
+    pub fn grid_from_world(&self, pos_in_world: Vec3) -> Vec3 {
+        (pos_in_world - self.bb.min) / self.cell_size()
+    }
+
+    /// Turns a normal in the grid, i.e. a gradient of the sampled distances, into one in the world.
+    ///
+    /// Only changes the direction if the cells aren't cubes.
+    pub fn world_from_grid_normal(&self, normal_in_grid: Vec3) -> Vec3 {
+        (normal_in_grid / self.cell_size()).normalize()
+    }
+
+    /// The world position of grid point `cell`.
+    pub fn world_from_cell(&self, [x, y, z]: Index3) -> Vec3 {
+        self.world_from_grid(Vec3::new(x as f32, y as f32, z as f32))
+    }
+
+    /// The grid point closest to `pos_in_world`, clamped to the grid.
+    pub fn nearest_cell(&self, pos_in_world: Vec3) -> Index3 {
+        let pos_in_grid = self.grid_from_world(pos_in_world).round();
+        [0, 1, 2].map(|axis| {
+            let max = self.resolution[axis].saturating_sub(1);
+            (pos_in_grid[axis].max(0.0) as usize).min(max)
+        })
+    }
+}
@@ -0,0 +1,109 @@
+use crate::Interpreter;
+use crate::InterpreterContext;
+use crate::Program;
+use crate::SignedDistance;
+use glam::Vec3;
+use std::marker::PhantomData;
+
+/// Interpreter contexts for a [`Program`], for evaluating it from many threads, e.g. for a multi-threaded raytracer.
+///
+/// Nothing is shared between the threads: each worker makes its own context with [`Self::new_context`]
+/// and reuses it for everything it evaluates, e.g. as the `init` of rayon's `map_init`.
+/// Making a context is cheaper than locking a shared one, so [`Self::with_context`] and [`Self::interpret`]
+/// make a new one for each call.
+/// The contexts borrow the program, so many threads can evaluate it without copying it.
+pub struct InterpreterPool<'a, SD: SignedDistance> {
+    program: &'a Program,
+    carrier: PhantomData<fn() -> SD>,
+}
+
+impl<'a, SD: SignedDistance + Copy + Clone> InterpreterPool<'a, SD> {
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            carrier: PhantomData,
+        }
+    }
+
+    pub fn program(&self) -> &'a Program {
+        self.program
+    }
+
+    /// A context for one worker, to reuse for everything it evaluates.
+    pub fn new_context(&self) -> InterpreterContext<'a, SD> {
+        Interpreter::new_context(&self.program.opcodes, &self.program.constants)
+    }
+
+    /// Calls `f` with a new context, e.g. to evaluate many points along a ray.
+    pub fn with_context<R>(&self, f: impl FnOnce(&mut InterpreterContext<'a, SD>) -> R) -> R {
+        f(&mut self.new_context())
+    }
+
+    /// Evaluates the program at `pos`, see [`Interpreter::interpret`].
+    pub fn interpret(&self, pos: Vec3) -> Option<SD> {
+        self.with_context(|context| Interpreter::interpret(context, pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Ray3;
+    use crate::sphere_tracing::trace;
+    use crate::sphere_tracing::Options;
+    use crate::CompiledSdf;
+    use crate::Graph;
+
+    #[test]
+    fn matches_single_threaded() {
+        let mut graph = Graph::default();
+        let node = graph.example(&Default::default());
+        let program = crate::compile(&graph, node);
+        let pool = &InterpreterPool::<f32>::new(&program);
+        let mut sdf = CompiledSdf::from_program(program.clone()).unwrap();
+
+        let opt = Options::default();
+        let ray = |i: usize| {
+            let t = i as f32;
+            Ray3 {
+                origin: Vec3::new((t * 0.37).sin(), (t * 0.11).cos(), -10.0),
+                dir: Vec3::new((t * 0.73).sin() * 0.1, 0.0, 1.0).normalize(),
+            }
+        };
+        let march = |context: &mut InterpreterContext<'_, f32>, i| {
+            let sd = |pos| Interpreter::interpret(context, pos).unwrap();
+            trace(sd, ray(i), 0.0..=100.0, &opt)
+        };
+
+        #[cfg(feature = "with_rayon")]
+        let hits: Vec<_> = {
+            use rayon::prelude::*;
+            (0..1000)
+                .into_par_iter()
+                .map_init(|| pool.new_context(), march)
+                .collect()
+        };
+        #[cfg(not(feature = "with_rayon"))]
+        let hits: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        let mut context = pool.new_context();
+                        (0..250)
+                            .map(|i| march(&mut context, thread * 250 + i))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect()
+        });
+
+        for (i, hit) in hits.into_iter().enumerate() {
+            assert_eq!(hit, sdf.march(ray(i), 0.0..=100.0, &opt));
+        }
+        assert_eq!(pool.interpret(Vec3::ONE), Some(sdf.distance(Vec3::ONE)));
+    }
+}
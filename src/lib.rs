@@ -1,313 +1,1699 @@
 //! Signed distance field function compiler/interpreter/discretizer/mesher.
+//!
+//! Without the `std` feature, only the grid and the mesher are available, for `no_std` targets with `alloc`.
+//! Enable the `libm` feature instead for the math.
 
 // crate-specific exceptions:
 #![forbid(unsafe_code)]
 #![allow(
     clippy::enum_glob_use,      // TODO: Add? Used a lot on the opcodes
 )]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use glam::Vec3;
+extern crate alloc;
 
 pub use opensaft_sdf::*;
 
+#[cfg(feature = "std")]
 mod program;
+#[cfg(feature = "std")]
 pub use program::*;
 
+#[cfg(feature = "std")]
 mod compiler;
+#[cfg(feature = "std")]
 pub use compiler::*;
 
+#[cfg(feature = "std")]
 mod graph;
+#[cfg(feature = "std")]
 pub use graph::*;
 
 mod grid3;
 pub use grid3::*;
 
+mod grid_transform;
+pub use grid_transform::*;
+
 mod mesh;
 pub use mesh::*;
 
+#[cfg(feature = "std")]
+mod adjacency;
+#[cfg(feature = "std")]
+pub use adjacency::*;
+
 mod marching_cubes;
 pub use marching_cubes::*;
 
+#[cfg(feature = "std")]
+mod surface_nets;
+
+mod progress;
+
+#[cfg(feature = "std")]
+mod mesh_cache;
+#[cfg(feature = "std")]
+pub use mesh_cache::*;
+
+#[cfg(feature = "std")]
+mod occupancy;
+#[cfg(feature = "std")]
+pub use occupancy::*;
+
+#[cfg(feature = "std")]
 pub mod sphere_tracing;
 
+#[cfg(feature = "std")]
 mod trace;
+#[cfg(feature = "std")]
 pub use trace::*;
 
+#[cfg(feature = "std")]
+mod compiled_sdf;
+#[cfg(feature = "std")]
+pub use compiled_sdf::*;
+
+#[cfg(feature = "std")]
+mod interpreter_pool;
+#[cfg(feature = "std")]
+pub use interpreter_pool::*;
+
+#[cfg(feature = "std")]
 mod codegen;
+#[cfg(feature = "std")]
 pub use codegen::*;
 
+#[cfg(feature = "with_spirv")]
+mod spirv_codegen;
+#[cfg(feature = "with_spirv")]
+pub use spirv_codegen::SPIRV_WORKGROUP_SIZE;
+
 mod math;
 pub use math::*;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
-#[cfg_attr(feature = "with_speedy", derive(speedy::Writable, speedy::Readable))]
-pub struct MeshOptions {
-    /// Desired mean resolution on the axis.
-    ///
-    /// the total number of grid points will be close to resolution ^ 3
-    pub mean_resolution: f32,
-
-    /// Trying to fit the number of cubes to the mean resolution can lead to some
-    /// extreme cases if the box is very narrow. Use these parameters to clamp
-    /// the resolution to desired "sane" bounds.
-    pub max_resolution: f32,
-    pub min_resolution: f32,
-}
+#[cfg(feature = "std")]
+mod meshing;
+#[cfg(feature = "std")]
+pub use meshing::*;
 
-impl MeshOptions {
-    pub fn low() -> Self {
-        Self {
-            mean_resolution: 32.0,
-            max_resolution: 64.0,
-            min_resolution: 8.0,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+    use glam::Vec4;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn it_works() {
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::default(), 1.0);
+
+        let node2 = graph.sphere(Vec3::default(), 0.5);
+        let node3 = graph.op_translate(node2, -Vec3::X);
+        let node4 = graph.op_translate(node2, Vec3::X);
+        let node5 = graph.op_translate(node2, Vec3::Y);
+
+        let union_node = graph.op_union_multi(vec![node, node3, node4, node5]);
+
+        let program = compile(&graph, union_node);
+        let mut grid = Grid3::<f32>::new([64, 64, 64]);
+        grid.set_truncated_sync(
+            |pos| {
+                let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+
+                let pos = Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32);
+                Interpreter::<f32>::interpret(&mut context, pos).unwrap()
+            },
+            2.0,
+        );
+
+        let mut grid2 = Grid3::new([64, 64, 64]);
+        grid2.set_truncated(
+            |pos| {
+                let pos = Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32);
+                sd_op_union(
+                    sd_sphere(pos, Vec3::default(), 1.0),
+                    sd_op_union(
+                        sd_sphere(pos + Vec3::X, Vec3::default(), 0.5),
+                        sd_op_union(
+                            sd_sphere(pos - Vec3::X, Vec3::default(), 0.5),
+                            sd_sphere(pos - Vec3::Y, Vec3::default(), 0.5),
+                        ),
+                    ),
+                )
+            },
+            2.0,
+        );
+
+        // grid and grid2 should be equal, up to rounding.
+        assert!(grid.approx_eq(&grid2, 1e-6));
+    }
+
+    #[test]
+    fn approx_eq() {
+        let grid = Grid3::from_fn_exact([3, 4, 5], |[x, y, z]| (x + y + z) as f32 * 0.25);
+        let mut nudged = Grid3::from_fn_exact([3, 4, 5], |p| grid[p]);
+        nudged[[1, 2, 3]] += 1e-7;
+        assert!(grid.approx_eq(&nudged, 1e-6));
+        assert!(grid != nudged);
+        nudged[[1, 2, 3]] += 1e-3;
+        assert!(!grid.approx_eq(&nudged, 1e-6));
+        assert!(!grid.approx_eq(&Grid3::new([3, 4, 6]), 1.0));
+
+        // Large values are compared relative to their size.
+        let large = Grid3::from_fn_exact([1, 1, 1], |_| 1e6);
+        let large_nudged = Grid3::from_fn_exact([1, 1, 1], |_| 1e6 + 0.5);
+        assert!(large.approx_eq(&large_nudged, 1e-6));
+
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let program = compile(&graph, sphere);
+        let nudged = program.with_constants(program.constants.iter().map(|c| c + 1e-7).collect());
+        assert!(program.approx_eq(&nudged, 1e-6));
+        let bigger = graph.sphere(Vec3::ZERO, 2.0);
+        assert!(!program.approx_eq(&compile(&graph, bigger), 1e-6));
     }
-}
 
-impl Default for MeshOptions {
-    fn default() -> Self {
-        Self {
-            mean_resolution: 64.0,
-            max_resolution: 128.0,
-            min_resolution: 8.0,
+    #[test]
+    fn tpms_lattices() {
+        let mut graph = Graph::default();
+        let bounds = graph.rounded_box(Vec3::splat(4.0), 0.0);
+        for lattice in [graph.gyroid(1.0, 0.3), graph.schwarz_p(1.0, 0.3)] {
+            let node = graph.op_intersect(lattice, bounds);
+            let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+            assert!(!mesh.indices.is_empty());
+            assert!(mesh.is_manifold());
         }
     }
-}
 
-pub fn transform_positions_in_place(
-    mesh: &mut TriangleMesh,
-    world_from_grid_f: impl Fn(Vec3) -> Vec3 + Send + Sync,
-) {
-    #[cfg(feature = "with_rayon")]
-    {
-        use rayon::prelude::*;
-
-        mesh.positions.par_iter_mut().for_each(|p| {
-            // transform to world:
-            *p = world_from_grid_f((*p).into()).into();
-        });
+    #[test]
+    fn link_is_a_single_loop() {
+        let mut graph = Graph::default();
+        let node = graph.link(0.5, 1.0, 0.25);
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        assert!(mesh.is_manifold());
+
+        // A closed genus-1 surface has an Euler characteristic of zero.
+        let num_faces = mesh.indices.len() / 3;
+        let num_edges = 3 * num_faces / 2;
+        let num_vertices = mesh.positions.len();
+        assert_eq!(num_vertices + num_faces, num_edges);
     }
 
-    #[cfg(not(feature = "with_rayon"))]
-    {
-        mesh.positions.iter_mut().for_each(|p| {
-            // transform to world:
-            *p = world_from_grid_f((*p).into()).into();
-        });
+    #[test]
+    fn cut_sphere_at_zero_is_hemisphere() {
+        let mut graph = Graph::default();
+        let node = graph.cut_sphere(1.0, 0.0);
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+
+        let bb = BoundingBox::from_points(mesh.positions.iter().map(|p| Vec3::from(*p)));
+        assert!(bb.min.y.abs() < 1e-3, "flat bottom at {}", bb.min.y);
+        assert!((bb.max.y - 1.0).abs() < 0.05);
+        assert!((bb.max.x - 1.0).abs() < 0.05);
+
+        // The flat bottom is a disc of radius one.
+        let num_bottom = mesh.positions.iter().filter(|p| p[1].abs() < 1e-3).count();
+        assert!(num_bottom > 10);
     }
-}
 
-pub fn gather_colors_in_place(
-    mesh: &mut TriangleMesh,
-    color_world: impl Fn(Vec3) -> Vec3 + Send + Sync,
-) {
-    #[cfg(feature = "with_rayon")]
-    {
-        use rayon::prelude::*;
+    #[test]
+    fn elongated_sphere_is_capsule() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 0.5);
+        let node = graph.op_elongate(sphere, Vec3::X);
+        let capsule = graph.capsule([-Vec3::X, Vec3::X], 0.5);
 
-        mesh.colors = mesh
-            .positions
-            .par_iter()
-            .map(|p| color_world(Vec3::new(p[0], p[1], p[2])).into())
-            .collect();
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        let bb = BoundingBox::from_points(mesh.positions.iter().map(|p| Vec3::from(*p)));
+        assert!((bb.size().x - 3.0).abs() < 0.05, "length {}", bb.size().x);
+        assert!((bb.size().y - 1.0).abs() < 0.05);
+        assert!((bb.size().z - 1.0).abs() < 0.05);
+
+        // Same distances as a capsule, inside and out.
+        let program = compile(&graph, node);
+        let capsule_program = compile(&graph, capsule);
+        let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+        let mut capsule_context =
+            Interpreter::new_context(&capsule_program.opcodes, &capsule_program.constants);
+        for pos in [
+            Vec3::ZERO,
+            Vec3::new(0.5, 0.2, 0.0),
+            Vec3::new(1.2, 0.0, 0.1),
+            Vec3::new(-2.0, 1.0, 0.5),
+        ] {
+            let d = Interpreter::<f32>::interpret(&mut context, pos).unwrap();
+            let expected = Interpreter::<f32>::interpret(&mut capsule_context, pos).unwrap();
+            assert!((d - expected).abs() < 1e-5, "{pos}: {d} vs {expected}");
+        }
     }
 
-    #[cfg(not(feature = "with_rayon"))]
-    {
-        mesh.colors = mesh
+    #[test]
+    fn clipped_gyroid() {
+        let mut graph = Graph::default();
+        let node = graph.gyroid(1.0, 0.3);
+        assert!(!graph.bounding_box(node).is_finite());
+
+        let clip_box = BoundingBox::from_center_size(Vec3::ZERO, Vec3::ONE);
+        let mesh = mesh_from_sdf_in_box(&graph, node, &clip_box, MeshOptions::low()).unwrap();
+        assert!(!mesh.positions.is_empty());
+        assert!(mesh.is_manifold());
+
+        let slack = Vec3::splat(0.01);
+        let bounds = BoundingBox::from_min_max(clip_box.min - slack, clip_box.max + slack);
+        assert!(mesh
             .positions
             .iter()
-            .map(|p| color_world(Vec3::new(p[0], p[1], p[2])).into())
-            .collect();
+            .all(|p| bounds.contains(Vec3::from(*p))));
+
+        let far_away = BoundingBox::from_center_size(Vec3::splat(100.0), Vec3::ONE);
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let mesh = mesh_from_sdf_in_box(&graph, sphere, &far_away, MeshOptions::low()).unwrap();
+        assert!(mesh.positions.is_empty());
     }
-}
 
-pub fn mesh_from_sdf_func(
-    bb: &BoundingBox,
-    resolution: [usize; 3],
-    sd_world: impl Fn(Vec3) -> f32 + Send + Sync,
-    color_world: impl Fn(Vec3) -> Vec3 + Send + Sync,
-) -> Result<TriangleMesh, Error> {
-    use glam::*;
+    #[test]
+    fn warp() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let ground = graph.plane(Vec4::new(0.0, 1.0, 0.0, 0.0));
+        let unwarped = graph.op_warp(sphere, ground, 0.0);
+        let lifted = graph.op_warp(sphere, ground, 0.5);
+
+        let sphere_program = compile(&graph, sphere);
+        let unwarped_program = compile(&graph, unwarped);
+        let lifted_program = compile(&graph, lifted);
+        let mut sphere_context =
+            Interpreter::new_context(&sphere_program.opcodes, &sphere_program.constants);
+        let mut unwarped_context =
+            Interpreter::new_context(&unwarped_program.opcodes, &unwarped_program.constants);
+        let mut lifted_context =
+            Interpreter::new_context(&lifted_program.opcodes, &lifted_program.constants);
+        for pos in [
+            Vec3::ZERO,
+            Vec3::new(0.5, 0.2, 0.0),
+            Vec3::new(1.2, -0.7, 0.1),
+            Vec3::new(-2.0, 1.0, 0.5),
+        ] {
+            let expected = Interpreter::<f32>::interpret(&mut sphere_context, pos).unwrap();
+            let d = Interpreter::<f32>::interpret(&mut unwarped_context, pos).unwrap();
+            assert_eq!(d, expected);
+
+            // Moving along the gradient of the ground plane moves the sphere down.
+            let expected =
+                Interpreter::<f32>::interpret(&mut sphere_context, pos + 0.5 * Vec3::Y).unwrap();
+            let d = Interpreter::<f32>::interpret(&mut lifted_context, pos).unwrap();
+            assert!((d - expected).abs() < 1e-3, "{pos}: {d} vs {expected}");
+        }
+    }
 
-    let world_from_grid_scale = bb.size().x / (resolution[0] as f32 - 1.0);
-    let grid_from_world_scale = 1.0 / world_from_grid_scale;
+    #[test]
+    fn nested_warp() {
+        // The warp field runs once per sample, instead of being compiled four times at every level.
+        let mut graph = Graph::default();
+        let mut warp = graph.gyroid(1.0, 0.1);
+        for _ in 0..6 {
+            let sphere = graph.sphere(Vec3::ZERO, 1.0);
+            warp = graph.op_warp(sphere, warp, 0.1);
+        }
+        let program = compile(&graph, warp);
+        assert!(program.opcodes.len() < 64);
+        assert!(surface_distance_to(&graph, warp, Vec3::new(0.3, 1.2, 0.4)).is_finite());
 
-    let world_from_grid_f = |pos_in_grid: Vec3| bb.min + world_from_grid_scale * pos_in_grid;
+        let (decompiled, root) = decompile(&program, &program.constants).unwrap();
+        assert_eq!(decompiled.nodes().count(), graph.nodes().count());
+        assert_eq!(compile(&decompiled, root), program);
 
-    let world_from_grid_i = |pos_in_grid: Index3| {
-        let pos_in_grid = Vec3::new(
-            pos_in_grid[0] as f32,
-            pos_in_grid[1] as f32,
-            pos_in_grid[2] as f32,
+        // A warp whose field doesn't end right before it is rejected.
+        let mut broken = program.clone();
+        let pop = broken
+            .opcodes
+            .iter()
+            .position(|&opcode| opcode == Opcode::PopWarpSample)
+            .unwrap();
+        broken.opcodes.remove(pop);
+        assert_eq!(
+            decompile(&broken, &broken.constants).unwrap_err(),
+            Error::BadProgram("PushWarp must follow its warp field")
         );
-        world_from_grid_f(pos_in_grid)
-    };
 
-    let sd_in_grid = |pos_in_grid| {
-        let pos_in_world = world_from_grid_i(pos_in_grid);
-        grid_from_world_scale * sd_world(pos_in_world)
-    };
+        // The gradient is estimated at the scale of the shape.
+        let distance = |scale: f32| {
+            let mut graph = Graph::default();
+            let sphere = graph.sphere(Vec3::ZERO, scale);
+            let field = graph.sphere(Vec3::X * scale, scale);
+            let warped = graph.op_warp(sphere, field, 0.5 * scale);
+            surface_distance_to(&graph, warped, Vec3::new(0.3, 1.2, 0.4) * scale) / scale
+        };
+        for scale in [1e-4, 1e4] {
+            assert!((distance(scale) - distance(1.0)).abs() < 1e-3, "{scale}");
+        }
+    }
 
-    let mut grid = Grid3::<f32>::new(resolution);
-    grid.set_truncated(sd_in_grid, 2.0);
+    #[test]
+    fn clamp_distance_prevents_overshoot() {
+        // A gyroid with a scale above one overestimates distances, and so does its smooth union with a sphere.
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::new(-1.0, 0.0, 0.0), 1.0);
+        let gyroid = graph.gyroid(2.0, 0.4);
+        let cube = graph.rounded_box(Vec3::ONE, 0.0);
+        let cube = graph.op_translate(cube, Vec3::X);
+        let lattice = graph.op_intersect(gyroid, cube);
+        let unclamped = graph.op_union_smooth(sphere, lattice, 0.5);
+        let clamped = graph.op_clamp_distance(unclamped, 0.25);
+
+        let mut unclamped_sdf = CompiledSdf::new(&graph, unclamped).unwrap();
+        let mut clamped_sdf = CompiledSdf::new(&graph, clamped).unwrap();
+        let mut distance_sdf = unclamped_sdf.clone();
+        let mut count_overshoots = |sdf: &mut CompiledSdf| {
+            (0..500)
+                .filter(|&i| {
+                    let t = i as f32;
+                    let origin = Vec3::new((t * 0.37).sin() * 3.0, (t * 0.11).cos() * 3.0, -6.0);
+                    let target = Vec3::new((t * 0.73).sin() * 2.0, (t * 0.19).cos() * 1.5, 0.0);
+                    let ray = Ray3 {
+                        origin,
+                        dir: (target - origin).normalize(),
+                    };
+                    let hit = sdf.march(ray, 0.0..=20.0, &Default::default());
+                    // Stepped past the surface, and stopped well inside.
+                    hit.is_hit && distance_sdf.distance(hit.pos) < -0.01
+                })
+                .count()
+        };
+        assert!(count_overshoots(&mut unclamped_sdf) > 0);
+        assert_eq!(count_overshoots(&mut clamped_sdf), 0);
 
-    // Check a single sample for NaN. Often a NaN will end up in the whole grid, so this'll catch it.
-    if !grid.data()[grid.data().len() / 2].is_finite() {
-        return Err(Error::EvaluatedToNaN);
+        // The surface stays where it is.
+        let pos = Vec3::new(0.3, -0.2, 0.1);
+        assert_eq!(
+            clamped_sdf.distance(pos),
+            0.25 * unclamped_sdf.distance(pos)
+        );
+        assert_eq!(graph.bounding_box(clamped), graph.bounding_box(unclamped));
     }
 
-    let mut mesh = grid.marching_cubes();
+    #[test]
+    fn revolved_circle_is_torus() {
+        let (big_r, small_r) = (1.0, 0.25);
+        let mut graph = Graph::default();
+        let circle = graph.sphere(Vec3::ZERO, small_r);
+        let node = graph.op_revolve(circle, big_r);
 
-    transform_positions_in_place(&mut mesh, world_from_grid_f);
-    gather_colors_in_place(&mut mesh, color_world);
+        let program = compile(&graph, node);
+        let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+        for pos in [
+            Vec3::ZERO,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.1, -0.9),
+            Vec3::new(0.7, 0.5, 0.7),
+            Vec3::new(-3.0, 1.0, 2.0),
+        ] {
+            let d = Interpreter::<f32>::interpret(&mut context, pos).unwrap();
+            let expected: f32 = sd_torus(pos, big_r, small_r);
+            assert!((d - expected).abs() < 1e-5, "{pos}: {d} vs {expected}");
+        }
 
-    Ok(mesh)
-}
+        let torus = graph.torus(big_r, small_r);
+        assert_eq!(graph.bounding_box(node), graph.bounding_box(torus));
+    }
 
-pub fn mesh_from_sdf_program(
-    program: &Program,
-    bb: &BoundingBox,
-    resolution: [usize; 3],
-) -> Result<TriangleMesh, Error> {
-    let color_func = |pos_in_world| {
-        let mut rgbd_context = Interpreter::new_context(&program.opcodes, &program.constants);
-        Interpreter::<RgbWithDistance>::interpret(&mut rgbd_context, pos_in_world)
-            .unwrap()
-            .material()
-            .rgb()
-    };
+    /// A floor below y=0 and a wall behind x=0, meeting in a corner along the Z axis.
+    fn floor_and_wall(graph: &mut Graph) -> (NodeId, NodeId) {
+        let floor = graph.rounded_box(Vec3::new(2.0, 0.5, 2.0), 0.0);
+        let floor = graph.op_translate(floor, Vec3::new(0.0, -0.5, 0.0));
+        let wall = graph.rounded_box(Vec3::new(0.5, 2.0, 2.0), 0.0);
+        let wall = graph.op_translate(wall, Vec3::new(-0.5, 0.0, 0.0));
+        (floor, wall)
+    }
 
-    let d_func = |pos_in_world| {
-        let mut d_context = Interpreter::new_context(&program.opcodes, &program.constants);
-        Interpreter::<f32>::interpret(&mut d_context, pos_in_world)
-            .unwrap()
-            .distance()
-    };
+    #[test]
+    fn union_stairs_profile() {
+        let mut graph = Graph::default();
+        let (floor, wall) = floor_and_wall(&mut graph);
+        let plain = graph.op_union(floor, wall);
+        let stairs = graph.op_union_stairs(floor, wall, 1.0, 4);
 
-    mesh_from_sdf_func(bb, resolution, d_func, color_func)
-}
+        let mut plain_sdf = CompiledSdf::new(&graph, plain).unwrap();
+        let mut stairs_sdf = CompiledSdf::new(&graph, stairs).unwrap();
 
-pub fn mesh_from_sdf(
-    graph: &Graph,
-    node: NodeId,
-    options: MeshOptions,
-) -> Result<TriangleMesh, Error> {
-    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options);
-    let program = compile(graph, node);
+        // Three steps, each a quarter wide and a quarter high, filling the corner.
+        for (x, height) in [(0.125, 0.75), (0.375, 0.5), (0.625, 0.25)] {
+            let below = Vec3::new(x, height - 0.05, 0.0);
+            let above = Vec3::new(x, height + 0.05, 0.0);
+            assert!(plain_sdf.distance(below) > 0.0);
+            assert!(stairs_sdf.distance(below) < 0.0, "{below}");
+            assert!(stairs_sdf.distance(above) > 0.0, "{above}");
+        }
+        assert!(stairs_sdf.distance(Vec3::new(0.875, 0.05, 0.0)) > 0.0);
 
-    mesh_from_sdf_program(&program, &bb, resolution)
-}
+        let mesh = mesh_from_sdf(&graph, stairs, MeshOptions::low()).unwrap();
+        let in_corner = |p: &[f32; 3]| p[0] > 0.05 && p[1] > 0.05 && p[2].abs() < 1.5;
+        assert!(mesh.positions.iter().any(in_corner));
+        let plain_mesh = mesh_from_sdf(&graph, plain, MeshOptions::low()).unwrap();
+        assert!(!plain_mesh.positions.iter().any(in_corner));
+    }
 
-/// Pick a good expanded bounding box and grid size from the given tight bounding box
-pub fn sdf_bb_and_resolution(bb: BoundingBox, options: MeshOptions) -> (BoundingBox, [usize; 3]) {
-    assert!(bb.is_finite(), "Bad opensaft bounding box: {:?}", bb);
-    assert!(bb.volume() > 0.0, "Bad opensaft bounding box: {:?}", bb);
-
-    // Add at least this many grid points on each side
-    let grid_padding = 1.0;
-
-    // preliminary so we can pad
-    let grid_from_world_scale = options.mean_resolution / bb.volume().cbrt();
-    let padding = grid_padding / grid_from_world_scale;
-    let bb = bb.expanded(Vec3::splat(padding));
-
-    // now actual:
-    let grid_from_world_scale = options.mean_resolution / bb.volume().cbrt();
-
-    let resolution = [
-        grid_from_world_scale * bb.size().x,
-        grid_from_world_scale * bb.size().y,
-        grid_from_world_scale * bb.size().z,
-    ];
-
-    let max_side = resolution[0].max(resolution[1]).max(resolution[2]);
-    let max_factor = if max_side > options.max_resolution {
-        options.max_resolution / max_side
-    } else {
-        1.0
-    };
-    let min_side = resolution[0].min(resolution[1]).min(resolution[2]);
-    let min_factor = if min_side < options.min_resolution {
-        options.min_resolution / min_side
-    } else {
-        1.0
-    };
-
-    // Let the minimum overrule the maximum.
-    let factor = min_factor.max(max_factor);
-
-    let grid_resolution = [
-        (factor * resolution[0]).ceil() as usize,
-        (factor * resolution[1]).ceil() as usize,
-        (factor * resolution[2]).ceil() as usize,
-    ];
-
-    /*
-    // Useful for debugging the above calculations. Turns out it's not as intuitive as expected to get it right.
-    println!(
-        "max_res: {} min_res: {} max_factor: {} min_factor: {} original_resolution: {:?} grid_resolution: {:?}",
-        options.max_resolution, options.min_resolution, max_factor, min_factor, resolution, grid_resolution
-    );
-    */
-
-    (bb, grid_resolution)
-}
+    #[test]
+    fn union_chamfer_is_flat() {
+        let mut graph = Graph::default();
+        let (floor, wall) = floor_and_wall(&mut graph);
+        let chamfer = graph.op_union_chamfer(floor, wall, 1.0);
+        let smooth = graph.op_union_smooth(floor, wall, 1.0);
 
-pub fn surface_distance_to(graph: &Graph, node: NodeId, pos: Vec3) -> f32 {
-    let program = compile(graph, node);
-    let mut d_context = Interpreter::new_context(&program.opcodes, &program.constants);
-    Interpreter::<f32>::interpret(&mut d_context, pos).unwrap()
-}
+        let mut chamfer_sdf = CompiledSdf::new(&graph, chamfer).unwrap();
+        let mut smooth_sdf = CompiledSdf::new(&graph, smooth).unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // The bevel is the plane `x + y = 1`, with the distance to it growing linearly.
+        for t in [0.1, 0.3, 0.5, 0.7, 0.9] {
+            let on_bevel = Vec3::new(t, 1.0 - t, 0.0);
+            assert!(chamfer_sdf.distance(on_bevel).abs() < 1e-5, "{on_bevel}");
+            let outside = on_bevel + 0.1 * Vec3::new(1.0, 1.0, 0.0).normalize();
+            assert!(
+                (chamfer_sdf.distance(outside) - 0.1).abs() < 1e-5,
+                "{outside}"
+            );
+        }
+
+        // The smooth union is rounded instead, and doesn't reach as far into the corner.
+        assert!(smooth_sdf.distance(Vec3::new(0.5, 0.5, 0.0)) > 0.1);
+
+        let bb = graph.bounding_box(chamfer);
+        let mesh = mesh_from_sdf(&graph, chamfer, MeshOptions::low()).unwrap();
+        for pos in &mesh.positions {
+            assert!(bb.expanded(Vec3::splat(0.1)).contains(Vec3::from(*pos)));
+        }
+    }
 
     #[test]
-    fn it_works() {
+    fn pipe_and_groove() {
         let mut graph = Graph::default();
-        let node = graph.sphere(Vec3::default(), 1.0);
+        let (floor, wall) = floor_and_wall(&mut graph);
+        let pipe = graph.op_pipe(floor, wall, 0.25);
+        let groove = graph.op_groove(floor, wall, 0.2, 0.05);
 
-        let node2 = graph.sphere(Vec3::default(), 0.5);
-        let node3 = graph.op_translate(node2, -Vec3::X);
-        let node4 = graph.op_translate(node2, Vec3::X);
-        let node5 = graph.op_translate(node2, Vec3::Y);
+        let mut pipe_sdf = CompiledSdf::new(&graph, pipe).unwrap();
+        let mut groove_sdf = CompiledSdf::new(&graph, groove).unwrap();
 
-        let union_node = graph.op_union_multi(vec![node, node3, node4, node5]);
+        // Near the corner, the pipe is a cylinder around the Z axis.
+        for pos in [
+            Vec3::new(0.3, 0.4, 0.0),
+            Vec3::new(0.1, 0.05, 1.0),
+            Vec3::new(-0.2, 0.1, -1.0),
+            Vec3::ZERO,
+        ] {
+            let d = pipe_sdf.distance(pos);
+            let expected = pos.truncate().length() - 0.25;
+            assert!((d - expected).abs() < 1e-5, "{pos}: {d} vs {expected}");
+        }
 
-        let program = compile(&graph, union_node);
+        // The groove is 0.1 wide and 0.2 deep, cut into the floor where the wall meets it.
+        for (pos, expected) in [
+            (Vec3::new(0.0, -0.1, 0.0), 0.05),
+            (Vec3::new(0.02, -0.19, 0.0), 0.01),
+            (Vec3::new(0.0, -0.3, 0.0), -0.1),
+            (Vec3::new(0.1, -0.1, 0.0), -0.05),
+            (Vec3::new(1.0, -0.1, 0.0), -0.1),
+            (Vec3::new(1.0, 0.3, 0.0), 0.3),
+        ] {
+            let d = groove_sdf.distance(pos);
+            assert!((d - expected).abs() < 1e-5, "{pos}: {d} vs {expected}");
+        }
+
+        let mesh = mesh_from_sdf(&graph, pipe, MeshOptions::low()).unwrap();
+        assert!(!mesh.indices.is_empty());
+        assert!(mesh.is_manifold());
+    }
+
+    #[test]
+    fn negative_scale_mirrors() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::new(1.0, 0.5, 0.25), 0.5);
+        let capsule = graph.capsule([Vec3::ZERO, Vec3::new(0.0, 2.0, 0.0)], 0.25);
+        let original = graph.op_union(sphere, capsule);
+        let mirrored = graph.op_scale(original, -2.0);
+
+        let bb = graph.bounding_box(original);
+        let mirrored_bb = graph.bounding_box(mirrored);
+        assert_eq!(mirrored_bb.min, -2.0 * bb.max);
+        assert_eq!(mirrored_bb.max, -2.0 * bb.min);
+
+        let mesh = mesh_from_sdf(&graph, mirrored, MeshOptions::low()).unwrap();
+        assert!(!mesh.positions.is_empty());
+        for pos in &mesh.positions {
+            let pos = Vec3::from(*pos);
+            let distance = surface_distance_to(&graph, original, pos / -2.0);
+            assert!(
+                distance.abs() < 0.05,
+                "{pos} is {distance} from the surface"
+            );
+        }
+    }
+
+    #[test]
+    fn affine_matches_transform_chain() {
+        let mut graph = Graph::default();
+        let child = graph.rounded_box(Vec3::new(1.0, 0.5, 0.25), 0.1);
+
+        let scale = 1.5;
+        let rotation = glam::Quat::from_euler(glam::EulerRot::XYZ, 0.3, -1.2, 2.0);
+        let translation = Vec3::new(1.0, -2.0, 0.5);
+
+        let scaled = graph.op_scale(child, scale);
+        let rotated = graph.op_rotate(scaled, rotation);
+        let chain = graph.op_translate(rotated, translation);
+
+        let affine = graph.op_affine(
+            child,
+            glam::Affine3A::from_scale_rotation_translation(
+                Vec3::splat(scale),
+                rotation,
+                translation,
+            ),
+        );
+
+        for pos in [
+            Vec3::ZERO,
+            translation,
+            Vec3::new(2.0, -1.0, 1.0),
+            Vec3::new(-3.0, 4.0, 0.5),
+        ] {
+            let expected = surface_distance_to(&graph, chain, pos);
+            let distance = surface_distance_to(&graph, affine, pos);
+            assert!(
+                (distance - expected).abs() < 1e-4,
+                "{distance} != {expected} at {pos}"
+            );
+        }
+
+        // Non-uniform scale stays conservative.
+        let stretched =
+            graph.op_affine(child, glam::Affine3A::from_scale(Vec3::new(1.0, 4.0, 1.0)));
+        assert!(surface_distance_to(&graph, stretched, Vec3::new(0.0, 5.0, 0.0)) <= 3.0);
+    }
+
+    #[test]
+    fn set_parallel_matches_set() {
+        let sd = |[x, y, z]: Index3| {
+            (Vec3::new(x as f32, y as f32, z as f32) - Vec3::new(7.0, 8.0, 9.0)).length() - 5.0
+        };
+        let mut serial = Grid3::<f32>::new([17, 19, 23]);
+        serial.set(sd);
+        let mut parallel = Grid3::<f32>::new([17, 19, 23]);
+        parallel.set_parallel(sd);
+
+        let bits = |grid: &Grid3<f32>| grid.data().iter().map(|d| d.to_bits()).collect::<Vec<_>>();
+        assert_eq!(bits(&serial), bits(&parallel));
+    }
+
+    #[test]
+    fn label_grid() {
+        let mut labels = Grid3::<u8>::new([4, 5, 6]);
+        assert!(labels.data().iter().all(|&label| label == 0));
+        labels[[3, 4, 5]] = 7;
+        labels[[1, 2, 3]] += 2;
+        assert_eq!(labels[[3, 4, 5]], 7);
+        assert_eq!(labels[[1, 2, 3]], 2);
+        assert_eq!(labels.data().iter().filter(|&&label| label != 0).count(), 2);
+
+        let sum = |[x, y, z]: Index3| (x + 10 * y + 100 * z) as u16;
+        let grid = Grid3::from_fn_exact([4, 5, 6], sum);
+        assert_eq!(grid.size(), [4, 5, 6]);
+        assert_eq!(grid[[3, 4, 5]], 543);
+        let mut set = Grid3::new([4, 5, 6]);
+        set.set(sum);
+        assert!(grid == set);
+    }
+
+    #[test]
+    fn downsample_2x() {
+        let center = Vec3::splat(31.5);
+        let radius = 20.0;
         let mut grid = Grid3::<f32>::new([64, 64, 64]);
-        grid.set_truncated_sync(
+        grid.set(|[x, y, z]| Vec3::new(x as f32, y as f32, z as f32).distance(center) - radius);
+
+        let coarse = grid.downsample_2x();
+        assert_eq!(coarse.size(), [32, 32, 32]);
+        let coarse_center = (center - 0.5) / 2.0;
+        let mesh = coarse.marching_cubes();
+        assert!(!mesh.positions.is_empty());
+        for p in &mesh.positions {
+            let r = Vec3::from(*p).distance(coarse_center);
+            assert!((r - radius / 2.0).abs() < 0.05, "{r}");
+        }
+
+        // Odd sizes repeat the last samples.
+        let mut odd = Grid3::<f32>::new([3, 1, 1]);
+        odd.data_mut().copy_from_slice(&[2.0, 4.0, 6.0]);
+        assert_eq!(odd.downsample_2x().data(), &[1.5, 3.0]);
+    }
+
+    #[test]
+    fn truncated_cells() {
+        let truncate_dist = 2.0;
+        let sd = |[x, y, z]: Index3| {
+            (Vec3::new(x as f32, y as f32, z as f32) - Vec3::new(15.5, 14.0, 16.5)).length() - 8.0
+        };
+        let mut grid = Grid3::<f32>::new([32, 32, 32]);
+        grid.set_truncated_sync(sd, truncate_dist);
+
+        let mut num_approximate = 0;
+        for z in 0..32 {
+            for y in 0..32 {
+                for x in 0..32 {
+                    let p = [x, y, z];
+                    if sd(p).abs() <= truncate_dist {
+                        assert!(!grid.is_truncated_cell(p), "{p:?}");
+                    }
+                    if grid[p] != sd(p) {
+                        assert!(grid.is_truncated_cell(p), "{p:?}");
+                        num_approximate += 1;
+                    }
+                }
+            }
+        }
+        assert!(num_approximate > 0);
+
+        // Scaling the distances, e.g. to world units, scales the exact range along with them.
+        let truncated_cells = |grid: &Grid3<f32>| {
+            let [w, h, d] = grid.size();
+            let cells =
+                (0..d).flat_map(|z| (0..h).flat_map(move |y| (0..w).map(move |x| [x, y, z])));
+            cells.map(|p| grid.is_truncated_cell(p)).collect::<Vec<_>>()
+        };
+        let truncated = truncated_cells(&grid);
+        let downsampled = truncated_cells(&grid.downsample_2x());
+        let quantized = grid.quantized();
+        grid.scale_distances(0.5);
+        assert_eq!(truncated_cells(&grid), truncated);
+        assert_eq!(truncated_cells(&grid.downsample_2x()), downsampled);
+        assert!(grid.quantized() == quantized);
+
+        grid.set(sd);
+        assert!(!grid.is_truncated_cell([0, 0, 0]));
+    }
+
+    #[test]
+    fn quantized_grid() {
+        let radius = 1.0;
+        let bb = BoundingBox::from_center_size(Vec3::ZERO, Vec3::splat(2.5));
+        let resolution = [32; 3];
+        let grid = sample_grid_func(
+            &bb,
+            resolution,
+            || (),
+            |(), pos: Vec3| pos.length() - radius,
+        )
+        .unwrap();
+        let quantized = grid.quantized();
+        assert_eq!(quantized.size(), grid.size());
+
+        let world_from_grid_scale = bb.size().x / (resolution[0] as f32 - 1.0);
+        let sphere_error = |mesh: &mut TriangleMesh| {
+            transform_positions_in_place(mesh, |p| bb.min + world_from_grid_scale * p);
+            mesh_sdf_error(mesh, |pos| pos.length() - radius)
+        };
+        let error = sphere_error(&mut grid.marching_cubes());
+        let quantized_error = sphere_error(&mut quantized.marching_cubes());
+
+        // Rounding moves the surface by at most half a step.
+        let tolerance = 0.5 * QuantizedDistance::STEP * world_from_grid_scale;
+        assert!(quantized_error.num_samples > 0);
+        assert!(quantized_error.max <= error.max + tolerance);
+    }
+
+    #[test]
+    fn occupancy() {
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::new(0.1, 0.2, 0.3), 1.0);
+        let (bb, grid) = sample_grid(&graph, node, MeshOptions::low()).unwrap();
+        let (occupancy_bb, occupancy) = sample_occupancy(&graph, node, MeshOptions::low()).unwrap();
+        assert_eq!(occupancy_bb, bb);
+        assert_eq!(occupancy.size(), grid.size());
+
+        let [w, h, d] = grid.size();
+        let mut num_inside = 0;
+        for z in 0..d {
+            for y in 0..h {
+                for x in 0..w {
+                    let inside = grid[[x, y, z]] < 0.0;
+                    assert_eq!(occupancy.is_inside([x, y, z]), inside, "{x} {y} {z}");
+                    num_inside += usize::from(inside);
+                }
+            }
+        }
+        assert!(num_inside > 0);
+        assert_eq!(occupancy.count_inside(), num_inside);
+        assert_eq!(grid.to_occupancy(), occupancy);
+
+        // A huge distance partway through a row skips to its end instead of overflowing.
+        let steep_plane = graph.create_node(Node::Plane(Vec4::new(1e30, 0.0, 0.0, 0.0)));
+        let half = graph.op_intersect(node, steep_plane);
+        let (_, occupancy) = sample_occupancy(&graph, half, MeshOptions::low()).unwrap();
+        assert!(occupancy.count_inside() > 0);
+        assert!(occupancy.count_inside() < num_inside);
+    }
+
+    #[test]
+    fn mesh_stats() {
+        let mut graph = Graph::default();
+        let node = graph.example(&Default::default());
+        let (mesh, stats) = mesh_from_sdf_with_stats(&graph, node, MeshOptions::low()).unwrap();
+        assert_eq!(
+            mesh.positions,
+            mesh_from_sdf(&graph, node, MeshOptions::low())
+                .unwrap()
+                .positions
+        );
+        assert_eq!(stats.vertices, mesh.positions.len());
+        assert_eq!(stats.triangles, mesh.indices.len() / 3);
+
+        let (_, grid) = sample_grid(&graph, node, MeshOptions::low()).unwrap();
+        let [w, h, d] = grid.size();
+        assert_eq!(stats.grid_cells, (w - 1) * (h - 1) * (d - 1));
+        assert_eq!(stats.surface_cells, grid.surface_cells().count());
+        assert!(0 < stats.surface_cells && stats.surface_cells < stats.grid_cells);
+
+        // Each vertex is evaluated once for its color, and the empty space is skipped.
+        let sampled = stats.sdf_evaluations - stats.vertices;
+        assert!(0 < sampled && sampled < w * h * d, "{sampled}");
+    }
+
+    #[test]
+    fn mesh_and_grid() {
+        let mut graph = Graph::default();
+        let node = graph.example(&Default::default());
+        let (mesh, bb, grid) = mesh_and_grid_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        assert_eq!(
+            mesh.positions,
+            mesh_from_sdf(&graph, node, MeshOptions::low())
+                .unwrap()
+                .positions
+        );
+        let (sampled_bb, sampled_grid) = sample_grid(&graph, node, MeshOptions::low()).unwrap();
+        assert_eq!(bb, sampled_bb);
+        assert_eq!(grid.data(), sampled_grid.data());
+
+        // The grid points on either side of the zero contour enclose the mesh, to within a cell.
+        let transform = GridTransform::new(bb, grid.size());
+        let [w, h, d] = grid.size();
+        let mut contour_points = vec![];
+        for z in 0..d {
+            for y in 0..h {
+                for x in 0..w {
+                    for [nx, ny, nz] in [[x + 1, y, z], [x, y + 1, z], [x, y, z + 1]] {
+                        if nx < w
+                            && ny < h
+                            && nz < d
+                            && (grid[[x, y, z]] < 0.0) != (grid[[nx, ny, nz]] < 0.0)
+                        {
+                            contour_points.push(transform.world_from_cell([x, y, z]));
+                            contour_points.push(transform.world_from_cell([nx, ny, nz]));
+                        }
+                    }
+                }
+            }
+        }
+        let contour_bb = BoundingBox::from_points(contour_points.into_iter());
+        let mesh_bb = BoundingBox::from_points(mesh.positions.iter().map(|&p| Vec3::from(p)));
+        let cell = transform.world_from_grid_scale();
+        assert!(contour_bb.min.cmple(mesh_bb.min + 1e-4).all());
+        assert!(contour_bb.max.cmpge(mesh_bb.max - 1e-4).all());
+        assert!(contour_bb.min.cmpge(mesh_bb.min - cell).all());
+        assert!(contour_bb.max.cmple(mesh_bb.max + cell).all());
+    }
+
+    #[test]
+    fn slice() {
+        let mut graph = Graph::default();
+        // A tilted plane through the center of the sphere, which is also the plane origin.
+        let center = Vec3::new(1.0, 1.0, 0.0);
+        let node = graph.sphere(center, 1.0);
+        let plane = Plane3::from_normal_point(center.normalize(), center);
+        assert!(plane.origin().distance(center) < 1e-6);
+        let resolution = [41, 21];
+        let distances =
+            slice_sdf(&graph, node, &plane, glam::Vec2::new(4.0, 2.0), resolution).unwrap();
+        assert_eq!(distances.len(), 41 * 21);
+
+        // Samples are 0.1 apart, so the distance is the distance to the center in the plane, minus one.
+        let (u, v) = plane.basis();
+        for j in 0..resolution[1] {
+            for i in 0..resolution[0] {
+                let in_plane = (i as f32 - 20.0) * 0.1 * u + (j as f32 - 10.0) * 0.1 * v;
+                let distance = distances[j * resolution[0] + i];
+                assert!((distance - (in_plane.length() - 1.0)).abs() < 1e-4);
+            }
+        }
+
+        // The zero contour crosses the middle row at radius one.
+        let middle_row = &distances[10 * 41..11 * 41];
+        assert!(middle_row[10].abs() < 1e-4 && middle_row[30].abs() < 1e-4);
+        assert!(middle_row[9] > 0.0 && middle_row[11] < 0.0);
+        assert!(middle_row[29] < 0.0 && middle_row[31] > 0.0);
+    }
+
+    #[test]
+    fn estimated_bounding_box() {
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::new(0.3, -0.2, 0.1), 1.0);
+        let analytic = graph.bounding_box(node);
+
+        let program = compile(&graph, node);
+        let search_region = BoundingBox::from_center_size(Vec3::ZERO, Vec3::splat(6.0));
+        let resolution = [31, 31, 31];
+        let estimated = estimate_bounding_box(
             |pos| {
                 let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
-
-                let pos = Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32);
                 Interpreter::<f32>::interpret(&mut context, pos).unwrap()
             },
-            2.0,
+            search_region,
+            resolution,
         );
 
-        let mut grid2 = Grid3::new([64, 64, 64]);
-        grid2.set_truncated(
+        let cell_size = 6.0 / 30.0;
+        assert!((estimated.min - analytic.min).abs().max_element() < cell_size);
+        assert!((estimated.max - analytic.max).abs().max_element() < cell_size);
+
+        let nothing = estimate_bounding_box(|_| 1.0, search_region, resolution);
+        assert!(nothing.is_nothing());
+    }
+
+    #[test]
+    fn thin_capsule_is_watertight() {
+        let mut graph = Graph::default();
+        let points = [Vec3::ZERO, Vec3::new(1.0, 0.5, 0.0)];
+        let node = graph.capsule(points, 0.01);
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        assert!(!mesh.indices.is_empty());
+        assert!(mesh.positions.iter().flatten().all(|c| c.is_finite()));
+
+        assert!(mesh.is_manifold());
+
+        // A segment has no inside, so there is nothing to mesh, but also no NaN.
+        let segment = graph.segment(points);
+        let mesh = mesh_from_sdf(&graph, segment, MeshOptions::low()).unwrap();
+        assert!(mesh.indices.is_empty());
+
+        // With both points the same, the capsule is a sphere.
+        let sphere = graph.capsule([Vec3::ONE, Vec3::ONE], 0.5);
+        assert_eq!(
+            surface_distance_to(&graph, sphere, Vec3::ZERO),
+            3.0f32.sqrt() - 0.5
+        );
+    }
+
+    #[test]
+    fn tube_has_one_hole() {
+        let mut graph = Graph::default();
+        let node = graph.tube(1.0, 0.6, 0.5);
+        for (pos, expected) in [
+            (Vec3::ZERO, 0.6),
+            (Vec3::new(0.8, 0.0, 0.0), -0.2),
+            (Vec3::new(0.0, 0.0, 0.9), -0.1),
+            (Vec3::new(0.0, 0.8, 0.8), 0.3),
+        ] {
+            let d = surface_distance_to(&graph, node, pos);
+            assert!((d - expected).abs() < 1e-6, "{pos}: {d} vs {expected}");
+        }
+
+        // The ends are capped, so the surface is closed, with genus 1 (Euler characteristic 0).
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::default()).unwrap();
+        let adjacency = mesh.build_adjacency();
+        assert!(adjacency.is_closed());
+        let num_vertices = adjacency.num_welded_vertices() as i64;
+        let num_edges = adjacency.edges().count() as i64;
+        let num_faces = (mesh.indices.len() / 3) as i64;
+        assert_eq!(num_vertices - num_edges + num_faces, 0);
+    }
+
+    #[test]
+    fn capped_cone() {
+        let mut graph = Graph::default();
+        let frustum = graph.capped_cone(1.0, 0.5, 2.0);
+        let cylinder = graph.capped_cone(0.75, 0.75, 2.0);
+        let cone = graph.capped_cone(1.0, 0.0, 2.0);
+        let expected_cylinder = graph.rounded_cylinder(0.75, 1.0, 0.0);
+        let expected_cylinder = graph.op_translate(expected_cylinder, Vec3::new(0.0, 1.0, 0.0));
+        let expected_cone = graph.cone(1.0, 2.0);
+
+        let bb = graph.bounding_box(frustum);
+        assert_eq!(bb.min, Vec3::new(-1.0, 0.0, -1.0));
+        assert_eq!(bb.max, Vec3::new(1.0, 2.0, 1.0));
+        for (pos, expected) in [
+            (Vec3::ZERO, -0.0),
+            (Vec3::new(1.0, 0.0, 0.0), 0.0),
+            (Vec3::new(0.5, 2.0, 0.0), 0.0),
+            (Vec3::new(0.0, 3.0, 0.0), 1.0),
+            (Vec3::new(0.0, -0.5, 0.0), 0.5),
+        ] {
+            let distance = surface_distance_to(&graph, frustum, pos);
+            assert!((distance - expected).abs() < 1e-5, "{pos}: {distance}");
+        }
+
+        for i in 0..200 {
+            let t = i as f32;
+            let pos = Vec3::new((t * 0.37).sin(), (t * 0.11).cos() + 1.0, (t * 0.73).sin()) * 1.5;
+            for (node, expected) in [(cylinder, expected_cylinder), (cone, expected_cone)] {
+                let distance = surface_distance_to(&graph, node, pos);
+                let expected = surface_distance_to(&graph, expected, pos);
+                assert!(
+                    (distance - expected).abs() < 1e-5,
+                    "{pos}: {distance} vs {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn egg_and_heart_are_connected() {
+        let mut graph = Graph::default();
+        let egg = graph.egg(1.0, 0.25);
+        let heart = graph.heart(1.0, 0.25);
+        let egg_top = 3f32.sqrt() * 0.75 + 0.25;
+        let heart_top = 0.75 + std::f32::consts::SQRT_2 / 4.0;
+
+        // The top of the heart is on the lobes, which dip where they meet in the middle.
+        for (node, min_y, max_y, top_x) in
+            [(egg, -1.0, egg_top, 0.0), (heart, 0.0, heart_top, 0.25)]
+        {
+            let bb = graph.bounding_box(node);
+            assert!((bb.min.y - min_y).abs() < 1e-6 && (bb.max.y - max_y).abs() < 1e-6);
+            assert!(surface_distance_to(&graph, node, Vec3::new(0.0, min_y, 0.0)).abs() < 1e-5);
+            assert!(surface_distance_to(&graph, node, Vec3::new(top_x, max_y, 0.0)).abs() < 1e-5);
+
+            let mesh = mesh_from_sdf(&graph, node, MeshOptions::default()).unwrap();
+            let adjacency = mesh.build_adjacency();
+            assert!(adjacency.is_closed());
+
+            // Flood fill from the first vertex should reach every vertex.
+            let mut visited = vec![false; mesh.positions.len()];
+            let mut stack = vec![0];
+            visited[0] = true;
+            while let Some(vertex) = stack.pop() {
+                for &neighbor in adjacency.vertex_neighbors(vertex) {
+                    if !visited[neighbor as usize] {
+                        visited[neighbor as usize] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            let num_reached = visited.iter().filter(|v| **v).count();
+            assert_eq!(num_reached, adjacency.num_welded_vertices());
+
+            let (lowest, highest) = mesh
+                .positions
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(lo, hi), p| {
+                    (lo.min(p[1]), hi.max(p[1]))
+                });
+            let tolerance = 0.1 * (max_y - min_y);
+            assert!((lowest - min_y).abs() < tolerance, "{lowest} vs {min_y}");
+            assert!((highest - max_y).abs() < tolerance, "{highest} vs {max_y}");
+        }
+    }
+
+    #[test]
+    fn convex_hull_tetrahedron() {
+        let mut graph = Graph::default();
+        let normals = [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, -1.0, -1.0),
+        ]
+        .map(Vec3::normalize);
+        let node = graph.convex_hull(normals.map(|n| n.extend(-0.5)).to_vec());
+        assert_eq!(surface_distance_to(&graph, node, Vec3::ZERO), -0.5);
+
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        assert!(!mesh.indices.is_empty());
+
+        // Each of the four faces shows up as a flat part of the mesh.
+        for normal in normals {
+            let num_triangles = mesh
+                .indices
+                .chunks(3)
+                .filter(|triangle| {
+                    let [a, b, c] =
+                        [0, 1, 2].map(|i| Vec3::from(mesh.positions[triangle[i] as usize]));
+                    (b - a).cross(c - a).normalize_or_zero().dot(normal) > 0.99
+                })
+                .count();
+            assert!(num_triangles > 10, "{normal}: {num_triangles}");
+        }
+    }
+
+    #[test]
+    fn interpolated_colors() {
+        let mut graph = Graph::default();
+        let red = graph.sphere(Vec3::new(-0.6, 0.0, 0.0), 1.0);
+        let red = graph.op_rgb(red, Vec3::new(1.0, 0.0, 0.0));
+        let blue = graph.sphere(Vec3::new(0.6, 0.0, 0.0), 1.0);
+        let blue = graph.op_rgb(blue, Vec3::new(0.0, 0.0, 1.0));
+        let node = graph.op_union_smooth(red, blue, 0.5);
+
+        let (bb, resolution) =
+            sdf_bb_and_resolution(graph.bounding_box(node), MeshOptions::low()).unwrap();
+        let program = compile(&graph, node);
+        let mesh = mesh_from_sdf_program_colored(&program, &bb, resolution).unwrap();
+        assert!(!mesh.positions.is_empty());
+        assert_eq!(mesh.colors.len(), mesh.positions.len());
+
+        let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+        for (pos, color) in mesh.positions.iter().zip(&mesh.colors) {
+            let expected =
+                Interpreter::<RgbaWithDistance>::interpret(&mut context, Vec3::from(*pos))
+                    .unwrap()
+                    .material()
+                    .rgba();
+            let error = (Vec4::from(*color) - expected).abs().max_element();
+            assert!(error < 0.1, "{color:?} != {expected} at {pos:?}");
+        }
+    }
+
+    #[test]
+    fn single_pass_matches_two_pass() {
+        let mut graph = Graph::default();
+        let node = graph.example(&ExampleParams::default());
+
+        let two_pass = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        let single_pass = mesh_from_sdf_colored(&graph, node, MeshOptions::low()).unwrap();
+
+        // The distances are the same, so only the colors differ.
+        assert!(!two_pass.positions.is_empty());
+        assert_eq!(single_pass.indices, two_pass.indices);
+        assert_eq!(single_pass.positions, two_pass.positions);
+        assert_eq!(single_pass.colors.len(), two_pass.colors.len());
+        let num_close = single_pass
+            .colors
+            .iter()
+            .zip(&two_pass.colors)
+            .filter(|(a, b)| (Vec4::from(**a) - Vec4::from(**b)).abs().max_element() < 0.1)
+            .count();
+        // Colors only differ where materials meet.
+        assert!(num_close as f32 > 0.95 * two_pass.colors.len() as f32);
+    }
+
+    #[test]
+    fn srgb_colors() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let node = graph.op_rgb(sphere, Vec3::splat(0.5));
+        let mut mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        assert!(mesh.colors.iter().all(|c| *c == [0.5, 0.5, 0.5, 1.0]));
+
+        let program = compile(&graph, node);
+        let color = |pos| {
+            let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+            Interpreter::<RgbaWithDistance>::interpret(&mut context, pos)
+                .unwrap()
+                .material()
+                .rgba()
+        };
+        gather_colors_in_place_with_color_space(&mut mesh, ColorSpace::Srgb, color);
+        assert!(!mesh.colors.is_empty());
+        for c in &mesh.colors {
+            assert!((c[0] - 0.735).abs() < 1e-3, "{c:?}");
+            assert_eq!(c[3], 1.0);
+        }
+
+        let material = Material::new(Vec3::splat(0.5));
+        assert!(
+            (material.to_srgb() - Vec3::splat(0.735))
+                .abs()
+                .max_element()
+                < 1e-3
+        );
+        let roundtripped = Material::from_srgb(material.to_srgb()).rgb();
+        assert!((roundtripped - material.rgb()).abs().max_element() < 1e-6);
+    }
+
+    #[test]
+    fn gather_colors_with_init() {
+        let mut graph = Graph::default();
+        let red = graph.sphere(Vec3::ZERO, 1.0);
+        let red = graph.op_rgb(red, Vec3::X);
+        let blue = graph.sphere(Vec3::X, 1.0);
+        let blue = graph.op_rgb(blue, Vec3::Z);
+        let node = graph.op_union_smooth(red, blue, 0.5);
+        let mut mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        let meshed_colors = std::mem::take(&mut mesh.colors);
+
+        let program = compile(&graph, node);
+        let new_context = || Interpreter::new_context(&program.opcodes, &program.constants);
+        let inits = AtomicUsize::new(0);
+        gather_colors_in_place_with_init(
+            &mut mesh,
+            || {
+                inits.fetch_add(1, Ordering::Relaxed);
+                new_context()
+            },
+            |context, pos| {
+                Interpreter::<RgbaWithDistance>::interpret(context, pos)
+                    .unwrap()
+                    .material()
+                    .rgba()
+            },
+        );
+        assert_eq!(mesh.colors, meshed_colors);
+
+        // Once per worker, not once per vertex.
+        let inits = inits.into_inner();
+        #[cfg(feature = "with_rayon")]
+        assert!(inits * 10 < mesh.positions.len(), "{inits} inits");
+        #[cfg(not(feature = "with_rayon"))]
+        assert_eq!(inits, 1);
+    }
+
+    #[test]
+    fn reused_contexts_match_fresh_contexts() {
+        let mut graph = Graph::default();
+        let red = graph.sphere(Vec3::new(-0.6, 0.0, 0.0), 1.0);
+        let red = graph.op_rgb(red, Vec3::new(1.0, 0.0, 0.0));
+        let blue = graph.rounded_box(Vec3::new(0.8, 0.5, 0.6), 0.1);
+        let blue = graph.op_translate(blue, Vec3::new(0.6, 0.0, 0.0));
+        let blue = graph.op_rgb(blue, Vec3::new(0.0, 0.0, 1.0));
+        let node = graph.op_union_smooth(red, blue, 0.5);
+
+        let (bb, resolution) =
+            sdf_bb_and_resolution(graph.bounding_box(node), MeshOptions::low()).unwrap();
+        let program = compile(&graph, node);
+
+        let reused = mesh_from_sdf_program(&program, &bb, resolution).unwrap();
+
+        // A new context for every sample, like before contexts were reused.
+        let fresh = mesh_from_sdf_func(
+            &bb,
+            resolution,
             |pos| {
-                let pos = Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32);
-                sd_op_union(
-                    sd_sphere(pos, Vec3::default(), 1.0),
-                    sd_op_union(
-                        sd_sphere(pos + Vec3::X, Vec3::default(), 0.5),
-                        sd_op_union(
-                            sd_sphere(pos - Vec3::X, Vec3::default(), 0.5),
-                            sd_sphere(pos - Vec3::Y, Vec3::default(), 0.5),
-                        ),
-                    ),
-                )
+                let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+                Interpreter::<f32>::interpret(&mut context, pos).unwrap()
             },
-            2.0,
+            |pos| {
+                let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+                Interpreter::<RgbaWithDistance>::interpret(&mut context, pos)
+                    .unwrap()
+                    .material()
+                    .rgba()
+            },
+        )
+        .unwrap();
+
+        assert!(!reused.positions.is_empty());
+        assert_eq!(reused.indices, fresh.indices);
+        assert_eq!(reused.positions, fresh.positions);
+        assert_eq!(reused.normals, fresh.normals);
+        assert_eq!(reused.colors, fresh.colors);
+    }
+
+    #[test]
+    fn alpha_in_vertex_colors() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let node = graph.op_rgba(sphere, Vec4::new(1.0, 0.5, 0.0, 0.5));
+
+        let mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+        assert!(!mesh.colors.is_empty());
+        assert!(mesh.colors.iter().all(|c| *c == [1.0, 0.5, 0.0, 0.5]));
+
+        let program = compile(&graph, node);
+        let (bb, resolution) =
+            sdf_bb_and_resolution(graph.bounding_box(node), MeshOptions::low()).unwrap();
+        let mesh = mesh_from_sdf_program_colored(&program, &bb, resolution).unwrap();
+        assert!(mesh.colors.iter().all(|c| (c[3] - 0.5).abs() < 1e-6));
+    }
+
+    #[test]
+    fn union_picks_nearest_material() {
+        let mut graph = Graph::default();
+        let dull = graph.sphere(Vec3::new(-1.0, 0.0, 0.0), 1.0);
+        let dull = graph.op_material(
+            dull,
+            Material::new(Vec3::ONE).with_metallic_roughness(0.2, 0.9),
+        );
+        let shiny = graph.sphere(Vec3::new(1.0, 0.0, 0.0), 1.0);
+        let shiny = graph.op_material(
+            shiny,
+            Material::new(Vec3::ONE).with_metallic_roughness(0.8, 0.1),
         );
+        let node = graph.op_union(dull, shiny);
 
-        // grid and grid2 should be equal.
-        assert!(grid == grid2);
+        let program = compile(&graph, node);
+        let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+        let mut material_at = |pos| {
+            Interpreter::<PbrWithDistance>::interpret(&mut context, pos)
+                .unwrap()
+                .material
+        };
+        assert_eq!(material_at(Vec3::new(-2.0, 0.0, 0.0)).metallic(), 0.2);
+        assert_eq!(material_at(Vec3::new(2.0, 0.0, 0.0)).metallic(), 0.8);
+        assert_eq!(material_at(Vec3::new(2.0, 0.0, 0.0)).roughness(), 0.1);
+    }
+
+    #[test]
+    fn surface_sample_material() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let material = Material::new(Vec3::new(1.0, 0.5, 0.25)).with_metallic_roughness(0.7, 0.3);
+        let colored = graph.op_material(sphere, material);
+        let plain = graph.sphere(Vec3::new(5.0, 0.0, 0.0), 1.0);
+        let node = graph.op_union(colored, plain);
+
+        let (distance, sampled) = surface_sample(&graph, node, Vec3::X);
+        assert!(distance.abs() < 1e-6);
+        assert_eq!(distance, surface_distance_to(&graph, node, Vec3::X));
+        assert_eq!(sampled, material);
+
+        let (distance, sampled) = surface_sample(&graph, node, Vec3::new(6.0, 0.0, 0.0));
+        assert!(distance.abs() < 1e-6);
+        assert_eq!(sampled, Material::default());
+    }
+
+    #[test]
+    fn smooth_subtract_keeps_material() {
+        let mut graph = Graph::default();
+        let red = Material::new(Vec3::new(1.0, 0.0, 0.0));
+        let blue = Material::new(Vec3::new(0.0, 0.0, 1.0));
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let sphere = graph.op_material(sphere, red);
+        let cutter = graph.sphere(Vec3::X, 0.6);
+        let cutter = graph.op_material(cutter, blue);
+        let node = graph.op_subtract_smooth(sphere, cutter, 0.3);
+
+        // Rays from the side of the cutter hit the carved surface and the fillet around it.
+        let mut sdf = CompiledSdf::new(&graph, node).unwrap();
+        let mut hits = vec![];
+        for y in -4..=4 {
+            for z in -4..=4 {
+                let ray = Ray3 {
+                    origin: Vec3::new(3.0, y as f32 * 0.2, z as f32 * 0.2),
+                    dir: -Vec3::X,
+                };
+                let hit = sdf.march(ray, 0.0..=10.0, &Default::default());
+                if hit.is_hit {
+                    hits.push(hit.pos);
+                }
+            }
+        }
+        // Only the rays through the corners miss, and many hit the carved surface, inside of the uncut sphere.
+        assert!(hits.len() >= 70, "{} hits", hits.len());
+        assert!(hits.iter().filter(|pos| pos.length() < 0.95).count() >= 30);
+        for pos in hits {
+            assert_eq!(sdf.sample(pos).1, red, "{pos}");
+        }
+    }
+
+    #[test]
+    fn mesh_with_transform() {
+        let mut graph = Graph::default();
+        let node = graph.rounded_box(Vec3::new(1.0, 2.0, 0.5), 0.2);
+        let options = MeshOptions::low();
+
+        let result = mesh_from_sdf_with_transform(&graph, node, options).unwrap();
+        let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options).unwrap();
+        assert_eq!(result.bb, bb);
+        assert_eq!(result.resolution, resolution);
+        assert_eq!(
+            result.mesh.positions,
+            mesh_from_sdf(&graph, node, options).unwrap().positions
+        );
+
+        let corner = Vec3::new(resolution[0] as f32 - 1.0, 0.0, 0.0);
+        assert!(
+            result
+                .world_from_grid(corner)
+                .distance(Vec3::new(bb.max.x, bb.min.y, bb.min.z))
+                < 1e-4
+        );
+        let pos = Vec3::new(0.1, 0.2, 0.3);
+        assert!(
+            result
+                .world_from_grid(result.grid_from_world(pos))
+                .distance(pos)
+                < 1e-5
+        );
+    }
+
+    #[test]
+    fn progress_increases_to_one() {
+        let mut graph = Graph::default();
+        let node = graph.example(&Default::default());
+        let fractions = std::sync::Mutex::new(vec![]);
+        let mesh = mesh_from_sdf_with_progress(&graph, node, MeshOptions::low(), &|fraction| {
+            fractions.lock().unwrap().push(fraction);
+        })
+        .unwrap();
+        assert_eq!(
+            mesh.positions,
+            mesh_from_sdf(&graph, node, MeshOptions::low())
+                .unwrap()
+                .positions
+        );
+
+        let fractions = fractions.into_inner().unwrap();
+        assert!(fractions.len() > 10);
+        assert!(fractions[0] > 0.0 && fractions[0] < 0.1);
+        assert!(fractions.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(fractions.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn cancel_from_other_thread() {
+        let mut graph = Graph::default();
+        let node = graph.example(&Default::default());
+        let options = MeshOptions {
+            mean_resolution: 256.0,
+            max_resolution: 256.0,
+            min_resolution: 256.0,
+            fixed_resolution: None,
+            axis_weights: Vec3::ONE,
+        };
+
+        let cancel = AtomicBool::new(true);
+        let result = mesh_from_sdf_cancellable(&graph, node, options, &|_| {}, &cancel);
+        assert_eq!(result.err(), Some(Error::Cancelled));
+
+        // Cancel from another thread as soon as the first slab is sampled.
+        cancel.store(false, std::sync::atomic::Ordering::Relaxed);
+        let (started, on_started) = std::sync::mpsc::channel();
+        let last_fraction = std::sync::Mutex::new(0.0);
+        let result = std::thread::scope(|scope| {
+            let cancel = &cancel;
+            scope.spawn(move || {
+                on_started.recv().unwrap();
+                cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+            });
+            let progress = |fraction| {
+                let _ = started.send(());
+                *last_fraction.lock().unwrap() = fraction;
+            };
+            mesh_from_sdf_cancellable(&graph, node, options, &progress, cancel)
+        });
+        assert_eq!(result.err(), Some(Error::Cancelled));
+        assert!(*last_fraction.lock().unwrap() < 0.5);
+    }
+
+    #[test]
+    fn degenerate_bounding_boxes() {
+        let options = MeshOptions::low();
+        assert_eq!(
+            sdf_bb_and_resolution(BoundingBox::everything(), options).err(),
+            Some(Error::InfiniteBoundingBox)
+        );
+        assert_eq!(
+            sdf_bb_and_resolution(BoundingBox::nothing(), options).err(),
+            Some(Error::EmptyBoundingBox)
+        );
+
+        // Boxes touching at a face intersect in a flat box, which gets a bit of thickness.
+        let mut graph = Graph::default();
+        let a = graph.rounded_box(Vec3::ONE, 0.0);
+        let b = graph.rounded_box(Vec3::ONE, 0.0);
+        let b = graph.op_translate(b, Vec3::new(2.0, 0.0, 0.0));
+        let flat = graph.op_intersect(a, b);
+        let flat_bb = graph.bounding_box(flat);
+        assert_eq!(flat_bb.volume(), 0.0);
+        let (bb, resolution) = sdf_bb_and_resolution(flat_bb, options).unwrap();
+        assert!(bb.volume() > 0.0 && bb.contains(flat_bb.center()));
+        assert!(resolution.iter().all(|r| *r >= 8));
+        assert!(mesh_from_sdf(&graph, flat, options).is_ok());
+
+        // Spheres that don't overlap have nothing in common to mesh.
+        let a = graph.sphere(Vec3::ZERO, 1.0);
+        let b = graph.sphere(Vec3::new(3.0, 0.0, 0.0), 1.0);
+        let empty = graph.op_intersect(a, b);
+        assert_eq!(
+            mesh_from_sdf(&graph, empty, options).err(),
+            Some(Error::EmptyBoundingBox)
+        );
+    }
+
+    #[test]
+    fn extrude_circle() {
+        let sd = extrude_profile(|p| p.length() - 1.0, 0.5);
+        for x in -6..=6 {
+            for y in -6..=6 {
+                for z in -6..=6 {
+                    let pos = Vec3::new(x as f32, y as f32, z as f32) * 0.3;
+                    // A capped cylinder of radius 1, along Z instead of Y.
+                    let cylinder =
+                        sd_rounded_cylinder_f(Vec3::new(pos.x, pos.z, pos.y), 1.0, 0.5, 0.0);
+                    assert!((sd(pos) - cylinder).abs() < 1e-5, "{pos}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_resolution() {
+        let mut graph = Graph::default();
+        let node = graph.rounded_box(Vec3::new(2.0, 1.0, 0.5), 0.0);
+        let options = MeshOptions::fixed([20, 30, 40]);
+
+        let (bb, grid) = sample_grid(&graph, node, options).unwrap();
+        assert_eq!(grid.size(), [20, 30, 40]);
+
+        // The cells are cubes, with at least one of padding around the shape.
+        let cell_size = bb.size() / Vec3::new(19.0, 29.0, 39.0);
+        assert!((cell_size - Vec3::splat(cell_size.x)).abs().max_element() < 1e-5);
+        let tight_bb = graph.bounding_box(node);
+        assert!(bb.contains(tight_bb.min - cell_size * 0.99));
+        assert!(bb.contains(tight_bb.max + cell_size * 0.99));
+
+        assert!(!mesh_from_sdf(&graph, node, options)
+            .unwrap()
+            .positions
+            .is_empty());
+
+        assert_eq!(
+            sdf_bb_and_resolution(tight_bb, MeshOptions::fixed([20, 3, 40])),
+            Err(Error::BadResolution([20, 3, 40]))
+        );
+    }
+
+    #[test]
+    fn axis_weights() {
+        let mut graph = Graph::default();
+        let plate = graph.rounded_box(Vec3::new(2.0, 2.0, 0.25), 0.0);
+        let bb = graph.bounding_box(plate);
+        let weighted = MeshOptions {
+            axis_weights: Vec3::new(1.0, 1.0, 2.0),
+            ..Default::default()
+        };
+
+        let (_, resolution) = sdf_bb_and_resolution(bb, MeshOptions::default()).unwrap();
+        let (weighted_bb, weighted_resolution) = sdf_bb_and_resolution(bb, weighted).unwrap();
+        assert_eq!(weighted_resolution[0], resolution[0]);
+        let ratio = weighted_resolution[2] as f32 / resolution[2] as f32;
+        assert!((1.8..=2.2).contains(&ratio), "{ratio}");
+
+        // The cells are half as deep as they are wide.
+        let cell_size = GridTransform::new(weighted_bb, weighted_resolution).cell_size();
+        assert!((cell_size.z * 2.0 - cell_size.x).abs() < 1e-5);
+        assert!(weighted_bb.contains(bb.min) && weighted_bb.contains(bb.max));
+
+        // The mesh is on the surface, with the normals of the sphere despite the stretched cells.
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let stretched = MeshOptions {
+            axis_weights: Vec3::new(1.0, 0.5, 3.0),
+            ..MeshOptions::low()
+        };
+        let mesh = mesh_from_sdf(&graph, sphere, stretched).unwrap();
+        let error = mesh_sdf_error(&mesh, |pos| pos.length() - 1.0);
+        assert!(error.max < 0.01, "{error:?}");
+        for (position, normal) in mesh.positions.iter().zip(&mesh.normals) {
+            let expected = Vec3::from(*position).normalize();
+            assert!(Vec3::from(*normal).dot(expected) > 0.98);
+        }
+
+        let flat = MeshOptions {
+            axis_weights: Vec3::new(1.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        assert_eq!(sdf_bb_and_resolution(bb, flat), Err(Error::BadAxisWeights));
+    }
+
+    #[test]
+    fn chunked_meshing() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::new(0.1, 0.2, 0.3), 1.0);
+        let node = graph.op_rgb(sphere, [1.0, 0.5, 0.25]);
+        let options = MeshOptions::fixed([20, 21, 22]);
+        let whole = mesh_from_sdf(&graph, node, options).unwrap();
+
+        // A single brick is the same as the whole grid.
+        let single = mesh_from_sdf_chunked(&graph, node, options, 22).unwrap();
+        assert_eq!(single.indices, whole.indices);
+        assert_eq!(single.positions, whole.positions);
+        assert_eq!(single.normals, whole.normals);
+        assert_eq!(single.colors, whole.colors);
+
+        // With many bricks, the vertices along the seams are welded, and end up where they are in the whole grid.
+        let chunked = mesh_from_sdf_chunked(&graph, node, options, 6).unwrap();
+        assert!(chunked.is_manifold());
+        assert_eq!(chunked.indices.len(), whole.indices.len());
+        let sorted_vertices = |mesh: &TriangleMesh| {
+            let mut vertices: Vec<_> = mesh
+                .positions
+                .iter()
+                .zip(&mesh.normals)
+                .map(|(p, n)| (p.map(f32::to_bits), n.map(f32::to_bits)))
+                .collect();
+            vertices.sort_unstable();
+            vertices
+        };
+        assert_eq!(sorted_vertices(&chunked), sorted_vertices(&whole));
+    }
+
+    #[test]
+    fn infinite_bounding_box() {
+        let mut graph = Graph::default();
+        let node = graph.plane(Vec4::new(0.0, 1.0, 0.0, 0.0));
+        let result = mesh_from_sdf(&graph, node, MeshOptions::low());
+        assert_eq!(result.err(), Some(Error::InfiniteBoundingBox));
+        assert_eq!(
+            sample_grid(&graph, node, MeshOptions::low()).err(),
+            Some(Error::InfiniteBoundingBox)
+        );
+    }
+
+    #[test]
+    fn grid_transform_roundtrip() {
+        let bb = BoundingBox::from_min_max(Vec3::new(-1.0, 2.0, 0.5), Vec3::new(3.0, 4.0, 1.5));
+        let transform = GridTransform::new(bb, [41, 21, 11]);
+        assert_eq!(transform.world_from_grid_scale(), 0.1);
+        assert_eq!(transform.world_from_cell([0, 0, 0]), bb.min);
+
+        for i in 0..100 {
+            let t = i as f32;
+            let p = Vec3::new((t * 0.37).sin(), (t * 0.11).cos(), (t * 0.73).sin()) * 5.0;
+            assert!(
+                transform
+                    .world_from_grid(transform.grid_from_world(p))
+                    .distance(p)
+                    < 1e-5
+            );
+        }
+
+        assert_eq!(
+            transform.nearest_cell(Vec3::new(-0.96, 2.04, 0.56)),
+            [0, 0, 1]
+        );
+        assert_eq!(
+            transform.nearest_cell(Vec3::new(1.0, 3.0, 1.0)),
+            [20, 10, 5]
+        );
+        assert_eq!(transform.nearest_cell(Vec3::splat(-100.0)), [0, 0, 0]);
+        assert_eq!(transform.nearest_cell(Vec3::splat(100.0)), [40, 20, 10]);
+    }
+
+    #[test]
+    fn vertex_ids() {
+        let mut graph = Graph::default();
+        let a = graph.sphere(Vec3::ZERO, 1.0);
+        let b = graph.sphere(Vec3::new(1.5, 0.0, 0.0), 0.75);
+        let b = graph.op_rgb(b, Vec3::X);
+        let node = graph.op_union(a, b);
+
+        let mesh = mesh_from_sdf_with_ids(&graph, node, MeshOptions::low()).unwrap();
+        assert_eq!(mesh.vertex_ids.len(), mesh.positions.len());
+        let mut ids = mesh.vertex_ids.clone();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 2);
+
+        // Each id is the opcode of a sphere, in the order they're compiled.
+        let program = compile(&graph, node);
+        assert!(ids
+            .iter()
+            .all(|id| program.opcodes[*id as usize] == Opcode::Sphere));
+        for (p, id) in mesh.positions.iter().zip(&mesh.vertex_ids) {
+            if p[0] < -0.5 {
+                assert_eq!(*id, ids[0]);
+            } else if p[0] > 2.0 {
+                assert_eq!(*id, ids[1]);
+            }
+        }
+
+        // Too deep for the interpreter's stack, as each union waits for the next one.
+        let mut deep = a;
+        for i in 0..100 {
+            let sphere = graph.sphere(Vec3::new(i as f32, 0.0, 0.0), 1.0);
+            deep = graph.op_union(sphere, deep);
+        }
+        assert!(matches!(
+            mesh_from_sdf_with_ids(&graph, deep, MeshOptions::low()),
+            Err(Error::StackOverflow)
+        ));
+    }
+
+    #[test]
+    fn too_deep_for_the_interpreter() {
+        let mut graph = Graph::default();
+        let mut deep = graph.sphere(Vec3::ZERO, 1.0);
+        for i in 0..100 {
+            let sphere = graph.sphere(Vec3::new(i as f32, 0.0, 0.0), 1.0);
+            deep = graph.op_union(sphere, deep);
+        }
+
+        let options = MeshOptions::low();
+        assert!(matches!(
+            mesh_from_sdf(&graph, deep, options),
+            Err(Error::StackOverflow)
+        ));
+        assert!(matches!(
+            mesh_from_sdf_colored(&graph, deep, options),
+            Err(Error::StackOverflow)
+        ));
+        assert!(matches!(
+            mesh_from_sdf_chunked(&graph, deep, options, 16),
+            Err(Error::StackOverflow)
+        ));
+        assert!(matches!(
+            sample_grid(&graph, deep, options),
+            Err(Error::StackOverflow)
+        ));
+        assert!(matches!(
+            sample_occupancy(&graph, deep, options),
+            Err(Error::StackOverflow)
+        ));
+        assert!(matches!(
+            slice_sdf(
+                &graph,
+                deep,
+                &Plane3::from_normal_point(Vec3::Z, Vec3::ZERO),
+                glam::Vec2::ONE,
+                [4, 4]
+            ),
+            Err(Error::StackOverflow)
+        ));
+        assert!(matches!(
+            CompiledSdf::new(&graph, deep),
+            Err(Error::StackOverflow)
+        ));
+    }
+
+    #[test]
+    fn sample_grid_matches_sdf() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let node = graph.op_translate(sphere, Vec3::new(0.5, 0.0, 0.0));
+
+        let (bb, grid) = sample_grid(&graph, node, MeshOptions::low()).unwrap();
+        assert!(bb.contains(Vec3::new(0.5, 0.0, 0.0)));
+
+        let mut num_checked = 0;
+        for (pos, distance) in grid.iter_world(&bb) {
+            // Far away cells only hold approximate distances.
+            let expected = surface_distance_to(&graph, node, pos);
+            if expected.abs() < 0.1 {
+                assert!((distance - expected).abs() < 1e-5);
+                num_checked += 1;
+            }
+        }
+        assert!(num_checked > 10);
     }
 }
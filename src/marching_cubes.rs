@@ -1,9 +1,15 @@
 #![allow(clippy::identity_op)]
 
+#[cfg(feature = "std")]
 use ahash::AHashMap as HashMap;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as HashMap;
 
 use super::*;
+use crate::progress::Progress;
+use alloc::vec::Vec;
 use glam::Vec3;
+use glam::Vec4;
 
 // Straight port of ol' Paul Bourke code.
 // Marching cubes like it's 1999.
@@ -328,18 +334,23 @@ impl Vertex {
     }
 }
 
+/// Where along a cube edge the surface crosses it, as used by [`Grid3::marching_cubes`].
+///
+/// Given the distances at the two ends of the edge, of opposite signs, returns how far along
+/// the edge from the first end the distance is zero, from 0 to 1, assuming it changes linearly.
+///
+/// See [`Grid3::marching_cubes_with_interpolation`] to try something else.
+pub fn linear_edge_crossing(a_distance: f32, b_distance: f32) -> f32 {
+    if (a_distance - b_distance).abs() < 0.00001 {
+        0.0
+    } else {
+        (-a_distance / (b_distance - a_distance)).clamp(0.0, 1.0)
+    }
+}
+
 pub struct MarchingCubes {}
 
 impl MarchingCubes {
-    fn vertex_lerp_t(a: &Vertex, b: &Vertex) -> f32 {
-        if (a.distance - b.distance).abs() < 0.00001 {
-            0.0
-        } else {
-            let isolevel = 0.0;
-            ((isolevel - a.distance) / (b.distance - a.distance)).clamp(0.0, 1.0)
-        }
-    }
-
     fn get_cube_index(distances: &[f32; 8]) -> Option<usize> {
         let mut cubeindex: usize = 0;
         for (i, &distance) in distances.iter().enumerate() {
@@ -353,9 +364,13 @@ impl MarchingCubes {
         }
     }
 
+    /// If `colors` is set, they are interpolated to `mesh.colors` along with the positions.
     fn march_cube(
         grid: &[Vertex; 8],
+        colors: Option<&[Vec4; 8]>,
+        ids: Option<&[u32; 8]>,
         cubeindex: usize,
+        edge_crossing: &impl Fn(f32, f32) -> f32,
         mesh: &mut TriangleMesh,
         vidx_lookup: &mut HashMap<(usize, usize), u32>,
     ) -> u32 {
@@ -370,10 +385,19 @@ impl MarchingCubes {
                 let edge_id = (a.index.min(b.index), a.index.max(b.index));
                 let vidx = *vidx_lookup.entry(edge_id).or_insert_with(|| {
                     let vidx = mesh.positions.len() as u32;
-                    let t = Self::vertex_lerp_t(a, b);
+                    let t = edge_crossing(a.distance, b.distance).clamp(0.0, 1.0);
                     mesh.positions.push(a.position.lerp(b.position, t).into());
                     mesh.normals
                         .push(a.normal.lerp(b.normal, t).normalize().into());
+                    if let Some(colors) = colors {
+                        mesh.colors
+                            .push(colors[verts.0].lerp(colors[verts.1], t).into());
+                    }
+                    if let Some(ids) = ids {
+                        // The primitive of the nearest corner.
+                        mesh.vertex_ids
+                            .push(if t < 0.5 { ids[verts.0] } else { ids[verts.1] });
+                    }
                     vidx
                 });
 
@@ -409,7 +433,7 @@ where
     /// Gives a mesh using colors from the given function
     pub fn marching_cubes_with_color(
         &self,
-        color: impl Fn(Vec3) -> Vec3 + Send + Sync,
+        color: impl Fn(Vec3) -> Vec4 + Send + Sync,
     ) -> TriangleMesh {
         let mut mesh = self.marching_cubes();
         #[cfg(feature = "with_rayon")]
@@ -432,24 +456,97 @@ where
         mesh
     }
 
+    /// Triangles wind counter-clockwise around the normals, which point along the distance gradient (outwards).
+    /// See [`TriangleMesh::flip_winding`] for the opposite.
+    ///
     /// Does NOT return colors
     pub fn marching_cubes(&self) -> TriangleMesh {
-        let size = self.size();
-        if size[0] <= 1 || size[1] <= 1 || size[2] <= 1 {
-            return Default::default();
-        }
+        self.marching_cubes_isolevel(0.0)
+    }
 
-        let max_vertices = (size[0] * size[1] * size[2]).clamp(1, 65536);
+    /// Extracts the surface where `distance == iso`, instead of the zero isosurface.
+    ///
+    /// A positive `iso` gives a surface outside of the zero isosurface (dilated), and a negative one inside it (eroded).
+    ///
+    /// Does NOT return colors
+    pub fn marching_cubes_isolevel(&self, iso: f32) -> TriangleMesh {
+        self.marching_cubes_impl(iso, false, false, linear_edge_crossing, &Progress::none())
+    }
 
-        let mut mesh = TriangleMesh {
-            indices: Vec::with_capacity(max_vertices),
-            positions: Vec::with_capacity(max_vertices),
-            normals: Vec::with_capacity(max_vertices),
-            colors: Default::default(), // no colors
-        };
+    /// Like [`Self::marching_cubes`], but reports the fraction done to `progress` after each z slab,
+    /// and stops early with a partial mesh once it's cancelled.
+    #[cfg(feature = "std")]
+    pub(crate) fn marching_cubes_with_progress(&self, progress: &Progress<'_>) -> TriangleMesh {
+        self.marching_cubes_impl(0.0, false, false, linear_edge_crossing, progress)
+    }
+
+    /// Like [`Self::marching_cubes`], but with a custom way of placing the vertices along the cube edges,
+    /// e.g. to experiment with higher order interpolation.
+    ///
+    /// `edge_crossing` gets the distances at the two ends of an edge, of opposite signs,
+    /// and returns how far along the edge from the first end to put the vertex, from 0 to 1.
+    /// The normals are interpolated the same way. [`linear_edge_crossing`] gives the same mesh as [`Self::marching_cubes`].
+    ///
+    /// Does NOT return colors
+    pub fn marching_cubes_with_interpolation(
+        &self,
+        edge_crossing: impl Fn(f32, f32) -> f32,
+    ) -> TriangleMesh {
+        self.marching_cubes_impl(0.0, false, false, edge_crossing, &Progress::none())
+    }
+
+    /// Like [`Self::marching_cubes`], but also returns colors, by interpolating the
+    /// materials of the grid (e.g. a `Grid3<RgbaWithDistance>`) the same way as the positions.
+    ///
+    /// This avoids evaluating the colors again at each vertex, like [`Self::marching_cubes_with_color`] does.
+    pub fn marching_cubes_with_interpolated_colors(&self) -> TriangleMesh {
+        self.marching_cubes_impl(0.0, true, false, linear_edge_crossing, &Progress::none())
+    }
 
-        let mut vidx_lookup = HashMap::with_capacity(max_vertices);
+    /// Like [`Self::marching_cubes`], but also fills in [`TriangleMesh::vertex_ids`], with the
+    /// primitive ids of a `Grid3<DistanceWithId>`, taken from the grid point nearest to each vertex.
+    ///
+    /// Does NOT return colors
+    pub fn marching_cubes_with_ids(&self) -> TriangleMesh {
+        self.marching_cubes_impl(0.0, false, true, linear_edge_crossing, &Progress::none())
+    }
+
+    /// The cells with a sign change across any of their edges, i.e. the ones marching cubes
+    /// extracts the surface from. Each cell is given by its corner with the lowest coordinates.
+    ///
+    /// Useful for custom surface extraction, e.g. point clouds.
+    pub fn surface_cells(&self) -> impl Iterator<Item = Index3> + '_ {
+        let [w, h, d] = self.size();
+        let data = self.data();
+        let is_inside =
+            move |x: usize, y: usize, z: usize| data[x + w * (y + h * z)].distance() < 0.0;
+
+        (0..d.saturating_sub(1))
+            .flat_map(move |z| (0..h.saturating_sub(1)).map(move |y| (y, z)))
+            .flat_map(move |(y, z)| (0..w.saturating_sub(1)).map(move |x| [x, y, z]))
+            .filter(move |&[x, y, z]| {
+                let first = is_inside(x, y, z);
+                (1..8).any(|corner| {
+                    is_inside(x + (corner & 1), y + ((corner >> 1) & 1), z + (corner >> 2)) != first
+                })
+            })
+    }
 
+    /// Marches the cells from `cells.start` up to `cells.end` into `output`, with positions in the grid
+    /// `self` is a brick of, see [`CellRange`]. Bricks marched into the same `output` share the vertices
+    /// on the grid edges they have in common.
+    ///
+    /// Grid points next to the cells are still used for the normals, so a brick should have
+    /// one more grid point around its cells, where the larger grid has one.
+    pub(crate) fn march_cells(
+        &self,
+        cells: &CellRange,
+        iso: f32,
+        edge_crossing: &impl Fn(f32, f32) -> f32,
+        progress: &Progress<'_>,
+        output: &mut MarchingCubesOutput,
+    ) {
+        let size = self.size();
         let data = self.data();
 
         // Setup strides
@@ -457,11 +554,19 @@ where
         let ys = size[0];
         let zs = size[0] * size[1];
 
-        for z in 0..(size[2] - 1) {
-            for y in 0..(size[1] - 1) {
-                let mut index = y * ys + z * zs;
+        // Vertices are keyed by the grid edge they are on, in the larger grid.
+        let [ox, oy, oz] = cells.offset;
+        let full_ys = cells.full_size[0];
+        let full_zs = cells.full_size[0] * cells.full_size[1];
+
+        for z in cells.start[2]..cells.end[2] {
+            if progress.is_cancelled() {
+                break;
+            }
+            for y in cells.start[1]..cells.end[1] {
+                for x in cells.start[0]..cells.end[0] {
+                    let index = x * xs + y * ys + z * zs;
 
-                for x in 0..(size[0] - 1) {
                     let offsets = [
                         index,
                         index + xs,
@@ -474,14 +579,14 @@ where
                     ];
 
                     let distances = [
-                        data[offsets[0]].distance(),
-                        data[offsets[1]].distance(),
-                        data[offsets[2]].distance(),
-                        data[offsets[3]].distance(),
-                        data[offsets[4]].distance(),
-                        data[offsets[5]].distance(),
-                        data[offsets[6]].distance(),
-                        data[offsets[7]].distance(),
+                        data[offsets[0]].distance() - iso,
+                        data[offsets[1]].distance() - iso,
+                        data[offsets[2]].distance() - iso,
+                        data[offsets[3]].distance() - iso,
+                        data[offsets[4]].distance() - iso,
+                        data[offsets[5]].distance() - iso,
+                        data[offsets[6]].distance() - iso,
+                        data[offsets[7]].distance() - iso,
                     ];
 
                     let cubeindex = MarchingCubes::get_cube_index(&distances);
@@ -489,9 +594,11 @@ where
                     if let Some(cubeindex) = cubeindex {
                         let get_vertex =
                             |x: usize, y: usize, z: usize, index: usize, distance: f32| -> Vertex {
-                                let pos = Vec3::new(x as f32, y as f32, z as f32);
+                                let (x_full, y_full, z_full) = (x + ox, y + oy, z + oz);
+                                let pos = Vec3::new(x_full as f32, y_full as f32, z_full as f32);
                                 let normal = self.fast_gradient(x, y, z, index, ys, zs);
-                                Vertex::new(pos, distance, normal, index)
+                                let full_index = x_full + y_full * full_ys + z_full * full_zs;
+                                Vertex::new(pos, distance, normal, full_index)
                             };
 
                         let grid_vertices = [
@@ -505,19 +612,185 @@ where
                             get_vertex(x + 0, y + 1, z + 1, offsets[7], distances[7]),
                         ];
 
+                        let colors = output
+                            .interpolate_colors
+                            .then(|| offsets.map(|offset| data[offset].material().rgba()));
+                        let ids = output.with_ids.then(|| {
+                            offsets.map(|offset| {
+                                data[offset].primitive_id().unwrap_or(DistanceWithId::NO_ID)
+                            })
+                        });
+
                         let _num_tris = MarchingCubes::march_cube(
                             &grid_vertices,
+                            colors.as_ref(),
+                            ids.as_ref(),
                             cubeindex,
-                            &mut mesh,
-                            &mut vidx_lookup,
+                            edge_crossing,
+                            &mut output.mesh,
+                            &mut output.vertex_of_edge,
                         );
                     }
-
-                    index += 1;
                 }
             }
+            let (first, last) = (cells.start[2], cells.end[2]);
+            progress.marched((z + 1 - first) as f32 / (last - first) as f32);
         }
+    }
 
-        mesh
+    fn marching_cubes_impl(
+        &self,
+        iso: f32,
+        interpolate_colors: bool,
+        with_ids: bool,
+        edge_crossing: impl Fn(f32, f32) -> f32,
+        progress: &Progress<'_>,
+    ) -> TriangleMesh {
+        let size = self.size();
+        if size[0] <= 1 || size[1] <= 1 || size[2] <= 1 {
+            return Default::default();
+        }
+
+        let max_vertices = (size[0] * size[1] * size[2]).clamp(1, 65536);
+        let mut output =
+            MarchingCubesOutput::with_capacity(max_vertices, interpolate_colors, with_ids);
+        self.march_cells(
+            &CellRange::whole(size),
+            iso,
+            &edge_crossing,
+            progress,
+            &mut output,
+        );
+        output.mesh
+    }
+}
+
+/// The cells of a grid to march with [`Grid3::march_cells`], where the grid may be a brick of a larger one.
+pub(crate) struct CellRange {
+    /// The first cell to march along each axis, by its corner with the lowest coordinates.
+    pub start: Index3,
+    /// One past the last cell to march along each axis.
+    pub end: Index3,
+    /// Where grid point `[0, 0, 0]` is in the larger grid.
+    pub offset: Index3,
+    /// The size of the larger grid.
+    pub full_size: Index3,
+}
+
+impl CellRange {
+    /// Every cell of a grid of `size`, which isn't part of a larger one.
+    pub fn whole(size: Index3) -> Self {
+        Self {
+            start: [0; 3],
+            end: size.map(|n| n - 1),
+            offset: [0; 3],
+            full_size: size,
+        }
+    }
+}
+
+/// The mesh built by [`Grid3::march_cells`], along with the vertex on each grid edge the surface crosses.
+pub(crate) struct MarchingCubesOutput {
+    pub mesh: TriangleMesh,
+    vertex_of_edge: HashMap<(usize, usize), u32>,
+    interpolate_colors: bool,
+    with_ids: bool,
+}
+
+impl MarchingCubesOutput {
+    /// If `interpolate_colors` is set, the colors of the grid are interpolated to the mesh, see
+    /// [`Grid3::marching_cubes_with_interpolated_colors`], and if `with_ids` is set, so are the primitive ids.
+    pub fn with_capacity(max_vertices: usize, interpolate_colors: bool, with_ids: bool) -> Self {
+        Self {
+            mesh: TriangleMesh {
+                indices: Vec::with_capacity(max_vertices),
+                positions: Vec::with_capacity(max_vertices),
+                normals: Vec::with_capacity(max_vertices),
+                colors: Vec::with_capacity(if interpolate_colors { max_vertices } else { 0 }),
+                uvs: Default::default(),
+                vertex_ids: Vec::with_capacity(if with_ids { max_vertices } else { 0 }),
+            },
+            #[cfg(feature = "std")]
+            vertex_of_edge: HashMap::with_capacity(max_vertices),
+            // A `BTreeMap` can't reserve space up front.
+            #[cfg(not(feature = "std"))]
+            vertex_of_edge: HashMap::new(),
+            interpolate_colors,
+            with_ids,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_interpolation() {
+        let center = Vec3::new(8.3, 7.9, 8.1);
+        let mut grid = Grid3::<f32>::new([16, 16, 16]);
+        grid.set(|[x, y, z]| Vec3::new(x as f32, y as f32, z as f32).distance(center) - 5.0);
+
+        let linear = grid.marching_cubes_with_interpolation(linear_edge_crossing);
+        let default = grid.marching_cubes();
+        assert_eq!(linear.indices, default.indices);
+        assert_eq!(linear.positions, default.positions);
+        assert_eq!(linear.normals, default.normals);
+
+        // Midpoints put every vertex halfway along a grid edge.
+        let midpoint = grid.marching_cubes_with_interpolation(|_, _| 0.5);
+        assert_eq!(midpoint.indices, default.indices);
+        for p in &midpoint.positions {
+            let num_half = p.iter().filter(|c| c.fract() == 0.5).count();
+            assert_eq!(num_half, 1, "{p:?}");
+        }
+    }
+
+    #[test]
+    fn isolevel() {
+        let radius = 10.0;
+        let center = Vec3::splat(16.0);
+        let mut grid = Grid3::<f32>::new([32, 32, 32]);
+        grid.set(|[x, y, z]| Vec3::new(x as f32, y as f32, z as f32).distance(center) - radius);
+
+        let mean_radius = |mesh: &TriangleMesh| {
+            let sum: f32 = mesh
+                .positions
+                .iter()
+                .map(|p| Vec3::from(*p).distance(center))
+                .sum();
+            sum / mesh.positions.len() as f32
+        };
+
+        let zero = mean_radius(&grid.marching_cubes());
+        let dilated = mean_radius(&grid.marching_cubes_isolevel(2.0));
+        let eroded = mean_radius(&grid.marching_cubes_isolevel(-2.0));
+
+        assert!((zero - radius).abs() < 0.1);
+        assert!((dilated - (radius + 2.0)).abs() < 0.1);
+        assert!((eroded - (radius - 2.0)).abs() < 0.1);
+    }
+
+    #[test]
+    fn surface_cells() {
+        let center = Vec3::splat(16.0);
+        let sphere_grid = |radius: f32| {
+            let mut grid = Grid3::<f32>::new([32, 32, 32]);
+            grid.set(|[x, y, z]| Vec3::new(x as f32, y as f32, z as f32).distance(center) - radius);
+            grid
+        };
+
+        let small = sphere_grid(5.0);
+        let large = sphere_grid(10.0);
+
+        // Twice the radius gives about four times the surface area.
+        let ratio = large.surface_cells().count() as f32 / small.surface_cells().count() as f32;
+        assert!((3.5..4.5).contains(&ratio), "{ratio}");
+
+        // Every surface cell straddles the surface.
+        for [x, y, z] in large.surface_cells() {
+            let cell_center = Vec3::new(x as f32, y as f32, z as f32) + Vec3::splat(0.5);
+            assert!((cell_center.distance(center) - 10.0).abs() < 3.0f32.sqrt());
+        }
     }
 }
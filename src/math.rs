@@ -3,6 +3,8 @@
 //! Only contains the types that are strictly necessary for `opensaft`.
 
 use glam::{Mat3A, Vec3};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
 
 /// A ray in 3-dimensional space: a line through space with a starting point and a direction.
 ///
@@ -26,6 +28,46 @@ impl Ray3 {
     }
 }
 
+/// An infinite plane in 3-dimensional space, made of all points `p` where `normal.dot(p) + d == 0`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "with_speedy", derive(speedy::Writable, speedy::Readable))]
+pub struct Plane3 {
+    /// Normal vector, normalized
+    pub normal: Vec3,
+    /// Distance from the origin, along the negative normal
+    pub d: f32,
+}
+
+impl Plane3 {
+    /// The plane through `point` facing along `normal`, which must be normalized.
+    #[inline]
+    pub fn from_normal_point(normal: Vec3, point: Vec3) -> Self {
+        Self {
+            normal,
+            d: -normal.dot(point),
+        }
+    }
+
+    /// Signed distance from the plane, positive on the side the normal points to.
+    #[inline]
+    pub fn distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.d
+    }
+
+    /// The point on the plane closest to the origin.
+    #[inline]
+    pub fn origin(&self) -> Vec3 {
+        -self.d * self.normal
+    }
+
+    /// Two normalized vectors spanning the plane, so that `u.cross(v) == normal`.
+    #[inline]
+    pub fn basis(&self) -> (Vec3, Vec3) {
+        self.normal.any_orthonormal_pair()
+    }
+}
+
 /// A 3-dimensional axis-aligned bounding box
 #[derive(Clone, Copy, Default, PartialEq)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
@@ -263,6 +305,34 @@ impl BoundingBox {
             && (self.min.z <= point.z && point.z <= self.max.z)
     }
 
+    /// The part of `ray` inside the box, as a range of `t`, starting at 0 if the ray starts inside.
+    ///
+    /// Returns `None` if the ray misses the box, or the box is behind it.
+    /// The end of the range is infinite for boxes that are infinite along the ray.
+    #[must_use]
+    pub fn ray_intersection(&self, ray: &Ray3) -> Option<core::ops::RangeInclusive<f32>> {
+        if self.is_nothing() {
+            return None;
+        }
+        // The slab test: intersect the ranges of `t` between the min and max planes of each axis.
+        let mut t_enter = 0.0_f32;
+        let mut t_exit = f32::INFINITY;
+        for axis in 0..3 {
+            let (origin, dir) = (ray.origin[axis], ray.dir[axis]);
+            if dir == 0.0 {
+                if origin < self.min[axis] || origin > self.max[axis] {
+                    return None;
+                }
+            } else {
+                let t0 = (self.min[axis] - origin) / dir;
+                let t1 = (self.max[axis] - origin) / dir;
+                t_enter = t_enter.max(t0.min(t1));
+                t_exit = t_exit.min(t0.max(t1));
+            }
+        }
+        (t_enter <= t_exit).then_some(t_enter..=t_exit)
+    }
+
     /// Expand with this much padding on each side.
     #[must_use]
     pub fn expanded(&self, padding: Vec3) -> Self {
@@ -295,19 +365,34 @@ impl BoundingBox {
         }
     }
 
-    /// Return a bounding box that contains this box after it has been transformed.
+    /// Return a bounding box that contains this box after it has been transformed,
+    /// i.e. that contains the transformed [`Self::corners`].
     ///
     /// Note that the rotated bounding box is very likely larger than the original,
     /// since it must be large enough to contain the now rotated box.
     #[must_use]
     #[cfg(not(target_arch = "spirv"))]
-    pub fn transform_affine3(&self, m: &glam::Affine3A) -> Self {
+    pub fn transformed(&self, m: &glam::Affine3A) -> Self {
         if self.is_nothing() {
             Self::nothing()
         } else {
             transform_bounding_box(self.half_size(), self.center(), m)
         }
     }
+
+    #[must_use]
+    #[cfg(not(target_arch = "spirv"))]
+    #[deprecated(note = "renamed to `transformed`")]
+    pub fn transform_affine3(&self, m: &glam::Affine3A) -> Self {
+        self.transformed(m)
+    }
+}
+
+/// `a` and `b` differ by at most `epsilon`, relative to the larger of them if that's above one.
+///
+/// Equal infinities are approximately equal too.
+pub(crate) fn approx_eq(a: f32, b: f32, epsilon: f32) -> bool {
+    a == b || (a - b).abs() <= epsilon * a.abs().max(b.abs()).max(1.0)
 }
 
 trait TransformPoint3 {
@@ -374,9 +459,11 @@ fn rotate_bounding_box(half_size: Vec3, center: Vec3, q: glam::Quat) -> Bounding
         abs_matrix3.mul_vec3(half_size)
     };
 
+    let center_rotated = q * center;
+
     BoundingBox {
-        min: center - half_size_rotated,
-        max: center + half_size_rotated,
+        min: center_rotated - half_size_rotated,
+        max: center_rotated + half_size_rotated,
     }
 }
 
@@ -393,15 +480,33 @@ mod test {
         assert!(bb.contains(Vec3::ZERO));
         assert!(bb.is_something());
         assert!(!bb.is_nothing());
-        let bb_rotated =
-            bb.transform_affine3(&Affine3A::from_quat(Quat::from_axis_angle(Vec3::X, 0.5)));
+        let bb_rotated = bb.transformed(&Affine3A::from_quat(Quat::from_axis_angle(Vec3::X, 0.5)));
         assert_eq!(bb, bb_rotated);
-        let bb_translated =
-            bb.transform_affine3(&Affine3A::from_translation(Vec3::new(2.0, 3.0, 5.0)));
+        let bb_translated = bb.transformed(&Affine3A::from_translation(Vec3::new(2.0, 3.0, 5.0)));
         assert!(bb_translated.is_something());
         assert!(bb_translated.contains(Vec3::new(2.0, 3.0, 5.0)));
     }
 
+    #[test]
+    fn test_transformed() {
+        let bb = BoundingBox::from_min_max(Vec3::new(1.0, 2.0, 3.0), Vec3::new(2.0, 5.0, 4.0));
+        assert_eq!(bb.transformed(&Affine3A::IDENTITY), bb);
+        assert_eq!(
+            BoundingBox::from_points(bb.corners().into_iter()),
+            bb,
+            "corners"
+        );
+
+        let rotation = Quat::from_rotation_y(FRAC_PI_2);
+        let transformed = bb.transformed(&Affine3A::from_quat(rotation));
+        let rotated = bb.rotated_around_origin(&rotation);
+        assert!(transformed.min.abs_diff_eq(rotated.min, 1e-6));
+        assert!(transformed.max.abs_diff_eq(rotated.max, 1e-6));
+        // x goes to -z and z to x.
+        assert!(transformed.min.abs_diff_eq(Vec3::new(3.0, 2.0, -2.0), 1e-6));
+        assert!(transformed.max.abs_diff_eq(Vec3::new(4.0, 5.0, -1.0), 1e-6));
+    }
+
     #[test]
     fn test_intersection() {
         assert_eq!(
@@ -418,6 +523,32 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_ray_intersection() {
+        let bb = BoundingBox::from_min_max(Vec3::splat(-1.0), Vec3::splat(1.0));
+        let ray = |origin, dir| Ray3 { origin, dir };
+
+        let through = ray(Vec3::new(0.5, 0.0, -5.0), Vec3::Z);
+        assert_eq!(bb.ray_intersection(&through), Some(4.0..=6.0));
+        let inside = ray(Vec3::ZERO, Vec3::X);
+        assert_eq!(bb.ray_intersection(&inside), Some(0.0..=1.0));
+        let behind = ray(Vec3::new(0.0, 0.0, 5.0), Vec3::Z);
+        assert_eq!(bb.ray_intersection(&behind), None);
+        let beside = ray(Vec3::new(2.0, 0.0, -5.0), Vec3::Z);
+        assert_eq!(bb.ray_intersection(&beside), None);
+
+        let diagonal = ray(Vec3::splat(-3.0), Vec3::ONE.normalize());
+        let range = bb.ray_intersection(&diagonal).unwrap();
+        assert!((range.start() - 2.0 * 3.0_f32.sqrt()).abs() < 1e-5);
+        assert!((range.end() - 4.0 * 3.0_f32.sqrt()).abs() < 1e-5);
+
+        assert_eq!(BoundingBox::nothing().ray_intersection(&through), None);
+        assert_eq!(
+            BoundingBox::everything().ray_intersection(&through),
+            Some(0.0..=f32::INFINITY)
+        );
+    }
+
     #[test]
     fn test_rotated_around_origin() {
         const EPSILON: f32 = 1e-6;
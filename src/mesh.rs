@@ -1,15 +1,136 @@
+use crate::BoundingBox;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use glam::Affine3A;
+use glam::Vec2;
+use glam::Vec3;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// How to store colors, which are linear inside this crate.
+///
+/// Most viewers interpret vertex colors in OBJ files as sRGB, so linear colors look too dark in them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    Linear,
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Converts a linear RGBA color to this color space. Alpha is always linear.
+    pub fn from_linear(self, [r, g, b, a]: [f32; 4]) -> [f32; 4] {
+        match self {
+            Self::Linear => [r, g, b, a],
+            Self::Srgb => [
+                crate::linear_to_srgb(r),
+                crate::linear_to_srgb(g),
+                crate::linear_to_srgb(b),
+                a,
+            ],
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct TriangleMesh {
     pub indices: Vec<u32>,
     pub positions: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
-    pub colors: Vec<[f32; 3]>,
+    /// Linear RGBA, unless gathered in another [`ColorSpace`].
+    pub colors: Vec<[f32; 4]>,
+    /// Texture coordinates. Empty unless generated, e.g. with [`Self::generate_triplanar_uvs`].
+    pub uvs: Vec<[f32; 2]>,
+    /// For each vertex, the primitive it's on, see [`crate::DistanceWithId`].
+    /// Empty unless meshed with ids, e.g. with [`crate::mesh_from_sdf_with_ids`].
+    pub vertex_ids: Vec<u32>,
 }
 
 impl TriangleMesh {
-    /// Convert a triangle mesh to an OBJ file
+    /// Assign texture coordinates to each vertex by projecting its position
+    /// onto the axis-aligned plane its normal is the most aligned with.
+    ///
+    /// The positions are multiplied by `scale` to get the UVs, so the texture repeats every `1.0 / scale` units.
+    pub fn generate_triplanar_uvs(&mut self, scale: f32) {
+        assert_eq!(self.positions.len(), self.normals.len());
+        self.uvs = self
+            .positions
+            .iter()
+            .zip(&self.normals)
+            .map(|(p, n)| {
+                let [x, y, z] = n.map(f32::abs);
+                let uv = if x >= y && x >= z {
+                    [p[2], p[1]]
+                } else if y >= z {
+                    [p[0], p[2]]
+                } else {
+                    [p[0], p[1]]
+                };
+                uv.map(|c| c * scale)
+            })
+            .collect();
+    }
+
+    /// Reverses the winding order of every triangle and negates the normals, turning the mesh inside out.
+    ///
+    /// The meshes from this crate wind counter-clockwise around outward-facing normals,
+    /// i.e. normals that point along the distance gradient. This is for renderers that expect clockwise:
+    /// the triangles still wind counter-clockwise around the negated normals.
+    pub fn flip_winding(&mut self) {
+        for triangle in self.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+        for n in &mut self.normals {
+            *n = (-Vec3::from(*n)).into();
+        }
+    }
+
+    /// Transforms the positions by `affine`, and the normals by its inverse transpose so they stay
+    /// perpendicular to the surface, e.g. under non-uniform scaling.
+    ///
+    /// Transforms that mirror the mesh also flip the winding, so it still matches the normals.
+    pub fn transform(&mut self, affine: &Affine3A) {
+        for p in &mut self.positions {
+            *p = affine.transform_point3(Vec3::from(*p)).into();
+        }
+        let normal_from_local = affine.matrix3.inverse().transpose();
+        for n in &mut self.normals {
+            *n = (normal_from_local * Vec3::from(*n))
+                .normalize_or_zero()
+                .into();
+        }
+        if affine.matrix3.determinant() < 0.0 {
+            for triangle in self.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    /// The geometric normal of each triangle, from the cross product of its edges,
+    /// e.g. for flat shading or STL export.
+    ///
+    /// Follows the winding order, so points outwards for meshes from this crate. Zero for degenerate triangles.
+    pub fn face_normals(&self) -> Vec<[f32; 3]> {
+        self.indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vec3::from(self.positions[i as usize]));
+                (b - a).cross(c - a).normalize_or_zero().into()
+            })
+            .collect()
+    }
+
+    /// Convert a triangle mesh to an OBJ file, writing the colors as they are.
+    ///
+    /// See [`Self::to_obj_with_color_space`] for viewers that expect sRGB colors.
     pub fn to_obj(&self) -> String {
-        use std::fmt::Write as FmtWrite;
+        self.to_obj_with_color_space(ColorSpace::Linear)
+    }
+
+    /// Convert a triangle mesh with linear colors to an OBJ file, with the colors in `color_space`.
+    pub fn to_obj_with_color_space(&self, color_space: ColorSpace) -> String {
+        use core::fmt::Write as FmtWrite;
 
         let mesh = self;
 
@@ -17,10 +138,11 @@ impl TriangleMesh {
         writeln!(&mut s, "# Generated by opensaft-ext library").unwrap();
 
         // Adding vertex colors after vertex positions is a non-standard extension,
-        // but a common one:
+        // but a common one. It has no alpha:
         writeln!(&mut s, "\n# Vertex positions and colors:").unwrap();
         assert_eq!(mesh.positions.len(), mesh.colors.len());
         for (p, c) in mesh.positions.iter().zip(&mesh.colors) {
+            let c = color_space.from_linear(*c);
             writeln!(s, "v {} {} {} {} {} {}", p[0], p[1], p[2], c[0], c[1], c[2]).unwrap();
         }
 
@@ -30,11 +152,116 @@ impl TriangleMesh {
             writeln!(&mut s, "vn {} {} {}", n[0], n[1], n[2]).unwrap();
         }
 
+        if !mesh.uvs.is_empty() {
+            writeln!(&mut s, "\n# Texture coordinates:").unwrap();
+            assert_eq!(mesh.positions.len(), mesh.uvs.len());
+            for uv in &mesh.uvs {
+                writeln!(&mut s, "vt {} {}", uv[0], uv[1]).unwrap();
+            }
+        }
+
         writeln!(&mut s, "\n# Triangle faces:").unwrap();
         assert_eq!(mesh.indices.len() % 3, 0);
         for t in mesh.indices.chunks(3) {
             // OBJ uses 1-based indexing, like some sort of cave man
-            writeln!(&mut s, "f {} {} {}", t[0] + 1, t[1] + 1, t[2] + 1).unwrap();
+            let [a, b, c] = [t[0] + 1, t[1] + 1, t[2] + 1];
+            if mesh.uvs.is_empty() {
+                writeln!(&mut s, "f {} {} {}", a, b, c).unwrap();
+            } else {
+                writeln!(&mut s, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}").unwrap();
+            }
+        }
+
+        writeln!(&mut s, "\n# End of obj file.").unwrap();
+
+        s
+    }
+}
+
+/// A mesh of quads, e.g. from [`crate::Grid3::surface_nets`], for tools that work better with quads,
+/// like subdivision and retopology.
+#[derive(Clone, Default)]
+pub struct QuadMesh {
+    /// Counter-clockwise. A triangle is encoded as a quad that repeats its last index.
+    pub faces: Vec<[u32; 4]>,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Linear RGBA, unless gathered in another [`ColorSpace`].
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl QuadMesh {
+    /// Keeps each triangle as a quad that repeats its last index.
+    pub fn from_triangles(mesh: &TriangleMesh) -> Self {
+        Self {
+            faces: mesh
+                .indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2], t[2]])
+                .collect(),
+            positions: mesh.positions.clone(),
+            normals: mesh.normals.clone(),
+            colors: mesh.colors.clone(),
+        }
+    }
+
+    /// Splits each quad into two triangles, and keeps triangles as they are.
+    pub fn triangulate(&self) -> TriangleMesh {
+        let mut indices = Vec::with_capacity(self.faces.len() * 6);
+        for &[a, b, c, d] in &self.faces {
+            indices.extend([a, b, c]);
+            if d != c {
+                indices.extend([a, c, d]);
+            }
+        }
+        TriangleMesh {
+            indices,
+            positions: self.positions.clone(),
+            normals: self.normals.clone(),
+            colors: self.colors.clone(),
+            uvs: vec![],
+            vertex_ids: vec![],
+        }
+    }
+
+    /// Convert a quad mesh to an OBJ file, with `f a b c d` faces.
+    ///
+    /// See [`Self::to_obj_with_color_space`] for viewers that expect sRGB colors.
+    pub fn to_obj(&self) -> String {
+        self.to_obj_with_color_space(ColorSpace::Linear)
+    }
+
+    /// Like [`Self::to_obj`], with the linear colors converted to `color_space`.
+    pub fn to_obj_with_color_space(&self, color_space: ColorSpace) -> String {
+        use core::fmt::Write as FmtWrite;
+
+        let mut s = String::new();
+        writeln!(&mut s, "# Generated by opensaft-ext library").unwrap();
+
+        writeln!(&mut s, "\n# Vertex positions and colors:").unwrap();
+        for (i, p) in self.positions.iter().enumerate() {
+            if let Some([r, g, b, _]) = self.colors.get(i).map(|c| color_space.from_linear(*c)) {
+                writeln!(s, "v {} {} {} {} {} {}", p[0], p[1], p[2], r, g, b).unwrap();
+            } else {
+                writeln!(s, "v {} {} {}", p[0], p[1], p[2]).unwrap();
+            }
+        }
+
+        writeln!(&mut s, "\n# Vertex normals:").unwrap();
+        assert_eq!(self.positions.len(), self.normals.len());
+        for n in &self.normals {
+            writeln!(&mut s, "vn {} {} {}", n[0], n[1], n[2]).unwrap();
+        }
+
+        writeln!(&mut s, "\n# Faces:").unwrap();
+        for &[a, b, c, d] in &self.faces {
+            // OBJ uses 1-based indexing
+            let [a, b, c, d] = [a + 1, b + 1, c + 1, d + 1];
+            if d == c {
+                writeln!(&mut s, "f {} {} {}", a, b, c).unwrap();
+            } else {
+                writeln!(&mut s, "f {} {} {} {}", a, b, c, d).unwrap();
+            }
         }
 
         writeln!(&mut s, "\n# End of obj file.").unwrap();
@@ -42,3 +269,279 @@ impl TriangleMesh {
         s
     }
 }
+
+/// How far a mesh is from the surface it was made from, see [`mesh_sdf_error`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ErrorStats {
+    /// The largest absolute distance of any sample.
+    pub max: f32,
+    /// The root mean square of the distances of all samples.
+    pub rms: f32,
+    pub num_samples: usize,
+}
+
+/// Measures how well `mesh` matches the surface of `sd`, e.g. to find the lowest
+/// [`crate::MeshOptions::mean_resolution`] that is within some tolerance.
+///
+/// Evaluates `sd` at every vertex and at four points inside every triangle.
+/// On a perfect mesh all distances would be zero.
+pub fn mesh_sdf_error(mesh: &TriangleMesh, sd: impl Fn(Vec3) -> f32) -> ErrorStats {
+    let mut max = 0.0_f32;
+    let mut sum_squared = 0.0_f64;
+    let mut num_samples = 0;
+    let mut add_sample = |pos: Vec3| {
+        let distance = sd(pos).abs();
+        max = max.max(distance);
+        sum_squared += f64::from(distance * distance);
+        num_samples += 1;
+    };
+
+    for pos in &mesh.positions {
+        add_sample(Vec3::from(*pos));
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        let corners = [triangle[0], triangle[1], triangle[2]]
+            .map(|index| Vec3::from(mesh.positions[index as usize]));
+        let centroid = (corners[0] + corners[1] + corners[2]) / 3.0;
+        add_sample(centroid);
+        for corner in corners {
+            add_sample(centroid.lerp(corner, 0.5));
+        }
+    }
+
+    ErrorStats {
+        max,
+        rms: if num_samples == 0 {
+            0.0
+        } else {
+            (sum_squared / num_samples as f64).sqrt() as f32
+        },
+        num_samples,
+    }
+}
+
+/// Meshes the height field `y = h(Vec2::new(x, z))` directly, e.g. for terrain,
+/// without sampling a 3D grid.
+///
+/// The mesh covers `bb` in x and z with `resolution` vertices along each of them,
+/// and the heights are clamped to the y range of `bb`.
+/// The positions are in world coordinates, the normals come from finite differences of `h`,
+/// and there are no colors.
+pub fn mesh_heightmap(
+    bb: &BoundingBox,
+    resolution: [usize; 2],
+    h: impl Fn(Vec2) -> f32,
+) -> TriangleMesh {
+    let [w, d] = resolution;
+    if w < 2 || d < 2 {
+        return TriangleMesh::default();
+    }
+
+    let min = Vec2::new(bb.min.x, bb.min.z);
+    let cell_size = Vec2::new(bb.size().x, bb.size().z) / Vec2::new(w as f32 - 1.0, d as f32 - 1.0);
+    let height = |p: Vec2| h(p).clamp(bb.min.y, bb.max.y);
+
+    let mut mesh = TriangleMesh::default();
+    for z in 0..d {
+        for x in 0..w {
+            let p = min + cell_size * Vec2::new(x as f32, z as f32);
+            let dx = Vec2::new(cell_size.x, 0.0);
+            let dz = Vec2::new(0.0, cell_size.y);
+            let slope_x = (height(p + dx) - height(p - dx)) / (2.0 * cell_size.x);
+            let slope_z = (height(p + dz) - height(p - dz)) / (2.0 * cell_size.y);
+
+            mesh.positions.push([p.x, height(p), p.y]);
+            mesh.normals
+                .push(Vec3::new(-slope_x, 1.0, -slope_z).normalize().into());
+        }
+    }
+
+    for z in 0..d - 1 {
+        for x in 0..w - 1 {
+            let index = |x: usize, z: usize| (x + w * z) as u32;
+            // Counter-clockwise when seen from above.
+            mesh.indices.extend([
+                index(x, z),
+                index(x, z + 1),
+                index(x + 1, z + 1),
+                index(x, z),
+                index(x + 1, z + 1),
+                index(x + 1, z),
+            ]);
+        }
+    }
+
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use glam::Vec3;
+
+    #[test]
+    fn triplanar_uvs() {
+        let mut graph = Graph::default();
+        let node = graph.rounded_box(Vec3::new(1.0, 2.0, 0.5), 0.2);
+        let mut mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+
+        mesh.generate_triplanar_uvs(0.5);
+        assert_eq!(mesh.uvs.len(), mesh.positions.len());
+        assert!(mesh.uvs.iter().flatten().all(|c| c.is_finite()));
+
+        let obj = mesh.to_obj();
+        assert_eq!(
+            obj.lines().filter(|l| l.starts_with("vt ")).count(),
+            mesh.uvs.len()
+        );
+        assert!(obj
+            .lines()
+            .filter(|l| l.starts_with("f "))
+            .all(|l| l.matches('/').count() == 6));
+    }
+
+    #[test]
+    fn flat_quad_face_normals() {
+        let mesh = TriangleMesh {
+            indices: vec![0, 1, 2, 0, 2, 3],
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [2.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ],
+            ..Default::default()
+        };
+        assert_eq!(mesh.face_normals(), vec![[0.0, 0.0, 1.0]; 2]);
+
+        let mut flipped = mesh;
+        flipped.flip_winding();
+        assert_eq!(flipped.face_normals(), vec![[0.0, 0.0, -1.0]; 2]);
+    }
+
+    #[test]
+    fn transform() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let mut mesh = mesh_from_sdf(&graph, sphere, MeshOptions::low()).unwrap();
+
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let rotation = glam::Quat::from_axis_angle(Vec3::new(1.0, 1.0, 0.0).normalize(), 1.0);
+        mesh.transform(&glam::Affine3A::from_rotation_translation(rotation, center));
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            let outward = (Vec3::from(*p) - center).normalize();
+            assert!((Vec3::from(*n).length() - 1.0).abs() < 1e-5);
+            assert!(Vec3::from(*n).dot(outward) > 0.9);
+        }
+
+        // Squashing and mirroring still leaves the normals pointing out, and matching the winding.
+        mesh.transform(&glam::Affine3A::from_scale(Vec3::new(-1.0, 0.5, 1.0)));
+        let face_normals = mesh.face_normals();
+        for (t, face_normal) in mesh.indices.chunks_exact(3).zip(face_normals) {
+            let n = Vec3::from(mesh.normals[t[0] as usize]);
+            assert!((n.length() - 1.0).abs() < 1e-5);
+            assert!(n.dot(Vec3::from(face_normal)) > 0.5);
+        }
+    }
+
+    #[test]
+    fn sdf_error_decreases_with_resolution() {
+        let mut graph = Graph::default();
+        let root = graph.example(&ExampleParams::default());
+        let program = compile(&graph, root);
+        let sd = |pos| {
+            let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+            Interpreter::<f32>::interpret(&mut context, pos).unwrap()
+        };
+
+        let error = |mean_resolution| {
+            let options = MeshOptions {
+                mean_resolution,
+                max_resolution: mean_resolution * 2.0,
+                min_resolution: 8.0,
+                fixed_resolution: None,
+                axis_weights: Vec3::ONE,
+            };
+            let mesh = mesh_from_sdf(&graph, root, options).unwrap();
+            mesh_sdf_error(&mesh, sd)
+        };
+
+        let low = error(16.0);
+        let high = error(64.0);
+        assert!(high.num_samples > low.num_samples);
+        assert!(high.rms < low.rms, "{high:?} vs {low:?}");
+        assert!(high.max < low.max, "{high:?} vs {low:?}");
+    }
+
+    #[test]
+    fn flip_winding() {
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::ZERO, 1.0);
+        let mut mesh = mesh_from_sdf(&graph, node, MeshOptions::low()).unwrap();
+
+        // The fraction of triangles winding counter-clockwise around their vertex normals.
+        let counter_clockwise = |mesh: &TriangleMesh| {
+            let num = mesh
+                .indices
+                .chunks_exact(3)
+                .filter(|triangle| {
+                    let [a, b, c] =
+                        [0, 1, 2].map(|i| Vec3::from(mesh.positions[triangle[i] as usize]));
+                    let normal = Vec3::from(mesh.normals[triangle[0] as usize]);
+                    (b - a).cross(c - a).dot(normal) > 0.0
+                })
+                .count();
+            num as f32 / (mesh.indices.len() / 3) as f32
+        };
+
+        // The fraction of vertex normals pointing away from the center of the sphere.
+        let outward = |mesh: &TriangleMesh| {
+            let num = mesh
+                .positions
+                .iter()
+                .zip(&mesh.normals)
+                .filter(|(p, n)| Vec3::from(**p).dot(Vec3::from(**n)) > 0.0)
+                .count();
+            num as f32 / mesh.positions.len() as f32
+        };
+
+        // Counter-clockwise with outward normals by default.
+        assert!(counter_clockwise(&mesh) > 0.99);
+        assert_eq!(outward(&mesh), 1.0);
+
+        // Clockwise as seen from outside when flipped, still consistent with the now inward normals.
+        let original = (mesh.indices.clone(), mesh.normals.clone());
+        let face_normals = mesh.face_normals();
+        mesh.flip_winding();
+        assert_ne!(mesh.indices, original.0);
+        assert!(counter_clockwise(&mesh) > 0.99);
+        assert_eq!(outward(&mesh), 0.0);
+        for (flipped, face_normal) in mesh.face_normals().iter().zip(&face_normals) {
+            assert_eq!(Vec3::from(*flipped), -Vec3::from(*face_normal));
+        }
+
+        mesh.flip_winding();
+        assert_eq!((mesh.indices, mesh.normals), original);
+    }
+
+    #[test]
+    fn flat_heightmap() {
+        let bb = BoundingBox::from_min_max(Vec3::new(-1.0, -5.0, 0.0), Vec3::new(1.0, 5.0, 3.0));
+        let mesh = mesh_heightmap(&bb, [5, 4], |_| 2.0);
+        assert_eq!(mesh.positions.len(), 5 * 4);
+        assert_eq!(mesh.indices.len(), 4 * 3 * 2 * 3);
+        assert!(mesh.positions.iter().all(|p| p[1] == 2.0));
+        assert!(mesh.normals.iter().all(|n| *n == [0.0, 1.0, 0.0]));
+        assert_eq!(mesh.positions[0], [-1.0, 2.0, 0.0]);
+        assert_eq!(mesh.positions[19], [1.0, 2.0, 3.0]);
+
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(mesh.positions[triangle[i] as usize]));
+            assert!((b - a).cross(c - a).y > 0.0);
+        }
+
+        // Heights are clamped to the bounding box.
+        let clamped = mesh_heightmap(&bb, [5, 4], |_| 10.0);
+        assert!(clamped.positions.iter().all(|p| p[1] == 5.0));
+    }
+}
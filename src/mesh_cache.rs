@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use crate::compile;
+use crate::mesh_from_sdf_program_with_grid;
+use crate::sdf_bb_and_resolution;
+use crate::Error;
+use crate::Graph;
+use crate::Grid3;
+use crate::MeshOptions;
+use crate::NodeId;
+use crate::TriangleMesh;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct MeshCacheKey {
+    program_hash: u64,
+    constant_hash: u64,
+    options: MeshOptionsKey,
+}
+
+/// The bits of the [`MeshOptions`] fields.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct MeshOptionsKey {
+    resolutions: [u32; 3],
+    fixed_resolution: Option<[usize; 3]>,
+    axis_weights: [u32; 3],
+}
+
+impl From<MeshOptions> for MeshOptionsKey {
+    fn from(options: MeshOptions) -> Self {
+        Self {
+            resolutions: [
+                options.mean_resolution.to_bits(),
+                options.max_resolution.to_bits(),
+                options.min_resolution.to_bits(),
+            ],
+            fixed_resolution: options.fixed_resolution,
+            axis_weights: options.axis_weights.to_array().map(f32::to_bits),
+        }
+    }
+}
+
+/// Remembers meshes by the hash of their compiled [`crate::Program`] and [`MeshOptions`],
+/// so that meshing an unchanged graph again is free.
+///
+/// When only the constants of a program change (e.g. when scrubbing a slider in an editor),
+/// the previous mesh of that program is replaced rather than kept around, and the buffer of the grid
+/// it was sampled on is reused for the new one, as long as the resolution stays the same.
+/// Everything is still sampled and meshed again, this only saves allocating the grid.
+#[derive(Default)]
+pub struct MeshCache {
+    meshes: HashMap<MeshCacheKey, TriangleMesh>,
+    /// The last grid used for each program hash, kept for its buffer.
+    grids: HashMap<u64, Grid3<f32>>,
+    hits: usize,
+    misses: usize,
+}
+
+impl MeshCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached mesh of `node`, or meshes it like [`crate::mesh_from_sdf`] does.
+    pub fn get_or_mesh(
+        &mut self,
+        graph: &Graph,
+        node: NodeId,
+        options: MeshOptions,
+    ) -> Result<&TriangleMesh, Error> {
+        let program = compile(graph, node);
+        let key = MeshCacheKey {
+            program_hash: program.program_hash(),
+            constant_hash: program.constant_hash(),
+            options: options.into(),
+        };
+
+        if self.meshes.contains_key(&key) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+
+            // Only the latest constants of a program are worth keeping.
+            self.meshes.retain(|other, _| {
+                other.program_hash != key.program_hash || other.options != key.options
+            });
+
+            let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+            let mut grid = match self.grids.remove(&key.program_hash) {
+                Some(grid) if grid.size() == resolution => grid,
+                _ => Grid3::new(resolution),
+            };
+            let mesh = mesh_from_sdf_program_with_grid(&program, &bb, &mut grid)?;
+            self.grids.insert(key.program_hash, grid);
+            self.meshes.insert(key, mesh);
+        }
+
+        Ok(&self.meshes[&key])
+    }
+
+    /// Number of cached meshes.
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Number of calls to [`Self::get_or_mesh`] that returned a cached mesh.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of calls to [`Self::get_or_mesh`] that had to mesh.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn clear(&mut self) {
+        self.meshes.clear();
+        self.grids.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn hit_and_invalidate() {
+        let mut cache = MeshCache::new();
+        let options = MeshOptions::low();
+
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::ZERO, 1.0);
+
+        let positions = cache
+            .get_or_mesh(&graph, node, options)
+            .unwrap()
+            .positions
+            .clone();
+        assert_eq!(cache.misses(), 1);
+        cache.get_or_mesh(&graph, node, options).unwrap();
+        assert_eq!(cache.hits(), 1);
+
+        // Changing a constant invalidates the mesh, and replaces it.
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::ZERO, 2.0);
+        let mesh = cache.get_or_mesh(&graph, node, options).unwrap();
+        assert_ne!(mesh.positions, positions);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+        assert_eq!(cache.len(), 1);
+
+        // The mesh is the same as without the cache, even though the grid got reused.
+        let expected = crate::mesh_from_sdf(&graph, node, options).unwrap();
+        let mesh = cache.get_or_mesh(&graph, node, options).unwrap();
+        assert_eq!(mesh.positions, expected.positions);
+    }
+
+    #[test]
+    fn fixed_resolution_is_part_of_the_key() {
+        let mut cache = MeshCache::new();
+
+        let mut graph = Graph::default();
+        let node = graph.sphere(Vec3::ZERO, 1.0);
+
+        let coarse = cache
+            .get_or_mesh(&graph, node, MeshOptions::fixed([8; 3]))
+            .unwrap()
+            .positions
+            .len();
+        let fine = cache
+            .get_or_mesh(&graph, node, MeshOptions::fixed([32; 3]))
+            .unwrap()
+            .positions
+            .len();
+        assert_eq!(cache.misses(), 2);
+        assert_ne!(coarse, fine);
+
+        cache
+            .get_or_mesh(&graph, node, MeshOptions::default())
+            .unwrap();
+        assert_eq!(cache.misses(), 3);
+        assert_eq!(cache.hits(), 0);
+    }
+}
@@ -0,0 +1,1016 @@
+//! Meshing, sampling and querying the signed distance fields of graphs and programs.
+
+use crate::compile;
+use crate::linear_edge_crossing;
+use crate::progress::Progress;
+use crate::sd_rounded_box;
+use crate::BoundingBox;
+use crate::CellRange;
+use crate::ColorSpace;
+use crate::CompiledSdf;
+use crate::DistanceWithId;
+use crate::Error;
+use crate::Graph;
+use crate::Grid3;
+use crate::GridTransform;
+use crate::Interpreter;
+use crate::InterpreterContext;
+use crate::MarchingCubesOutput;
+use crate::Material;
+use crate::NodeId;
+use crate::OccupancyGrid;
+use crate::Plane3;
+use crate::Program;
+use crate::RgbaWithDistance;
+use crate::SignedDistance;
+use crate::TriangleMesh;
+use glam::Vec3;
+use glam::Vec4;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// How finely to sample the bounding box of a node for meshing, see [`sdf_bb_and_resolution`].
+///
+/// By default the grid cells are cubes, so how many grid points each axis gets follows from the shape
+/// of the bounding box. For more detail across a thin object, e.g. a plate, raise [`Self::axis_weights`]
+/// along its thin axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "with_speedy", derive(speedy::Writable, speedy::Readable))]
+pub struct MeshOptions {
+    /// Desired mean resolution on the axis.
+    ///
+    /// the total number of grid points will be close to resolution ^ 3
+    pub mean_resolution: f32,
+
+    /// Trying to fit the number of cubes to the mean resolution can lead to some
+    /// extreme cases if the box is very narrow. Use these parameters to clamp
+    /// the resolution to desired "sane" bounds.
+    pub max_resolution: f32,
+    pub min_resolution: f32,
+
+    /// Use exactly this many grid points on each axis, ignoring the resolutions above, see [`Self::fixed`].
+    pub fixed_resolution: Option<[usize; 3]>,
+
+    /// Scales the number of grid points along each axis, before clamping to
+    /// [`Self::min_resolution`] and [`Self::max_resolution`], e.g. `(1, 1, 2)` for twice the detail along z.
+    ///
+    /// The cells are stretched accordingly, so they are only cubes when all weights are the same.
+    /// Must be positive and finite, or meshing returns [`Error::BadAxisWeights`]. Ignored with a fixed resolution.
+    pub axis_weights: Vec3,
+}
+
+impl MeshOptions {
+    pub fn low() -> Self {
+        Self {
+            mean_resolution: 32.0,
+            max_resolution: 64.0,
+            min_resolution: 8.0,
+            fixed_resolution: None,
+            axis_weights: Vec3::ONE,
+        }
+    }
+
+    /// A grid of exactly `resolution` points, e.g. `[128; 3]`, whatever the size of the bounding box.
+    ///
+    /// The bounding box is still padded, and grown on the axes where it's relatively thin
+    /// so that the grid cells stay cubes. Each axis needs at least 4 points, or meshing returns [`Error::BadResolution`].
+    pub fn fixed(resolution: [usize; 3]) -> Self {
+        Self {
+            fixed_resolution: Some(resolution),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            mean_resolution: 64.0,
+            max_resolution: 128.0,
+            min_resolution: 8.0,
+            fixed_resolution: None,
+            axis_weights: Vec3::ONE,
+        }
+    }
+}
+
+pub fn transform_positions_in_place(
+    mesh: &mut TriangleMesh,
+    world_from_grid_f: impl Fn(Vec3) -> Vec3 + Send + Sync,
+) {
+    #[cfg(feature = "with_rayon")]
+    {
+        use rayon::prelude::*;
+
+        mesh.positions.par_iter_mut().for_each(|p| {
+            // transform to world:
+            *p = world_from_grid_f((*p).into()).into();
+        });
+    }
+
+    #[cfg(not(feature = "with_rayon"))]
+    {
+        mesh.positions.iter_mut().for_each(|p| {
+            // transform to world:
+            *p = world_from_grid_f((*p).into()).into();
+        });
+    }
+}
+
+/// Moves the vertices of a mesh of a grid to the world, and turns the normals to match if the cells aren't cubes.
+fn transform_to_world_in_place(mesh: &mut TriangleMesh, transform: &GridTransform) {
+    transform_positions_in_place(mesh, |p| transform.world_from_grid(p));
+
+    let cell_size = transform.cell_size();
+    if cell_size != Vec3::splat(cell_size.x) {
+        for normal in &mut mesh.normals {
+            *normal = transform.world_from_grid_normal((*normal).into()).into();
+        }
+    }
+}
+
+pub fn gather_colors_in_place(
+    mesh: &mut TriangleMesh,
+    color_world: impl Fn(Vec3) -> Vec4 + Send + Sync,
+) {
+    gather_colors_in_place_with_init(mesh, || (), |(), p| color_world(p));
+}
+
+/// Like [`gather_colors_in_place`], but converts the linear colors from `color_world` to `color_space`.
+pub fn gather_colors_in_place_with_color_space(
+    mesh: &mut TriangleMesh,
+    color_space: ColorSpace,
+    color_world: impl Fn(Vec3) -> Vec4 + Send + Sync,
+) {
+    gather_colors_in_place_with_init(
+        mesh,
+        || (),
+        |(), p| color_space.from_linear(color_world(p).into()).into(),
+    );
+}
+
+/// Like [`gather_colors_in_place`], but each worker gets its own state from `init`,
+/// which is passed to `color_world` for every vertex, e.g. to reuse an interpreter context.
+pub fn gather_colors_in_place_with_init<S>(
+    mesh: &mut TriangleMesh,
+    init: impl Fn() -> S + Send + Sync,
+    color_world: impl Fn(&mut S, Vec3) -> Vec4 + Send + Sync,
+) {
+    #[cfg(feature = "with_rayon")]
+    {
+        use rayon::prelude::*;
+
+        mesh.colors = mesh
+            .positions
+            .par_iter()
+            .map_init(init, |state, p| {
+                color_world(state, Vec3::new(p[0], p[1], p[2])).into()
+            })
+            .collect();
+    }
+
+    #[cfg(not(feature = "with_rayon"))]
+    {
+        let mut state = init();
+        mesh.colors = mesh
+            .positions
+            .iter()
+            .map(|p| color_world(&mut state, Vec3::new(p[0], p[1], p[2])).into())
+            .collect();
+    }
+}
+
+/// Interprets `program` once, so that a program that is too deep for the interpreter's stack
+/// is an error up front, instead of a panic while sampling.
+pub(crate) fn check_interpretable<SD: SignedDistance>(program: &Program) -> Result<(), Error> {
+    let mut context = Interpreter::<SD>::new_context(&program.opcodes, &program.constants);
+    Interpreter::<SD>::interpret(&mut context, Vec3::ZERO)
+        .map(|_| ())
+        .ok_or(Error::StackOverflow)
+}
+
+/// Interprets a program that passed [`check_interpretable`].
+pub(crate) fn interpret_checked<SD: SignedDistance + Default>(
+    context: &mut InterpreterContext<'_, SD>,
+    pos: Vec3,
+) -> SD {
+    // Can't fail, as whether the interpreter can run a program doesn't depend on the position.
+    Interpreter::<SD>::interpret(context, pos).unwrap_or_default()
+}
+
+/// Samples `sd_world` on a grid spanning `bb`, with the distances in grid units.
+///
+/// Each worker gets its own state from `init`, see [`Grid3::set_truncated_with_init`].
+pub(crate) fn sample_grid_func<T: SignedDistance + Default + Send, S>(
+    bb: &BoundingBox,
+    resolution: [usize; 3],
+    init: impl Fn() -> S + Send + Sync,
+    sd_world: impl Fn(&mut S, Vec3) -> T + Send + Sync,
+) -> Result<Grid3<T>, Error> {
+    let mut grid = Grid3::<T>::new(resolution);
+    sample_grid_into(&mut grid, bb, init, sd_world, &Progress::none())?;
+    Ok(grid)
+}
+
+/// Like [`sample_grid_func`], but overwrites an existing grid, using its size as the resolution.
+fn sample_grid_into<T: SignedDistance + Default + Send, S>(
+    grid: &mut Grid3<T>,
+    bb: &BoundingBox,
+    init: impl Fn() -> S + Send + Sync,
+    sd_world: impl Fn(&mut S, Vec3) -> T + Send + Sync,
+    progress: &Progress<'_>,
+) -> Result<(), Error> {
+    let transform = GridTransform::new(*bb, grid.size());
+    let grid_from_world_scale = transform.grid_from_world_scale();
+
+    let sd_in_grid = |state: &mut S, pos_in_grid| {
+        let pos_in_world = transform.world_from_cell(pos_in_grid);
+        sd_world(state, pos_in_world).multiply_distance_by(grid_from_world_scale)
+    };
+
+    grid.set_truncated_with_progress(init, sd_in_grid, 2.0, progress);
+    if progress.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    // Check a single sample for NaN. Often a NaN will end up in the whole grid, so this'll catch it.
+    if !grid.data()[grid.data().len() / 2].is_distance_finite() {
+        return Err(Error::EvaluatedToNaN);
+    }
+
+    Ok(())
+}
+
+pub fn mesh_from_sdf_func(
+    bb: &BoundingBox,
+    resolution: [usize; 3],
+    sd_world: impl Fn(Vec3) -> f32 + Send + Sync,
+    color_world: impl Fn(Vec3) -> Vec4 + Send + Sync,
+) -> Result<TriangleMesh, Error> {
+    let mut grid = Grid3::new(resolution);
+    mesh_from_sdf_func_with_grid(
+        &mut grid,
+        bb,
+        || (),
+        |(), p| sd_world(p),
+        || (),
+        |(), p| color_world(p),
+        &Progress::none(),
+    )
+}
+
+/// Like [`mesh_from_sdf_func`], but reuses `grid` for the sampling, using its size as the resolution.
+///
+/// Each worker gets its own state from `init_sd` for sampling the distances,
+/// and from `init_color` for gathering the colors.
+fn mesh_from_sdf_func_with_grid<S, C>(
+    grid: &mut Grid3<f32>,
+    bb: &BoundingBox,
+    init_sd: impl Fn() -> S + Send + Sync,
+    sd_world: impl Fn(&mut S, Vec3) -> f32 + Send + Sync,
+    init_color: impl Fn() -> C + Send + Sync,
+    color_world: impl Fn(&mut C, Vec3) -> Vec4 + Send + Sync,
+    progress: &Progress<'_>,
+) -> Result<TriangleMesh, Error> {
+    let transform = GridTransform::new(*bb, grid.size());
+
+    sample_grid_into(grid, bb, init_sd, sd_world, progress)?;
+
+    let mut mesh = grid.marching_cubes_with_progress(progress);
+    if progress.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    transform_to_world_in_place(&mut mesh, &transform);
+    gather_colors_in_place_with_init(&mut mesh, init_color, color_world);
+    progress.done();
+
+    Ok(mesh)
+}
+
+pub fn mesh_from_sdf_program(
+    program: &Program,
+    bb: &BoundingBox,
+    resolution: [usize; 3],
+) -> Result<TriangleMesh, Error> {
+    let mut grid = Grid3::new(resolution);
+    mesh_from_sdf_program_with_grid(program, bb, &mut grid)
+}
+
+/// Like [`mesh_from_sdf_program`], but reuses `grid` for the sampling, using its size as the resolution.
+pub(crate) fn mesh_from_sdf_program_with_grid(
+    program: &Program,
+    bb: &BoundingBox,
+    grid: &mut Grid3<f32>,
+) -> Result<TriangleMesh, Error> {
+    mesh_from_sdf_program_with_progress(program, bb, grid, &Progress::none(), None)
+}
+
+/// Like [`mesh_from_sdf_program_with_grid`], but reports how much is done to `progress`,
+/// and counts the evaluations of the program in `sdf_evaluations`, if given.
+fn mesh_from_sdf_program_with_progress(
+    program: &Program,
+    bb: &BoundingBox,
+    grid: &mut Grid3<f32>,
+    progress: &Progress<'_>,
+    sdf_evaluations: Option<&AtomicUsize>,
+) -> Result<TriangleMesh, Error> {
+    check_interpretable::<RgbaWithDistance>(program)?;
+    let count = || {
+        if let Some(sdf_evaluations) = sdf_evaluations {
+            sdf_evaluations.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+
+    let color_func = |rgbd_context: &mut _, pos_in_world| {
+        count();
+        interpret_checked::<RgbaWithDistance>(rgbd_context, pos_in_world)
+            .material()
+            .rgba()
+    };
+
+    let d_func = |d_context: &mut _, pos_in_world| {
+        count();
+        interpret_checked::<f32>(d_context, pos_in_world).distance()
+    };
+
+    mesh_from_sdf_func_with_grid(
+        grid,
+        bb,
+        || Interpreter::<f32>::new_context(&program.opcodes, &program.constants),
+        d_func,
+        || Interpreter::<RgbaWithDistance>::new_context(&program.opcodes, &program.constants),
+        color_func,
+        progress,
+    )
+}
+
+/// Like [`mesh_from_sdf_program`], but evaluates the program once per grid point for both
+/// distance and color, and interpolates the colors along with the vertex positions.
+///
+/// This is faster, and gives smoother color transitions, but colors are only as detailed as the grid.
+pub fn mesh_from_sdf_program_colored(
+    program: &Program,
+    bb: &BoundingBox,
+    resolution: [usize; 3],
+) -> Result<TriangleMesh, Error> {
+    check_interpretable::<RgbaWithDistance>(program)?;
+    let transform = GridTransform::new(*bb, resolution);
+
+    let grid = sample_grid_func(
+        bb,
+        resolution,
+        || Interpreter::<RgbaWithDistance>::new_context(&program.opcodes, &program.constants),
+        |rgbd_context, pos_in_world| {
+            interpret_checked::<RgbaWithDistance>(rgbd_context, pos_in_world)
+        },
+    )?;
+
+    let mut mesh = grid.marching_cubes_with_interpolated_colors();
+
+    transform_to_world_in_place(&mut mesh, &transform);
+
+    Ok(mesh)
+}
+
+pub fn mesh_from_sdf(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<TriangleMesh, Error> {
+    Ok(mesh_from_sdf_with_transform(graph, node, options)?.mesh)
+}
+
+/// Like [`mesh_from_sdf`], but calls `progress` with the fraction done, from 0 to 1, e.g. for a progress bar.
+///
+/// The fraction is reported a couple of times per z slab of the grid, and only ever increases.
+/// With the `with_rayon` feature, `progress` can be called from any of the worker threads,
+/// also while an earlier call with a smaller fraction is still running.
+pub fn mesh_from_sdf_with_progress(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+    progress: &(dyn Fn(f32) + Sync),
+) -> Result<TriangleMesh, Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    let progress = Progress::new(progress, resolution);
+    mesh_from_sdf_program_with_progress(&program, &bb, &mut Grid3::new(resolution), &progress, None)
+}
+
+/// Like [`mesh_from_sdf_with_progress`], but stops early with [`Error::Cancelled`] once `cancel` is set,
+/// e.g. from another thread when the graph has changed and the mesh isn't needed anymore.
+///
+/// `cancel` is checked before each row of the grid is sampled, and before each z slab is meshed.
+/// Pass `&|_| {}` as `progress` if you only need the cancellation.
+pub fn mesh_from_sdf_cancellable(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+    progress: &(dyn Fn(f32) + Sync),
+    cancel: &AtomicBool,
+) -> Result<TriangleMesh, Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    let progress = Progress::new(progress, resolution).with_cancel(cancel);
+    mesh_from_sdf_program_with_progress(&program, &bb, &mut Grid3::new(resolution), &progress, None)
+}
+
+/// Like [`mesh_from_sdf`], but instead of colors, tags each vertex with the primitive it's on,
+/// in [`TriangleMesh::vertex_ids`], e.g. for picking or per-instance shading.
+///
+/// The ids are the index of the primitive's opcode in the compiled program, see [`DistanceWithId`],
+/// like for [`crate::OutputType::DistanceWithId`] in the generated code.
+pub fn mesh_from_sdf_with_ids(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<TriangleMesh, Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    check_interpretable::<DistanceWithId>(&program)?;
+    let transform = GridTransform::new(bb, resolution);
+
+    let grid = sample_grid_func(
+        &bb,
+        resolution,
+        || Interpreter::<DistanceWithId>::new_context(&program.opcodes, &program.constants),
+        |context, pos_in_world| interpret_checked::<DistanceWithId>(context, pos_in_world),
+    )?;
+
+    let mut mesh = grid.marching_cubes_with_ids();
+    transform_to_world_in_place(&mut mesh, &transform);
+
+    Ok(mesh)
+}
+
+/// Like [`mesh_from_sdf`], but evaluates the graph once per grid point for both distance and color,
+/// see [`mesh_from_sdf_program_colored`].
+pub fn mesh_from_sdf_colored(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<TriangleMesh, Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    mesh_from_sdf_program_colored(&program, &bb, resolution)
+}
+
+/// The output of [`mesh_from_sdf_with_transform`].
+#[derive(Clone)]
+pub struct MeshResult {
+    /// In world coordinates.
+    pub mesh: TriangleMesh,
+    /// The bounding box of the sampled grid, as picked by [`sdf_bb_and_resolution`].
+    pub bb: BoundingBox,
+    /// The size of the sampled grid.
+    pub resolution: [usize; 3],
+}
+
+impl MeshResult {
+    /// The mapping between the sampled grid and the world.
+    pub fn grid_transform(&self) -> GridTransform {
+        GridTransform::new(self.bb, self.resolution)
+    }
+
+    /// The size of the largest side of a grid cell in world units, see [`GridTransform::world_from_grid_scale`].
+    pub fn world_from_grid_scale(&self) -> f32 {
+        self.grid_transform().world_from_grid_scale()
+    }
+
+    /// The transform that was applied to the vertex positions.
+    pub fn world_from_grid(&self, pos_in_grid: Vec3) -> Vec3 {
+        self.grid_transform().world_from_grid(pos_in_grid)
+    }
+
+    pub fn grid_from_world(&self, pos_in_world: Vec3) -> Vec3 {
+        self.grid_transform().grid_from_world(pos_in_world)
+    }
+}
+
+/// Like [`mesh_from_sdf`], but also returns the grid it sampled, e.g. for resampling at the vertices.
+pub fn mesh_from_sdf_with_transform(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<MeshResult, Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+
+    let mesh = mesh_from_sdf_program(&program, &bb, resolution)?;
+    Ok(MeshResult {
+        mesh,
+        bb,
+        resolution,
+    })
+}
+
+/// Statistics about a meshing operation, see [`mesh_from_sdf_with_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeshStats {
+    /// The number of cells of the sampled grid, i.e. one less than the resolution along each axis.
+    pub grid_cells: usize,
+    /// The number of cells the surface passes through, see [`Grid3::surface_cells`].
+    pub surface_cells: usize,
+    pub vertices: usize,
+    pub triangles: usize,
+    /// How many times the field was evaluated, both for sampling the grid and for the vertex colors.
+    ///
+    /// Grid points far from the surface are skipped (see [`Grid3::set_truncated`]),
+    /// so this is usually much less than the number of grid points.
+    pub sdf_evaluations: usize,
+}
+
+/// Like [`mesh_from_sdf`], but also returns statistics about the work done, e.g. to compare meshing options.
+pub fn mesh_from_sdf_with_stats(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<(TriangleMesh, MeshStats), Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+
+    let sdf_evaluations = AtomicUsize::new(0);
+    let mut grid = Grid3::new(resolution);
+    let mesh = mesh_from_sdf_program_with_progress(
+        &program,
+        &bb,
+        &mut grid,
+        &Progress::none(),
+        Some(&sdf_evaluations),
+    )?;
+
+    let stats = MeshStats {
+        grid_cells: resolution.iter().map(|n| n.saturating_sub(1)).product(),
+        surface_cells: grid.surface_cells().count(),
+        vertices: mesh.positions.len(),
+        triangles: mesh.indices.len() / 3,
+        sdf_evaluations: sdf_evaluations.into_inner(),
+    };
+    Ok((mesh, stats))
+}
+
+/// Like [`mesh_from_sdf`], but only meshes the part of `node` within `clip_box`.
+///
+/// The surface is capped where the box cuts it, so the mesh stays closed.
+/// This makes it possible to mesh infinite fields like [`Graph::gyroid`], whose bounding box is everything.
+///
+/// `clip_box` must be finite. Returns an empty mesh if it doesn't overlap the bounding box of `node`.
+pub fn mesh_from_sdf_in_box(
+    graph: &Graph,
+    node: NodeId,
+    clip_box: &BoundingBox,
+    options: MeshOptions,
+) -> Result<TriangleMesh, Error> {
+    assert!(clip_box.is_finite(), "Bad clip box: {:?}", clip_box);
+    let bb = graph.bounding_box(node).intersection(*clip_box);
+    if bb.is_nothing() {
+        return Ok(TriangleMesh::default());
+    }
+
+    let (bb, resolution) = sdf_bb_and_resolution(bb, options)?;
+    let program = compile(graph, node);
+    check_interpretable::<RgbaWithDistance>(&program)?;
+
+    let (clip_center, clip_half_size) = (clip_box.center(), clip_box.half_size());
+    let d_func = |d_context: &mut _, pos_in_world: Vec3| {
+        let distance = interpret_checked::<f32>(d_context, pos_in_world);
+        let clip_distance: f32 = sd_rounded_box(pos_in_world - clip_center, clip_half_size, 0.0);
+        distance.max(clip_distance)
+    };
+    let color_func = |rgbd_context: &mut _, pos_in_world| {
+        interpret_checked::<RgbaWithDistance>(rgbd_context, pos_in_world)
+            .material()
+            .rgba()
+    };
+
+    mesh_from_sdf_func_with_grid(
+        &mut Grid3::new(resolution),
+        &bb,
+        || Interpreter::<f32>::new_context(&program.opcodes, &program.constants),
+        d_func,
+        || Interpreter::<RgbaWithDistance>::new_context(&program.opcodes, &program.constants),
+        color_func,
+        &Progress::none(),
+    )
+}
+
+/// Like [`mesh_from_sdf`], but samples and meshes the grid in bricks of at most `brick_size` points along each axis,
+/// so only one brick of the grid is in memory at a time, e.g. for resolutions where the whole grid wouldn't fit.
+///
+/// Neighboring bricks share the grid points on the faces between them, and the vertices there are welded,
+/// so the mesh is the same as from [`mesh_from_sdf`], up to the order of the vertices and triangles.
+/// `brick_size` must be at least 2.
+pub fn mesh_from_sdf_chunked(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+    brick_size: usize,
+) -> Result<TriangleMesh, Error> {
+    assert!(brick_size >= 2, "Bad brick size: {brick_size}");
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    check_interpretable::<RgbaWithDistance>(&program)?;
+    let transform = GridTransform::new(bb, resolution);
+    let grid_from_world_scale = transform.grid_from_world_scale();
+
+    let num_cells = resolution.map(|n| n - 1);
+    let cells_per_brick = brick_size - 1;
+    let mut output = MarchingCubesOutput::with_capacity(0, false, false);
+    for z in (0..num_cells[2]).step_by(cells_per_brick) {
+        for y in (0..num_cells[1]).step_by(cells_per_brick) {
+            for x in (0..num_cells[0]).step_by(cells_per_brick) {
+                let start = [x, y, z];
+                let end =
+                    [0, 1, 2].map(|axis| (start[axis] + cells_per_brick).min(num_cells[axis]));
+                // Also sample the grid points around the cells, so the normals match those of the whole grid.
+                let min = start.map(|c| c.saturating_sub(1));
+                let max = [0, 1, 2].map(|axis| (end[axis] + 1).min(resolution[axis] - 1));
+
+                let mut brick = Grid3::new([0, 1, 2].map(|axis| max[axis] - min[axis] + 1));
+                brick.set_truncated_with_init(
+                    || Interpreter::<f32>::new_context(&program.opcodes, &program.constants),
+                    |d_context, [x, y, z]| {
+                        let pos_in_world =
+                            transform.world_from_cell([x + min[0], y + min[1], z + min[2]]);
+                        interpret_checked::<f32>(d_context, pos_in_world) * grid_from_world_scale
+                    },
+                    2.0,
+                );
+                // Like `sample_grid_into`, check a single sample for NaN.
+                if !brick.data()[brick.data().len() / 2].is_finite() {
+                    return Err(Error::EvaluatedToNaN);
+                }
+
+                let cells = CellRange {
+                    start: [0, 1, 2].map(|axis| start[axis] - min[axis]),
+                    end: [0, 1, 2].map(|axis| end[axis] - min[axis]),
+                    offset: min,
+                    full_size: resolution,
+                };
+                brick.march_cells(
+                    &cells,
+                    0.0,
+                    &linear_edge_crossing,
+                    &Progress::none(),
+                    &mut output,
+                );
+            }
+        }
+    }
+
+    let mut mesh = output.mesh;
+    transform_to_world_in_place(&mut mesh, &transform);
+    gather_colors_in_place_with_init(
+        &mut mesh,
+        || Interpreter::<RgbaWithDistance>::new_context(&program.opcodes, &program.constants),
+        |rgbd_context, pos_in_world| {
+            interpret_checked::<RgbaWithDistance>(rgbd_context, pos_in_world)
+                .material()
+                .rgba()
+        },
+    );
+    Ok(mesh)
+}
+
+/// Samples the signed distance field on the same grid [`mesh_from_sdf`] would use, without meshing it.
+///
+/// Returns the bounding box of the grid along with the grid itself, with distances in world units.
+/// Use [`GridTransform`] to map between grid points and world positions, or [`Grid3::iter_world`].
+///
+/// Like for meshing, cells more than a couple of cells away from the surface
+/// only hold approximate distances (see [`Grid3::set_truncated`]).
+pub fn sample_grid(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<(BoundingBox, Grid3<f32>), Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    check_interpretable::<f32>(&program)?;
+
+    let mut grid = sample_grid_func(
+        &bb,
+        resolution,
+        || Interpreter::<f32>::new_context(&program.opcodes, &program.constants),
+        |d_context, pos_in_world| interpret_checked::<f32>(d_context, pos_in_world),
+    )?;
+
+    grid.scale_distances(GridTransform::new(bb, resolution).world_from_grid_scale());
+
+    Ok((bb, grid))
+}
+
+/// Like [`mesh_from_sdf`], but also returns the grid the mesh was built from, e.g. for screen-space effects,
+/// so the field doesn't have to be sampled twice by also calling [`sample_grid`].
+///
+/// The bounding box and grid are the same as those returned by [`sample_grid`], with distances in world units.
+pub fn mesh_and_grid_from_sdf(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<(TriangleMesh, BoundingBox, Grid3<f32>), Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+
+    let mut grid = Grid3::new(resolution);
+    let mesh = mesh_from_sdf_program_with_grid(&program, &bb, &mut grid)?;
+
+    grid.scale_distances(GridTransform::new(bb, resolution).world_from_grid_scale());
+
+    Ok((mesh, bb, grid))
+}
+
+/// Samples the signed distance field on a 2D grid in `plane`, e.g. for a cross-section preview.
+///
+/// The grid is centered on [`Plane3::origin`] and spans `extent` along the [`Plane3::basis`] vectors `u` and `v`.
+/// Returns `resolution[0] * resolution[1]` distances in world units, in row-major order,
+/// i.e. sample `[i, j]` is at index `j * resolution[0] + i`, at
+/// `origin + (i / (resolution[0] - 1) - 0.5) * extent.x * u + (j / (resolution[1] - 1) - 0.5) * extent.y * v`.
+pub fn slice_sdf(
+    graph: &Graph,
+    node: NodeId,
+    plane: &Plane3,
+    extent: glam::Vec2,
+    resolution: [usize; 2],
+) -> Result<Vec<f32>, Error> {
+    let program = compile(graph, node);
+    check_interpretable::<f32>(&program)?;
+    let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+
+    let origin = plane.origin();
+    let (u, v) = plane.basis();
+    let step = |index: usize, resolution: usize| {
+        if resolution > 1 {
+            index as f32 / (resolution - 1) as f32 - 0.5
+        } else {
+            0.0
+        }
+    };
+
+    let mut distances = Vec::with_capacity(resolution[0] * resolution[1]);
+    for j in 0..resolution[1] {
+        for i in 0..resolution[0] {
+            let pos = origin
+                + step(i, resolution[0]) * extent.x * u
+                + step(j, resolution[1]) * extent.y * v;
+            distances.push(interpret_checked::<f32>(&mut context, pos));
+        }
+    }
+    Ok(distances)
+}
+
+/// Like [`sample_grid`], but only stores which grid points are inside.
+///
+/// Only the sign of the distance is needed, so each distance evaluation also
+/// fills in all the following points along the x axis that are closer than it.
+pub fn sample_occupancy(
+    graph: &Graph,
+    node: NodeId,
+    options: MeshOptions,
+) -> Result<(BoundingBox, OccupancyGrid), Error> {
+    let (bb, resolution) = sdf_bb_and_resolution(graph.bounding_box(node), options)?;
+    let program = compile(graph, node);
+    check_interpretable::<f32>(&program)?;
+
+    let transform = GridTransform::new(bb, resolution);
+    let grid_from_world_scale = transform.grid_from_world_scale();
+
+    let mut occupancy = OccupancyGrid::new(resolution);
+    occupancy.set_rows(|y, z, words| {
+        let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+        let mut x = 0;
+        while x < resolution[0] {
+            let pos_in_world = transform.world_from_cell([x, y, z]);
+            let distance = interpret_checked::<f32>(&mut context, pos_in_world);
+            let inside = distance < 0.0;
+
+            // The sign can't change closer to the sample than its distance.
+            let skip = (distance.abs() * grid_from_world_scale).ceil().max(1.0) as usize;
+            let end = x.saturating_add(skip).min(resolution[0]);
+            if inside {
+                for x in x..end {
+                    words[x / 64] |= 1 << (x % 64);
+                }
+            }
+            x = end;
+        }
+    });
+
+    Ok((bb, occupancy))
+}
+
+/// Pick a good expanded bounding box and grid size from the given tight bounding box
+///
+/// With [`MeshOptions::fixed_resolution`], the grid size is the given one, and only the bounding box is picked.
+///
+/// Returns [`Error::EmptyBoundingBox`] if `bb` contains nothing, e.g. for an intersection of shapes that
+/// don't overlap, [`Error::InfiniteBoundingBox`] if it isn't finite, e.g. for a plane,
+/// [`Error::BadResolution`] if the fixed resolution has fewer than four grid points along an axis,
+/// and [`Error::BadAxisWeights`] if the axis weights aren't all positive and finite.
+pub fn sdf_bb_and_resolution(
+    bb: BoundingBox,
+    options: MeshOptions,
+) -> Result<(BoundingBox, [usize; 3]), Error> {
+    if bb.is_nothing() {
+        return Err(Error::EmptyBoundingBox);
+    }
+    if !bb.is_finite() {
+        return Err(Error::InfiniteBoundingBox);
+    }
+
+    // Flat boxes, e.g. of a segment or of shapes touching at a face, have no volume to pick a resolution from.
+    let bb = if bb.volume() > 0.0 {
+        bb
+    } else {
+        bb.expanded(Vec3::splat(0.01 * bb.size().max_element().max(1.0)))
+    };
+
+    if let Some(resolution) = options.fixed_resolution {
+        if resolution.iter().any(|&r| r < 4) {
+            return Err(Error::BadResolution(resolution));
+        }
+        // One grid point of padding on each side, and cubic cells large enough for the widest axis.
+        let cell_size = (0..3)
+            .map(|axis| bb.size()[axis] / (resolution[axis] - 3) as f32)
+            .fold(0.0, f32::max);
+        let half_size = Vec3::from(resolution.map(|r| (r - 1) as f32)) * cell_size * 0.5;
+        let center = bb.center();
+        let bb = BoundingBox::from_min_max(center - half_size, center + half_size);
+        return Ok((bb, resolution));
+    }
+
+    let weights = options.axis_weights;
+    if !(weights.is_finite() && weights.cmpgt(Vec3::ZERO).all()) {
+        return Err(Error::BadAxisWeights);
+    }
+
+    // Add at least this many grid points on each side
+    let grid_padding = 1.0;
+
+    // preliminary so we can pad
+    let grid_from_world_scale = options.mean_resolution / bb.volume().cbrt();
+    let padding = grid_padding / grid_from_world_scale;
+    let bb = bb.expanded(Vec3::splat(padding));
+
+    // now actual:
+    let grid_from_world_scale = options.mean_resolution / bb.volume().cbrt();
+
+    let resolution = [
+        grid_from_world_scale * bb.size().x * weights.x,
+        grid_from_world_scale * bb.size().y * weights.y,
+        grid_from_world_scale * bb.size().z * weights.z,
+    ];
+
+    let max_side = resolution[0].max(resolution[1]).max(resolution[2]);
+    let max_factor = if max_side > options.max_resolution {
+        options.max_resolution / max_side
+    } else {
+        1.0
+    };
+    let min_side = resolution[0].min(resolution[1]).min(resolution[2]);
+    let min_factor = if min_side < options.min_resolution {
+        options.min_resolution / min_side
+    } else {
+        1.0
+    };
+
+    // Let the minimum overrule the maximum.
+    let factor = min_factor.max(max_factor);
+
+    let grid_resolution = [
+        (factor * resolution[0]).ceil() as usize,
+        (factor * resolution[1]).ceil() as usize,
+        (factor * resolution[2]).ceil() as usize,
+    ];
+
+    /*
+    // Useful for debugging the above calculations. Turns out it's not as intuitive as expected to get it right.
+    println!(
+        "max_res: {} min_res: {} max_factor: {} min_factor: {} original_resolution: {:?} grid_resolution: {:?}",
+        options.max_resolution, options.min_resolution, max_factor, min_factor, resolution, grid_resolution
+    );
+    */
+
+    // Fit the box to the grid, with the cell size along x, and the other axes relative to it by their weights.
+    let cell_size = bb.size().x / (grid_resolution[0] as f32 - 1.0) * weights.x / weights;
+    let [w, h, d] = grid_resolution.map(|r| r as f32 - 1.0);
+    let bb = BoundingBox::from_min_max(bb.min, bb.min + cell_size * Vec3::new(w, h, d));
+
+    Ok((bb, grid_resolution))
+}
+
+/// Estimates a tight bounding box of the surface of `sd` by sampling it on a grid with
+/// `resolution` points spanning `search_region`.
+///
+/// Useful when [`Graph::bounding_box`] is infinite or very loose, e.g. for planes, twists or repeats,
+/// before calling [`sdf_bb_and_resolution`]. Samples near the surface are projected onto it
+/// along the gradient, so the estimate is usually much tighter than one grid cell.
+/// Only surface within `search_region` is found, and the result is clamped to it.
+///
+/// Returns [`BoundingBox::nothing`] if no surface was found.
+pub fn estimate_bounding_box(
+    sd: impl Fn(Vec3) -> f32,
+    search_region: BoundingBox,
+    resolution: [usize; 3],
+) -> BoundingBox {
+    assert!(
+        resolution.iter().all(|&r| r >= 2),
+        "Need at least two samples along each axis"
+    );
+    let cell_size = search_region.size()
+        / Vec3::new(
+            resolution[0] as f32 - 1.0,
+            resolution[1] as f32 - 1.0,
+            resolution[2] as f32 - 1.0,
+        );
+    let near_surface = cell_size.length();
+    let epsilon = 0.01 * cell_size.min_element();
+
+    let mut bb = BoundingBox::nothing();
+    for z in 0..resolution[2] {
+        for y in 0..resolution[1] {
+            for x in 0..resolution[0] {
+                let pos = search_region.min + cell_size * Vec3::new(x as f32, y as f32, z as f32);
+                let distance = sd(pos);
+                if distance <= 0.0 {
+                    bb.extend(pos);
+                }
+                if distance.abs() < near_surface {
+                    let gradient = Vec3::new(
+                        sd(pos + epsilon * Vec3::X) - sd(pos - epsilon * Vec3::X),
+                        sd(pos + epsilon * Vec3::Y) - sd(pos - epsilon * Vec3::Y),
+                        sd(pos + epsilon * Vec3::Z) - sd(pos - epsilon * Vec3::Z),
+                    );
+                    if let Some(normal) = gradient.try_normalize() {
+                        bb.extend(pos - distance * normal);
+                    }
+                }
+            }
+        }
+    }
+
+    if bb.is_nothing() {
+        bb
+    } else {
+        bb.intersection(search_region)
+    }
+}
+
+/// Extrudes the 2D signed distance field `profile` in the XY plane along Z, into a prism
+/// reaching `half_depth` in each direction, e.g. to carve a text or SVG outline into a part.
+///
+/// The distance is exact wherever `profile` is, including at the edges of the caps,
+/// unlike the common `max(profile(pos.xy), abs(pos.z) - half_depth)`.
+pub fn extrude_profile(
+    profile: impl Fn(glam::Vec2) -> f32,
+    half_depth: f32,
+) -> impl Fn(Vec3) -> f32 {
+    move |pos| {
+        let d = glam::Vec2::new(profile(pos.truncate()), pos.z.abs() - half_depth);
+        d.x.max(d.y).min(0.0) + d.max(glam::Vec2::ZERO).length()
+    }
+}
+
+/// Compiles `node` and evaluates it once, use [`CompiledSdf`] to query many points.
+///
+/// # Panics
+///
+/// If the program is too deep for the interpreter, see [`Error::StackOverflow`].
+pub fn surface_distance_to(graph: &Graph, node: NodeId, pos: Vec3) -> f32 {
+    CompiledSdf::new(graph, node)
+        .expect("the program fits the interpreter")
+        .distance(pos)
+}
+
+/// The point on the surface of `node` closest to `pos`, e.g. for snapping points to the surface in tools.
+///
+/// Steps from `pos` along the negative gradient of the distance field (from finite differences)
+/// by the distance, at most `iterations` times, stopping early on the surface.
+/// One step is enough for exact distance fields, but e.g. smooth unions only give a bound
+/// on the distance, and take a few more.
+///
+/// This compiles `node` on every call, use [`CompiledSdf::closest_surface_point`] to query many points.
+///
+/// # Panics
+///
+/// If the program is too deep for the interpreter, see [`Error::StackOverflow`].
+pub fn closest_surface_point(graph: &Graph, node: NodeId, pos: Vec3, iterations: usize) -> Vec3 {
+    CompiledSdf::new(graph, node)
+        .expect("the program fits the interpreter")
+        .closest_surface_point(pos, iterations)
+}
+
+/// Like [`surface_distance_to`], but also returns the material of the surface nearest to `pos`,
+/// e.g. for a custom renderer or a paint tool. Use [`CompiledSdf::sample`] to query many points.
+///
+/// # Panics
+///
+/// If the program is too deep for the interpreter, see [`Error::StackOverflow`].
+pub fn surface_sample(graph: &Graph, node: NodeId, pos: Vec3) -> (f32, Material) {
+    CompiledSdf::new(graph, node)
+        .expect("the program fits the interpreter")
+        .sample(pos)
+}
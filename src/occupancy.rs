@@ -0,0 +1,203 @@
+use crate::Grid3;
+use crate::Index3;
+use crate::SignedDistance;
+use crate::TriangleMesh;
+
+/// Which points of a 3D grid are inside a surface, one bit each.
+///
+/// Uses 32 times less memory than a [`Grid3<f32>`], which is all that is needed for
+/// e.g. collision, occupancy queries or voxel export.
+///
+/// Each row along the x axis starts on a new `u64`, so rows can be filled independently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OccupancyGrid {
+    size: Index3,
+    words_per_row: usize,
+    bits: Vec<u64>,
+}
+
+impl OccupancyGrid {
+    /// A grid where everything is outside.
+    pub fn new(size: Index3) -> Self {
+        let words_per_row = size[0].div_ceil(64);
+        Self {
+            size,
+            words_per_row,
+            bits: vec![0; words_per_row * size[1] * size[2]],
+        }
+    }
+
+    pub fn size(&self) -> Index3 {
+        self.size
+    }
+
+    #[inline]
+    pub fn is_inside(&self, p: Index3) -> bool {
+        let (word, bit) = self.word_and_bit(p);
+        self.bits[word] & (1 << bit) != 0
+    }
+
+    #[inline]
+    pub fn set_inside(&mut self, p: Index3, inside: bool) {
+        let (word, bit) = self.word_and_bit(p);
+        if inside {
+            self.bits[word] |= 1 << bit;
+        } else {
+            self.bits[word] &= !(1 << bit);
+        }
+    }
+
+    /// The number of grid points that are inside.
+    pub fn count_inside(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Sets every point, one row at a time. `row_fn` gets the y and z of the row,
+    /// and fills in the row of `size[0]` points along the x axis.
+    pub(crate) fn set_rows(&mut self, row_fn: impl Fn(usize, usize, &mut [u64]) + Send + Sync) {
+        let h = self.size[1];
+        let words_per_row = self.words_per_row;
+        if words_per_row == 0 {
+            return;
+        }
+
+        #[cfg(feature = "with_rayon")]
+        {
+            use rayon::prelude::*;
+            self.bits
+                .par_chunks_mut(words_per_row)
+                .enumerate()
+                .for_each(|(row, words)| row_fn(row % h, row / h, words));
+        }
+
+        #[cfg(not(feature = "with_rayon"))]
+        {
+            self.bits
+                .chunks_mut(words_per_row)
+                .enumerate()
+                .for_each(|(row, words)| row_fn(row % h, row / h, words));
+        }
+    }
+
+    /// A blocky mesh with a unit cube around each inside grid point, e.g. for a voxel art look.
+    ///
+    /// Only the faces between inside and outside points are included, each with its own
+    /// four vertices so that the normals are flat. Points outside the grid count as outside.
+    ///
+    /// Like [`Grid3::marching_cubes`], the positions are in grid coordinates, and there are no colors.
+    pub fn to_voxel_mesh(&self) -> TriangleMesh {
+        let mut mesh = TriangleMesh::default();
+        let [w, h, d] = self.size;
+
+        for z in 0..d {
+            for y in 0..h {
+                for x in 0..w {
+                    if !self.is_inside([x, y, z]) {
+                        continue;
+                    }
+                    let p = [x, y, z];
+                    for axis in 0..3 {
+                        for positive in [false, true] {
+                            let neighbor = if positive {
+                                Some(p[axis] + 1).filter(|n| *n < self.size[axis])
+                            } else {
+                                p[axis].checked_sub(1)
+                            };
+                            let neighbor_inside = neighbor.is_some_and(|n| {
+                                let mut neighbor = p;
+                                neighbor[axis] = n;
+                                self.is_inside(neighbor)
+                            });
+                            if !neighbor_inside {
+                                add_voxel_face(&mut mesh, p, axis, positive);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        mesh
+    }
+
+    #[inline]
+    fn word_and_bit(&self, p: Index3) -> (usize, usize) {
+        debug_assert!(p[0] < self.size[0]);
+        debug_assert!(p[1] < self.size[1]);
+        debug_assert!(p[2] < self.size[2]);
+        let row = p[1] + self.size[1] * p[2];
+        (row * self.words_per_row + p[0] / 64, p[0] % 64)
+    }
+}
+
+/// Adds the face of the unit cube around `p` that faces along `axis`, in the positive or negative direction.
+fn add_voxel_face(mesh: &mut TriangleMesh, p: Index3, axis: usize, positive: bool) {
+    // `u` and `v` span the face, with `u × v` pointing along `axis`.
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut corners = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+    if !positive {
+        corners.reverse(); // counter-clockwise when seen from outside
+    }
+    let sign = if positive { 1.0 } else { -1.0 };
+
+    let mut normal = [0.0; 3];
+    normal[axis] = sign;
+
+    let first_index = mesh.positions.len() as u32;
+    for [cu, cv] in corners {
+        let mut position = p.map(|c| c as f32);
+        position[axis] += 0.5 * sign;
+        position[u] += cu;
+        position[v] += cv;
+        mesh.positions.push(position);
+        mesh.normals.push(normal);
+    }
+    mesh.indices
+        .extend([0, 1, 2, 0, 2, 3].iter().map(|corner| first_index + corner));
+}
+
+impl<T: SignedDistance + Sync> Grid3<T> {
+    /// Which grid points have a negative distance.
+    pub fn to_occupancy(&self) -> OccupancyGrid {
+        let [w, h, _] = self.size();
+        let mut occupancy = OccupancyGrid::new(self.size());
+        occupancy.set_rows(|y, z, words| {
+            for x in 0..w {
+                if self.data()[x + w * (y + h * z)].distance() < 0.0 {
+                    words[x / 64] |= 1 << (x % 64);
+                }
+            }
+        });
+        occupancy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn voxel_mesh() {
+        let mut occupancy = OccupancyGrid::new([3, 3, 3]);
+        occupancy.set_inside([1, 1, 1], true);
+        let mesh = occupancy.to_voxel_mesh();
+        assert_eq!(mesh.indices.len(), 12 * 3);
+        assert_eq!(mesh.positions.len(), 6 * 4);
+
+        // Every triangle winds counter-clockwise around its outward normal.
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(mesh.positions[triangle[i] as usize]));
+            let normal = Vec3::from(mesh.normals[triangle[0] as usize]);
+            assert!((b - a).cross(c - a).dot(normal) > 0.0);
+            assert!((a - Vec3::ONE).dot(normal) > 0.0);
+        }
+
+        // The face between two neighbors is culled, and the grid edge counts as outside.
+        occupancy.set_inside([2, 1, 1], true);
+        assert_eq!(occupancy.to_voxel_mesh().indices.len(), 10 * 2 * 3);
+    }
+}
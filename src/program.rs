@@ -1,3 +1,5 @@
+#[cfg(feature = "with_bincode")]
+use crate::Error;
 use num_enum::TryFromPrimitiveError;
 use opensaft_sdf::Opcode;
 use std::collections::hash_map::DefaultHasher;
@@ -33,6 +35,28 @@ pub struct Program {
 }
 
 impl Program {
+    /// The first bytes of `to_versioned_bytes`, with the `with_bincode` feature.
+    pub const MAGIC: [u8; 4] = *b"SAFT";
+
+    /// The version of the format written by `to_versioned_bytes`, with the `with_bincode` feature.
+    ///
+    /// Bump this whenever the encoding of [`Program`] changes, e.g. when opcodes are renumbered.
+    ///
+    /// Version 2 added alpha to the constants of [`Opcode::Material`], and version 3 metallic and roughness.
+    pub const FORMAT_VERSION: u32 = 3;
+
+    /// Like `==`, but allows the constants to differ by `epsilon`, relative to their size if that's above one,
+    /// e.g. to compare programs compiled on different platforms.
+    pub fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.opcodes == other.opcodes
+            && self.constants.len() == other.constants.len()
+            && self
+                .constants
+                .iter()
+                .zip(&other.constants)
+                .all(|(&a, &b)| crate::math::approx_eq(a, b, epsilon))
+    }
+
     #[must_use]
     pub fn with_constants(&self, constants: Vec<f32>) -> Self {
         Self {
@@ -41,6 +65,51 @@ impl Program {
         }
     }
 
+    /// The union of this program and `other`, like compiling [`crate::Graph::op_union`] of the two nodes.
+    ///
+    /// Opcodes only refer to the stack relative to its top and read constants in order,
+    /// so the two programs are simply concatenated with a [`Opcode::Union`] at the end.
+    #[must_use]
+    pub fn union_with(&self, other: &Self) -> Self {
+        let body = |program: &Self| match program.opcodes.split_last() {
+            Some((Opcode::End, body)) => body.to_vec(),
+            _ => program.opcodes.clone(),
+        };
+        let mut opcodes = body(self);
+        opcodes.extend(body(other));
+        opcodes.extend([Opcode::Union, Opcode::End]);
+        Self {
+            constants: [self.constants.as_slice(), &other.constants].concat(),
+            opcodes,
+        }
+    }
+
+    /// Overwrites the constants of the primitive `node` wherever it was compiled, e.g. to animate it
+    /// without compiling again. See [`crate::compile_with_constant_map`].
+    ///
+    /// `values` are laid out as the interpreter reads them, e.g. center and radius for a sphere.
+    /// Fails if `node` is not a primitive in `constant_map`, or `values` has the wrong length.
+    pub fn set_node_constants(
+        &mut self,
+        constant_map: &crate::ConstantMap,
+        node: crate::NodeId,
+        values: &[f32],
+    ) -> Result<(), crate::Error> {
+        let ranges = constant_map.ranges(node);
+        // Check every range first, so that a bad one doesn't leave the constants half updated.
+        if ranges.is_empty()
+            || ranges.iter().any(|range| {
+                range.len() != values.len() || self.constants.get(range.clone()).is_none()
+            })
+        {
+            return Err(crate::Error::BadConstants);
+        }
+        for range in ranges {
+            self.constants[range.clone()].copy_from_slice(values);
+        }
+        Ok(())
+    }
+
     pub fn constant_hash(&self) -> u64 {
         constants_hash(&self.constants)
     }
@@ -63,6 +132,80 @@ impl Program {
         bincode::deserialize(bytes)
     }
 
+    /// Human readable RON, with the constants written as decimal numbers.
+    ///
+    /// Use [`Self::to_ron_with_constant_bits`] if the constants must survive a roundtrip bit-exactly,
+    /// e.g. NaN payloads.
+    #[cfg(feature = "with_ron")]
+    pub fn to_ron(&self) -> String {
+        RonProgram {
+            opcodes: self.opcodes.clone(),
+            constants: self.constants.clone(),
+            constant_bits: vec![],
+        }
+        .to_ron()
+    }
+
+    /// Like [`Self::to_ron`], but with the constants written as their `u32` bit patterns.
+    #[cfg(feature = "with_ron")]
+    pub fn to_ron_with_constant_bits(&self) -> String {
+        RonProgram {
+            opcodes: self.opcodes.clone(),
+            constants: vec![],
+            constant_bits: self.constants.iter().map(|c| c.to_bits()).collect(),
+        }
+        .to_ron()
+    }
+
+    /// Reads the output of either [`Self::to_ron`] or [`Self::to_ron_with_constant_bits`].
+    #[cfg(feature = "with_ron")]
+    pub fn from_ron(ron: &str) -> Result<Self, ron::error::SpannedError> {
+        let program: RonProgram = ron::from_str(ron)?;
+        let constants = if program.constant_bits.is_empty() {
+            program.constants
+        } else {
+            program
+                .constant_bits
+                .into_iter()
+                .map(f32::from_bits)
+                .collect()
+        };
+        Ok(Self {
+            constants,
+            opcodes: program.opcodes,
+        })
+    }
+
+    /// Like [`Self::as_bytes`], but prefixed with a magic number and [`Self::FORMAT_VERSION`],
+    /// so that [`Self::from_versioned_bytes`] can refuse files written by an incompatible version.
+    #[cfg(feature = "with_bincode")]
+    pub fn to_versioned_bytes(&self) -> Result<Vec<u8>, std::boxed::Box<bincode::ErrorKind>> {
+        let mut bytes = Vec::with_capacity(8);
+        bytes.extend_from_slice(&Self::MAGIC);
+        bytes.extend_from_slice(&Self::FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    /// Reads the output of [`Self::to_versioned_bytes`].
+    ///
+    /// Returns [`Error::UnsupportedVersion`] if the bytes were written with another format version.
+    #[cfg(feature = "with_bincode")]
+    pub fn from_versioned_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 8 {
+            return Err(Error::BadProgram("missing header"));
+        }
+        let (header, payload) = bytes.split_at(8);
+        if header[..4] != Self::MAGIC {
+            return Err(Error::BadProgram("not a versioned program"));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != Self::FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(bincode::deserialize(payload)?)
+    }
+
     pub fn from_raw(opcodes: &[u32], constants: &[f32]) -> Result<Self, UnknownOpcodeError> {
         // We use collect to convert from a Vec<Result<..>> to a Result<Vec<..>>. Neat!
         let opcodes = opcodes
@@ -102,3 +245,232 @@ impl Program {
             .unwrap_or_else(|e| format!("(failed to disassemble: {:?}", e))
     }
 }
+
+/// The RON representation of a [`Program`], which has the constants either as numbers or as bit patterns.
+#[cfg(feature = "with_ron")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RonProgram {
+    opcodes: Vec<Opcode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    constants: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    constant_bits: Vec<u32>,
+}
+
+#[cfg(feature = "with_ron")]
+impl RonProgram {
+    fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("a program is always representable as RON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn animate_node_constants() {
+        use crate::*;
+        use glam::Vec3;
+
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let box_ = graph.rounded_box(Vec3::ONE, 0.1);
+        let box_ = graph.op_translate(box_, Vec3::X * 5.0);
+        let root = graph.op_union(sphere, box_);
+
+        let (mut program, constant_map) = compile_with_constant_map(&graph, root);
+        assert_eq!(program, compile(&graph, root));
+        assert_eq!(constant_map.ranges(sphere).len(), 1);
+        assert!(constant_map.ranges(root).is_empty());
+
+        for frame in 0..10 {
+            let radius = 1.0 + frame as f32 * 0.1;
+            program
+                .set_node_constants(&constant_map, sphere, &[0.0, 0.0, 0.0, radius])
+                .unwrap();
+            let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+            let distance = Interpreter::<f32>::interpret(&mut context, Vec3::ZERO).unwrap();
+            assert!((distance + radius).abs() < 1e-6, "{distance}");
+        }
+
+        assert_eq!(
+            program.set_node_constants(&constant_map, sphere, &[1.0]),
+            Err(Error::BadConstants)
+        );
+        assert_eq!(
+            program.set_node_constants(&constant_map, root, &[]),
+            Err(Error::BadConstants)
+        );
+
+        // With a map that doesn't fit the program, nothing is changed, not even the ranges that fit.
+        let moved = graph.op_translate(sphere, Vec3::Y * 3.0);
+        let twice = graph.op_union(sphere, moved);
+        let (mut program, constant_map) = compile_with_constant_map(&graph, twice);
+        let ranges = constant_map.ranges(sphere);
+        assert_eq!(ranges.len(), 2);
+        program.constants.truncate(ranges[1].start);
+        let constants = program.constants.clone();
+        assert_eq!(
+            program.set_node_constants(&constant_map, sphere, &[0.0, 0.0, 0.0, 2.0]),
+            Err(Error::BadConstants)
+        );
+        assert_eq!(program.constants, constants);
+    }
+
+    #[test]
+    fn union_with() {
+        use crate::*;
+        use glam::Vec3;
+
+        let mut graph = Graph::default();
+        let a = graph.example(&ExampleParams::default());
+        let b = graph.rounded_box(Vec3::new(1.0, 0.5, 0.25), 0.1);
+        let b = graph.op_translate(b, Vec3::new(0.5, 1.0, 0.0));
+        let union = graph.op_union(a, b);
+
+        let expected = compile(&graph, union);
+        let program = compile(&graph, a).union_with(&compile(&graph, b));
+        for i in 0..1000 {
+            let t = i as f32;
+            let pos = Vec3::new((t * 0.37).sin(), (t * 0.11).cos(), (t * 0.73).sin()) * 3.0;
+            let mut expected_context =
+                Interpreter::new_context(&expected.opcodes, &expected.constants);
+            let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+            assert_eq!(
+                Interpreter::<RgbaWithDistance>::interpret(&mut context, pos),
+                Interpreter::<RgbaWithDistance>::interpret(&mut expected_context, pos)
+            );
+        }
+    }
+
+    #[test]
+    fn opcode_metadata() {
+        use crate::*;
+        use glam::Vec3;
+        use glam::Vec4;
+
+        let mut graph = Graph::default();
+        let example = graph.example(&ExampleParams::default());
+        let operations = graph.example_operations(&ExampleParams::default());
+        let hull = graph.convex_hull(vec![Vec4::X - Vec4::W, Vec4::Y - Vec4::W]);
+        let tube = graph.tube(1.0, 0.5, 0.3);
+        let gyroid = graph.gyroid(1.0, 0.1);
+        let warped = graph.op_warp(tube, gyroid, 0.2);
+        let revolved = graph.op_revolve(hull, 1.0);
+        let elongated = graph.op_elongate(revolved, Vec3::X);
+        let transformed = graph.op_affine(elongated, glam::Affine3A::from_scale(Vec3::Y + 1.0));
+        let root = graph.op_union_multi(vec![example, operations, hull, warped, transformed]);
+        let program = compile(&graph, root);
+
+        let mut depth = 0;
+        let mut num_constants = 0;
+        for opcode in &program.opcodes {
+            if *opcode == Opcode::ConvexHull {
+                num_constants += 4 * program.constants[num_constants] as usize;
+            }
+            let (pops, pushes) = opcode.stack_effect();
+            assert!(depth >= i32::from(pops));
+            depth += i32::from(pushes) - i32::from(pops);
+            num_constants += opcode.constant_count();
+        }
+        assert_eq!(depth, 1);
+        assert_eq!(num_constants, program.constants.len());
+    }
+
+    #[cfg(feature = "with_arbitrary")]
+    #[test]
+    fn interpret_arbitrary_programs() {
+        use super::*;
+        use crate::*;
+        use arbitrary::Arbitrary;
+        use glam::Vec3;
+
+        // A small xorshift to feed `arbitrary`, so the test is deterministic.
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let bytes: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        // Malformed programs must return `None` rather than panic.
+        let mut num_valid = 0;
+        for chunk in bytes.chunks(256) {
+            let mut u = arbitrary::Unstructured::new(chunk);
+            let Ok(mut program) = Program::arbitrary(&mut u) else {
+                continue;
+            };
+            for with_end in [false, true] {
+                if with_end {
+                    program.opcodes.push(Opcode::End);
+                }
+                let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+                if Interpreter::<f32>::interpret(&mut context, Vec3::ONE).is_some() {
+                    num_valid += 1;
+                }
+                let mut context = Interpreter::new_context(&program.opcodes, &program.constants);
+                Interpreter::<RgbaWithDistance>::interpret(&mut context, Vec3::ONE);
+            }
+        }
+        assert!(num_valid > 0);
+    }
+
+    #[cfg(feature = "with_bincode")]
+    #[test]
+    fn versioned_bytes() {
+        use super::*;
+        use crate::*;
+
+        let mut graph = Graph::default();
+        let root = graph.example(&ExampleParams::default());
+        let program = compile(&graph, root);
+
+        let mut bytes = program.to_versioned_bytes().unwrap();
+        assert_eq!(Program::from_versioned_bytes(&bytes), Ok(program.clone()));
+
+        bytes[4] += 1;
+        assert_eq!(
+            Program::from_versioned_bytes(&bytes),
+            Err(Error::UnsupportedVersion(Program::FORMAT_VERSION + 1))
+        );
+
+        // Plain `as_bytes` output has no header.
+        assert!(Program::from_versioned_bytes(&program.as_bytes().unwrap()).is_err());
+
+        // A truncated payload is reported with the bincode error.
+        bytes[4] -= 1;
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            Program::from_versioned_bytes(&bytes),
+            Err(Error::Serialization(_))
+        ));
+    }
+
+    #[cfg(feature = "with_ron")]
+    #[test]
+    fn ron_roundtrip() {
+        use super::*;
+        use crate::*;
+
+        let mut graph = Graph::default();
+        let root = graph.example(&ExampleParams::default());
+        let program = compile(&graph, root);
+
+        let roundtripped = Program::from_ron(&program.to_ron()).unwrap();
+        assert_eq!(roundtripped, program);
+
+        // Values that are easily lost when written as decimal numbers, like NaN payloads.
+        let mut constants = program.constants.clone();
+        constants.extend([-0.0, f32::from_bits(0x7fc0_1234), f32::MIN_POSITIVE / 3.0]);
+        let program = program.with_constants(constants);
+        let roundtripped = Program::from_ron(&program.to_ron_with_constant_bits()).unwrap();
+        assert_eq!(roundtripped.opcodes, program.opcodes);
+        let bits = |program: &Program| -> Vec<u32> {
+            program.constants.iter().map(|c| c.to_bits()).collect()
+        };
+        assert_eq!(bits(&roundtripped), bits(&program));
+    }
+}
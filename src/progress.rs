@@ -0,0 +1,80 @@
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::Index3;
+
+/// Reports how much of a meshing operation is done to a callback, see [`crate::mesh_from_sdf_with_progress`],
+/// and tells it when to stop, see [`crate::mesh_from_sdf_cancellable`].
+///
+/// Sampling the grid is reported per z slab, as [`Self::SAMPLING_SHARE`] of the total,
+/// followed by marching cubes per z slab, as [`Self::MARCHING_SHARE`].
+/// Whatever comes after (e.g. gathering colors) is reported by [`Self::done`].
+pub(crate) struct Progress<'a> {
+    callback: &'a (dyn Fn(f32) + Sync),
+    cancel: Option<&'a AtomicBool>,
+    grid_size: Index3,
+    rows_sampled: AtomicUsize,
+    /// The bits of the largest fraction passed to the callback. Rows can be sampled in parallel
+    /// and finish in any order, so this is what keeps the reported fractions increasing.
+    /// The fractions aren't negative, so their bits order like the fractions themselves.
+    reported: AtomicU32,
+}
+
+impl<'a> Progress<'a> {
+    const SAMPLING_SHARE: f32 = 0.8;
+    const MARCHING_SHARE: f32 = 0.15;
+
+    pub fn new(callback: &'a (dyn Fn(f32) + Sync), grid_size: Index3) -> Self {
+        Self {
+            callback,
+            cancel: None,
+            grid_size,
+            rows_sampled: AtomicUsize::new(0),
+            reported: Default::default(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_cancel(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Doesn't report anything, and is never cancelled.
+    pub fn none() -> Self {
+        Self::new(&|_| {}, [0; 3])
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    fn report(&self, fraction: f32) {
+        let bits = fraction.to_bits();
+        if self.reported.fetch_max(bits, Ordering::Relaxed) < bits {
+            (self.callback)(fraction);
+        }
+    }
+
+    /// Called once for each row along the x axis of the grid, from any thread.
+    pub fn row_sampled(&self) {
+        let [_, h, d] = self.grid_size;
+        let rows_sampled = self.rows_sampled.fetch_add(1, Ordering::Relaxed) + 1;
+        if h > 0 && rows_sampled % h == 0 {
+            self.report(Self::SAMPLING_SHARE * rows_sampled as f32 / (h * d) as f32);
+        }
+    }
+
+    /// `fraction` of the marching cubes is done.
+    pub fn marched(&self, fraction: f32) {
+        self.report(Self::SAMPLING_SHARE + Self::MARCHING_SHARE * fraction);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn done(&self) {
+        self.report(1.0);
+    }
+}
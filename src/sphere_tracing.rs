@@ -7,8 +7,17 @@ pub struct Options {
     /// Don't take more steps than this
     max_steps: usize,
 
-    /// 1.0. Set to lower if your field is unreliable (i.e. underestimates distances).
+    /// 1.0. Set to lower if your field is unreliable (i.e. overestimates distances).
     step_constant: f32,
+
+    /// 1.0. Over-relaxation factor, see [`Self::with_relaxation`].
+    relaxation: f32,
+
+    /// 0.001. Hit tolerance per unit along the ray, see [`Self::with_hit_epsilon_relative`].
+    hit_epsilon_relative: f32,
+
+    /// 0.0. Hit tolerance independent of `t`, see [`Self::with_hit_epsilon_absolute`].
+    hit_epsilon_absolute: f32,
 }
 
 impl Default for Options {
@@ -16,10 +25,63 @@ impl Default for Options {
         Self {
             max_steps: 1024,
             step_constant: 1.0,
+            relaxation: 1.0,
+            hit_epsilon_relative: 0.001,
+            hit_epsilon_absolute: 0.0,
         }
     }
 }
 
+impl Options {
+    /// Don't take more steps than this. Default: 1024.
+    #[must_use]
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Set to lower than 1.0 if your field is unreliable (i.e. overestimates distances). Default: 1.0.
+    ///
+    /// To only slow down where the field is unreliable, see [`crate::Graph::op_clamp_distance`] instead.
+    #[must_use]
+    pub fn with_step_constant(mut self, step_constant: f32) -> Self {
+        self.step_constant = step_constant;
+        self
+    }
+
+    /// Over-relaxation from "Enhanced Sphere Tracing" (Keinert et al. 2014).
+    ///
+    /// Steps `relaxation` times further than the distance, and goes back to normal steps
+    /// if that overshot the surface. Values around 1.2-1.6 can save a lot of steps on smooth fields.
+    /// Default: 1.0 (plain sphere tracing).
+    #[must_use]
+    pub fn with_relaxation(mut self, relaxation: f32) -> Self {
+        self.relaxation = relaxation;
+        self
+    }
+
+    /// A point counts as a hit if its distance is at most
+    /// `hit_epsilon_absolute + hit_epsilon_relative * t`.
+    ///
+    /// The relative part keeps the tolerance roughly one pixel wide in perspective views.
+    /// Lower it for huge scenes seen up close. Default: 0.001.
+    #[must_use]
+    pub fn with_hit_epsilon_relative(mut self, hit_epsilon_relative: f32) -> Self {
+        self.hit_epsilon_relative = hit_epsilon_relative;
+        self
+    }
+
+    /// See [`Self::with_hit_epsilon_relative`].
+    ///
+    /// Set this to hit tiny objects close to the ray origin, where the relative tolerance is almost zero.
+    /// Default: 0.0.
+    #[must_use]
+    pub fn with_hit_epsilon_absolute(mut self, hit_epsilon_absolute: f32) -> Self {
+        self.hit_epsilon_absolute = hit_epsilon_absolute;
+        self
+    }
+}
+
 /// A point along the ray, and some info about it.
 /// Often this is the point along a march that was (approximately) closest to a surface.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -33,6 +95,10 @@ pub struct ClosestHit {
     /// Is this point considered a hit on the surface?
     /// If false, this point is the closest point we've found to a surface.
     pub is_hit: bool,
+    /// How many steps the march took in total.
+    pub steps_taken: usize,
+    /// The march was aborted after `max_steps`, so a miss may not be a real miss.
+    pub reached_max_steps: bool,
 }
 
 impl Default for ClosestHit {
@@ -48,6 +114,8 @@ impl ClosestHit {
             pos: Vec3::splat(f32::NAN),
             dist: f32::INFINITY,
             is_hit: false,
+            steps_taken: 0,
+            reached_max_steps: false,
         }
     }
 
@@ -88,15 +156,33 @@ pub fn trace(
     let mut closest_angle_distance = f32::INFINITY;
     let mut closest = ClosestHit::miss();
 
-    for _ in 0..opt.max_steps {
+    let mut relaxation = opt.relaxation;
+    let mut prev_dist = 0.0_f32;
+    let mut step_length = 0.0;
+
+    for step in 0..opt.max_steps {
+        let steps_taken = step + 1;
         let pos = ray.point_along(t);
         let dist = sd(pos);
-        if dist <= 0.001 * t {
+
+        if relaxation > 1.0 && dist.abs() + prev_dist.abs() < step_length {
+            // The unbounding spheres of this and the previous point don't overlap,
+            // so we may have stepped over the surface. Go back and stop relaxing.
+            relaxation = 1.0;
+            t -= step_length;
+            step_length = prev_dist * opt.step_constant;
+            t += step_length;
+            continue;
+        }
+
+        if dist <= opt.hit_epsilon_absolute + opt.hit_epsilon_relative * t {
             return ClosestHit {
                 t,
                 pos,
                 dist,
                 is_hit: true,
+                steps_taken,
+                reached_max_steps: false,
             };
         } else {
             if t > 0.0 {
@@ -109,19 +195,130 @@ pub fn trace(
                         pos,
                         dist,
                         is_hit: false,
+                        ..ClosestHit::miss()
                     };
                 }
             }
 
-            t += dist * opt.step_constant;
+            prev_dist = dist;
+            step_length = dist * opt.step_constant * relaxation;
+            t += step_length;
 
             if t >= *t_range.end() {
-                return closest;
+                return ClosestHit {
+                    steps_taken,
+                    ..closest
+                };
             }
         }
     }
 
-    // TODO: communicate that max_steps has been reached,
-    // i.e. that the march was prematurely aborted
-    closest
+    ClosestHit {
+        steps_taken: opt.max_steps,
+        reached_max_steps: true,
+        ..closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reached_max_steps() {
+        let sd = |pos: Vec3| pos.distance(Vec3::new(0.0, 0.0, 100.0)) - 1.0;
+        let ray = Ray3 {
+            origin: Vec3::ZERO,
+            dir: Vec3::Z,
+        };
+
+        let hit = trace(sd, ray, 0.0..=1000.0, &Options::default());
+        assert!(hit.is_hit);
+        assert!(!hit.reached_max_steps);
+
+        let opt = Options {
+            max_steps: 1,
+            ..Default::default()
+        };
+        let aborted = trace(sd, ray, 0.0..=1000.0, &opt);
+        assert!(!aborted.is_hit);
+        assert!(aborted.reached_max_steps);
+        assert_eq!(aborted.steps_taken, 1);
+
+        let miss = trace(sd, ray, 0.0..=10.0, &opt);
+        assert!(!miss.reached_max_steps);
+    }
+
+    #[test]
+    fn relaxation() {
+        // A ray at a shallow angle to a large sphere needs many plain steps.
+        let center = Vec3::new(0.0, -1001.0, 0.0);
+        let sd = |pos: Vec3| pos.distance(center) - 1000.0;
+        let ray = Ray3 {
+            origin: Vec3::ZERO,
+            dir: Vec3::new(0.0, -0.1, 1.0).normalize(),
+        };
+
+        let plain = trace(sd, ray, 0.0..=1000.0, &Options::default());
+        let relaxed = trace(
+            sd,
+            ray,
+            0.0..=1000.0,
+            &Options::default().with_relaxation(1.5),
+        );
+
+        assert!(plain.is_hit && relaxed.is_hit);
+        assert!(
+            relaxed.steps_taken < plain.steps_taken,
+            "{} >= {}",
+            relaxed.steps_taken,
+            plain.steps_taken
+        );
+        assert!(plain.pos.distance(relaxed.pos) < 0.05);
+    }
+
+    #[test]
+    fn step_constant() {
+        let sd = |pos: Vec3| pos.distance(Vec3::new(0.0, 0.0, 10.0)) - 1.0;
+        let ray = Ray3 {
+            origin: Vec3::ZERO,
+            dir: Vec3::Z,
+        };
+
+        let opt = Options::default().with_max_steps(100);
+        let full = trace(sd, ray, 0.0..=100.0, &opt);
+        let half = trace(sd, ray, 0.0..=100.0, &opt.with_step_constant(0.5));
+
+        assert!(full.is_hit && half.is_hit);
+        assert!(
+            half.steps_taken > full.steps_taken,
+            "{} <= {}",
+            half.steps_taken,
+            full.steps_taken
+        );
+        assert!((half.t - 9.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn hit_epsilon() {
+        // A ray that passes just outside a tiny sphere close to the origin.
+        let center = Vec3::new(0.0, 0.0015, 0.1);
+        let sd = |pos: Vec3| pos.distance(center) - 0.001;
+        let ray = Ray3 {
+            origin: Vec3::ZERO,
+            dir: Vec3::Z,
+        };
+
+        let relative = trace(sd, ray, 0.0..=1.0, &Options::default());
+        assert!(!relative.is_hit);
+
+        let absolute = trace(
+            sd,
+            ray,
+            0.0..=1.0,
+            &Options::default().with_hit_epsilon_absolute(0.001),
+        );
+        assert!(absolute.is_hit);
+        assert!((absolute.t - 0.1).abs() < 0.002);
+    }
 }
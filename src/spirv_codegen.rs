@@ -0,0 +1,2129 @@
+//! Compiles a [`Program`] straight to a SPIR-V module, for [`crate::Backend::SpirV`].
+//!
+//! The module mirrors what the GLSL backend generates together with `sdf.glsl`,
+//! so that it can be used without compiling any shaders at runtime.
+
+use crate::Error;
+use crate::Opcode;
+use crate::OutputType;
+use crate::Program;
+use crate::WARP_GRADIENT_OFFSETS;
+use rspirv::binary::Assemble;
+use rspirv::dr::Builder;
+use rspirv::dr::Operand;
+use rspirv::spirv;
+use rspirv::spirv::GLOp;
+use rspirv::spirv::Word;
+use std::collections::HashMap;
+
+/// The number of invocations in a workgroup of the generated compute shader.
+pub const SPIRV_WORKGROUP_SIZE: u32 = 64;
+
+/// Only fails when there is no block to insert into, which would be a bug in this module.
+fn ok<T>(result: Result<T, rspirv::dr::Error>) -> T {
+    result.expect("SPIR-V instructions are only added inside of a block")
+}
+
+/// A value on the distance stack.
+#[derive(Copy, Clone)]
+struct Sd {
+    distance: Word,
+    /// The `vec3` color for [`OutputType::DistanceWithRgb`], the `float` id for [`OutputType::DistanceWithId`],
+    /// and `None` for [`OutputType::DistanceOnly`].
+    payload: Option<Word>,
+}
+
+/// A `PushWarpSample` loop, whose body is being emitted.
+struct WarpSampleLoop {
+    header: Word,
+    continue_target: Word,
+    merge: Word,
+    /// The `uint` index of the sample, and the `vec4` of the distances sampled so far.
+    index: Word,
+    samples: Word,
+    next_index: Word,
+    next_samples: Word,
+}
+
+struct Types {
+    void: Word,
+    bool: Word,
+    bvec3: Word,
+    uint: Word,
+    uvec3: Word,
+    float: Word,
+    vec2: Word,
+    vec3: Word,
+    vec4: Word,
+}
+
+struct SpirvGen<'a> {
+    b: Builder,
+    glsl: Word,
+    t: Types,
+    output_type: OutputType,
+
+    constants: &'a [f32],
+    constant_index: usize,
+    /// The storage buffer with the constants as `uint`s, or `None` if they are baked into the module.
+    constant_buffer: Option<Word>,
+    /// With dynamic constants, the `uint` index that `constant_index` is relative to,
+    /// once it depends on constants, e.g. after a convex hull with a dynamic number of planes.
+    constant_base: Option<Word>,
+    float_constants: HashMap<u32, Word>,
+    uint_constants: HashMap<u32, Word>,
+
+    sd_stack: Vec<Sd>,
+    position_stack: Vec<Word>,
+    current_position: Word,
+    warp_sample_stack: Vec<WarpSampleLoop>,
+}
+
+impl<'a> SpirvGen<'a> {
+    fn f32c(&mut self, value: f32) -> Word {
+        let float = self.t.float;
+        let b = &mut self.b;
+        *self
+            .float_constants
+            .entry(value.to_bits())
+            .or_insert_with(|| b.constant_f32(float, value))
+    }
+
+    fn u32c(&mut self, value: u32) -> Word {
+        let uint = self.t.uint;
+        let b = &mut self.b;
+        *self
+            .uint_constants
+            .entry(value)
+            .or_insert_with(|| b.constant_u32(uint, value))
+    }
+
+    fn vec2c(&mut self, x: f32, y: f32) -> Word {
+        let components = [self.f32c(x), self.f32c(y)];
+        self.b.constant_composite(self.t.vec2, components)
+    }
+
+    fn splat3c(&mut self, value: f32) -> Word {
+        let c = self.f32c(value);
+        self.b.constant_composite(self.t.vec3, [c, c, c])
+    }
+
+    // Reading constants, in the same order as the interpreter.
+
+    fn float32(&mut self) -> Word {
+        let index = self.constant_index;
+        self.constant_index += 1;
+        match self.constant_buffer {
+            Some(buffer) => {
+                let index = self.constant_index_word(index);
+                self.load_constant(buffer, index)
+            }
+            // Reading past the end is reported once the whole program has been read.
+            None => self.f32c(self.constants.get(index).copied().unwrap_or_default()),
+        }
+    }
+
+    /// The `uint` index of the constant at `index`, relative to `constant_base`.
+    fn constant_index_word(&mut self, index: usize) -> Word {
+        let index_constant = self.u32c(index as u32);
+        match self.constant_base {
+            Some(base) => ok(self.b.i_add(self.t.uint, None, base, index_constant)),
+            None => index_constant,
+        }
+    }
+
+    /// Reads the constant at the `uint` `index` from the constant buffer.
+    fn load_constant(&mut self, buffer: Word, index: Word) -> Word {
+        let member = self.u32c(0);
+        let pointer_type =
+            self.b
+                .type_pointer(None, spirv::StorageClass::StorageBuffer, self.t.uint);
+        let pointer = ok(self
+            .b
+            .access_chain(pointer_type, None, buffer, [member, index]));
+        let bits = ok(self.b.load(self.t.uint, None, pointer, None, []));
+        ok(self.b.bitcast(self.t.float, None, bits))
+    }
+
+    fn vec3(&mut self) -> Word {
+        let components = [self.float32(), self.float32(), self.float32()];
+        self.construct(self.t.vec3, components)
+    }
+
+    fn vec4(&mut self) -> Word {
+        let components = [
+            self.float32(),
+            self.float32(),
+            self.float32(),
+            self.float32(),
+        ];
+        self.construct(self.t.vec4, components)
+    }
+
+    /// Skips a constant that is used at code generation time, returning its index.
+    fn skip_constant(&mut self) -> usize {
+        self.constant_index += 1;
+        self.constant_index - 1
+    }
+
+    // Arithmetic, where `ty` is the type of the result.
+
+    fn add(&mut self, ty: Word, a: Word, b: Word) -> Word {
+        ok(self.b.f_add(ty, None, a, b))
+    }
+
+    fn sub(&mut self, ty: Word, a: Word, b: Word) -> Word {
+        ok(self.b.f_sub(ty, None, a, b))
+    }
+
+    fn mul(&mut self, ty: Word, a: Word, b: Word) -> Word {
+        ok(self.b.f_mul(ty, None, a, b))
+    }
+
+    fn div(&mut self, ty: Word, a: Word, b: Word) -> Word {
+        ok(self.b.f_div(ty, None, a, b))
+    }
+
+    fn neg(&mut self, ty: Word, a: Word) -> Word {
+        ok(self.b.f_negate(ty, None, a))
+    }
+
+    /// `vector * scalar`
+    fn scale(&mut self, ty: Word, vector: Word, scalar: Word) -> Word {
+        ok(self.b.vector_times_scalar(ty, None, vector, scalar))
+    }
+
+    fn dot(&mut self, a: Word, b: Word) -> Word {
+        ok(self.b.dot(self.t.float, None, a, b))
+    }
+
+    fn ext(&mut self, ty: Word, op: GLOp, args: &[Word]) -> Word {
+        let args = args.iter().map(|arg| Operand::IdRef(*arg));
+        ok(self.b.ext_inst(ty, None, self.glsl, op as u32, args))
+    }
+
+    fn min(&mut self, a: Word, b: Word) -> Word {
+        self.ext(self.t.float, GLOp::FMin, &[a, b])
+    }
+
+    fn max(&mut self, a: Word, b: Word) -> Word {
+        self.ext(self.t.float, GLOp::FMax, &[a, b])
+    }
+
+    fn abs(&mut self, a: Word) -> Word {
+        self.ext(self.t.float, GLOp::FAbs, &[a])
+    }
+
+    fn sign(&mut self, a: Word) -> Word {
+        self.ext(self.t.float, GLOp::FSign, &[a])
+    }
+
+    fn sqrt(&mut self, a: Word) -> Word {
+        self.ext(self.t.float, GLOp::Sqrt, &[a])
+    }
+
+    fn length(&mut self, v: Word) -> Word {
+        self.ext(self.t.float, GLOp::Length, &[v])
+    }
+
+    fn clamp(&mut self, x: Word, lo: f32, hi: f32) -> Word {
+        let (lo, hi) = (self.f32c(lo), self.f32c(hi));
+        self.ext(self.t.float, GLOp::FClamp, &[x, lo, hi])
+    }
+
+    fn lt(&mut self, a: Word, b: Word) -> Word {
+        ok(self.b.f_ord_less_than(self.t.bool, None, a, b))
+    }
+
+    fn gt(&mut self, a: Word, b: Word) -> Word {
+        ok(self.b.f_ord_greater_than(self.t.bool, None, a, b))
+    }
+
+    fn select(&mut self, ty: Word, condition: Word, a: Word, b: Word) -> Word {
+        ok(self.b.select(ty, None, condition, a, b))
+    }
+
+    fn construct(&mut self, ty: Word, components: impl IntoIterator<Item = Word>) -> Word {
+        ok(self.b.composite_construct(ty, None, components))
+    }
+
+    fn extract(&mut self, v: Word, index: u32) -> Word {
+        ok(self.b.composite_extract(self.t.float, None, v, [index]))
+    }
+
+    fn xz(&mut self, v: Word) -> Word {
+        ok(self.b.vector_shuffle(self.t.vec2, None, v, v, [0, 2]))
+    }
+
+    fn xyz(&mut self, v: Word) -> Word {
+        ok(self.b.vector_shuffle(self.t.vec3, None, v, v, [0, 1, 2]))
+    }
+
+    /// `length(v.xz)`, the distance from the Y axis.
+    fn length_xz(&mut self, v: Word) -> Word {
+        let xz = self.xz(v);
+        self.length(xz)
+    }
+
+    /// `min(max(d.x, d.y), 0.0) + length(max(d, 0.0))` for a `vec2`, the distance to a 2D box.
+    fn box_2d(&mut self, d: Word) -> Word {
+        let (x, y) = (self.extract(d, 0), self.extract(d, 1));
+        let zero = self.f32c(0.0);
+        let inside = self.max(x, y);
+        let inside = self.min(inside, zero);
+        let zero2 = self.vec2c(0.0, 0.0);
+        let outside = self.ext(self.t.vec2, GLOp::FMax, &[d, zero2]);
+        let outside = self.length(outside);
+        self.add(self.t.float, inside, outside)
+    }
+
+    // The distance stack.
+
+    fn pop(&mut self) -> Result<Sd, Error> {
+        self.sd_stack.pop().ok_or(Error::BadStack)
+    }
+
+    fn push_primitive(&mut self, distance: Word, opcode_index: usize) {
+        let payload = match self.output_type {
+            OutputType::DistanceOnly => None,
+            OutputType::DistanceWithRgb => Some(self.splat3c(1.0)),
+            OutputType::DistanceWithId => Some(self.f32c(opcode_index as f32)),
+        };
+        self.sd_stack.push(Sd { distance, payload });
+    }
+
+    fn select_sd(&mut self, condition: Word, a: Sd, b: Sd) -> Sd {
+        let distance = self.select(self.t.float, condition, a.distance, b.distance);
+        let payload = match (self.output_type, a.payload, b.payload) {
+            (OutputType::DistanceWithRgb, Some(a), Some(b)) => {
+                // Before SPIR-V 1.4, selecting vectors needs a condition per component.
+                let condition = self.construct(self.t.bvec3, [condition; 3]);
+                Some(self.select(self.t.vec3, condition, a, b))
+            }
+            (OutputType::DistanceWithId, Some(a), Some(b)) => {
+                Some(self.select(self.t.float, condition, a, b))
+            }
+            _ => None,
+        };
+        Sd { distance, payload }
+    }
+
+    /// `mix(a, b, h)`, where the id of `b` wins if `h > 0.5`.
+    fn mix_sd(&mut self, a: Sd, b: Sd, h: Word) -> Sd {
+        let distance = self.ext(self.t.float, GLOp::FMix, &[a.distance, b.distance, h]);
+        let payload = match (self.output_type, a.payload, b.payload) {
+            (OutputType::DistanceWithRgb, Some(a), Some(b)) => {
+                let h = self.construct(self.t.vec3, [h; 3]);
+                Some(self.ext(self.t.vec3, GLOp::FMix, &[a, b, h]))
+            }
+            (OutputType::DistanceWithId, Some(a), Some(b)) => {
+                let half = self.f32c(0.5);
+                let b_wins = self.gt(h, half);
+                Some(self.select(self.t.float, b_wins, b, a))
+            }
+            _ => None,
+        };
+        Sd { distance, payload }
+    }
+
+    fn union(&mut self, d1: Sd, d2: Sd) -> Sd {
+        let condition = self.lt(d1.distance, d2.distance);
+        self.select_sd(condition, d1, d2)
+    }
+
+    fn subtract(&mut self, d1: Sd, d2: Sd) -> Sd {
+        let neg_distance1 = self.neg(self.t.float, d1.distance);
+        let condition = self.gt(neg_distance1, d2.distance);
+        let d1 = Sd {
+            distance: neg_distance1,
+            ..d1
+        };
+        self.select_sd(condition, d1, d2)
+    }
+
+    fn intersect(&mut self, d1: Sd, d2: Sd) -> Sd {
+        let condition = self.gt(d1.distance, d2.distance);
+        self.select_sd(condition, d1, d2)
+    }
+
+    /// `clamp(0.5 + sign * 0.5 * (a - b) / size, 0.0, 1.0)`, the blend factor of the smooth operations.
+    fn smooth_blend(&mut self, sign: f32, difference: Word, size: Word) -> Word {
+        let f = self.t.float;
+        let half = self.f32c(0.5);
+        let signed_half = self.f32c(sign * 0.5);
+        let h = self.mul(f, signed_half, difference);
+        let h = self.div(f, h, size);
+        let h = self.add(f, half, h);
+        self.clamp(h, 0.0, 1.0)
+    }
+
+    /// `size * h * (1.0 - h)`
+    fn smooth_correction(&mut self, size: Word, h: Word) -> Word {
+        let f = self.t.float;
+        let one = self.f32c(1.0);
+        let one_minus_h = self.sub(f, one, h);
+        let correction = self.mul(f, size, h);
+        self.mul(f, correction, one_minus_h)
+    }
+
+    /// The distance to a 45° bevel, from the sum or difference of the distances and the bevel size.
+    fn chamfer(&mut self, combined_distance: Word) -> Word {
+        let frac_1_sqrt_2 = self.f32c(std::f32::consts::FRAC_1_SQRT_2);
+        self.mul(self.t.float, combined_distance, frac_1_sqrt_2)
+    }
+
+    /// `v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v)`, rotating `v` by the quaternion `q`.
+    fn mul_quat(&mut self, q: Word, v: Word) -> Word {
+        let vec3 = self.t.vec3;
+        let q_xyz = self.xyz(q);
+        let q_w = self.extract(q, 3);
+        let inner = self.ext(vec3, GLOp::Cross, &[q_xyz, v]);
+        let w_v = self.scale(vec3, v, q_w);
+        let inner = self.add(vec3, inner, w_v);
+        let outer = self.ext(vec3, GLOp::Cross, &[q_xyz, inner]);
+        let two = self.f32c(2.0);
+        let outer = self.scale(vec3, outer, two);
+        self.add(vec3, v, outer)
+    }
+
+    fn push_position(&mut self, new_position: Word) {
+        self.position_stack.push(self.current_position);
+        self.current_position = new_position;
+    }
+
+    fn pop_position(&mut self) -> Result<(), Error> {
+        self.current_position = self.position_stack.pop().ok_or(Error::BadStack)?;
+        Ok(())
+    }
+}
+
+// The loop sampling the warp field of a warp.
+impl<'a> SpirvGen<'a> {
+    /// Starts the loop body that samples the warp field at `pos + epsilon * WARP_GRADIENT_OFFSETS[i]`.
+    fn begin_warp_sample_loop(&mut self, pos: Word, epsilon: Word) {
+        let (uint, vec3, vec4) = (self.t.uint, self.t.vec3, self.t.vec4);
+        let zero = self.f32c(0.0);
+        let no_samples = self.b.constant_composite(vec4, [zero; 4]);
+        let (first_index, num_samples) = (self.u32c(0), self.u32c(4));
+
+        let entry = self.b.id();
+        let sample_loop = WarpSampleLoop {
+            header: self.b.id(),
+            continue_target: self.b.id(),
+            merge: self.b.id(),
+            index: self.b.id(),
+            samples: self.b.id(),
+            next_index: self.b.id(),
+            next_samples: self.b.id(),
+        };
+        let body = self.b.id();
+        ok(self.b.branch(entry));
+        ok(self.b.begin_block(Some(entry)));
+        ok(self.b.branch(sample_loop.header));
+
+        ok(self.b.begin_block(Some(sample_loop.header)));
+        let index_sources = [
+            (first_index, entry),
+            (sample_loop.next_index, sample_loop.continue_target),
+        ];
+        ok(self.b.phi(uint, Some(sample_loop.index), index_sources));
+        let samples_sources = [
+            (no_samples, entry),
+            (sample_loop.next_samples, sample_loop.continue_target),
+        ];
+        ok(self.b.phi(vec4, Some(sample_loop.samples), samples_sources));
+        let in_range = ok(self
+            .b
+            .u_less_than(self.t.bool, None, sample_loop.index, num_samples));
+        let header_block = self.b.selected_block();
+        ok(self
+            .b
+            .branch_conditional(in_range, body, sample_loop.merge, []));
+        ok(self.b.select_block(header_block));
+        ok(self.b.insert_loop_merge(
+            rspirv::dr::InsertPoint::FromEnd(1),
+            sample_loop.merge,
+            sample_loop.continue_target,
+            spirv::LoopControl::NONE,
+            [],
+        ));
+
+        ok(self.b.begin_block(Some(body)));
+        // Each component of the offsets, by the index of the offset.
+        let mut offset = [0; 3];
+        for (axis, component) in offset.iter_mut().enumerate() {
+            let components = WARP_GRADIENT_OFFSETS.map(|offset| self.f32c(offset[axis]));
+            let components = self.b.constant_composite(vec4, components);
+            *component = ok(self.b.vector_extract_dynamic(
+                self.t.float,
+                None,
+                components,
+                sample_loop.index,
+            ));
+        }
+        let offset = self.construct(vec3, offset);
+        let offset = self.scale(vec3, offset, epsilon);
+        let new_position = self.add(vec3, pos, offset);
+        self.push_position(new_position);
+        self.warp_sample_stack.push(sample_loop);
+    }
+
+    /// Ends the loop body with the `distance` of the sample, and continues after the loop.
+    /// The loop stays on `warp_sample_stack` for the `PushWarp` to read the samples from.
+    fn end_warp_sample_loop(&mut self, distance: Word) -> Result<(), Error> {
+        let (uint, vec4) = (self.t.uint, self.t.vec4);
+        let one = self.u32c(1);
+        let sample_loop = self
+            .warp_sample_stack
+            .last()
+            .ok_or(Error::BadProgram("PopWarpSample without PushWarpSample"))?;
+        let (samples, index) = (sample_loop.samples, sample_loop.index);
+        let (next_samples, next_index) = (sample_loop.next_samples, sample_loop.next_index);
+        let (header, continue_target, merge) = (
+            sample_loop.header,
+            sample_loop.continue_target,
+            sample_loop.merge,
+        );
+        ok(self
+            .b
+            .vector_insert_dynamic(vec4, Some(next_samples), samples, distance, index));
+        ok(self.b.branch(continue_target));
+
+        ok(self.b.begin_block(Some(continue_target)));
+        ok(self.b.i_add(uint, Some(next_index), index, one));
+        ok(self.b.branch(header));
+
+        ok(self.b.begin_block(Some(merge)));
+        Ok(())
+    }
+}
+
+// The primitives, reading their constants and returning their distance, like in `sdf.glsl`.
+impl<'a> SpirvGen<'a> {
+    fn sd_plane(&mut self, pos: Word) -> Word {
+        let plane = self.vec4();
+        let normal = self.xyz(plane);
+        let offset = self.extract(plane, 3);
+        let distance = self.dot(pos, normal);
+        self.add(self.t.float, distance, offset)
+    }
+
+    /// The bits of -infinity, like `sd_convex_hull_empty` in `sdf.glsl`, as some shading languages have no infinite literals.
+    fn sd_convex_hull_empty(&mut self) -> Word {
+        let bits = self.u32c(f32::NEG_INFINITY.to_bits());
+        ok(self.b.bitcast(self.t.float, None, bits))
+    }
+
+    fn sd_convex_hull(&mut self, pos: Word) -> Result<Word, Error> {
+        let empty = self.sd_convex_hull_empty();
+        let Some(buffer) = self.constant_buffer else {
+            let num_planes = self.constants.get(self.skip_constant());
+            let num_planes = num_planes.copied().unwrap_or_default() as usize;
+            if num_planes > self.constants.len().saturating_sub(self.constant_index) / 4 {
+                return Err(Error::BadConstants);
+            }
+            let mut distance = empty;
+            for _ in 0..num_planes {
+                let plane_distance = self.sd_plane(pos);
+                distance = self.max(distance, plane_distance);
+            }
+            return Ok(distance);
+        };
+
+        // The number of planes is read at runtime, and so are the indices of the constants after them,
+        // so that the module only depends on the opcodes.
+        let (f, uint, bool) = (self.t.float, self.t.uint, self.t.bool);
+        let num_planes = self.float32();
+        let num_planes = ok(self.b.convert_f_to_u(uint, None, num_planes));
+        let first_plane = self.constant_index_word(self.constant_index);
+        let (zero, one, four) = (self.u32c(0), self.u32c(1), self.u32c(4));
+        let plane_constants = ok(self.b.i_mul(uint, None, num_planes, four));
+        self.constant_base = Some(ok(self.b.i_add(uint, None, first_plane, plane_constants)));
+        self.constant_index = 0;
+
+        // A loop where the header checks the index, and the body is also the continue target.
+        let (entry, header, body, merge) = (self.b.id(), self.b.id(), self.b.id(), self.b.id());
+        let (next_index, next_distance) = (self.b.id(), self.b.id());
+        ok(self.b.branch(entry));
+        ok(self.b.begin_block(Some(entry)));
+        ok(self.b.branch(header));
+
+        ok(self.b.begin_block(Some(header)));
+        let index = ok(self.b.phi(uint, None, [(zero, entry), (next_index, body)]));
+        let distance = ok(self.b.phi(f, None, [(empty, entry), (next_distance, body)]));
+        let in_range = ok(self.b.u_less_than(bool, None, index, num_planes));
+        let header_block = self.b.selected_block();
+        ok(self.b.branch_conditional(in_range, body, merge, []));
+        ok(self.b.select_block(header_block));
+        ok(self.b.insert_loop_merge(
+            rspirv::dr::InsertPoint::FromEnd(1),
+            merge,
+            body,
+            spirv::LoopControl::NONE,
+            [],
+        ));
+
+        ok(self.b.begin_block(Some(body)));
+        let offset = ok(self.b.i_mul(uint, None, index, four));
+        let plane_index = ok(self.b.i_add(uint, None, first_plane, offset));
+        let mut components = [0; 4];
+        for (c, component) in components.iter_mut().enumerate() {
+            let c = self.u32c(c as u32);
+            let component_index = ok(self.b.i_add(uint, None, plane_index, c));
+            *component = self.load_constant(buffer, component_index);
+        }
+        let plane = self.construct(self.t.vec4, components);
+        let normal = self.xyz(plane);
+        let offset = self.extract(plane, 3);
+        let plane_distance = self.dot(pos, normal);
+        let plane_distance = self.add(f, plane_distance, offset);
+        let args = [distance, plane_distance].map(Operand::IdRef);
+        let max = GLOp::FMax as u32;
+        ok(self
+            .b
+            .ext_inst(f, Some(next_distance), self.glsl, max, args));
+        ok(self.b.i_add(uint, Some(next_index), index, one));
+        ok(self.b.branch(header));
+
+        ok(self.b.begin_block(Some(merge)));
+        Ok(distance)
+    }
+
+    fn sd_sphere(&mut self, pos: Word) -> Word {
+        let center = self.vec3();
+        let radius = self.float32();
+        let offset = self.sub(self.t.vec3, pos, center);
+        let distance = self.length(offset);
+        self.sub(self.t.float, distance, radius)
+    }
+
+    fn sd_capsule(&mut self, pos: Word) -> Word {
+        let (f, vec3) = (self.t.float, self.t.vec3);
+        let p0 = self.vec3();
+        let p1 = self.vec3();
+        let radius = self.float32();
+        let pa = self.sub(vec3, pos, p0);
+        let ba = self.sub(vec3, p1, p0);
+        let length_squared = self.dot(ba, ba);
+        let h = self.dot(pa, ba);
+        let h = self.div(f, h, length_squared);
+        let h = self.clamp(h, 0.0, 1.0);
+        // Both points being the same would divide by zero, but then it's a sphere.
+        let zero = self.f32c(0.0);
+        let is_segment = self.gt(length_squared, zero);
+        let h = self.select(f, is_segment, h, zero);
+        let closest = self.scale(vec3, ba, h);
+        let offset = self.sub(vec3, pa, closest);
+        let distance = self.length(offset);
+        self.sub(f, distance, radius)
+    }
+
+    fn sd_rounded_cylinder(&mut self, pos: Word) -> Word {
+        let f = self.t.float;
+        let cylinder_radius = self.float32();
+        let half_height = self.float32();
+        let rounding_radius = self.float32();
+        let x = self.length_xz(pos);
+        let x = self.sub(f, x, cylinder_radius);
+        let x = self.add(f, x, rounding_radius);
+        let y = self.extract(pos, 1);
+        let y = self.abs(y);
+        let y = self.sub(f, y, half_height);
+        let y = self.add(f, y, rounding_radius);
+        let d = self.construct(self.t.vec2, [x, y]);
+        let distance = self.box_2d(d);
+        self.sub(f, distance, rounding_radius)
+    }
+
+    fn sd_tube(&mut self, pos: Word) -> Word {
+        let f = self.t.float;
+        let outer_r = self.float32();
+        let inner_r = self.float32();
+        let half_height = self.float32();
+        let half = self.f32c(0.5);
+        let mid_r = self.add(f, outer_r, inner_r);
+        let mid_r = self.mul(f, half, mid_r);
+        let half_thickness = self.sub(f, outer_r, inner_r);
+        let half_thickness = self.mul(f, half, half_thickness);
+        let x = self.length_xz(pos);
+        let x = self.sub(f, x, mid_r);
+        let x = self.abs(x);
+        let x = self.sub(f, x, half_thickness);
+        let y = self.extract(pos, 1);
+        let y = self.abs(y);
+        let y = self.sub(f, y, half_height);
+        let d = self.construct(self.t.vec2, [x, y]);
+        self.box_2d(d)
+    }
+
+    fn sd_egg(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let radius = self.float32();
+        let tip_radius = self.float32();
+        let k = self.f32c(3.0_f32.sqrt());
+        let px = self.length_xz(pos);
+        let py = self.extract(pos, 1);
+        let p = self.construct(vec2, [px, py]);
+        let r = self.sub(f, radius, tip_radius);
+
+        let bottom = self.length(p);
+        let bottom = self.sub(f, bottom, r);
+        let k_r = self.mul(f, k, r);
+        let tip_y = self.sub(f, py, k_r);
+        let tip = self.construct(vec2, [px, tip_y]);
+        let tip = self.length(tip);
+        let side_x = self.add(f, px, r);
+        let side = self.construct(vec2, [side_x, py]);
+        let side = self.length(side);
+        let two_r = self.add(f, r, r);
+        let side = self.sub(f, side, two_r);
+
+        let zero = self.f32c(0.0);
+        let is_bottom = self.lt(py, zero);
+        let k_side_x = self.mul(f, k, side_x);
+        let is_tip = self.lt(k_side_x, py);
+        let distance = self.select(f, is_tip, tip, side);
+        let distance = self.select(f, is_bottom, bottom, distance);
+        self.sub(f, distance, tip_radius)
+    }
+
+    fn sd_heart(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let size = self.float32();
+        let half_thickness = self.float32();
+        let x = self.extract(pos, 0);
+        let x = self.abs(x);
+        let px = self.div(f, x, size);
+        let y = self.extract(pos, 1);
+        let py = self.div(f, y, size);
+        let p = self.construct(vec2, [px, py]);
+        let sum = self.add(f, px, py);
+
+        let lobe_center = self.vec2c(0.25, 0.75);
+        let lobe = self.sub(vec2, p, lobe_center);
+        let lobe = self.length(lobe);
+        let lobe_radius = self.f32c(std::f32::consts::SQRT_2 / 4.0);
+        let lobe = self.sub(f, lobe, lobe_radius);
+
+        let top = self.vec2c(0.0, 1.0);
+        let to_top = self.sub(vec2, p, top);
+        let to_top = self.dot(to_top, to_top);
+        let zero = self.f32c(0.0);
+        let half = self.f32c(0.5);
+        let along = self.max(sum, zero);
+        let along = self.mul(f, half, along);
+        let along = self.construct(vec2, [along, along]);
+        let to_point = self.sub(vec2, p, along);
+        let to_point = self.dot(to_point, to_point);
+        let body = self.min(to_top, to_point);
+        let body = self.sqrt(body);
+        let side = self.sub(f, px, py);
+        let side = self.sign(side);
+        let body = self.mul(f, body, side);
+
+        let one = self.f32c(1.0);
+        let is_lobe = self.gt(sum, one);
+        let heart = self.select(f, is_lobe, lobe, body);
+        let heart = self.mul(f, heart, size);
+        let z = self.extract(pos, 2);
+        let z = self.abs(z);
+        let z = self.sub(f, z, half_thickness);
+        let d = self.construct(vec2, [heart, z]);
+        self.box_2d(d)
+    }
+
+    fn sd_tapered_capsule(&mut self, pos: Word) -> Word {
+        let (f, vec3) = (self.t.float, self.t.vec3);
+        let p0 = self.vec3();
+        let r0 = self.float32();
+        let p1 = self.vec3();
+        let r1 = self.float32();
+
+        // sampling independent computations (only depend on shape)
+        let ba = self.sub(vec3, p1, p0);
+        let l2 = self.dot(ba, ba);
+        let rr = self.sub(f, r0, r1);
+        let rr2 = self.mul(f, rr, rr);
+        let a2 = self.sub(f, l2, rr2);
+        let one = self.f32c(1.0);
+        let il2 = self.div(f, one, l2);
+
+        // sampling dependant computations
+        let pa = self.sub(vec3, pos, p0);
+        let y = self.dot(pa, ba);
+        let z = self.sub(f, y, l2);
+        let pa_l2 = self.scale(vec3, pa, l2);
+        let ba_y = self.scale(vec3, ba, y);
+        let x2 = self.sub(vec3, pa_l2, ba_y);
+        let x2 = self.dot(x2, x2);
+        let y2 = self.mul(f, y, y);
+        let y2 = self.mul(f, y2, l2);
+        let z2 = self.mul(f, z, z);
+        let z2 = self.mul(f, z2, l2);
+
+        // single square root!
+        let k = self.sign(rr);
+        let k = self.mul(f, k, rr2);
+        let k = self.mul(f, k, x2);
+        let sign_z = self.sign(z);
+        let cap1 = self.mul(f, sign_z, a2);
+        let cap1 = self.mul(f, cap1, z2);
+        let is_cap1 = self.gt(cap1, k);
+        let sign_y = self.sign(y);
+        let cap0 = self.mul(f, sign_y, a2);
+        let cap0 = self.mul(f, cap0, y2);
+        let is_cap0 = self.lt(cap0, k);
+
+        let to_cap1 = self.add(f, x2, z2);
+        let to_cap1 = self.sqrt(to_cap1);
+        let to_cap1 = self.mul(f, to_cap1, il2);
+        let to_cap1 = self.sub(f, to_cap1, r1);
+        let to_cap0 = self.add(f, x2, y2);
+        let to_cap0 = self.sqrt(to_cap0);
+        let to_cap0 = self.mul(f, to_cap0, il2);
+        let to_cap0 = self.sub(f, to_cap0, r0);
+        let to_side = self.mul(f, x2, a2);
+        let to_side = self.mul(f, to_side, il2);
+        let to_side = self.sqrt(to_side);
+        let y_rr = self.mul(f, y, rr);
+        let to_side = self.add(f, to_side, y_rr);
+        let to_side = self.mul(f, to_side, il2);
+        let to_side = self.sub(f, to_side, r0);
+
+        let distance = self.select(f, is_cap0, to_cap0, to_side);
+        self.select(f, is_cap1, to_cap1, distance)
+    }
+
+    fn sd_cone(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let r = self.float32();
+        let h = self.float32();
+        let q = self.construct(vec2, [r, h]);
+        let wx = self.length_xz(pos);
+        let y = self.extract(pos, 1);
+        let wy = self.sub(f, h, y);
+        let w = self.construct(vec2, [wx, wy]);
+
+        let wq = self.dot(w, q);
+        let qq = self.dot(q, q);
+        let t = self.div(f, wq, qq);
+        let t = self.clamp(t, 0.0, 1.0);
+        let a = self.scale(vec2, q, t);
+        let a = self.sub(vec2, w, a);
+        let t = self.div(f, wx, r);
+        let t = self.clamp(t, 0.0, 1.0);
+        let bx = self.mul(f, r, t);
+        let b = self.construct(vec2, [bx, h]);
+        let b = self.sub(vec2, w, b);
+        let aa = self.dot(a, a);
+        let bb = self.dot(b, b);
+        let d = self.min(aa, bb);
+
+        let wx_h = self.mul(f, wx, h);
+        let wy_r = self.mul(f, wy, r);
+        let s0 = self.sub(f, wx_h, wy_r);
+        let s1 = self.sub(f, wy, h);
+        let s = self.max(s0, s1);
+        let d = self.sqrt(d);
+        let s = self.sign(s);
+        self.mul(f, d, s)
+    }
+
+    fn sd_capped_cone(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let r1 = self.float32();
+        let r2 = self.float32();
+        let height = self.float32();
+        let half = self.f32c(0.5);
+        let h = self.mul(f, half, height);
+        let qx = self.length_xz(pos);
+        let y = self.extract(pos, 1);
+        let qy = self.sub(f, y, h);
+        let q = self.construct(vec2, [qx, qy]);
+        let k1 = self.construct(vec2, [r2, h]);
+        let k2x = self.sub(f, r2, r1);
+        let k2 = self.construct(vec2, [k2x, height]);
+
+        let zero = self.f32c(0.0);
+        let is_below = self.lt(qy, zero);
+        let r = self.select(f, is_below, r1, r2);
+        let cax = self.min(qx, r);
+        let cax = self.sub(f, qx, cax);
+        let cay = self.abs(qy);
+        let cay = self.sub(f, cay, h);
+        let ca = self.construct(vec2, [cax, cay]);
+
+        let to_top = self.sub(vec2, k1, q);
+        let t = self.dot(to_top, k2);
+        let k2k2 = self.dot(k2, k2);
+        let t = self.div(f, t, k2k2);
+        let t = self.clamp(t, 0.0, 1.0);
+        let cb = self.sub(vec2, q, k1);
+        let along = self.scale(vec2, k2, t);
+        let cb = self.add(vec2, cb, along);
+
+        let caca = self.dot(ca, ca);
+        let cbcb = self.dot(cb, cb);
+        let d = self.min(caca, cbcb);
+        let d = self.sqrt(d);
+        let cbx = self.extract(cb, 0);
+        let is_inside_side = self.lt(cbx, zero);
+        let is_inside_caps = self.lt(cay, zero);
+        let neg_d = self.neg(f, d);
+        let inside = self.select(f, is_inside_caps, neg_d, d);
+        self.select(f, is_inside_side, inside, d)
+    }
+
+    fn sd_rounded_box(&mut self, pos: Word) -> Word {
+        let (f, vec3) = (self.t.float, self.t.vec3);
+        let half_size = self.vec3();
+        let radius = self.float32();
+        let q = self.ext(vec3, GLOp::FAbs, &[pos]);
+        let q = self.sub(vec3, q, half_size);
+        let radius3 = self.construct(vec3, [radius; 3]);
+        let q = self.add(vec3, q, radius3);
+        let zero3 = self.splat3c(0.0);
+        let outside = self.ext(vec3, GLOp::FMax, &[q, zero3]);
+        let outside = self.length(outside);
+        let (x, y, z) = (self.extract(q, 0), self.extract(q, 1), self.extract(q, 2));
+        let inside = self.max(y, z);
+        let inside = self.max(x, inside);
+        let zero = self.f32c(0.0);
+        let inside = self.min(inside, zero);
+        let distance = self.add(f, outside, inside);
+        self.sub(f, distance, radius)
+    }
+
+    fn sd_torus(&mut self, pos: Word) -> Word {
+        let f = self.t.float;
+        let big_r = self.float32();
+        let small_r = self.float32();
+        let x = self.length_xz(pos);
+        let x = self.sub(f, x, big_r);
+        let y = self.extract(pos, 1);
+        let q = self.construct(self.t.vec2, [x, y]);
+        let distance = self.length(q);
+        self.sub(f, distance, small_r)
+    }
+
+    fn sd_torus_sector(&mut self, pos: Word) -> Word {
+        let f = self.t.float;
+        let big_r = self.float32();
+        let small_r = self.float32();
+        let sin = self.float32();
+        let cos = self.float32();
+        let x = self.extract(pos, 0);
+        let x = self.abs(x);
+        let y = self.extract(pos, 1);
+        let z = self.extract(pos, 2);
+        let pos = self.construct(self.t.vec3, [x, y, z]);
+
+        let cos_x = self.mul(f, cos, x);
+        let sin_z = self.mul(f, sin, z);
+        let in_sector = self.gt(cos_x, sin_z);
+        let sin_x = self.mul(f, x, sin);
+        let cos_z = self.mul(f, z, cos);
+        let k_sector = self.add(f, sin_x, cos_z);
+        let k_end = self.length_xz(pos);
+        let k = self.select(f, in_sector, k_sector, k_end);
+
+        let d = self.dot(pos, pos);
+        let big_r2 = self.mul(f, big_r, big_r);
+        let d = self.add(f, d, big_r2);
+        let two_big_r = self.add(f, big_r, big_r);
+        let two_big_r_k = self.mul(f, two_big_r, k);
+        let d = self.sub(f, d, two_big_r_k);
+        let zero = self.f32c(0.0);
+        let d = self.max(d, zero);
+        let d = self.sqrt(d);
+        self.sub(f, d, small_r)
+    }
+
+    fn sd_biconvex_lens(&mut self, pos: Word) -> Word {
+        let f = self.t.float;
+        let lower_sagitta = self.float32();
+        let upper_sagitta = self.float32();
+        let chord = self.float32();
+        let half = self.f32c(0.5);
+        let chord_radius = self.mul(f, chord, half);
+        let chord_radius2 = self.mul(f, chord_radius, chord_radius);
+        let zero = self.f32c(0.0);
+
+        // A sphere through the edge of the chord, with its surface `sagitta` from the chord.
+        let sphere = |this: &mut Self, sagitta: Word, flip: bool| {
+            let sagitta2 = this.mul(f, sagitta, sagitta);
+            let radius = this.add(f, chord_radius2, sagitta2);
+            let diameter = this.add(f, sagitta, sagitta);
+            let radius = this.div(f, radius, diameter);
+            let center_y = this.sub(f, radius, sagitta);
+            let center_y = if flip {
+                this.neg(f, center_y)
+            } else {
+                center_y
+            };
+            let center = this.construct(this.t.vec3, [zero, center_y, zero]);
+            let offset = this.sub(this.t.vec3, pos, center);
+            let distance = this.length(offset);
+            this.sub(f, distance, radius)
+        };
+        let lower = sphere(self, lower_sagitta, false);
+        let upper = sphere(self, upper_sagitta, true);
+        let is_lower = self.gt(lower, upper);
+        self.select(f, is_lower, lower, upper)
+    }
+
+    fn sd_vesica(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let radius = self.float32();
+        let distance = self.float32();
+        let qx = self.length_xz(pos);
+        let qy = self.extract(pos, 1);
+        let qy = self.abs(qy);
+        let q = self.construct(vec2, [qx, qy]);
+        let r2 = self.mul(f, radius, radius);
+        let d2 = self.mul(f, distance, distance);
+        let b = self.sub(f, r2, d2);
+        let zero = self.f32c(0.0);
+        let b = self.max(b, zero);
+        let b = self.sqrt(b);
+
+        let lhs = self.sub(f, qy, b);
+        let lhs = self.mul(f, lhs, distance);
+        let rhs = self.mul(f, qx, b);
+        let at_tip = self.gt(lhs, rhs);
+        let tip = self.construct(vec2, [zero, b]);
+        let tip = self.sub(vec2, q, tip);
+        let tip = self.length(tip);
+        let neg_distance = self.neg(f, distance);
+        let center = self.construct(vec2, [neg_distance, zero]);
+        let side = self.sub(vec2, q, center);
+        let side = self.length(side);
+        let side = self.sub(f, side, radius);
+        self.select(f, at_tip, tip, side)
+    }
+
+    /// `w = sqrt(max(radius * radius - height * height, 0.0))` and `q = vec2(length(pos.xz), pos.y)`
+    /// for the cut spheres.
+    fn cut_sphere_w_q(&mut self, pos: Word, radius: Word, height: Word) -> (Word, Word) {
+        let f = self.t.float;
+        let r2 = self.mul(f, radius, radius);
+        let h2 = self.mul(f, height, height);
+        let w = self.sub(f, r2, h2);
+        let zero = self.f32c(0.0);
+        let w = self.max(w, zero);
+        let w = self.sqrt(w);
+        let qx = self.length_xz(pos);
+        let qy = self.extract(pos, 1);
+        let q = self.construct(self.t.vec2, [qx, qy]);
+        (w, q)
+    }
+
+    fn sd_cut_sphere(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let radius = self.float32();
+        let height = self.float32();
+        let (w, q) = self.cut_sphere_w_q(pos, radius, height);
+        let (qx, qy) = (self.extract(q, 0), self.extract(q, 1));
+
+        let s0 = self.sub(f, height, radius);
+        let s0 = self.mul(f, s0, qx);
+        let s0 = self.mul(f, s0, qx);
+        let s1 = self.add(f, height, radius);
+        let two = self.f32c(2.0);
+        let two_qy = self.mul(f, two, qy);
+        let s1 = self.sub(f, s1, two_qy);
+        let w2 = self.mul(f, w, w);
+        let s1 = self.mul(f, w2, s1);
+        let s0 = self.add(f, s0, s1);
+        let h_qx = self.mul(f, height, qx);
+        let w_qy = self.mul(f, w, qy);
+        let s1 = self.sub(f, h_qx, w_qy);
+        let s = self.max(s0, s1);
+
+        let sphere = self.length(q);
+        let sphere = self.sub(f, sphere, radius);
+        let cap = self.sub(f, height, qy);
+        let rim = self.construct(vec2, [w, height]);
+        let rim = self.sub(vec2, q, rim);
+        let rim = self.length(rim);
+
+        let zero = self.f32c(0.0);
+        let on_sphere = self.lt(s, zero);
+        let on_cap = self.lt(qx, w);
+        let distance = self.select(f, on_cap, cap, rim);
+        self.select(f, on_sphere, sphere, distance)
+    }
+
+    fn sd_cut_hollow_sphere(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let radius = self.float32();
+        let height = self.float32();
+        let thickness = self.float32();
+        let (w, q) = self.cut_sphere_w_q(pos, radius, height);
+        let (qx, qy) = (self.extract(q, 0), self.extract(q, 1));
+
+        let h_qx = self.mul(f, height, qx);
+        let w_qy = self.mul(f, w, qy);
+        let at_rim = self.lt(h_qx, w_qy);
+        let rim = self.construct(vec2, [w, height]);
+        let rim = self.sub(vec2, q, rim);
+        let rim = self.length(rim);
+        let shell = self.length(q);
+        let shell = self.sub(f, shell, radius);
+        let shell = self.abs(shell);
+        let distance = self.select(f, at_rim, rim, shell);
+        self.sub(f, distance, thickness)
+    }
+
+    fn sd_link(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let length = self.float32();
+        let big_r = self.float32();
+        let small_r = self.float32();
+        let (x, y, z) = (
+            self.extract(pos, 0),
+            self.extract(pos, 1),
+            self.extract(pos, 2),
+        );
+        let y = self.abs(y);
+        let y = self.sub(f, y, length);
+        let zero = self.f32c(0.0);
+        let y = self.max(y, zero);
+        let q = self.construct(vec2, [x, y]);
+        let q = self.length(q);
+        let q = self.sub(f, q, big_r);
+        let d = self.construct(vec2, [q, z]);
+        let d = self.length(d);
+        self.sub(f, d, small_r)
+    }
+
+    fn sd_solid_angle(&mut self, pos: Word) -> Word {
+        let (f, vec2) = (self.t.float, self.t.vec2);
+        let sin = self.float32();
+        let cos = self.float32();
+        let radius = self.float32();
+        let c = self.construct(vec2, [sin, cos]);
+        let qx = self.length_xz(pos);
+        let qy = self.extract(pos, 1);
+        let q = self.construct(vec2, [qx, qy]);
+        let l = self.length(q);
+        let l = self.sub(f, l, radius);
+        // Not `clamp`, which is undefined for a negative radius.
+        let t = self.dot(q, c);
+        let zero = self.f32c(0.0);
+        let t = self.max(t, zero);
+        let t = self.min(t, radius);
+        let m = self.scale(vec2, c, t);
+        let m = self.sub(vec2, q, m);
+        let m = self.length(m);
+        let cos_qx = self.mul(f, cos, qx);
+        let sin_qy = self.mul(f, sin, qy);
+        let side = self.sub(f, cos_qx, sin_qy);
+        let side = self.sign(side);
+        let m = self.mul(f, m, side);
+        self.max(l, m)
+    }
+
+    fn sd_gyroid(&mut self, pos: Word) -> Word {
+        let (f, vec3) = (self.t.float, self.t.vec3);
+        let scale = self.float32();
+        let thickness = self.float32();
+        let sin = self.ext(vec3, GLOp::Sin, &[pos]);
+        let yzx = ok(self.b.vector_shuffle(vec3, None, pos, pos, [1, 2, 0]));
+        let cos = self.ext(vec3, GLOp::Cos, &[yzx]);
+        let g = self.dot(sin, cos);
+        let g = self.abs(g);
+        let g = self.mul(f, g, scale);
+        self.sub(f, g, thickness)
+    }
+
+    fn sd_schwarz_p(&mut self, pos: Word) -> Word {
+        let f = self.t.float;
+        let scale = self.float32();
+        let thickness = self.float32();
+        let cos = self.ext(self.t.vec3, GLOp::Cos, &[pos]);
+        let one = self.splat3c(1.0);
+        let p = self.dot(cos, one);
+        let p = self.abs(p);
+        let p = self.mul(f, p, scale);
+        self.sub(f, p, thickness)
+    }
+}
+
+impl<'a> SpirvGen<'a> {
+    /// Emits the function evaluating the program at its `vec3` parameter, and returns its id.
+    fn build_function(
+        &mut self,
+        program: &Program,
+        output_type: Word,
+        name: &str,
+    ) -> Result<Word, Error> {
+        use Opcode::*;
+
+        let (f, vec3) = (self.t.float, self.t.vec3);
+        let function_type = self.b.type_function(output_type, [vec3]);
+        let function = ok(self.b.begin_function(
+            output_type,
+            None,
+            spirv::FunctionControl::NONE,
+            function_type,
+        ));
+        self.b.name(function, name);
+        self.current_position = ok(self.b.function_parameter(vec3));
+        ok(self.b.begin_block(None));
+
+        for (opcode_index, opcode) in program.opcodes.iter().enumerate() {
+            let pos = self.current_position;
+            let primitive = match opcode {
+                Plane => Some(self.sd_plane(pos)),
+                ConvexHull => Some(self.sd_convex_hull(pos)?),
+                Sphere => Some(self.sd_sphere(pos)),
+                Capsule => Some(self.sd_capsule(pos)),
+                RoundedCylinder => Some(self.sd_rounded_cylinder(pos)),
+                Tube => Some(self.sd_tube(pos)),
+                Egg => Some(self.sd_egg(pos)),
+                Heart => Some(self.sd_heart(pos)),
+                TaperedCapsule => Some(self.sd_tapered_capsule(pos)),
+                Cone => Some(self.sd_cone(pos)),
+                CappedCone => Some(self.sd_capped_cone(pos)),
+                RoundedBox => Some(self.sd_rounded_box(pos)),
+                Torus => Some(self.sd_torus(pos)),
+                TorusSector => Some(self.sd_torus_sector(pos)),
+                BiconvexLens => Some(self.sd_biconvex_lens(pos)),
+                Vesica => Some(self.sd_vesica(pos)),
+                CutSphere => Some(self.sd_cut_sphere(pos)),
+                CutHollowSphere => Some(self.sd_cut_hollow_sphere(pos)),
+                Link => Some(self.sd_link(pos)),
+                SolidAngle => Some(self.sd_solid_angle(pos)),
+                Gyroid => Some(self.sd_gyroid(pos)),
+                SchwarzP => Some(self.sd_schwarz_p(pos)),
+                Material => {
+                    let sd = self.pop()?;
+                    let rgba = self.vec4();
+                    // There is no room for the rest of the material, like in `sdf.glsl`.
+                    self.skip_constant();
+                    self.skip_constant();
+                    let payload = match self.output_type {
+                        OutputType::DistanceWithRgb => Some(self.xyz(rgba)),
+                        OutputType::DistanceOnly | OutputType::DistanceWithId => sd.payload,
+                    };
+                    self.sd_stack.push(Sd { payload, ..sd });
+                    None
+                }
+                Union => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let sd = self.union(d1, d2);
+                    self.sd_stack.push(sd);
+                    None
+                }
+                Subtract => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let sd = self.subtract(d1, d2);
+                    self.sd_stack.push(sd);
+                    None
+                }
+                Intersect => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let sd = self.intersect(d1, d2);
+                    self.sd_stack.push(sd);
+                    None
+                }
+                UnionSmooth => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let difference = self.sub(f, d2.distance, d1.distance);
+                    let h = self.smooth_blend(1.0, difference, size);
+                    let mixed = self.mix_sd(d2, d1, h);
+                    let correction = self.smooth_correction(size, h);
+                    let distance = self.sub(f, mixed.distance, correction);
+                    self.sd_stack.push(Sd { distance, ..mixed });
+                    None
+                }
+                SubtractSmooth => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let sum = self.add(f, d2.distance, d1.distance);
+                    let h = self.smooth_blend(-1.0, sum, size);
+                    let neg_distance1 = self.neg(f, d1.distance);
+                    // Keeps the payload of d2, like `sd_op_subtract_smooth`.
+                    let mixed = self.ext(f, GLOp::FMix, &[d2.distance, neg_distance1, h]);
+                    let correction = self.smooth_correction(size, h);
+                    let distance = self.add(f, mixed, correction);
+                    self.sd_stack.push(Sd { distance, ..d2 });
+                    None
+                }
+                IntersectSmooth => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let difference = self.sub(f, d2.distance, d1.distance);
+                    let h = self.smooth_blend(-1.0, difference, size);
+                    let mixed = self.mix_sd(d2, d1, h);
+                    let correction = self.smooth_correction(size, h);
+                    let distance = self.add(f, mixed.distance, correction);
+                    self.sd_stack.push(Sd { distance, ..mixed });
+                    None
+                }
+                UnionChamfer => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let chamfer = self.add(f, d1.distance, d2.distance);
+                    let chamfer = self.sub(f, chamfer, size);
+                    let chamfer = self.chamfer(chamfer);
+                    let union = self.union(d1, d2);
+                    let distance = self.min(union.distance, chamfer);
+                    self.sd_stack.push(Sd { distance, ..union });
+                    None
+                }
+                SubtractChamfer => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let chamfer = self.sub(f, d2.distance, d1.distance);
+                    let chamfer = self.add(f, chamfer, size);
+                    let chamfer = self.chamfer(chamfer);
+                    let subtract = self.subtract(d1, d2);
+                    let distance = self.max(subtract.distance, chamfer);
+                    self.sd_stack.push(Sd {
+                        distance,
+                        ..subtract
+                    });
+                    None
+                }
+                IntersectChamfer => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let chamfer = self.add(f, d1.distance, d2.distance);
+                    let chamfer = self.add(f, chamfer, size);
+                    let chamfer = self.chamfer(chamfer);
+                    let intersect = self.intersect(d1, d2);
+                    let distance = self.max(intersect.distance, chamfer);
+                    self.sd_stack.push(Sd {
+                        distance,
+                        ..intersect
+                    });
+                    None
+                }
+                UnionStairs => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let size = self.float32();
+                    let steps = self.float32();
+                    let (a, b) = (d1.distance, d2.distance);
+                    let step = self.div(f, size, steps);
+                    let u = self.sub(f, b, size);
+                    let stairs = self.sub(f, u, a);
+                    let stairs = self.add(f, stairs, step);
+                    let two_steps = self.add(f, step, step);
+                    // `FMod` takes the sign of the divisor, like `rem_euclid` for a positive one.
+                    let stairs = ok(self.b.f_mod(f, None, stairs, two_steps));
+                    let stairs = self.sub(f, stairs, step);
+                    let stairs = self.abs(stairs);
+                    let stairs = self.add(f, a, stairs);
+                    let stairs = self.add(f, u, stairs);
+                    let half = self.f32c(0.5);
+                    let stairs = self.mul(f, half, stairs);
+                    let closest = self.union(d1, d2);
+                    let distance = self.min(a, b);
+                    let distance = self.min(distance, stairs);
+                    self.sd_stack.push(Sd {
+                        distance,
+                        ..closest
+                    });
+                    None
+                }
+                Pipe => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let radius = self.float32();
+                    let (abs1, abs2) = (self.abs(d1.distance), self.abs(d2.distance));
+                    let first_is_closest = self.lt(abs1, abs2);
+                    let closest = self.select_sd(first_is_closest, d1, d2);
+                    let both = self.construct(self.t.vec2, [d1.distance, d2.distance]);
+                    let distance = self.length(both);
+                    let distance = self.sub(f, distance, radius);
+                    self.sd_stack.push(Sd {
+                        distance,
+                        ..closest
+                    });
+                    None
+                }
+                Groove => {
+                    let (d1, d2) = (self.pop()?, self.pop()?);
+                    let depth = self.float32();
+                    let half_width = self.float32();
+                    let deepest = self.add(f, d2.distance, depth);
+                    let abs1 = self.abs(d1.distance);
+                    let widest = self.sub(f, half_width, abs1);
+                    let groove = self.min(deepest, widest);
+                    let outside = self.gt(d2.distance, groove);
+                    let d1 = Sd {
+                        distance: groove,
+                        ..d1
+                    };
+                    let sd = self.select_sd(outside, d2, d1);
+                    self.sd_stack.push(sd);
+                    None
+                }
+                PushTranslation => {
+                    let translation = self.vec3();
+                    let new_position = self.add(vec3, pos, translation);
+                    self.push_position(new_position);
+                    None
+                }
+                PopTransform => {
+                    self.pop_position()?;
+                    None
+                }
+                PushRotation => {
+                    let rotation = self.vec4();
+                    let new_position = self.mul_quat(rotation, pos);
+                    self.push_position(new_position);
+                    None
+                }
+                PushScale => {
+                    let inv_scale = self.float32();
+                    let new_position = self.scale(vec3, pos, inv_scale);
+                    self.push_position(new_position);
+                    None
+                }
+                // Both scale the distance to compensate for the transform.
+                PopScale | PopAffine => {
+                    self.pop_position()?;
+                    let scale = self.float32();
+                    let sd = self.pop()?;
+                    let distance = self.mul(f, sd.distance, scale);
+                    self.sd_stack.push(Sd { distance, ..sd });
+                    None
+                }
+                PushAffine => {
+                    let axes = [self.vec3(), self.vec3(), self.vec3()];
+                    let mut new_position = self.vec3();
+                    for (index, axis) in axes.into_iter().enumerate() {
+                        let component = self.extract(pos, index as u32);
+                        let along_axis = self.scale(vec3, axis, component);
+                        new_position = self.add(vec3, new_position, along_axis);
+                    }
+                    self.push_position(new_position);
+                    None
+                }
+                PushRevolve => {
+                    let offset = self.float32();
+                    let x = self.length_xz(pos);
+                    let x = self.sub(f, x, offset);
+                    let y = self.extract(pos, 1);
+                    let zero = self.f32c(0.0);
+                    let new_position = self.construct(vec3, [x, y, zero]);
+                    self.push_position(new_position);
+                    None
+                }
+                PushWarpSample => {
+                    let epsilon = self.float32();
+                    self.begin_warp_sample_loop(pos, epsilon);
+                    None
+                }
+                PopWarpSample => {
+                    self.pop_position()?;
+                    let sd = self.pop()?;
+                    self.end_warp_sample_loop(sd.distance)?;
+                    None
+                }
+                PushWarp => {
+                    let strength = self.float32();
+                    let epsilon = self.float32();
+                    let distances =
+                        if opcode_index > 0 && program.opcodes[opcode_index - 1] == PopWarpSample {
+                            // `PopWarpSample` has just checked that there is a loop.
+                            let samples = self.warp_sample_stack.pop().unwrap().samples;
+                            [0, 1, 2, 3].map(|i| self.extract(samples, i))
+                        } else {
+                            let mut distances = [0; 4];
+                            for distance in distances.iter_mut().rev() {
+                                *distance = self.pop()?.distance;
+                            }
+                            distances
+                        };
+                    let mut gradient = self.splat3c(0.0);
+                    for (offset, distance) in WARP_GRADIENT_OFFSETS.iter().zip(distances) {
+                        let offset = offset.to_array().map(|c| self.f32c(c));
+                        let offset = self.b.constant_composite(vec3, offset);
+                        let offset = self.scale(vec3, offset, distance);
+                        gradient = self.add(vec3, gradient, offset);
+                    }
+                    let four = self.f32c(4.0);
+                    let step = self.mul(f, four, epsilon);
+                    let step = self.div(f, strength, step);
+                    let gradient = self.scale(vec3, gradient, step);
+                    let new_position = self.add(vec3, pos, gradient);
+                    self.push_position(new_position);
+                    None
+                }
+                PushElongate => {
+                    let amount = self.vec3();
+                    // Not `clamp`, which is undefined for a negative amount.
+                    let neg_amount = self.neg(vec3, amount);
+                    let clamped = self.ext(vec3, GLOp::FMax, &[pos, neg_amount]);
+                    let clamped = self.ext(vec3, GLOp::FMin, &[clamped, amount]);
+                    let new_position = self.sub(vec3, pos, clamped);
+                    self.push_position(new_position);
+                    None
+                }
+                PopElongate => {
+                    self.pop_position()?;
+                    let amount = self.vec3();
+                    let sd = self.pop()?;
+                    let q = self.ext(vec3, GLOp::FAbs, &[self.current_position]);
+                    let q = self.sub(vec3, q, amount);
+                    let (x, y, z) = (self.extract(q, 0), self.extract(q, 1), self.extract(q, 2));
+                    let inside = self.max(y, z);
+                    let inside = self.max(x, inside);
+                    let zero = self.f32c(0.0);
+                    let inside = self.min(inside, zero);
+                    let distance = self.add(f, sd.distance, inside);
+                    self.sd_stack.push(Sd { distance, ..sd });
+                    None
+                }
+                ScaleDistance => {
+                    let factor = self.float32();
+                    let sd = self.pop()?;
+                    let distance = self.mul(f, sd.distance, factor);
+                    self.sd_stack.push(Sd { distance, ..sd });
+                    None
+                }
+                End => break,
+            };
+            if let Some(distance) = primitive {
+                self.push_primitive(distance, opcode_index);
+            }
+        }
+
+        let sd = self.pop()?;
+        let result = match (self.output_type, sd.payload) {
+            (OutputType::DistanceWithRgb, Some(rgb)) => {
+                self.construct(self.t.vec4, [rgb, sd.distance])
+            }
+            (OutputType::DistanceWithId, Some(id)) => {
+                self.construct(self.t.vec2, [sd.distance, id])
+            }
+            _ => sd.distance,
+        };
+        ok(self.b.ret_value(result));
+        ok(self.b.end_function());
+
+        if !self.warp_sample_stack.is_empty() {
+            return Err(Error::BadProgram(
+                "PushWarpSample without a matching PushWarp",
+            ));
+        }
+        // Past a dynamic number of planes, the indices of the constants are only known at runtime.
+        if self.constant_base.is_none() && self.constant_index > self.constants.len() {
+            return Err(Error::BadConstants);
+        }
+        Ok(function)
+    }
+}
+
+/// Declares a storage buffer with a runtime array of `element_type` at `binding`, and returns its variable.
+fn storage_buffer(
+    b: &mut Builder,
+    element_type: Word,
+    stride: u32,
+    binding: u32,
+    writable: bool,
+    name: &str,
+) -> Word {
+    // Fresh ids, so that buffers with the same element type don't share their decorations.
+    let array_id = b.id();
+    let array = b.type_runtime_array_id(Some(array_id), element_type);
+    b.decorate(
+        array,
+        spirv::Decoration::ArrayStride,
+        [Operand::LiteralInt32(stride)],
+    );
+    let block_id = b.id();
+    let block = b.type_struct_id(Some(block_id), [array]);
+    b.decorate(block, spirv::Decoration::Block, []);
+    b.member_decorate(
+        block,
+        0,
+        spirv::Decoration::Offset,
+        [Operand::LiteralInt32(0)],
+    );
+    if !writable {
+        b.member_decorate(block, 0, spirv::Decoration::NonWritable, []);
+    }
+    let pointer = b.type_pointer(None, spirv::StorageClass::StorageBuffer, block);
+    let variable = b.variable(pointer, None, spirv::StorageClass::StorageBuffer, None);
+    b.decorate(
+        variable,
+        spirv::Decoration::DescriptorSet,
+        [Operand::LiteralInt32(0)],
+    );
+    b.decorate(
+        variable,
+        spirv::Decoration::Binding,
+        [Operand::LiteralInt32(binding)],
+    );
+    b.name(variable, name);
+    variable
+}
+
+/// Builds a SPIR-V 1.3 module with a compute shader entry point called `function_name`,
+/// that evaluates the program at each position in a buffer, see [`crate::Backend::SpirV`].
+///
+/// Fails for malformed programs, e.g. with [`Error::BadStack`] when the stack runs out of distances.
+pub(crate) fn build_spirv_module(
+    program: &Program,
+    function_name: &str,
+    output_type: OutputType,
+    dynamic_constants: bool,
+) -> Result<rspirv::dr::Module, Error> {
+    let mut b = Builder::new();
+    b.set_version(1, 3);
+    b.capability(spirv::Capability::Shader);
+    let glsl = b.ext_inst_import("GLSL.std.450");
+    b.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+
+    let float = b.type_float(32);
+    let uint = b.type_int(32, 0);
+    let bool = b.type_bool();
+    let t = Types {
+        void: b.type_void(),
+        bool,
+        bvec3: b.type_vector(bool, 3),
+        uint,
+        uvec3: b.type_vector(uint, 3),
+        float,
+        vec2: b.type_vector(float, 2),
+        vec3: b.type_vector(float, 3),
+        vec4: b.type_vector(float, 4),
+    };
+    let (result_type, result_stride) = match output_type {
+        OutputType::DistanceOnly => (t.float, 4),
+        OutputType::DistanceWithRgb => (t.vec4, 16),
+        OutputType::DistanceWithId => (t.vec2, 8),
+    };
+
+    let positions = storage_buffer(&mut b, t.vec4, 16, 0, false, "positions");
+    let results = storage_buffer(&mut b, result_type, result_stride, 1, true, "results");
+    let constant_buffer =
+        dynamic_constants.then(|| storage_buffer(&mut b, t.uint, 4, 2, false, "constants"));
+    let invocation_id_pointer = b.type_pointer(None, spirv::StorageClass::Input, t.uvec3);
+    let invocation_id = b.variable(
+        invocation_id_pointer,
+        None,
+        spirv::StorageClass::Input,
+        None,
+    );
+    b.decorate(
+        invocation_id,
+        spirv::Decoration::BuiltIn,
+        [Operand::BuiltIn(spirv::BuiltIn::GlobalInvocationId)],
+    );
+
+    let mut gen = SpirvGen {
+        b,
+        glsl,
+        t,
+        output_type,
+        constants: &program.constants,
+        constant_index: 0,
+        constant_buffer,
+        constant_base: None,
+        float_constants: HashMap::new(),
+        uint_constants: HashMap::new(),
+        sd_stack: vec![],
+        position_stack: vec![],
+        current_position: 0,
+        warp_sample_stack: vec![],
+    };
+    let function = gen.build_function(program, result_type, &format!("{function_name}_base"))?;
+
+    // The entry point evaluates the function at `positions[i].xyz` into `results[i]`.
+    let (void, uint, vec3, vec4) = (gen.t.void, gen.t.uint, gen.t.vec3, gen.t.vec4);
+    let zero = gen.u32c(0);
+    let b = &mut gen.b;
+    let main_type = b.type_function(void, []);
+    let main = ok(b.begin_function(void, None, spirv::FunctionControl::NONE, main_type));
+    b.entry_point(
+        spirv::ExecutionModel::GLCompute,
+        main,
+        function_name,
+        [invocation_id],
+    );
+    b.execution_mode(
+        main,
+        spirv::ExecutionMode::LocalSize,
+        [SPIRV_WORKGROUP_SIZE, 1, 1],
+    );
+    ok(b.begin_block(None));
+    let id = ok(b.load(gen.t.uvec3, None, invocation_id, None, []));
+    let index = ok(b.composite_extract(uint, None, id, [0]));
+    let len = ok(b.array_length(uint, None, positions, 0));
+    let in_range = ok(b.u_less_than(gen.t.bool, None, index, len));
+    let (body, merge) = (b.id(), b.id());
+    // rspirv treats OpSelectionMerge as the end of a block, so it goes in right before the branch.
+    let header = b.selected_block();
+    ok(b.branch_conditional(in_range, body, merge, []));
+    ok(b.select_block(header));
+    ok(b.insert_selection_merge(
+        rspirv::dr::InsertPoint::FromEnd(1),
+        merge,
+        spirv::SelectionControl::NONE,
+    ));
+
+    ok(b.begin_block(Some(body)));
+    let position_pointer = b.type_pointer(None, spirv::StorageClass::StorageBuffer, vec4);
+    let position = ok(b.access_chain(position_pointer, None, positions, [zero, index]));
+    let position = ok(b.load(vec4, None, position, None, []));
+    let position = ok(b.vector_shuffle(vec3, None, position, position, [0, 1, 2]));
+    let result = ok(b.function_call(result_type, None, function, [position]));
+    let result_pointer = b.type_pointer(None, spirv::StorageClass::StorageBuffer, result_type);
+    let result_pointer = ok(b.access_chain(result_pointer, None, results, [zero, index]));
+    ok(b.store(result_pointer, result, None, []));
+    ok(b.branch(merge));
+
+    ok(b.begin_block(Some(merge)));
+    ok(b.ret());
+    ok(b.end_function());
+
+    Ok(gen.b.module())
+}
+
+/// See [`build_spirv_module`].
+pub(crate) fn build_spirv(
+    program: &Program,
+    function_name: &str,
+    output_type: OutputType,
+    dynamic_constants: bool,
+) -> Result<Vec<u32>, Error> {
+    build_spirv_module(program, function_name, output_type, dynamic_constants)
+        .map(|module| module.assemble())
+}
+
+#[cfg(test)]
+#[allow(clippy::wildcard_enum_match_arm)] // the modules only use a small part of SPIR-V
+mod tests {
+    use super::*;
+    use crate::Graph;
+    use glam::Vec3;
+    use glam::Vec4;
+    use num_traits::FromPrimitive;
+    use opensaft_sdf::DistanceWithId;
+    use opensaft_sdf::Interpreter;
+    use rspirv::dr::Instruction;
+    use rspirv::dr::Module;
+    use rspirv::spirv::Op;
+
+    /// A value of the subset of SPIR-V that the modules use, where scalars have one component.
+    #[derive(Clone, Debug)]
+    enum Value {
+        Float(Vec<f32>),
+        Uint(Vec<u32>),
+        Bool(Vec<bool>),
+        /// The element at an index of the constant buffer.
+        Constant(usize),
+    }
+
+    impl Value {
+        fn floats(&self) -> &[f32] {
+            match self {
+                Self::Float(v) => v,
+                other => panic!("expected floats, got {other:?}"),
+            }
+        }
+
+        fn uints(&self) -> &[u32] {
+            match self {
+                Self::Uint(v) => v,
+                other => panic!("expected uints, got {other:?}"),
+            }
+        }
+
+        fn bools(&self) -> &[bool] {
+            match self {
+                Self::Bool(v) => v,
+                other => panic!("expected bools, got {other:?}"),
+            }
+        }
+
+        fn component(&self, index: usize) -> Self {
+            match self {
+                Self::Float(v) => Self::Float(vec![v[index]]),
+                Self::Uint(v) => Self::Uint(vec![v[index]]),
+                Self::Bool(v) => Self::Bool(vec![v[index]]),
+                Self::Constant(_) => panic!("pointers have no components"),
+            }
+        }
+
+        fn concat(values: impl IntoIterator<Item = Self>) -> Self {
+            let mut values = values.into_iter();
+            let mut result = values.next().unwrap();
+            for value in values {
+                match (&mut result, value) {
+                    (Self::Float(a), Self::Float(b)) => a.extend(b),
+                    (Self::Uint(a), Self::Uint(b)) => a.extend(b),
+                    (Self::Bool(a), Self::Bool(b)) => a.extend(b),
+                    (a, b) => panic!("can't concatenate {a:?} and {b:?}"),
+                }
+            }
+            result
+        }
+    }
+
+    /// Applies `f` to each component of `a` and `b`, where a scalar goes with every component of a vector.
+    fn zip<T: Copy, R>(a: &[T], b: &[T], f: impl Fn(T, T) -> R) -> Vec<R> {
+        let len = a.len().max(b.len());
+        let at = |v: &[T], i: usize| if v.len() == 1 { v[0] } else { v[i] };
+        (0..len).map(|i| f(at(a, i), at(b, i))).collect()
+    }
+
+    fn id(operand: &Operand) -> Word {
+        match operand {
+            Operand::IdRef(id) => *id,
+            other => panic!("expected an id, got {other:?}"),
+        }
+    }
+
+    fn literal(operand: &Operand) -> u32 {
+        match operand {
+            Operand::LiteralInt32(value) | Operand::LiteralExtInstInteger(value) => *value,
+            other => panic!("expected an integer literal, got {other:?}"),
+        }
+    }
+
+    /// Runs the `{function_name}_base` function of a module on the CPU at each of the `positions`,
+    /// with `constants` in the constant buffer.
+    fn evaluate(
+        module: &Module,
+        function_name: &str,
+        constants: &[f32],
+        positions: &[Vec3],
+    ) -> Vec<Vec<f32>> {
+        let mut kinds = HashMap::new();
+        let mut values = HashMap::new();
+        for inst in &module.types_global_values {
+            let result_id = inst.result_id;
+            match inst.class.opcode {
+                Op::TypeFloat | Op::TypeInt | Op::TypeBool => {
+                    kinds.insert(result_id.unwrap(), inst.class.opcode);
+                }
+                Op::TypeVector => {
+                    let kind = kinds[&id(&inst.operands[0])];
+                    kinds.insert(result_id.unwrap(), kind);
+                }
+                Op::Constant => {
+                    let value = match (kinds[&inst.result_type.unwrap()], &inst.operands[0]) {
+                        (Op::TypeFloat, Operand::LiteralFloat32(value)) => {
+                            Value::Float(vec![*value])
+                        }
+                        (Op::TypeFloat, Operand::LiteralInt32(bits)) => {
+                            Value::Float(vec![f32::from_bits(*bits)])
+                        }
+                        (_, operand) => Value::Uint(vec![literal(operand)]),
+                    };
+                    values.insert(result_id.unwrap(), value);
+                }
+                Op::ConstantComposite => {
+                    let components = inst.operands.iter().map(|c| values[&id(c)].clone());
+                    let value = Value::concat(components.collect::<Vec<_>>());
+                    values.insert(result_id.unwrap(), value);
+                }
+                _ => {}
+            }
+        }
+
+        let name = format!("{function_name}_base");
+        let function_id = module
+            .debug_names
+            .iter()
+            .find(|inst| inst.operands[1] == Operand::LiteralString(name.clone()))
+            .map(|inst| id(&inst.operands[0]))
+            .unwrap();
+        let function = module
+            .functions
+            .iter()
+            .find(|function| function.def_id() == Some(function_id))
+            .unwrap();
+        let parameter = function.parameters[0].result_id.unwrap();
+        let mut results = vec![];
+        for pos in positions {
+            values.insert(parameter, Value::Float(pos.to_array().to_vec()));
+            let mut block = &function.blocks[0];
+            let mut previous_label = None;
+            'blocks: loop {
+                let mut next_label = None;
+                for inst in &block.instructions {
+                    let operand = |i: usize| &values[&id(&inst.operands[i])];
+                    let value = match inst.class.opcode {
+                        Op::SelectionMerge | Op::LoopMerge => continue,
+                        Op::Branch => {
+                            next_label = Some(id(&inst.operands[0]));
+                            break;
+                        }
+                        Op::BranchConditional => {
+                            let target = if operand(0).bools()[0] { 1 } else { 2 };
+                            next_label = Some(id(&inst.operands[target]));
+                            break;
+                        }
+                        Op::ReturnValue => {
+                            results.push(operand(0).floats().to_vec());
+                            break 'blocks;
+                        }
+                        Op::Phi => {
+                            let source = inst
+                                .operands
+                                .chunks(2)
+                                .find(|source| Some(id(&source[1])) == previous_label)
+                                .unwrap();
+                            values[&id(&source[0])].clone()
+                        }
+                        _ => evaluate_instruction(inst, &kinds, constants, operand),
+                    };
+                    values.insert(inst.result_id.unwrap(), value);
+                }
+                previous_label = block.label_id();
+                block = function
+                    .blocks
+                    .iter()
+                    .find(|block| block.label_id() == next_label)
+                    .unwrap();
+            }
+        }
+        results
+    }
+
+    fn evaluate_instruction<'v>(
+        inst: &Instruction,
+        kinds: &HashMap<Word, Op>,
+        constants: &[f32],
+        operand: impl Fn(usize) -> &'v Value,
+    ) -> Value {
+        let floats = |i: usize| operand(i).floats();
+        let float_op = |f: fn(f32, f32) -> f32| Value::Float(zip(floats(0), floats(1), f));
+        let compare = |f: fn(f32, f32) -> bool| Value::Bool(zip(floats(0), floats(1), f));
+        let uint_op =
+            |f: fn(u32, u32) -> u32| Value::Uint(zip(operand(0).uints(), operand(1).uints(), f));
+        match inst.class.opcode {
+            Op::FAdd => float_op(|a, b| a + b),
+            Op::FSub => float_op(|a, b| a - b),
+            Op::FMul | Op::VectorTimesScalar => float_op(|a, b| a * b),
+            Op::FDiv => float_op(|a, b| a / b),
+            Op::FMod => float_op(|a, b| a - b * (a / b).floor()),
+            Op::FNegate => Value::Float(floats(0).iter().map(|a| -a).collect()),
+            Op::Dot => Value::Float(vec![zip(floats(0), floats(1), |a, b| a * b).iter().sum()]),
+            Op::FOrdLessThan => compare(|a, b| a < b),
+            Op::FOrdGreaterThan => compare(|a, b| a > b),
+            Op::IAdd => uint_op(u32::wrapping_add),
+            Op::IMul => uint_op(u32::wrapping_mul),
+            Op::ULessThan => Value::Bool(zip(operand(0).uints(), operand(1).uints(), |a, b| a < b)),
+            Op::ConvertFToU => Value::Uint(floats(0).iter().map(|a| *a as u32).collect()),
+            Op::Bitcast => match kinds[&inst.result_type.unwrap()] {
+                Op::TypeFloat => Value::Float(
+                    operand(0)
+                        .uints()
+                        .iter()
+                        .map(|a| f32::from_bits(*a))
+                        .collect(),
+                ),
+                _ => Value::Uint(floats(0).iter().map(|a| a.to_bits()).collect()),
+            },
+            Op::Select => {
+                let condition = operand(0).bools();
+                let pick = |i: usize| {
+                    if condition[i.min(condition.len() - 1)] {
+                        1
+                    } else {
+                        2
+                    }
+                };
+                let len = match operand(1) {
+                    Value::Float(v) => v.len(),
+                    other => other.uints().len(),
+                };
+                Value::concat((0..len).map(|i| operand(pick(i)).component(i)))
+            }
+            Op::CompositeConstruct => {
+                Value::concat((0..inst.operands.len()).map(|i| operand(i).clone()))
+            }
+            Op::CompositeExtract => operand(0).component(literal(&inst.operands[1]) as usize),
+            Op::VectorShuffle => {
+                let both = Value::concat([operand(0).clone(), operand(1).clone()]);
+                Value::concat(
+                    inst.operands[2..]
+                        .iter()
+                        .map(|i| both.component(literal(i) as usize)),
+                )
+            }
+            Op::VectorExtractDynamic => operand(0).component(operand(1).uints()[0] as usize),
+            Op::VectorInsertDynamic => {
+                let mut vector = floats(0).to_vec();
+                vector[operand(2).uints()[0] as usize] = floats(1)[0];
+                Value::Float(vector)
+            }
+            // Only the constant buffer is accessed, as `constants[0][index]`.
+            Op::AccessChain => Value::Constant(operand(2).uints()[0] as usize),
+            Op::Load => match operand(0) {
+                Value::Constant(index) => Value::Uint(vec![constants[*index].to_bits()]),
+                other => panic!("can't load from {other:?}"),
+            },
+            Op::ExtInst => {
+                let args = |i: usize| floats(i + 2);
+                let unary =
+                    |f: fn(f32) -> f32| Value::Float(args(0).iter().copied().map(f).collect());
+                let binary = |f: fn(f32, f32) -> f32| Value::Float(zip(args(0), args(1), f));
+                match GLOp::from_u32(literal(&inst.operands[1])).unwrap() {
+                    GLOp::FAbs => unary(f32::abs),
+                    GLOp::FSign => unary(|a| {
+                        if a > 0.0 {
+                            1.0
+                        } else if a < 0.0 {
+                            -1.0
+                        } else {
+                            0.0
+                        }
+                    }),
+                    GLOp::Sqrt => unary(f32::sqrt),
+                    GLOp::Sin => unary(f32::sin),
+                    GLOp::Cos => unary(f32::cos),
+                    GLOp::FMin => binary(f32::min),
+                    GLOp::FMax => binary(f32::max),
+                    GLOp::FClamp => {
+                        let clamped = zip(args(0), args(1), f32::max);
+                        Value::Float(zip(&clamped, args(2), f32::min))
+                    }
+                    GLOp::FMix => {
+                        let difference = zip(args(1), args(0), |b, a| b - a);
+                        let step = zip(&difference, args(2), |d, t| d * t);
+                        Value::Float(zip(args(0), &step, |a, s| a + s))
+                    }
+                    GLOp::Length => {
+                        Value::Float(vec![args(0).iter().map(|a| a * a).sum::<f32>().sqrt()])
+                    }
+                    GLOp::Cross => {
+                        let a = Vec3::from_slice(args(0));
+                        let b = Vec3::from_slice(args(1));
+                        Value::Float(a.cross(b).to_array().to_vec())
+                    }
+                    op => panic!("unsupported extended instruction {op:?}"),
+                }
+            }
+            op => panic!("unsupported instruction {op:?}"),
+        }
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let mut graph = Graph::default();
+        let example = graph.example(&Default::default());
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let other_sphere = graph.sphere(Vec3::X, 1.0);
+        let gyroid = graph.gyroid(1.0, 0.1);
+        let warped = graph.op_warp(sphere, gyroid, 0.2);
+        let revolved = graph.op_revolve(sphere, 1.0);
+        let elongated = graph.op_elongate(warped, Vec3::new(1.0, 0.0, 0.5));
+        let stairs = graph.op_union_stairs(sphere, other_sphere, 0.5, 3);
+        let pipe = graph.op_pipe(sphere, other_sphere, 0.1);
+        let groove = graph.op_groove(revolved, stairs, 0.1, 0.05);
+        let chamfer = graph.op_subtract_chamfer(elongated, groove, 0.2);
+        let hull = graph.convex_hull(vec![
+            Vec4::new(1.0, 0.0, 0.0, -1.0),
+            Vec4::new(-1.0, 0.0, 0.0, -1.0),
+            Vec4::new(0.0, 1.0, 0.0, -0.5),
+        ]);
+        let primitives = vec![
+            graph.schwarz_p(1.0, 0.3),
+            graph.solid_angle(0.7, 1.0),
+            graph.link(0.5, 1.0, 0.25),
+            graph.cut_sphere(1.0, 0.2),
+            graph.cut_hollow_sphere(1.0, 0.2, 0.1),
+            graph.vesica(1.0, 0.5),
+            graph.tube(1.0, 0.5, 0.3),
+            graph.egg(1.0, 0.25),
+            graph.heart(1.0, 0.25),
+            graph.capped_cone(1.0, 0.5, 2.0),
+        ];
+        let primitives = graph.op_union_multi(primitives);
+        let primitives = graph.op_affine(
+            primitives,
+            glam::Affine3A::from_scale_rotation_translation(
+                Vec3::new(1.0, 2.0, 3.0),
+                glam::Quat::from_rotation_z(0.3),
+                Vec3::Y,
+            ),
+        );
+        let clamped = graph.op_clamp_distance(pipe, 0.5);
+        let positions: Vec<Vec3> = (0..200)
+            .map(|i| {
+                let t = i as f32;
+                Vec3::new((t * 0.37).sin(), (t * 0.11).cos(), (t * 0.73).sin()) * 3.0
+            })
+            .collect();
+
+        // Each part on its own, as a union would hide the parts that are further away.
+        for root in [hull, example, chamfer, primitives, clamped] {
+            let program = crate::compile(&graph, root);
+            for dynamic_constants in [false, true] {
+                let build = |output_type| {
+                    let module =
+                        build_spirv_module(&program, "scene", output_type, dynamic_constants);
+                    evaluate(&module.unwrap(), "scene", &program.constants, &positions)
+                };
+                let distances = build(OutputType::DistanceOnly);
+                let distances_with_ids = build(OutputType::DistanceWithId);
+                let mut num_same_ids = 0;
+                for (i, &pos) in positions.iter().enumerate() {
+                    let mut context =
+                        Interpreter::new_context(&program.opcodes, &program.constants);
+                    let expected = Interpreter::<f32>::interpret(&mut context, pos).unwrap();
+                    let tolerance = 1e-4 * (1.0 + expected.abs());
+                    assert!(
+                        (distances[i][0] - expected).abs() <= tolerance,
+                        "{pos}: {} != {expected}",
+                        distances[i][0]
+                    );
+
+                    let mut context =
+                        Interpreter::new_context(&program.opcodes, &program.constants);
+                    let expected =
+                        Interpreter::<DistanceWithId>::interpret(&mut context, pos).unwrap();
+                    let [distance, id] = distances_with_ids[i][..] else {
+                        panic!("expected a vec2");
+                    };
+                    assert!((distance - expected.distance).abs() <= tolerance);
+                    if id as u32 == expected.id {
+                        num_same_ids += 1;
+                    }
+                }
+                // Rounding can pick the other side of a union that is almost a tie.
+                assert!(num_same_ids >= positions.len() - 2, "{num_same_ids}");
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_programs() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::ZERO, 1.0);
+        let other_sphere = graph.sphere(Vec3::X, 1.0);
+        let root = graph.op_union(sphere, other_sphere);
+        let program = crate::compile(&graph, root);
+        let build = |program: &Program| {
+            build_spirv_module(program, "scene", OutputType::DistanceOnly, false).map(|_| ())
+        };
+        assert_eq!(build(&program), Ok(()));
+
+        let mut too_few_distances = program.clone();
+        too_few_distances.opcodes.remove(0);
+        assert_eq!(build(&too_few_distances), Err(Error::BadStack));
+
+        let mut too_few_constants = program.clone();
+        too_few_constants.constants.pop();
+        assert_eq!(build(&too_few_constants), Err(Error::BadConstants));
+
+        let mut unmatched_transform = program.clone();
+        unmatched_transform.opcodes.insert(0, Opcode::PopTransform);
+        assert_eq!(build(&unmatched_transform), Err(Error::BadStack));
+
+        let hull = graph.convex_hull(vec![Vec4::X - Vec4::W]);
+        let mut too_many_planes = crate::compile(&graph, hull);
+        too_many_planes.constants[0] = 1e9;
+        assert_eq!(build(&too_many_planes), Err(Error::BadConstants));
+    }
+}
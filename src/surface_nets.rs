@@ -0,0 +1,155 @@
+use ahash::AHashMap as HashMap;
+
+use crate::Grid3;
+use crate::Index3;
+use crate::QuadMesh;
+use crate::SignedDistance;
+use glam::Vec3;
+
+/// The corners of a cell, as offsets from its lowest corner. Bit `i` is the offset along axis `i`.
+const CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+impl<T> Grid3<T>
+where
+    T: SignedDistance,
+{
+    /// Extracts the zero isosurface as quads, using naive surface nets.
+    ///
+    /// There is one vertex per surface cell (see [`Self::surface_cells`]), at the mean of where the surface
+    /// crosses its edges, and one quad for each grid edge the surface crosses, connecting the four cells around it.
+    /// This gives fewer, more evenly sized faces than [`Self::marching_cubes`], which suits subdivision and retopology.
+    ///
+    /// Like [`Self::marching_cubes`], the positions are in grid coordinates, and there are no colors.
+    pub fn surface_nets(&self) -> QuadMesh {
+        let distance = |p: Index3| self[p].distance();
+        let corner_offsets = CORNERS.map(|corner| Vec3::from(corner.map(|c| c as f32)));
+
+        let mut mesh = QuadMesh::default();
+        let cells: Vec<Index3> = self.surface_cells().collect();
+        let mut vertex_of_cell = HashMap::with_capacity(cells.len());
+        for &cell in &cells {
+            let corners =
+                CORNERS.map(|[x, y, z]| distance([cell[0] + x, cell[1] + y, cell[2] + z]));
+
+            let mut sum = Vec3::ZERO;
+            let mut num_crossings = 0;
+            for (i, &a) in corners.iter().enumerate() {
+                for axis in 0..3 {
+                    let j = i | (1 << axis);
+                    let b = corners[j];
+                    if j != i && (a < 0.0) != (b < 0.0) {
+                        sum += corner_offsets[i].lerp(corner_offsets[j], a / (a - b));
+                        num_crossings += 1;
+                    }
+                }
+            }
+            let local = sum / num_crossings as f32;
+
+            // The gradient of the trilinear interpolation of the corners at `local`.
+            let mut gradient = Vec3::ZERO;
+            for (offset, d) in CORNERS.iter().zip(corners) {
+                let weight = |axis: usize| {
+                    if offset[axis] == 1 {
+                        local[axis]
+                    } else {
+                        1.0 - local[axis]
+                    }
+                };
+                let sign = |axis: usize| if offset[axis] == 1 { 1.0 } else { -1.0 };
+                gradient += d * Vec3::new(
+                    sign(0) * weight(1) * weight(2),
+                    weight(0) * sign(1) * weight(2),
+                    weight(0) * weight(1) * sign(2),
+                );
+            }
+
+            vertex_of_cell.insert(cell, mesh.positions.len() as u32);
+            let position = Vec3::from(cell.map(|c| c as f32)) + local;
+            mesh.positions.push(position.into());
+            mesh.normals
+                .push(gradient.try_normalize().unwrap_or(Vec3::Y).into());
+        }
+
+        // One quad per crossed edge, from `p` along `axis`, where all four cells around it exist.
+        // Any crossed edge belongs to the surface cell it is the lowest corner of.
+        for &p in &cells {
+            for axis in 0..3 {
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                if p[u] == 0 || p[v] == 0 {
+                    continue;
+                }
+                let mut end = p;
+                end[axis] += 1;
+                let (a, b) = (distance(p), distance(end));
+                if (a < 0.0) == (b < 0.0) {
+                    continue;
+                }
+
+                let offset = |du: usize, dv: usize| {
+                    let mut c = p;
+                    c[u] -= du;
+                    c[v] -= dv;
+                    vertex_of_cell.get(&c).copied()
+                };
+                if let (Some(c0), Some(c1), Some(c2), Some(c3)) =
+                    (offset(0, 0), offset(1, 0), offset(1, 1), offset(0, 1))
+                {
+                    // Counter-clockwise around `axis`, which is outwards if the distance increases along it.
+                    mesh.faces.push(if a < 0.0 {
+                        [c0, c1, c2, c3]
+                    } else {
+                        [c0, c3, c2, c1]
+                    });
+                }
+            }
+        }
+
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_quads() {
+        let half_size = Vec3::new(6.0, 4.0, 5.0);
+        let center = Vec3::splat(10.0);
+        let mut grid = Grid3::<f32>::new([20, 20, 20]);
+        grid.set(|[x, y, z]| {
+            let q = (Vec3::new(x as f32, y as f32, z as f32) - center).abs() - half_size;
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+        });
+
+        let mesh = grid.surface_nets();
+        assert!(!mesh.faces.is_empty());
+        assert_eq!(mesh.positions.len(), grid.surface_cells().count());
+        let num_quads = mesh.faces.iter().filter(|f| f[2] != f[3]).count();
+        assert!(num_quads as f32 > 0.9 * mesh.faces.len() as f32);
+
+        // Every face winds counter-clockwise around the outward normal.
+        for face in &mesh.faces {
+            let [a, b, c, _] = face.map(|i| Vec3::from(mesh.positions[i as usize]));
+            let normal = Vec3::from(mesh.normals[face[0] as usize]);
+            assert!((b - a).cross(c - a).dot(normal) > 0.0);
+        }
+
+        let triangles = mesh.triangulate();
+        assert_eq!(triangles.indices.len(), 6 * num_quads);
+
+        let obj = mesh.to_obj();
+        let faces: Vec<&str> = obj.lines().filter(|l| l.starts_with("f ")).collect();
+        assert_eq!(faces.len(), mesh.faces.len());
+        assert!(faces.iter().all(|f| f.split(' ').count() == 5));
+    }
+}
@@ -7,6 +7,13 @@ use glam::Vec3;
 
 /// Marches a ray from `t_range.start()` until `t_range.end()`,
 /// returning the first hit, or the place where the trace got closest to the surface.
+///
+/// This compiles `root` on every call, use [`crate::CompiledSdf::march`] to trace many rays.
+/// See [`march_auto`] to march wherever the ray is inside the bounding box of `root` instead.
+///
+/// # Panics
+///
+/// If the program is too deep for the interpreter, see [`crate::Error::StackOverflow`].
 pub fn march(
     graph: &Graph,
     root: NodeId,
@@ -14,13 +21,65 @@ pub fn march(
     t_range: std::ops::RangeInclusive<f32>,
     opt: &Options,
 ) -> ClosestHit {
-    // we could use the bounding box as an optimization to limit `t_range`
-    let program = crate::compile(graph, root);
-    let mut sd = to_sd_func(&program);
-    crate::sphere_tracing::trace(&mut sd, ray, t_range, opt)
+    crate::CompiledSdf::new(graph, root)
+        .expect("the program fits the interpreter")
+        .march(ray, t_range, opt)
+}
+
+/// How far [`march_auto`] marches into bounding boxes that are infinite along the ray, e.g. of planes.
+const MARCH_AUTO_MAX_LENGTH: f32 = 10_000.0;
+
+/// Like [`march`], but only marches the part of the ray inside the bounding box of `root`,
+/// see [`crate::BoundingBox::ray_intersection`], so there is no `t_range` to get wrong.
+///
+/// Returns [`ClosestHit::miss`] without marching if the ray misses the bounding box.
+pub fn march_auto(graph: &Graph, root: NodeId, ray: Ray3, opt: &Options) -> ClosestHit {
+    let Some(t_range) = graph.bounding_box(root).ray_intersection(&ray) else {
+        return ClosestHit::miss();
+    };
+    let t_end = t_range.end().min(t_range.start() + MARCH_AUTO_MAX_LENGTH);
+    march(graph, root, ray, *t_range.start()..=t_end, opt)
 }
 
 pub fn to_sd_func(program: &crate::Program) -> impl FnMut(Vec3) -> f32 + '_ {
     let mut d_context = crate::Interpreter::new_context(&program.opcodes, &program.constants);
     move |pos: Vec3| crate::Interpreter::<f32>::interpret(&mut d_context, pos).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec4;
+
+    #[test]
+    fn march_auto_hits_far_away() {
+        let mut graph = Graph::default();
+        let sphere = graph.sphere(Vec3::new(0.0, 0.0, 50.0), 1.0);
+        let opt = Options::default();
+        let ray = Ray3 {
+            origin: Vec3::ZERO,
+            dir: Vec3::Z,
+        };
+
+        assert!(!march(&graph, sphere, ray, 0.0..=10.0, &opt).is_hit);
+        let hit = march_auto(&graph, sphere, ray, &opt);
+        assert!(hit.is_hit);
+        assert!((hit.t - 49.0).abs() < 0.1, "{}", hit.t);
+
+        let beside = Ray3 {
+            origin: Vec3::new(5.0, 0.0, 0.0),
+            dir: Vec3::Z,
+        };
+        assert!(!march_auto(&graph, sphere, beside, &opt).is_hit);
+
+        // Planes have infinite bounding boxes.
+        let floor = graph.plane(Vec4::new(0.0, 1.0, 0.0, 0.0));
+        let down = Ray3 {
+            origin: Vec3::new(0.0, 5.0, 0.0),
+            dir: -Vec3::Y,
+        };
+        let hit = march_auto(&graph, floor, down, &opt);
+        assert!(hit.is_hit);
+        assert!((hit.t - 5.0).abs() < 0.1, "{}", hit.t);
+    }
+}